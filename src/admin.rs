@@ -0,0 +1,431 @@
+//! Administrative areas from the `boundary=administrative` relations of the extract, from countries at admin_level 2
+//! down to neighbourhoods at 10. Their outlines are assembled from the member ways, every area links to the smallest
+//! area of a lower level it's in, and every address to the smallest area it's in.
+//!
+//! Relations come after the ways and nodes they're made of, so the nodes of every way are kept while parsing like for
+//! `--ways`. Boundaries that the extract cut off can't be closed and are left out.
+
+use std::collections::HashMap;
+
+use sea_orm::sea_query::Expr;
+use sea_orm::{ActiveValue, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
+use serde_json::{json, Value};
+
+use crate::batch::{BatchInsert, Upsert};
+use crate::boundaries::Ring;
+use crate::database::live_nodes;
+use crate::entities::*;
+use crate::hull::Point;
+use crate::ways::NodeIndex;
+
+const MIN_LEVEL: i32 = 2;
+const MAX_LEVEL: i32 = 10;
+
+/// Size of the grid cells areas are indexed by, in degrees.
+const CELL_SIZE: f64 = 0.25;
+
+/// Addresses read and updated at a time.
+const PAGE_SIZE: u64 = 5000;
+
+const BATCH_SIZE: usize = 64;
+const PENDING_WRITES: usize = 4;
+
+#[derive(Default)]
+struct Relation {
+    id: i64,
+    name: Option<String>,
+    admin_level: Option<i32>,
+    administrative: bool,
+    outer: Vec<i64>,
+    inner: Vec<i64>,
+}
+
+/// Collects the ways and boundary relations of the extract while it's parsed.
+#[derive(Default)]
+pub struct Collector {
+    /// The id of every way with where its nodes start in `refs` and how many there are
+    ways: Vec<(i64, usize, usize)>,
+    refs: Vec<i64>,
+    relations: Vec<Relation>,
+    current: Option<Relation>,
+}
+
+impl Collector {
+    pub fn add_way(&mut self, id: i64, refs: &[i64]) {
+        // A later version of the same way, in full history files
+        if self.ways.last().is_some_and(|(last, _, _)| *last == id) {
+            self.ways.pop();
+        }
+
+        self.ways.push((id, self.refs.len(), refs.len()));
+        self.refs.extend_from_slice(refs);
+    }
+
+    pub fn start_relation(&mut self, id: i64) {
+        self.current = Some(Relation { id, ..Default::default() });
+    }
+
+    pub fn member(&mut self, kind: &str, id: i64, role: &str) {
+        let Some(relation) = &mut self.current else {
+            return;
+        };
+
+        match (kind, role) {
+            ("way", "outer" | "") => relation.outer.push(id),
+            ("way", "inner") => relation.inner.push(id),
+            _ => {}
+        }
+    }
+
+    pub fn tag(&mut self, key: &str, value: &str) {
+        let Some(relation) = &mut self.current else {
+            return;
+        };
+
+        match key {
+            "name" => relation.name = Some(value.to_string()),
+            "admin_level" => relation.admin_level = value.trim().parse().ok(),
+            "boundary" => relation.administrative = value == "administrative",
+            _ => {}
+        }
+    }
+
+    /// Keeps the relation that was being parsed when it's an administrative boundary. Deleted versions in full
+    /// history files remove the earlier version.
+    pub fn end_element(&mut self, visible: bool) {
+        let Some(relation) = self.current.take() else {
+            return;
+        };
+
+        if self.relations.last().is_some_and(|last| last.id == relation.id) {
+            self.relations.pop();
+        }
+
+        let level = relation.admin_level.unwrap_or(0);
+
+        if visible && relation.administrative && relation.name.is_some() && (MIN_LEVEL..=MAX_LEVEL).contains(&level) {
+            self.relations.push(relation);
+        }
+    }
+
+    fn way(&self, id: i64) -> Option<&[i64]> {
+        self.ways
+            .binary_search_by_key(&id, |(id, _, _)| *id)
+            .ok()
+            .map(|i| &self.refs[self.ways[i].1..self.ways[i].1 + self.ways[i].2])
+    }
+
+    /// The areas of the boundaries that could be closed, and how many couldn't.
+    pub fn finish(mut self, nodes: &mut NodeIndex) -> (Vec<admin_area::ActiveModel>, usize) {
+        // Extracts are sorted by id, this only happens for hand made files
+        if self.ways.windows(2).any(|pair| pair[0].0 > pair[1].0) {
+            self.ways.sort_by_key(|(id, _, _)| *id);
+        }
+
+        let mut shapes = Vec::new();
+        let mut broken = 0;
+
+        for relation in &self.relations {
+            let outer = self.rings(&relation.outer, nodes);
+            let inner = self.rings(&relation.inner, nodes);
+
+            match (outer, inner) {
+                (Some(outer), Some(inner)) if !outer.is_empty() => shapes.push(Shape::new(relation, outer, inner)),
+                _ => broken += 1,
+            }
+        }
+
+        let index = Index::new(shapes.iter().map(|shape| (shape.id, shape.level, shape.rings())).collect());
+
+        let areas = shapes.into_iter()
+            .map(|shape| {
+                let parent = shape.interior.and_then(|(lon, lat)| index.parent(lat, lon, shape.level));
+                let (lon, lat) = shape.interior.unwrap_or(shape.outer[0][0]);
+
+                admin_area::ActiveModel {
+                    id: ActiveValue::Set(shape.id),
+                    name: ActiveValue::Set(shape.name.clone()),
+                    admin_level: ActiveValue::Set(shape.level),
+                    parent_id: ActiveValue::Set(parent),
+                    lat: ActiveValue::Set(lat),
+                    lon: ActiveValue::Set(lon),
+                    geojson: ActiveValue::Set(shape.geojson().to_string()),
+                }
+            })
+            .collect();
+
+        (areas, broken)
+    }
+
+    /// Joins the ways into closed rings of lon, lat by their shared end nodes. `None` when a way or node is missing or
+    /// a ring doesn't close.
+    fn rings(&self, ways: &[i64], nodes: &mut NodeIndex) -> Option<Vec<Vec<Point>>> {
+        let mut open: Vec<Vec<i64>> = Vec::new();
+        let mut closed = Vec::new();
+
+        for id in ways {
+            let refs = self.way(*id)?;
+
+            if refs.len() < 2 {
+                continue;
+            }
+
+            open.push(refs.to_vec());
+        }
+
+        while let Some(mut line) = open.pop() {
+            while line.len() < 4 || line.first() != line.last() {
+                let end = *line.last().expect("lines have at least two nodes");
+                let next = open.iter().position(|next| next.first() == Some(&end) || next.last() == Some(&end))?;
+                let mut next = open.swap_remove(next);
+
+                if next.last() == Some(&end) {
+                    next.reverse();
+                }
+
+                line.extend_from_slice(&next[1..]);
+            }
+
+            closed.push(line);
+        }
+
+        closed.iter()
+            .map(|ring| nodes.line(ring).map(|points| points.into_iter().map(|(lat, lon)| (lon, lat)).collect()))
+            .collect()
+    }
+}
+
+/// The outline of a boundary, with the holes of each outer ring.
+struct Shape {
+    id: i64,
+    name: String,
+    level: i32,
+    outer: Vec<Vec<Point>>,
+    inner: Vec<Vec<Point>>,
+    /// A point inside the area as lon, lat
+    interior: Option<Point>,
+}
+
+impl Shape {
+    fn new(relation: &Relation, outer: Vec<Vec<Point>>, inner: Vec<Vec<Point>>) -> Self {
+        let mut shape = Self {
+            id: relation.id,
+            name: relation.name.clone().unwrap_or_default(),
+            level: relation.admin_level.unwrap_or(MAX_LEVEL),
+            outer,
+            inner,
+            interior: None,
+        };
+
+        shape.interior = interior(&shape.outer.iter().chain(&shape.inner).collect::<Vec<_>>());
+        shape
+    }
+
+    fn rings(&self) -> Vec<Vec<Point>> {
+        self.outer.iter().chain(&self.inner).cloned().collect()
+    }
+
+    /// A MultiPolygon with every hole in the outer ring it's in.
+    fn geojson(&self) -> Value {
+        let outer: Vec<Ring> = self.outer.iter().map(|ring| Ring::new(ring.clone())).collect();
+        let mut polygons: Vec<Vec<&Vec<Point>>> = self.outer.iter().map(|ring| vec![ring]).collect();
+
+        for hole in &self.inner {
+            if let Some(i) = outer.iter().position(|ring| ring.contains(hole[0])) {
+                polygons[i].push(hole);
+            }
+        }
+
+        let coordinates: Vec<Vec<Vec<[f64; 2]>>> = polygons.iter()
+            .map(|polygon| polygon.iter().map(|ring| ring.iter().map(|(lon, lat)| [*lon, *lat]).collect()).collect())
+            .collect();
+
+        json!({ "type": "MultiPolygon", "coordinates": coordinates })
+    }
+}
+
+/// A point inside the rings, for linking an area to the one it's in without testing its outline, which runs along
+/// the borders of its parent. It's the middle of the widest stretch inside the area along the latitude halfway its
+/// bounding box.
+fn interior(rings: &[&Vec<Point>]) -> Option<Point> {
+    let points = rings.iter().flat_map(|ring| ring.iter());
+    let (min_lat, max_lat) = points.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), (_, lat)| (min.min(*lat), max.max(*lat)));
+    let y = (min_lat + max_lat) / 2.0;
+
+    let mut crossings: Vec<f64> = rings.iter()
+        .flat_map(|ring| ring.windows(2))
+        .filter(|edge| (edge[0].1 > y) != (edge[1].1 > y))
+        .map(|edge| edge[0].0 + (y - edge[0].1) * (edge[1].0 - edge[0].0) / (edge[1].1 - edge[0].1))
+        .collect();
+
+    crossings.sort_by(f64::total_cmp);
+
+    crossings.chunks_exact(2)
+        .max_by(|a, b| (a[1] - a[0]).total_cmp(&(b[1] - b[0])))
+        .map(|stretch| ((stretch[0] + stretch[1]) / 2.0, y))
+}
+
+struct IndexedArea {
+    id: i64,
+    level: i32,
+    /// min_lon, min_lat, max_lon, max_lat
+    bbox: [f64; 4],
+    rings: Vec<Ring>,
+}
+
+impl IndexedArea {
+    /// Inside an odd number of rings, which leaves out the holes.
+    fn contains(&self, lat: f64, lon: f64) -> bool {
+        lon >= self.bbox[0] && lat >= self.bbox[1] && lon <= self.bbox[2] && lat <= self.bbox[3]
+            && self.rings.iter().filter(|ring| ring.contains((lon, lat))).count() % 2 == 1
+    }
+}
+
+/// The areas by the grid cells their bounding box covers.
+struct Index {
+    areas: Vec<IndexedArea>,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+fn cell(lat: f64, lon: f64) -> (i32, i32) {
+    ((lat / CELL_SIZE).floor() as i32, (lon / CELL_SIZE).floor() as i32)
+}
+
+impl Index {
+    fn new(areas: Vec<(i64, i32, Vec<Vec<Point>>)>) -> Self {
+        let mut indexed = Vec::new();
+        let mut cells: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+
+        for (id, level, rings) in areas {
+            let mut bbox = [f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY];
+
+            for &(lon, lat) in rings.iter().flatten() {
+                bbox = [bbox[0].min(lon), bbox[1].min(lat), bbox[2].max(lon), bbox[3].max(lat)];
+            }
+
+            if bbox[0] > bbox[2] {
+                continue;
+            }
+
+            let ((south, west), (north, east)) = (cell(bbox[1], bbox[0]), cell(bbox[3], bbox[2]));
+
+            for lat in south..=north {
+                for lon in west..=east {
+                    cells.entry((lat, lon)).or_default().push(indexed.len());
+                }
+            }
+
+            indexed.push(IndexedArea { id, level, bbox, rings: rings.into_iter().map(Ring::new).collect() });
+        }
+
+        Self { areas: indexed, cells }
+    }
+
+    /// The areas the location is in.
+    fn containing(&self, lat: f64, lon: f64) -> impl Iterator<Item = &IndexedArea> {
+        self.cells.get(&cell(lat, lon))
+            .into_iter()
+            .flatten()
+            .map(|i| &self.areas[*i])
+            .filter(move |area| area.contains(lat, lon))
+    }
+
+    /// The smallest area the location is in.
+    fn smallest(&self, lat: f64, lon: f64) -> Option<i64> {
+        self.containing(lat, lon).max_by_key(|area| area.level).map(|area| area.id)
+    }
+
+    /// The smallest area of a lower level than `level` the location is in.
+    fn parent(&self, lat: f64, lon: f64, level: i32) -> Option<i64> {
+        self.containing(lat, lon).filter(|area| area.level < level).max_by_key(|area| area.level).map(|area| area.id)
+    }
+}
+
+/// The rings of a stored MultiPolygon.
+fn stored_rings(geojson: &str) -> Vec<Vec<Point>> {
+    let Ok(Value::Object(geometry)) = serde_json::from_str(geojson) else {
+        return Vec::new();
+    };
+
+    geometry.get("coordinates")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_array)
+        .flatten()
+        .filter_map(Value::as_array)
+        .map(|ring| ring.iter().filter_map(|position| Some((position[0].as_f64()?, position[1].as_f64()?))).collect())
+        .collect()
+}
+
+/// Replaces the stored areas.
+pub async fn write(db: &DatabaseConnection, areas: Vec<admin_area::ActiveModel>) -> Result<(), DbErr> {
+    admin_area::Entity::delete_many().exec(db).await?;
+
+    let mut rows = BatchInsert::new(Upsert::new(db.clone(), None), BATCH_SIZE, PENDING_WRITES, None);
+
+    for area in areas {
+        rows.push(area).await?;
+    }
+
+    rows.finish().await
+}
+
+/// Sets `admin_area_id` of every live address to the smallest stored area it's in, returning how many are in one.
+pub async fn resolve(db: &DatabaseConnection) -> Result<u64, DbErr> {
+    let areas: Vec<(i64, i32, String)> = admin_area::Entity::find()
+        .select_only()
+        .columns([admin_area::Column::Id, admin_area::Column::AdminLevel, admin_area::Column::Geojson])
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let index = Index::new(areas.into_iter().map(|(id, level, geojson)| (id, level, stored_rings(&geojson))).collect());
+
+    node::Entity::update_many()
+        .col_expr(node::Column::AdminAreaId, Expr::value(Option::<i64>::None))
+        .filter(node::Column::AdminAreaId.is_not_null())
+        .exec(db)
+        .await?;
+
+    let mut resolved = 0;
+    let mut after_id = i64::MIN;
+
+    loop {
+        let nodes: Vec<(i64, f64, f64)> = live_nodes()
+            .select_only()
+            .columns([node::Column::Id, node::Column::Lat, node::Column::Lon])
+            .filter(node::Column::Id.gt(after_id))
+            .order_by_asc(node::Column::Id)
+            .limit(PAGE_SIZE)
+            .into_tuple()
+            .all(db)
+            .await?;
+
+        let Some(&(last_id, _, _)) = nodes.last() else {
+            break;
+        };
+
+        let mut located: HashMap<i64, Vec<i64>> = HashMap::new();
+
+        for (id, lat, lon) in nodes {
+            if let Some(area) = index.smallest(lat, lon) {
+                located.entry(area).or_default().push(id);
+            }
+        }
+
+        for (area, ids) in located {
+            resolved += ids.len() as u64;
+
+            node::Entity::update_many()
+                .col_expr(node::Column::AdminAreaId, Expr::value(area))
+                .filter(node::Column::Id.is_in(ids))
+                .exec(db)
+                .await?;
+        }
+
+        after_id = last_id;
+    }
+
+    Ok(resolved)
+}
@@ -0,0 +1,377 @@
+//! Approximate postcode boundaries and adjacency from the addresses in each postcode.
+
+use std::collections::HashMap;
+
+use geo::orient::{Direction, Orient};
+use geo::{BooleanOps, EuclideanLength, LineString, MultiLineString, MultiPolygon, Polygon};
+use sea_orm::{ActiveValue, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
+use serde_json::json;
+
+use crate::batch::{BatchInsert, Upsert};
+use crate::boundaries::{Boundaries, Ring};
+use crate::database::{live_nodes, postcode_pages, postcode_range};
+use crate::entities::*;
+use crate::hull::{concave_hull, convex_hull, Point, DEFAULT_CONCAVITY};
+use crate::progress::Progress;
+use crate::spatial::{haversine, METERS_PER_DEGREE};
+use crate::voronoi::voronoi_cells;
+
+/// The property Natural Earth names its countries by, with their ISO 3166-1 alpha-2 code.
+pub const DEFAULT_PROPERTY: &str = "ISO_A2";
+
+const BATCH_SIZE: usize = 512;
+/// Batches of areas or neighbors being written while the next ones are computed.
+const PENDING_WRITES: usize = 4;
+
+/// How the area of a postcode is derived.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Strategy {
+    /// A concave hull around the addresses, the space between postcodes is left uncovered.
+    ConcaveHull,
+    /// A Voronoi cell around the centroid, every coordinate within the country belongs to exactly one postcode.
+    Voronoi,
+}
+
+impl Strategy {
+    pub const NAMES: [&'static str; 2] = ["concave-hull", "voronoi"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "concave-hull" => Some(Self::ConcaveHull),
+            "voronoi" => Some(Self::Voronoi),
+            _ => None,
+        }
+    }
+}
+
+/// A postcode with the locations of its addresses.
+struct Members {
+    postcode: String,
+    country: Option<String>,
+    coordinates: Vec<(f64, f64)>,
+}
+
+/// The postcode centroids of one country and the points that can be on the outline of all its addresses.
+#[derive(Default)]
+struct Country {
+    centroids: Vec<(String, usize, (f64, f64))>,
+    outline: Vec<(f64, f64)>,
+}
+
+/// Rebuilds `postcode_area` and `postcode_neighbors`. With [`Strategy::ConcaveHull`] postcodes whose addresses don't
+/// span an area (fewer than three distinct points, or all on a line) are left out. Neighbors always come from the
+/// Voronoi cells, as concave hulls rarely touch. Cells are clipped to the country in `borders` named by its ISO code,
+/// neighbors only count the border they share on land.
+pub async fn build(db: &DatabaseConnection, strategy: Strategy, borders: Option<&Boundaries>) -> Result<(), DbErr> {
+    postcode_area::Entity::delete_many().exec(db).await?;
+    postcode_neighbors::Entity::delete_many().exec(db).await?;
+
+    let mut countries: HashMap<Option<String>, Country> = HashMap::new();
+
+    let mut areas = BatchInsert::new(Upsert::new(db.clone(), None), BATCH_SIZE, PENDING_WRITES, None);
+    let mut neighbors = BatchInsert::new(Upsert::new(db.clone(), None), BATCH_SIZE, PENDING_WRITES, None);
+
+    let mut last: Option<String> = None;
+    let mut progress = Progress::new("Building postcode areas", postcode_pages(db, BATCH_SIZE as u64).await?);
+
+    while let Some(page) = next_page(db, last.as_deref()).await? {
+        last = page.last().map(|members| members.postcode.clone());
+
+        for members in page {
+            let count = members.coordinates.len();
+            let sum = members.coordinates.iter().fold((0.0, 0.0), |sum, (lat, lon)| (sum.0 + lat, sum.1 + lon));
+            let points = project(&members.coordinates);
+            let country = countries.entry(members.country).or_default();
+
+            // Only the convex hull of a postcode can end up on the outline of the country
+            let hull = convex_hull(&points);
+            country.outline.extend(hull.iter().map(|&i| members.coordinates[i]));
+            country.centroids.push((members.postcode.clone(), count, (sum.0 / count as f64, sum.1 / count as f64)));
+
+            if strategy == Strategy::ConcaveHull {
+                let ring = concave_hull(&points, DEFAULT_CONCAVITY).iter().map(|&i| members.coordinates[i]).collect();
+
+                if let Some(area) = to_area(members.postcode, count, vec![vec![ring]]) {
+                    areas.push(area).await?;
+                }
+            }
+        }
+
+        progress.advance(1);
+    }
+
+    // Cells are clipped to the boundary of the country when it's known, or else to the convex hull of its addresses
+    for (code, country) in countries {
+        let scale = scale(&country.outline);
+        let centroids: Vec<(f64, f64)> = country.centroids.iter().map(|(_, _, centroid)| *centroid).collect();
+        let sites = project_with(&centroids, scale);
+        let border = code.as_deref().zip(borders).and_then(|(code, borders)| Border::new(borders, code, scale, &sites));
+
+        let region: Vec<Point> = match &border {
+            Some(border) => border.region.clone(),
+            None => {
+                let outline = project_with(&country.outline, scale);
+                convex_hull(&outline).into_iter().map(|i| outline[i]).collect()
+            }
+        };
+
+        let cells = voronoi_cells(&sites, &region);
+
+        for (i, cell) in cells.iter().enumerate() {
+            for &(j, edge) in &cell.neighbors {
+                let length = match &border {
+                    Some(border) => border.clip_edge(edge),
+                    None => distance(edge.0, edge.1),
+                };
+
+                // Cells across a bay or a strait don't share any border on land
+                if length == 0.0 {
+                    continue;
+                }
+
+                let ((postcode, _, a), (neighbor, _, b)) = (&country.centroids[i], &country.centroids[j]);
+
+                neighbors.push(postcode_neighbors::ActiveModel {
+                    postcode: ActiveValue::Set(postcode.clone()),
+                    neighbor: ActiveValue::Set(neighbor.clone()),
+                    border_length: ActiveValue::Set(length * METERS_PER_DEGREE),
+                    distance: ActiveValue::Set(haversine(a.0, a.1, b.0, b.1)),
+                }).await?;
+            }
+        }
+
+        if strategy != Strategy::Voronoi {
+            continue;
+        }
+
+        for ((postcode, count, _), cell) in country.centroids.into_iter().zip(cells) {
+            let polygons = match &border {
+                Some(border) => border.clip_cell(cell.ring),
+                None => vec![vec![cell.ring]],
+            };
+            let polygons = polygons.into_iter()
+                .map(|rings| rings.into_iter().map(|ring| ring.into_iter().map(|(x, lat)| (lat, x / scale)).collect()).collect())
+                .collect();
+
+            if let Some(area) = to_area(postcode, count, polygons) {
+                areas.push(area).await?;
+            }
+        }
+    }
+
+    areas.finish().await?;
+    neighbors.finish().await
+}
+
+/// The next `BATCH_SIZE` postcodes after `last` with their addresses.
+async fn next_page(db: &DatabaseConnection, last: Option<&str>) -> Result<Option<Vec<Members>>, DbErr> {
+    let Some((first, end)) = postcode_range(db, last, BATCH_SIZE as u64).await? else {
+        return Ok(None);
+    };
+
+    let rows: Vec<(String, Option<String>, f64, f64)> = live_nodes()
+        .select_only()
+        .column(node::Column::Postcode)
+        .column(node::Column::Country)
+        .column(node::Column::Lat)
+        .column(node::Column::Lon)
+        .filter(node::Column::Postcode.between(first, end))
+        .order_by_asc(node::Column::Postcode)
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    Ok(Some(rows
+        .chunk_by(|a, b| a.0 == b.0)
+        .map(|group| Members {
+            postcode: group[0].0.clone(),
+            country: group.iter().find_map(|(_, country, _, _)| country.clone()),
+            coordinates: group.iter().map(|(_, _, lat, lon)| (*lat, *lon)).collect(),
+        })
+        .collect()))
+}
+
+/// Longitude is scaled so distances are roughly equal in both directions.
+fn scale(coordinates: &[(f64, f64)]) -> f64 {
+    let mean_lat = coordinates.iter().map(|(lat, _)| lat).sum::<f64>() / coordinates.len() as f64;
+
+    mean_lat.to_radians().cos()
+}
+
+fn project(coordinates: &[(f64, f64)]) -> Vec<Point> {
+    project_with(coordinates, scale(coordinates))
+}
+
+fn project_with(coordinates: &[(f64, f64)], scale: f64) -> Vec<Point> {
+    coordinates.iter().map(|(lat, lon)| (lon * scale, *lat)).collect()
+}
+
+/// Builds the row for polygons of `(lat, lon)` coordinates, each a counter-clockwise outer ring followed by its
+/// clockwise holes. A single polygon is stored as a Polygon, more as a MultiPolygon.
+fn to_area(postcode: String, point_count: usize, polygons: Vec<Vec<Vec<(f64, f64)>>>) -> Option<postcode_area::ActiveModel> {
+    // Rings are closed by repeating the first point
+    let polygons: Vec<Vec<Vec<(f64, f64)>>> = polygons.into_iter()
+        .filter(|rings| rings.first().is_some_and(|outer| outer.len() >= 3))
+        .map(|rings| rings.into_iter()
+            .filter(|ring| ring.len() >= 3)
+            .map(|ring| ring.iter().chain(ring.first()).copied().collect())
+            .collect())
+        .collect();
+
+    let wkt_polygon = |rings: &Vec<Vec<(f64, f64)>>| format!(
+        "({})",
+        rings.iter()
+            .map(|ring| format!("({})", ring.iter().map(|(lat, lon)| format!("{} {}", lon, lat)).collect::<Vec<_>>().join(", ")))
+            .collect::<Vec<_>>()
+            .join(", "),
+    );
+    let geojson_polygon = |rings: &Vec<Vec<(f64, f64)>>| rings.iter()
+        .map(|ring| ring.iter().map(|(lat, lon)| [*lon, *lat]).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    let (wkt, geojson) = match &polygons[..] {
+        [] => return None,
+        [polygon] => (
+            format!("POLYGON{}", wkt_polygon(polygon)),
+            json!({ "type": "Polygon", "coordinates": geojson_polygon(polygon) }),
+        ),
+        _ => (
+            format!("MULTIPOLYGON({})", polygons.iter().map(wkt_polygon).collect::<Vec<_>>().join(", ")),
+            json!({ "type": "MultiPolygon", "coordinates": polygons.iter().map(geojson_polygon).collect::<Vec<_>>() }),
+        ),
+    };
+
+    Some(postcode_area::ActiveModel {
+        postcode: ActiveValue::Set(postcode),
+        point_count: ActiveValue::Set(point_count as i32),
+        wkt: ActiveValue::Set(wkt),
+        geojson: ActiveValue::Set(geojson.to_string()),
+    })
+}
+
+fn distance(a: Point, b: Point) -> f64 {
+    (a.0 - b.0).hypot(a.1 - b.1)
+}
+
+/// The lower left and upper right corner around the points.
+fn bounds(points: impl IntoIterator<Item = Point>) -> (Point, Point) {
+    points.into_iter().fold(((f64::INFINITY, f64::INFINITY), (f64::NEG_INFINITY, f64::NEG_INFINITY)), |(min, max), p| {
+        ((min.0.min(p.0), min.1.min(p.1)), (max.0.max(p.0), max.1.max(p.1)))
+    })
+}
+
+fn overlaps((min, max): (Point, Point), (other_min, other_max): (Point, Point)) -> bool {
+    min.0 <= other_max.0 && other_min.0 <= max.0 && min.1 <= other_max.1 && other_min.1 <= max.1
+}
+
+/// The boundary of a country in projected coordinates. Its edges are bucketed in a grid, so only the cells it actually
+/// runs through are intersected with it, boundaries along coastlines have tens of thousands of points.
+struct Border {
+    shape: MultiPolygon,
+    /// Every outer ring and hole, a point is inside when it's in an odd number of them
+    rings: Vec<Ring>,
+    /// The counter-clockwise rectangle around the boundary and all centroids, the region the cells are cut from
+    region: Vec<Point>,
+    origin: Point,
+    size: f64,
+    columns: usize,
+    rows: usize,
+    edges: Vec<Vec<(Point, Point)>>,
+}
+
+impl Border {
+    /// The boundary of the country with the code, `None` when the boundaries don't have it.
+    fn new(borders: &Boundaries, code: &str, scale: f64, sites: &[Point]) -> Option<Self> {
+        let project = |ring: &Ring| ring.points().iter().map(|(lon, lat)| (lon * scale, *lat)).collect::<Vec<Point>>();
+
+        let polygons: Vec<Vec<Vec<Point>>> = borders.polygons(code)
+            .map(|rings| rings.iter().map(project).filter(|ring| ring.len() >= 3).collect::<Vec<_>>())
+            .filter(|rings| !rings.is_empty())
+            .collect();
+
+        if polygons.is_empty() {
+            return None;
+        }
+
+        let shape = MultiPolygon::new(polygons.iter()
+            .map(|rings| Polygon::new(
+                LineString::from(rings[0].clone()),
+                rings[1..].iter().map(|ring| LineString::from(ring.clone())).collect(),
+            ))
+            .collect());
+
+        let (min, max) = bounds(polygons.iter().flatten().flatten().chain(sites).copied());
+        let region = vec![min, (max.0, min.1), max, (min.0, max.1)];
+
+        // Roughly one cell per bucket
+        let size = (((max.0 - min.0) * (max.1 - min.1)) / sites.len() as f64).sqrt().max(1e-9);
+        let columns = ((max.0 - min.0) / size) as usize + 1;
+        let rows = ((max.1 - min.1) / size) as usize + 1;
+        let mut edges = vec![Vec::new(); columns * rows];
+
+        for ring in polygons.iter().flatten() {
+            for (i, &a) in ring.iter().enumerate() {
+                let b = ring[(i + 1) % ring.len()];
+                let (first, last) = (bucket_of(min, size, bounds([a, b]).0), bucket_of(min, size, bounds([a, b]).1));
+
+                for y in first.1..=last.1.min(rows - 1) {
+                    for x in first.0..=last.0.min(columns - 1) {
+                        edges[y * columns + x].push((a, b));
+                    }
+                }
+            }
+        }
+
+        let rings = polygons.into_iter().flatten().map(Ring::new).collect();
+
+        Some(Self { shape, rings, region, origin: min, size, columns, rows, edges })
+    }
+
+    fn contains(&self, point: Point) -> bool {
+        self.rings.iter().filter(|ring| ring.contains(point)).count() % 2 == 1
+    }
+
+    /// Whether an edge of the boundary might run through the rectangle.
+    fn crosses(&self, rectangle: (Point, Point)) -> bool {
+        let (first, last) = (bucket_of(self.origin, self.size, rectangle.0), bucket_of(self.origin, self.size, rectangle.1));
+
+        (first.1..=last.1.min(self.rows - 1))
+            .flat_map(|y| (first.0..=last.0.min(self.columns - 1)).map(move |x| (x, y)))
+            .any(|(x, y)| self.edges[y * self.columns + x].iter().any(|&(a, b)| overlaps(bounds([a, b]), rectangle)))
+    }
+
+    /// The polygons of the cell within the boundary, each an outer ring followed by its holes without repeating the
+    /// first point.
+    fn clip_cell(&self, ring: Vec<Point>) -> Vec<Vec<Vec<Point>>> {
+        let rectangle = bounds(ring.iter().copied());
+
+        if ring.is_empty() || !self.crosses(rectangle) {
+            return if ring.first().is_some_and(|&corner| self.contains(corner)) { vec![vec![ring]] } else { Vec::new() };
+        }
+
+        let cell = MultiPolygon::new(vec![Polygon::new(LineString::from(ring), Vec::new())]);
+        let clipped = cell.intersection(&self.shape).orient(Direction::Default);
+
+        let open = |line: &LineString| line.points().map(|point| point.x_y()).take(line.0.len().saturating_sub(1)).collect();
+
+        clipped.into_iter()
+            .map(|polygon| std::iter::once(polygon.exterior()).chain(polygon.interiors()).map(open).collect())
+            .collect()
+    }
+
+    /// The length of the edge within the boundary.
+    fn clip_edge(&self, (a, b): (Point, Point)) -> f64 {
+        if !self.crosses(bounds([a, b])) {
+            let middle = ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+
+            return if self.contains(middle) { distance(a, b) } else { 0.0 };
+        }
+
+        self.shape.clip(&MultiLineString::new(vec![LineString::from(vec![a, b])]), false).euclidean_length()
+    }
+}
+
+/// The bucket of a point, points before the origin are in the first bucket.
+fn bucket_of(origin: Point, size: f64, point: Point) -> (usize, usize) {
+    (((point.0 - origin.0) / size).max(0.0) as usize, ((point.1 - origin.1) / size).max(0.0) as usize)
+}
@@ -0,0 +1,197 @@
+//! Suggestions for an address while it's being typed, like `kerkstr 12 amst`. Every street within a postcode is a
+//! suggestion, found with the full text search of the backend on its street, city and postcode and ranked by how well
+//! it matches and by how many addresses it has.
+
+use std::collections::HashMap;
+
+use sea_orm::{ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, DbBackend, DbErr, EntityTrait, QueryFilter, Statement, TransactionTrait};
+use serde::Serialize;
+
+use crate::database::live_nodes;
+use crate::entities::*;
+use crate::query::Ranked;
+
+/// Suggestions the full text search returns, those with the most addresses, before they're ranked.
+const CANDIDATES: u64 = 200;
+
+/// Fills `suggestion` with every street within a postcode from the live nodes. Run it before single-street
+/// postcodes are collapsed, so the number of addresses is that of the extract.
+pub async fn build(db: &DatabaseConnection) -> Result<(), DbErr> {
+    let backend = db.get_database_backend();
+
+    let search_text = match backend {
+        // concat_ws is only in SQLite since 3.44
+        DbBackend::Sqlite => "LOWER(street || ' ' || COALESCE(city, '') || ' ' || postcode)",
+        DbBackend::Postgres | DbBackend::MySql => "LOWER(CONCAT_WS(' ', street, city, postcode))",
+    };
+
+    let transaction = db.begin().await?;
+
+    suggestion::Entity::delete_many().exec(&transaction).await?;
+
+    transaction.execute_unprepared(&format!(
+        "INSERT INTO suggestion (street, city, postcode, country, addresses, lat, lon, search_text) \
+        SELECT street, city, postcode, MAX(country), COUNT(*), AVG(lat), AVG(lon), {} FROM node \
+        WHERE street IS NOT NULL AND deleted_at IS NULL AND superseded_by IS NULL \
+        GROUP BY street, city, postcode",
+        search_text,
+    )).await?;
+
+    // The FTS5 table only holds the index, it's told to read the new rows
+    if backend == DbBackend::Sqlite {
+        transaction.execute_unprepared("INSERT INTO suggestion_fts(suggestion_fts) VALUES('rebuild')").await?;
+    }
+
+    transaction.commit().await
+}
+
+#[derive(Serialize)]
+pub struct Suggestion {
+    /// The suggestion written out, like `Kerkstraat 12, 1017GC Amsterdam`
+    pub label: String,
+    pub street: String,
+    /// The house number of the query, when the street has it within the postcode
+    pub house_number: Option<String>,
+    pub postcode: String,
+    pub city: Option<String>,
+    pub country: Option<String>,
+    /// The address with the house number, otherwise the center of the street within the postcode
+    pub lat: f64,
+    pub lon: f64,
+    pub addresses: i32,
+    pub score: f64,
+}
+
+/// The words of a query and the house number in it. A number right after a word, like the 12 of `kerkstr 12`, is
+/// taken as the house number, other numbers are searched for as part of a postcode.
+fn tokenize(query: &str) -> (Vec<String>, Option<String>) {
+    let mut words: Vec<String> = Vec::new();
+    let mut house_number = None;
+
+    for token in query.split(|c: char| !c.is_alphanumeric()).filter(|token| !token.is_empty()) {
+        let token = token.to_lowercase();
+        let after_word = words.last().is_some_and(|last| last.chars().all(char::is_alphabetic));
+        // Digits with at most one letter, like 12 or 12a
+        let digits = token.trim_end_matches(char::is_alphabetic);
+        let is_house_number = !digits.is_empty()
+            && digits.len() <= 4
+            && digits.chars().all(|c| c.is_ascii_digit())
+            && token.chars().count() - digits.len() <= 1;
+
+        if house_number.is_none() && after_word && is_house_number {
+            house_number = Some(token);
+        } else {
+            words.push(token);
+        }
+    }
+
+    (words, house_number)
+}
+
+/// Suggestions whose street, city and postcode have words starting with every word of the query.
+async fn candidates(db: &DatabaseConnection, words: &[String]) -> Result<Vec<suggestion::Model>, DbErr> {
+    let backend = db.get_database_backend();
+
+    let (sql, query) = match backend {
+        DbBackend::Sqlite => (
+            "SELECT suggestion.* FROM suggestion JOIN suggestion_fts ON suggestion_fts.rowid = suggestion.id \
+                WHERE suggestion_fts MATCH ? ORDER BY suggestion.addresses DESC LIMIT ?",
+            words.iter().map(|word| format!("\"{}\"*", word)).collect::<Vec<_>>().join(" "),
+        ),
+        DbBackend::Postgres => (
+            "SELECT * FROM suggestion WHERE to_tsvector('simple', search_text) @@ to_tsquery('simple', $1) \
+                ORDER BY addresses DESC LIMIT $2",
+            words.iter().map(|word| format!("{}:*", word)).collect::<Vec<_>>().join(" & "),
+        ),
+        DbBackend::MySql => (
+            "SELECT * FROM suggestion WHERE MATCH(search_text) AGAINST (? IN BOOLEAN MODE) ORDER BY addresses DESC LIMIT ?",
+            words.iter().map(|word| format!("+{}*", word)).collect::<Vec<_>>().join(" "),
+        ),
+    };
+
+    suggestion::Entity::find()
+        .from_raw_sql(Statement::from_sql_and_values(backend, sql, [query.into(), (CANDIDATES as i64).into()]))
+        .all(db)
+        .await
+}
+
+/// How well the words match the suggestion, from 0 to 1. A word matches the word of the suggestion it's the
+/// longest part of, a whole word counts fully.
+fn similarity(words: &[String], suggestion: &suggestion::Model) -> f64 {
+    let targets: Vec<&str> = suggestion.search_text.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()).collect();
+
+    let total: f64 = words.iter()
+        .map(|word| targets.iter()
+            .filter(|target| target.starts_with(word.as_str()))
+            .map(|target| word.chars().count() as f64 / target.chars().count() as f64)
+            .fold(0.0, f64::max))
+        .sum();
+
+    total / words.len() as f64
+}
+
+fn label(street: &str, house_number: Option<&str>, postcode: &str, city: Option<&str>) -> String {
+    let mut label = street.to_string();
+
+    if let Some(house_number) = house_number {
+        label = format!("{} {}", label, house_number);
+    }
+
+    label = format!("{}, {}", label, postcode);
+
+    if let Some(city) = city {
+        label = format!("{} {}", label, city);
+    }
+
+    label
+}
+
+/// The best `limit` suggestions for a query after the cursor `after`, with the cursor of the next page. Streets that
+/// have the house number of the query come first.
+pub async fn suggest(db: &DatabaseConnection, query: &str, after: Option<&Ranked>, limit: u64) -> Result<(Vec<Suggestion>, Option<String>), DbErr> {
+    let (words, house_number) = tokenize(query);
+
+    if words.is_empty() {
+        return Ok((Vec::new(), None));
+    }
+
+    let candidates = candidates(db, &words).await?;
+
+    let mut located: HashMap<(String, String), (f64, f64)> = HashMap::new();
+
+    if let (Some(house_number), false) = (&house_number, candidates.is_empty()) {
+        let streets = candidates.iter().fold(Condition::any(), |condition, candidate| {
+            condition.add(node::Column::Street.eq(candidate.street.as_str()).and(node::Column::Postcode.eq(candidate.postcode.as_str())))
+        });
+
+        for node in live_nodes().filter(node::Column::HouseNumber.eq(house_number.to_uppercase())).filter(streets).all(db).await? {
+            if let Some(street) = node.street {
+                located.insert((street, node.postcode), (node.lat, node.lon));
+            }
+        }
+    }
+
+    let suggestions: Vec<Suggestion> = candidates.into_iter()
+        .map(|candidate| {
+            let location = located.get(&(candidate.street.clone(), candidate.postcode.clone()));
+            let house_number = location.and(house_number.as_ref()).map(|house_number| house_number.to_uppercase());
+            let (lat, lon) = location.copied().unwrap_or((candidate.lat, candidate.lon));
+            let score = similarity(&words, &candidate) + (candidate.addresses as f64).ln_1p() / 10.0 + if location.is_some() { 1.0 } else { 0.0 };
+
+            Suggestion {
+                label: label(&candidate.street, house_number.as_deref(), &candidate.postcode, candidate.city.as_deref()),
+                street: candidate.street,
+                house_number,
+                postcode: candidate.postcode,
+                city: candidate.city,
+                country: candidate.country,
+                lat,
+                lon,
+                addresses: candidate.addresses,
+                score,
+            }
+        })
+        .collect();
+
+    Ok(Ranked::page(suggestions, after, Some(limit), |suggestion| (suggestion.score, suggestion.label.as_str())))
+}
@@ -0,0 +1,120 @@
+//! Differential builds, which start from the previous artifact instead of an empty database. The new database is a
+//! copy of the baseline, elements whose version is already stored are skipped without being finished or written and
+//! the rows of elements that are gone from the extract are removed at the end.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QuerySelect};
+use sqlx::sqlite::SqliteConnectOptions;
+
+use crate::entities::*;
+use crate::error::Error;
+use crate::external::EXTERNAL_IDS;
+
+/// Ids per update statement, keeps the number of bound values under SQLite's limit.
+const UPDATE_IDS: usize = 1024;
+
+/// Copies the baseline to the file of `db_uri`, which has to be a SQLite database that doesn't exist yet. The
+/// write-ahead log is copied along, in case the baseline wasn't closed cleanly.
+pub fn copy(baseline: &Path, db_uri: &str) -> Result<(), Error> {
+    if !db_uri.starts_with("sqlite:") {
+        return Err(Error::Usage("--baseline requires a SQLite --db".to_string()));
+    }
+
+    let target = SqliteConnectOptions::from_str(db_uri)
+        .map_err(|e| Error::Usage(format!("invalid --db: {}", e)))?
+        .get_filename()
+        .to_path_buf();
+
+    if target.exists() {
+        return Err(Error::Usage(format!("--baseline builds a new database, {} already exists", target.display())));
+    }
+
+    let copy_error = |e: std::io::Error| Error::Output(format!("failed to copy {} to {}: {}", baseline.display(), target.display(), e));
+
+    if let Some(directory) = target.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+        std::fs::create_dir_all(directory).map_err(copy_error)?;
+    }
+
+    std::fs::copy(baseline, &target).map_err(copy_error)?;
+
+    let wal = with_suffix(baseline, "-wal");
+
+    if wal.exists() {
+        std::fs::copy(&wal, with_suffix(&target, "-wal")).map_err(copy_error)?;
+    }
+
+    Ok(())
+}
+
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+
+    PathBuf::from(path)
+}
+
+/// The OSM elements of the baseline by id, with the version their row was written from.
+pub struct Baseline {
+    versions: HashMap<i64, i32>,
+    skipped: u64,
+}
+
+impl Baseline {
+    /// Reads the rows that aren't deleted. External rows are left to `import-external`.
+    pub async fn load(db: &DatabaseConnection) -> Result<Self, DbErr> {
+        let rows: Vec<(i64, i32)> = node::Entity::find()
+            .select_only()
+            .column(node::Column::Id)
+            .column(node::Column::Version)
+            .filter(node::Column::DeletedAt.is_null())
+            .filter(node::Column::Id.lt(EXTERNAL_IDS))
+            .into_tuple()
+            .all(db)
+            .await?;
+
+        Ok(Self { versions: rows.into_iter().collect(), skipped: 0 })
+    }
+
+    /// Whether the row of the element was written from this version, in which case the element doesn't have to be
+    /// processed again and is marked as still in the extract.
+    pub fn unchanged(&mut self, id: i64, version: i32) -> bool {
+        if self.versions.get(&id) != Some(&version) {
+            return false;
+        }
+
+        self.versions.remove(&id);
+        self.skipped += 1;
+
+        true
+    }
+
+    /// Marks the element as still in the extract, for elements that are written or deleted. Changed elements that
+    /// no longer have an address aren't, so their row is removed.
+    pub fn seen(&mut self, id: i64) {
+        self.versions.remove(&id);
+    }
+
+    /// Removes the rows of elements that weren't in the extract, keeping them with the time of the removal like
+    /// deleted elements. Returns the number of unchanged elements and of removed rows.
+    pub async fn finish(self, db: &DatabaseConnection) -> Result<(u64, u64), DbErr> {
+        let deleted_at = chrono::offset::Local::now().naive_local();
+        let vanished: Vec<i64> = self.versions.into_keys().collect();
+        let mut removed = 0;
+
+        for ids in vanished.chunks(UPDATE_IDS) {
+            removed += node::Entity::update_many()
+                .col_expr(node::Column::DeletedAt, Expr::value(deleted_at))
+                .filter(node::Column::DeletedAt.is_null())
+                .filter(node::Column::Id.is_in(ids.iter().copied()))
+                .exec(db)
+                .await?
+                .rows_affected;
+        }
+
+        Ok((self.skipped, removed))
+    }
+}
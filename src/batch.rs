@@ -0,0 +1,368 @@
+//! Writes rows in batches from background tasks, so the producer doesn't wait for the database.
+//!
+//! Rows go through a channel to a dispatcher task that collects them into batches and writes each batch from its own
+//! task. The number of batches in flight is bounded, once it's reached the dispatcher waits for whichever batch
+//! finishes first and the producer waits when the channel is full. A slow batch only holds up its own slot. The
+//! dispatcher also writes a partial batch once its oldest row has waited for the flush interval, so rows from an
+//! input that trickles in don't sit in memory indefinitely.
+//!
+//! A batch that fails because the connection dropped is written again once the pool has a new one, which is safe as
+//! it's a single transaction that either committed or didn't. The retries are shared by every batch of an import. A
+//! failed write hands its batch back, so nothing is copied in case it fails.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use sea_orm::{ActiveModelTrait, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait, IntoActiveModel, PrimaryKeyTrait, QueryTrait, StatementBuilder, TransactionTrait};
+use sea_orm::sea_query::OnConflict;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::{JoinError, JoinHandle, JoinSet};
+use tokio::time::Instant;
+
+use crate::database::{insert_rows, pool_options, transient};
+
+type PrimaryKey<A> = <<<A as ActiveModelTrait>::Entity as EntityTrait>::PrimaryKey as PrimaryKeyTrait>::ValueType;
+
+/// Retries used so far, out of `--write-retries`.
+static RETRIES: AtomicU32 = AtomicU32::new(0);
+
+/// The pause before the first retry of a batch, doubled for every next one.
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Statements run in a single transaction, kept to run them again when the transaction fails.
+pub type Statements = Vec<Box<dyn StatementBuilder + Send + Sync>>;
+
+/// Where batches are written to.
+pub trait Sink: Clone + Send + Sync + 'static {
+    type Row: Send + 'static;
+    /// Identifies a row to delete.
+    type Key: Send + 'static;
+    type Error: Display + Send + 'static;
+    /// Rows and deletions on their way to be written, like the statements they became.
+    type Batch: Send + 'static;
+
+    /// Collects the rows and the deleted ones into a batch.
+    fn batch(&self, rows: Vec<Self::Row>, deleted: Vec<Self::Key>) -> Self::Batch;
+
+    /// Writes the rows and removes the deleted ones. A batch that failed is handed back with the error, to write it
+    /// again.
+    fn write(&self, batch: Self::Batch) -> impl Future<Output = Result<(), Failed<Self>>> + Send;
+
+    /// Rows with the same id replace each other, only the last one in a batch is written. `None` keeps the row.
+    fn id(_row: &Self::Row) -> Option<i64> {
+        None
+    }
+
+    /// Whether writing the batch again might succeed, like after a dropped connection.
+    fn transient(_error: &Self::Error) -> bool {
+        false
+    }
+}
+
+/// A batch that couldn't be written.
+pub struct Failed<S: Sink> {
+    pub error: S::Error,
+    pub batch: S::Batch,
+}
+
+/// The outcome of writing one batch.
+pub struct Written<E> {
+    pub rows: usize,
+    pub deleted: usize,
+    pub result: Result<(), E>,
+}
+
+enum Message<S: Sink> {
+    Row(S::Row),
+    Delete(S::Key),
+    /// Write what's been collected and reply once every batch is done
+    Flush(oneshot::Sender<Vec<Written<S::Error>>>),
+}
+
+pub struct BatchInsert<S: Sink> {
+    messages: mpsc::Sender<Message<S>>,
+    /// Only finishes early, when a batch failed
+    dispatcher: Option<JoinHandle<Result<(), S::Error>>>,
+}
+
+impl<S: Sink> BatchInsert<S> {
+    /// Writes every `batch_size` rows and deletions, with at most `max_pending` batches being written at once. With a
+    /// `flush_interval` partial batches are written once their oldest row has waited that long.
+    pub fn new(sink: S, batch_size: usize, max_pending: usize, flush_interval: Option<Duration>) -> Self {
+        let (messages, receiver) = mpsc::channel(batch_size);
+
+        let dispatcher = Dispatcher {
+            sink,
+            batch_size,
+            max_pending,
+            flush_interval,
+            rows: Vec::with_capacity(batch_size),
+            deleted: Vec::new(),
+            oldest: None,
+            pending: JoinSet::new(),
+            done: Vec::new(),
+        };
+
+        Self { messages, dispatcher: Some(tokio::spawn(dispatcher.run(receiver))) }
+    }
+
+    /// Queues a row. Fails with the error of an earlier batch, after which the batcher can't be used anymore.
+    pub async fn push(&mut self, row: S::Row) -> Result<(), S::Error> {
+        self.send(Message::Row(row)).await
+    }
+
+    /// Queues a deletion. Fails like [`BatchInsert::push`].
+    pub async fn delete(&mut self, key: S::Key) -> Result<(), S::Error> {
+        self.send(Message::Delete(key)).await
+    }
+
+    async fn send(&mut self, message: Message<S>) -> Result<(), S::Error> {
+        if self.messages.send(message).await.is_err() {
+            return Err(self.failure().await);
+        }
+
+        Ok(())
+    }
+
+    /// The error the dispatcher stopped on.
+    async fn failure(&mut self) -> S::Error {
+        let dispatcher = self.dispatcher.take().expect("the batcher is not used after it failed");
+
+        match joined(dispatcher.await) {
+            Err(e) => e,
+            Ok(()) => unreachable!("the dispatcher only stops early on errors"),
+        }
+    }
+
+    /// Writes the rest and waits until every batch is committed or failed, also when one fails. Returns the outcome of
+    /// every batch written since the last call.
+    pub async fn flush_and_wait(&mut self) -> Vec<Written<S::Error>> {
+        let (reply, done) = oneshot::channel();
+
+        if self.messages.send(Message::Flush(reply)).await.is_err() {
+            return vec![Written { rows: 0, deleted: 0, result: Err(self.failure().await) }];
+        }
+
+        done.await.expect("the dispatcher replies to every flush")
+    }
+
+    /// Writes the rest and waits for every batch, returning the first error.
+    pub async fn finish(mut self) -> Result<(), S::Error> {
+        self.flush_and_wait().await.into_iter().try_for_each(|written| written.result)
+    }
+}
+
+struct Dispatcher<S: Sink> {
+    sink: S,
+    batch_size: usize,
+    max_pending: usize,
+    flush_interval: Option<Duration>,
+    rows: Vec<S::Row>,
+    deleted: Vec<S::Key>,
+    /// When the first row of the current batch arrived
+    oldest: Option<Instant>,
+    pending: JoinSet<Written<S::Error>>,
+    /// Batches that were written while waiting for a free slot
+    done: Vec<Written<S::Error>>,
+}
+
+impl<S: Sink> Dispatcher<S> {
+    /// Runs until the batcher is dropped, or until a batch fails while waiting for a free slot. Batches still being
+    /// written then are cancelled.
+    async fn run(mut self, mut messages: mpsc::Receiver<Message<S>>) -> Result<(), S::Error> {
+        loop {
+            // Nothing is taken from the channel while every slot is busy, which makes the producer wait
+            while self.pending.len() >= self.max_pending {
+                let Some(written) = self.pending.join_next().await.map(joined) else {
+                    break;
+                };
+
+                let Written { rows, deleted, result } = written;
+                result?;
+                self.done.push(Written { rows, deleted, result: Ok(()) });
+            }
+
+            tokio::select! {
+                message = messages.recv() => match message {
+                    Some(Message::Row(row)) => {
+                        self.oldest.get_or_insert_with(Instant::now);
+                        self.rows.push(row);
+                    }
+                    Some(Message::Delete(key)) => {
+                        self.oldest.get_or_insert_with(Instant::now);
+                        self.deleted.push(key);
+                    }
+                    Some(Message::Flush(reply)) => {
+                        self.flush();
+
+                        let mut done = std::mem::take(&mut self.done);
+
+                        while let Some(written) = self.pending.join_next().await {
+                            done.push(joined(written));
+                        }
+
+                        let _ = reply.send(done);
+                        continue;
+                    }
+                    None => return Ok(()),
+                },
+                _ = flush_due(self.oldest, self.flush_interval) => {
+                    self.flush();
+                    continue;
+                }
+            }
+
+            if self.rows.len() + self.deleted.len() >= self.batch_size {
+                self.flush();
+            }
+        }
+    }
+
+    /// Starts writing what's been collected so far.
+    fn flush(&mut self) {
+        if self.rows.is_empty() && self.deleted.is_empty() {
+            return;
+        }
+
+        let rows = deduplicated::<S>(std::mem::replace(&mut self.rows, Vec::with_capacity(self.batch_size)));
+        let deleted = std::mem::take(&mut self.deleted);
+        let sink = self.sink.clone();
+        self.oldest = None;
+
+        self.pending.spawn(async move {
+            let (row_count, deleted_count) = (rows.len(), deleted.len());
+
+            Written { rows: row_count, deleted: deleted_count, result: write_retrying(&sink, sink.batch(rows, deleted)).await }
+        });
+    }
+}
+
+/// Writes a batch, and writes it again after a transient error while there are retries left.
+async fn write_retrying<S: Sink>(sink: &S, mut batch: S::Batch) -> Result<(), S::Error> {
+    let budget = pool_options().write_retries;
+    let mut delay = FIRST_RETRY_DELAY;
+
+    loop {
+        let e = match sink.write(batch).await {
+            Ok(()) => return Ok(()),
+            Err(failed) => {
+                batch = failed.batch;
+                failed.error
+            }
+        };
+
+        if !S::transient(&e) || RETRIES.fetch_add(1, Ordering::Relaxed) >= budget {
+            return Err(e);
+        }
+
+        println!("Warning: writing a batch failed, retrying in {}s: {}", delay.as_secs(), e);
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RETRY_DELAY);
+    }
+}
+
+/// Runs the statements in a single transaction.
+pub async fn execute(db: &DatabaseConnection, statements: &Statements) -> Result<(), DbErr> {
+    let transaction = db.begin().await?;
+    let backend = transaction.get_database_backend();
+
+    for statement in statements {
+        transaction.execute(statement.build(&backend)).await?;
+    }
+
+    transaction.commit().await
+}
+
+/// Keeps the last row of every id, as inserting the same key twice in one statement fails on some databases.
+fn deduplicated<S: Sink>(rows: Vec<S::Row>) -> Vec<S::Row> {
+    let last: HashMap<i64, usize> = rows.iter()
+        .enumerate()
+        .filter_map(|(i, row)| S::id(row).map(|id| (id, i)))
+        .collect();
+
+    rows.into_iter()
+        .enumerate()
+        .filter(|(i, row)| S::id(row).is_none_or(|id| last[&id] == *i))
+        .map(|(_, row)| row)
+        .collect()
+}
+
+/// Waits until the oldest row has waited for the interval, or forever without rows or an interval.
+async fn flush_due(oldest: Option<Instant>, interval: Option<Duration>) {
+    match (oldest, interval) {
+        (Some(oldest), Some(interval)) => tokio::time::sleep_until(oldest + interval).await,
+        _ => std::future::pending().await,
+    }
+}
+
+fn joined<T>(result: Result<T, JoinError>) -> T {
+    // Writes are only cancelled by dropping the batcher, so a failed join is a panic
+    result.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+}
+
+/// Inserts rows into any table, each batch in a single transaction. `on_conflict` decides what happens to rows that
+/// already exist, without it they fail the batch. Deletions are by primary key.
+pub struct Upsert<A> {
+    db: DatabaseConnection,
+    on_conflict: Option<OnConflict>,
+    row: PhantomData<fn() -> A>,
+}
+
+impl<A> Upsert<A> {
+    pub fn new(db: DatabaseConnection, on_conflict: Option<OnConflict>) -> Self {
+        Self { db, on_conflict, row: PhantomData }
+    }
+}
+
+impl<A> Clone for Upsert<A> {
+    fn clone(&self) -> Self {
+        Self { db: self.db.clone(), on_conflict: self.on_conflict.clone(), row: PhantomData }
+    }
+}
+
+impl<A> Sink for Upsert<A>
+where
+    A: ActiveModelTrait + Send + 'static,
+    <A::Entity as EntityTrait>::Model: IntoActiveModel<A>,
+    PrimaryKey<A>: Send + 'static,
+{
+    type Row = A;
+    type Key = PrimaryKey<A>;
+    type Error = DbErr;
+    type Batch = Statements;
+
+    fn transient(e: &DbErr) -> bool {
+        transient(e)
+    }
+
+    fn batch(&self, mut rows: Vec<A>, deleted: Vec<Self::Key>) -> Statements {
+        let mut statements: Statements = Vec::new();
+
+        while !rows.is_empty() {
+            let rest = rows.split_off(rows.len().min(insert_rows::<A::Entity>()));
+            let mut insert = A::Entity::insert_many(rows);
+
+            if let Some(on_conflict) = &self.on_conflict {
+                insert = insert.on_conflict(on_conflict.clone());
+            }
+
+            statements.push(Box::new(insert.into_query()));
+            rows = rest;
+        }
+
+        for key in deleted {
+            statements.push(Box::new(A::Entity::delete_by_id(key).into_query()));
+        }
+
+        statements
+    }
+
+    async fn write(&self, batch: Statements) -> Result<(), Failed<Self>> {
+        execute(&self.db, &batch).await.map_err(|error| Failed { error, batch })
+    }
+}
@@ -0,0 +1,180 @@
+//! Named areas from a GeoJSON file of polygons, like country or timezone boundaries, to look up which one a location
+//! is in. Coordinates are compared as they are, which is close enough away from the poles and the antimeridian.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::hull::Point;
+
+/// Horizontal bands a ring's edges are sorted into, so a point is only tested against the edges at its latitude.
+/// Boundaries along coastlines have tens of thousands of points.
+const BANDS: usize = 256;
+
+#[derive(Deserialize)]
+struct Collection {
+    features: Vec<Feature>,
+}
+
+#[derive(Deserialize)]
+struct Feature {
+    #[serde(default)]
+    properties: Option<HashMap<String, Value>>,
+    geometry: Option<Geometry>,
+}
+
+/// Positions can have an altitude, only the first two numbers are used.
+#[derive(Deserialize)]
+#[serde(tag = "type", content = "coordinates")]
+enum Geometry {
+    Polygon(Vec<Vec<Vec<f64>>>),
+    MultiPolygon(Vec<Vec<Vec<Vec<f64>>>>),
+    #[serde(other)]
+    Other,
+}
+
+/// A ring of a polygon as lon, lat, with the edges in each band of its latitudes.
+pub struct Ring {
+    points: Vec<Point>,
+    min_lat: f64,
+    band_height: f64,
+    /// The index of the end point of every edge that spans the band
+    bands: Vec<Vec<u32>>,
+}
+
+impl Ring {
+    pub fn new(points: Vec<Point>) -> Self {
+        let min_lat = points.iter().map(|point| point.1).fold(f64::INFINITY, f64::min);
+        let max_lat = points.iter().map(|point| point.1).fold(f64::NEG_INFINITY, f64::max);
+        let band_height = ((max_lat - min_lat) / BANDS as f64).max(f64::EPSILON);
+        let mut bands = vec![Vec::new(); BANDS];
+
+        for i in 0..points.len() {
+            let (a, b) = (points[i].1, points[(i + points.len() - 1) % points.len()].1);
+            let first = ((a.min(b) - min_lat) / band_height) as usize;
+            let last = (((a.max(b) - min_lat) / band_height) as usize).min(BANDS - 1);
+
+            for band in &mut bands[first.min(BANDS - 1)..=last] {
+                band.push(i as u32);
+            }
+        }
+
+        Self { points, min_lat, band_height, bands }
+    }
+
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+
+    /// Whether the point is inside the ring, by counting how many edges a ray to the east crosses.
+    pub fn contains(&self, (x, y): Point) -> bool {
+        let band = ((y - self.min_lat) / self.band_height) as usize;
+
+        if y < self.min_lat || band >= BANDS {
+            return false;
+        }
+
+        let mut inside = false;
+
+        for &i in &self.bands[band] {
+            let (xi, yi) = self.points[i as usize];
+            let (xj, yj) = self.points[(i as usize + self.points.len() - 1) % self.points.len()];
+
+            if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+                inside = !inside;
+            }
+        }
+
+        inside
+    }
+}
+
+struct Area {
+    name: String,
+    /// min_lon, min_lat, max_lon, max_lat
+    bbox: [f64; 4],
+    /// Every polygon is an outer ring followed by its holes
+    polygons: Vec<Vec<Ring>>,
+}
+
+pub struct Boundaries {
+    areas: Vec<Area>,
+}
+
+fn polygon(rings: Vec<Vec<Vec<f64>>>) -> Option<Vec<Ring>> {
+    rings.into_iter()
+        .map(|positions| {
+            positions.iter()
+                .map(|position| match position[..] {
+                    [lon, lat, ..] => Some((lon, lat)),
+                    _ => None,
+                })
+                .collect::<Option<_>>()
+                .map(Ring::new)
+        })
+        .collect()
+}
+
+impl Boundaries {
+    /// Reads a FeatureCollection of polygons named by the `property` of each feature, other features are skipped.
+    pub fn load(path: &Path, property: &str) -> Result<Self, Box<dyn Error>> {
+        let contents = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let collection: Collection = serde_json::from_str(&contents)
+            .map_err(|e| format!("{} isn't a GeoJSON FeatureCollection: {}", path.display(), e))?;
+
+        let mut areas = Vec::new();
+
+        for feature in collection.features {
+            let name = match feature.properties.as_ref().and_then(|properties| properties.get(property)) {
+                Some(Value::String(name)) if !name.is_empty() => name.clone(),
+                Some(Value::Number(name)) => name.to_string(),
+                _ => continue,
+            };
+
+            let polygons = match feature.geometry {
+                Some(Geometry::Polygon(rings)) => polygon(rings).map(|polygon| vec![polygon]),
+                Some(Geometry::MultiPolygon(polygons)) => polygons.into_iter().map(polygon).collect(),
+                // Points like capitals can be in the same file
+                _ => continue,
+            };
+            let polygons = polygons.ok_or_else(|| format!("{}: {} has a position without two coordinates", path.display(), name))?;
+
+            let mut bbox = [f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY];
+
+            for &(lon, lat) in polygons.iter().filter_map(|polygon| polygon.first()).flat_map(|ring| ring.points()) {
+                bbox = [bbox[0].min(lon), bbox[1].min(lat), bbox[2].max(lon), bbox[3].max(lat)];
+            }
+
+            areas.push(Area { name, bbox, polygons });
+        }
+
+        if areas.is_empty() {
+            return Err(format!("{} has no polygons with a {:?} property", path.display(), property).into());
+        }
+
+        Ok(Self { areas })
+    }
+
+    /// The polygons of every area with the name, each an outer ring followed by its holes.
+    pub fn polygons<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a [Ring]> {
+        self.areas.iter()
+            .filter(move |area| area.name == name)
+            .flat_map(|area| area.polygons.iter().map(Vec::as_slice))
+    }
+
+    /// The name of the first area the location is in.
+    pub fn locate(&self, lat: f64, lon: f64) -> Option<&str> {
+        let point = (lon, lat);
+
+        self.areas.iter()
+            .filter(|area| lon >= area.bbox[0] && lat >= area.bbox[1] && lon <= area.bbox[2] && lat <= area.bbox[3])
+            .find(|area| area.polygons.iter().any(|polygon| match polygon.split_first() {
+                Some((outer, holes)) => outer.contains(point) && !holes.iter().any(|hole| hole.contains(point)),
+                None => false,
+            }))
+            .map(|area| area.name.as_str())
+    }
+}
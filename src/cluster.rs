@@ -0,0 +1,155 @@
+//! Merges nodes that are tagged with the same address a few meters apart, like an address node next to the entrance
+//! of the same building.
+
+use std::collections::HashMap;
+
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder};
+
+use crate::database::{live_nodes, postcode_pages, postcode_range};
+use crate::entities::*;
+use crate::h3;
+use crate::plus_code;
+use crate::progress::Progress;
+use crate::spatial::haversine;
+
+const BATCH_SIZE: u64 = 512;
+
+/// Postcode, street, house number, house name and unit, already normalized by the country profile.
+type AddressKey = (String, Option<String>, Option<String>, Option<String>, Option<String>);
+
+/// Merges every cluster of nodes with an identical address where each node is within `max_distance` meters of another
+/// one in the cluster. The merged row keeps the coordinates of the main entrance, any other entrance, or otherwise
+/// the center of the cluster, and takes the fields it's missing from the other nodes. Returns the number of removed
+/// rows.
+pub async fn merge(db: &DatabaseConnection, max_distance: f64) -> Result<u64, DbErr> {
+    let mut removed = 0;
+    let mut last: Option<String> = None;
+    let mut progress = Progress::new("Merging duplicate addresses", postcode_pages(db, BATCH_SIZE).await?);
+
+    while let Some((first, end)) = postcode_range(db, last.as_deref(), BATCH_SIZE).await? {
+        let nodes = live_nodes()
+            .filter(node::Column::Postcode.between(first.as_str(), end.as_str()))
+            .order_by_asc(node::Column::Id)
+            .all(db)
+            .await?;
+
+        let mut addresses: HashMap<AddressKey, Vec<node::Model>> = HashMap::new();
+
+        for node in nodes {
+            let key = (node.postcode.clone(), node.street.clone(), node.house_number.clone(), node.house_name_normalized.clone(), node.unit.clone());
+            addresses.entry(key).or_default().push(node);
+        }
+
+        let mut duplicates = Vec::new();
+
+        for cluster in addresses.into_values().filter(|nodes| nodes.len() > 1).flat_map(|nodes| clusters(nodes, max_distance)) {
+            let Some((keep, rest)) = merged(cluster) else {
+                continue;
+            };
+
+            duplicates.extend(rest);
+
+            node::Entity::update(node::ActiveModel::from(keep).reset_all()).exec(db).await?;
+        }
+
+        for ids in duplicates.chunks(BATCH_SIZE as usize) {
+            removed += node::Entity::delete_many()
+                .filter(node::Column::Id.is_in(ids.iter().copied()))
+                .exec(db)
+                .await?
+                .rows_affected;
+        }
+
+        last = Some(end);
+        progress.advance(1);
+    }
+
+    Ok(removed)
+}
+
+/// Splits nodes into groups that are connected by hops of at most `max_distance` meters.
+fn clusters(nodes: Vec<node::Model>, max_distance: f64) -> Vec<Vec<node::Model>> {
+    let mut cluster_of: Vec<usize> = (0..nodes.len()).collect();
+
+    fn root(cluster_of: &mut [usize], mut i: usize) -> usize {
+        while cluster_of[i] != i {
+            cluster_of[i] = cluster_of[cluster_of[i]];
+            i = cluster_of[i];
+        }
+
+        i
+    }
+
+    for i in 0..nodes.len() {
+        for j in i + 1..nodes.len() {
+            if haversine(nodes[i].lat, nodes[i].lon, nodes[j].lat, nodes[j].lon) <= max_distance {
+                let (a, b) = (root(&mut cluster_of, i), root(&mut cluster_of, j));
+                cluster_of[a] = b;
+            }
+        }
+    }
+
+    let mut grouped: HashMap<usize, Vec<node::Model>> = HashMap::new();
+
+    for (i, node) in nodes.into_iter().enumerate() {
+        grouped.entry(root(&mut cluster_of, i)).or_default().push(node);
+    }
+
+    grouped.into_values().collect()
+}
+
+/// The node to keep with the merged coordinates and fields, and the ids of the nodes to remove. `None` for a single
+/// node.
+fn merged(mut cluster: Vec<node::Model>) -> Option<(node::Model, Vec<i64>)> {
+    if cluster.len() < 2 {
+        return None;
+    }
+
+    let count = cluster.len() as f64;
+    let center = (
+        cluster.iter().map(|node| node.lat).sum::<f64>() / count,
+        cluster.iter().map(|node| node.lon).sum::<f64>() / count,
+    );
+
+    let entrance_rank = |node: &node::Model| match node.entrance.as_deref() {
+        Some("main") => 0,
+        Some(_) => 1,
+        None => 2,
+    };
+
+    // The main entrance or otherwise any other entrance, the merged row is located there
+    let entrance = cluster.iter()
+        .filter(|node| node.entrance.is_some())
+        .min_by_key(|node| entrance_rank(node))
+        .map(|node| (node.lat, node.lon, node.entrance.clone()));
+
+    // The preferred source first, then entrances, then the lowest id as the nodes are sorted by id
+    let keep = cluster.iter().enumerate().min_by_key(|(_, node)| (node.source_rank, entrance_rank(node))).map(|(i, _)| i)?;
+
+    let mut keep = cluster.swap_remove(keep);
+
+    match entrance {
+        Some((lat, lon, entrance)) => (keep.lat, keep.lon, keep.entrance) = (lat, lon, entrance),
+        None => (keep.lat, keep.lon) = center,
+    }
+
+    keep.plus_code = Some(plus_code::encode(keep.lat, keep.lon));
+    keep.grid_cell = keep.grid_cell.map(|_| plus_code::grid_cell(keep.lat, keep.lon));
+    keep.h3 = keep.h3.and_then(|cell| h3::same_resolution(cell, keep.lat, keep.lon));
+
+    for other in &cluster {
+        keep.city = keep.city.take().or_else(|| other.city.clone());
+        keep.country = keep.country.take().or_else(|| other.country.clone());
+        keep.province = keep.province.take().or_else(|| other.province.clone());
+        keep.postcode_extension = keep.postcode_extension.take().or_else(|| other.postcode_extension.clone());
+        keep.outcode = keep.outcode.take().or_else(|| other.outcode.clone());
+        keep.incode = keep.incode.take().or_else(|| other.incode.clone());
+        keep.block_number = keep.block_number.take().or_else(|| other.block_number.clone());
+        keep.neighbourhood = keep.neighbourhood.take().or_else(|| other.neighbourhood.clone());
+        keep.quarter = keep.quarter.take().or_else(|| other.quarter.clone());
+        keep.source = keep.source.take().or_else(|| other.source.clone());
+        keep.source_date = keep.source_date.or(other.source_date);
+    }
+
+    Some((keep, cluster.into_iter().map(|node| node.id).collect()))
+}
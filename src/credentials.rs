@@ -0,0 +1,367 @@
+//! Finds the credentials for S3 and Cloud Storage where their SDKs look for them, in the same order:
+//!
+//! - S3: `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` with an optional `AWS_SESSION_TOKEN`, the `AWS_PROFILE`
+//!   profile (`default` otherwise) of `~/.aws/credentials` and `~/.aws/config`, a web identity token from
+//!   `AWS_WEB_IDENTITY_TOKEN_FILE` for the role in `AWS_ROLE_ARN` like on EKS, ECS task credentials, then the role of
+//!   the instance from the EC2 metadata service.
+//! - Cloud Storage: the HMAC key in `GCS_HMAC_ACCESS_KEY_ID` and `GCS_HMAC_SECRET`, the service account or user in
+//!   `GOOGLE_APPLICATION_CREDENTIALS` or the application default credentials of gcloud, then the service account of
+//!   the metadata server on GCE, GKE and Cloud Run.
+//!
+//! Profiles that sign in with SSO, run a `credential_process` or assume a `role_arn` aren't supported, nor are
+//! external account (workload identity federation) files.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use reqwest::blocking::{Client, RequestBuilder};
+use rsa::pkcs1v15::SigningKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::signature::{SignatureEncoding, Signer};
+use rsa::RsaPrivateKey;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::Sha256;
+use xml::reader::{EventReader, XmlEvent};
+
+const DEFAULT_REGION: &str = "us-east-1";
+/// Metadata services only answer on their own network, anywhere else the request has to fail fast.
+const METADATA_TIMEOUT: Duration = Duration::from_secs(2);
+const TOKEN_TIMEOUT: Duration = Duration::from_secs(30);
+const EC2_METADATA: &str = "http://169.254.169.254";
+/// Seconds the EC2 metadata session token is valid for.
+const EC2_TOKEN_TTL: &str = "21600";
+const ECS_METADATA: &str = "http://169.254.170.2";
+const STS_ENDPOINT: &str = "https://sts.amazonaws.com";
+const GCE_METADATA: &str = "metadata.google.internal";
+const GOOGLE_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+const GCS_SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
+/// Seconds the token of a service account is requested for, the longest Google allows.
+const GOOGLE_TOKEN_LIFETIME: i64 = 3600;
+
+#[derive(Clone)]
+pub enum Secret {
+    /// An access key, requests are signed with AWS Signature Version 4
+    Key { access_key: String, secret_key: String, session_token: Option<String> },
+    /// An OAuth access token, sent as a bearer token
+    Token(String),
+}
+
+#[derive(Clone)]
+pub struct Credentials {
+    pub secret: Secret,
+    /// When temporary credentials stop working, `None` for keys that don't expire
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Credentials {
+    fn key(access_key: String, secret_key: String, session_token: Option<String>) -> Self {
+        Self { secret: Secret::Key { access_key, secret_key, session_token }, expires_at: None }
+    }
+}
+
+/// Temporary credentials as the ECS and EC2 metadata services return them.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AwsCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    token: Option<String>,
+    expiration: Option<String>,
+}
+
+impl AwsCredentials {
+    fn into_credentials(self) -> Result<Credentials, String> {
+        Ok(Credentials {
+            secret: Secret::Key { access_key: self.access_key_id, secret_key: self.secret_access_key, session_token: self.token },
+            expires_at: self.expiration.as_deref().map(parse_expiration).transpose()?,
+        })
+    }
+}
+
+/// A credentials file of Google Cloud, only the types that can be used without another identity provider.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum GoogleKey {
+    ServiceAccount { client_email: String, private_key: String, token_uri: Option<String> },
+    AuthorizedUser { client_id: String, client_secret: String, refresh_token: String },
+}
+
+#[derive(Deserialize)]
+struct GoogleToken {
+    access_token: String,
+    expires_in: i64,
+}
+
+impl GoogleToken {
+    fn into_credentials(self) -> Credentials {
+        Credentials { secret: Secret::Token(self.access_token), expires_at: Some(Utc::now() + chrono::Duration::seconds(self.expires_in)) }
+    }
+}
+
+pub fn variable(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn home() -> Option<PathBuf> {
+    variable("HOME").map(PathBuf::from)
+}
+
+fn client(timeout: Duration) -> Result<Client, String> {
+    Client::builder().timeout(timeout).build().map_err(|e| e.to_string())
+}
+
+fn text(request: RequestBuilder) -> Result<String, String> {
+    request.send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.text())
+        .map_err(|e| e.to_string())
+}
+
+fn json<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, String> {
+    serde_json::from_str(&text(request)?).map_err(|e| format!("unexpected response: {}", e))
+}
+
+fn parse_expiration(expiration: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(expiration)
+        .map(|expiration| expiration.with_timezone(&Utc))
+        .map_err(|e| format!("invalid expiration {:?}: {}", expiration, e))
+}
+
+/// The keys of a section of an INI file like `~/.aws/credentials`, `None` when the file or the section doesn't exist.
+fn ini_section(path: &Path, section: &str) -> Option<HashMap<String, String>> {
+    let content = std::fs::read_to_string(path).ok()?;
+    let mut keys = None;
+    let mut current = false;
+
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            current = name.trim() == section;
+
+            if current {
+                keys.get_or_insert_with(HashMap::new);
+            }
+        } else if let (true, Some((key, value))) = (current, line.split_once('=')) {
+            keys.get_or_insert_with(HashMap::new).insert(key.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    keys
+}
+
+/// The keys of the `AWS_PROFILE` profile, those in the credentials file win over those in the config file.
+fn aws_profile() -> Option<(String, HashMap<String, String>)> {
+    let profile = variable("AWS_PROFILE").unwrap_or("default".to_string());
+    let credentials = variable("AWS_SHARED_CREDENTIALS_FILE").map(PathBuf::from).or_else(|| home().map(|home| home.join(".aws/credentials")));
+    let config = variable("AWS_CONFIG_FILE").map(PathBuf::from).or_else(|| home().map(|home| home.join(".aws/config")));
+
+    // Only the default profile is named without the prefix in the config file
+    let config_section = if profile == "default" { profile.clone() } else { format!("profile {}", profile) };
+    let config_keys = config.and_then(|config| ini_section(&config, &config_section));
+    let credentials_keys = credentials.and_then(|credentials| ini_section(&credentials, &profile));
+
+    if config_keys.is_none() && credentials_keys.is_none() {
+        return None;
+    }
+
+    let mut keys = config_keys.unwrap_or_default();
+    keys.extend(credentials_keys.unwrap_or_default());
+
+    Some((profile, keys))
+}
+
+/// The region of the bucket: `AWS_REGION`, `AWS_DEFAULT_REGION`, the `region` of the profile, or `us-east-1`.
+pub fn aws_region() -> String {
+    variable("AWS_REGION")
+        .or_else(|| variable("AWS_DEFAULT_REGION"))
+        .or_else(|| aws_profile().and_then(|(_, mut keys)| keys.remove("region")))
+        .unwrap_or(DEFAULT_REGION.to_string())
+}
+
+/// The text of the elements of an XML document by their name.
+fn xml_elements(body: &str) -> HashMap<String, String> {
+    let mut elements = HashMap::new();
+    let mut current = None;
+
+    for event in EventReader::from_str(body).into_iter().map_while(Result::ok) {
+        match event {
+            XmlEvent::StartElement { name, .. } => current = Some(name.local_name),
+            XmlEvent::Characters(text) => {
+                if let Some(name) = current.take() {
+                    elements.insert(name, text);
+                }
+            }
+            XmlEvent::EndElement { .. } => current = None,
+            _ => {}
+        }
+    }
+
+    elements
+}
+
+fn web_identity(token_file: &str, role: &str) -> Result<Credentials, String> {
+    let token = std::fs::read_to_string(token_file).map_err(|e| format!("{}: {}", token_file, e))?;
+    let endpoint = variable("AWS_ENDPOINT_URL_STS").unwrap_or(STS_ENDPOINT.to_string());
+    let session = variable("AWS_ROLE_SESSION_NAME").unwrap_or(env!("CARGO_PKG_NAME").to_string());
+
+    let body = text(client(TOKEN_TIMEOUT)?.get(endpoint).query(&[
+        ("Action", "AssumeRoleWithWebIdentity"),
+        ("Version", "2011-06-15"),
+        ("RoleArn", role),
+        ("RoleSessionName", &session),
+        ("WebIdentityToken", token.trim()),
+    ]))?;
+    let mut elements = xml_elements(&body);
+    let mut element = |name: &str| elements.remove(name).ok_or_else(|| format!("no {} in the response of STS", name));
+
+    Ok(Credentials {
+        secret: Secret::Key { access_key: element("AccessKeyId")?, secret_key: element("SecretAccessKey")?, session_token: Some(element("SessionToken")?) },
+        expires_at: Some(parse_expiration(&element("Expiration")?)?),
+    })
+}
+
+/// The credentials of the task role on ECS, `None` outside a container that has one.
+fn ecs() -> Option<Result<Credentials, String>> {
+    let url = match variable("AWS_CONTAINER_CREDENTIALS_RELATIVE_URI") {
+        Some(path) => format!("{}{}", ECS_METADATA, path),
+        None => variable("AWS_CONTAINER_CREDENTIALS_FULL_URI")?,
+    };
+
+    let token = variable("AWS_CONTAINER_AUTHORIZATION_TOKEN").or_else(|| {
+        variable("AWS_CONTAINER_AUTHORIZATION_TOKEN_FILE").and_then(|path| std::fs::read_to_string(path).ok()).map(|token| token.trim().to_string())
+    });
+
+    Some((|| {
+        let mut request = client(METADATA_TIMEOUT)?.get(&url);
+
+        if let Some(token) = token {
+            request = request.header("authorization", token);
+        }
+
+        json::<AwsCredentials>(request)?.into_credentials()
+    })().map_err(|e| format!("ECS credentials from {}: {}", url, e)))
+}
+
+/// The credentials of the role of the EC2 instance, through a session of the metadata service (IMDSv2).
+fn ec2() -> Result<Credentials, String> {
+    let endpoint = variable("AWS_EC2_METADATA_SERVICE_ENDPOINT").unwrap_or(EC2_METADATA.to_string());
+    let endpoint = endpoint.trim_end_matches('/');
+    let client = client(METADATA_TIMEOUT)?;
+
+    let token = text(client.put(format!("{}/latest/api/token", endpoint)).header("x-aws-ec2-metadata-token-ttl-seconds", EC2_TOKEN_TTL))?;
+    let roles = text(client.get(format!("{}/latest/meta-data/iam/security-credentials/", endpoint)).header("x-aws-ec2-metadata-token", &token))?;
+    let role = roles.lines().next().filter(|role| !role.is_empty()).ok_or("the instance has no role")?;
+
+    json::<AwsCredentials>(client.get(format!("{}/latest/meta-data/iam/security-credentials/{}", endpoint, role)).header("x-aws-ec2-metadata-token", &token))?
+        .into_credentials()
+}
+
+/// The credentials for S3.
+pub fn s3() -> Result<Credentials, String> {
+    if let (Some(access_key), Some(secret_key)) = (variable("AWS_ACCESS_KEY_ID"), variable("AWS_SECRET_ACCESS_KEY")) {
+        return Ok(Credentials::key(access_key, secret_key, variable("AWS_SESSION_TOKEN")));
+    }
+
+    if let Some((profile, mut keys)) = aws_profile() {
+        if let (Some(access_key), Some(secret_key)) = (keys.remove("aws_access_key_id"), keys.remove("aws_secret_access_key")) {
+            return Ok(Credentials::key(access_key, secret_key, keys.remove("aws_session_token")));
+        }
+
+        if ["sso_session", "sso_start_url", "credential_process", "role_arn"].iter().any(|key| keys.contains_key(*key)) {
+            return Err(format!("AWS profile {} uses SSO, a credential_process or a role_arn, which isn't supported", profile));
+        }
+    }
+
+    if let (Some(token_file), Some(role)) = (variable("AWS_WEB_IDENTITY_TOKEN_FILE"), variable("AWS_ROLE_ARN")) {
+        return web_identity(&token_file, &role).map_err(|e| format!("web identity credentials for {}: {}", role, e));
+    }
+
+    if let Some(credentials) = ecs() {
+        return credentials;
+    }
+
+    if variable("AWS_EC2_METADATA_DISABLED").is_some_and(|disabled| disabled.eq_ignore_ascii_case("true")) {
+        return Err("no AWS credentials in the environment or ~/.aws, and the EC2 metadata service is disabled".to_string());
+    }
+
+    ec2().map_err(|e| format!("no AWS credentials in the environment or ~/.aws, nor from the EC2 metadata service ({})", e))
+}
+
+/// Trades a JWT signed with the key of the service account for an access token.
+fn service_account(email: &str, private_key: &str, token_uri: &str) -> Result<Credentials, String> {
+    let now = Utc::now().timestamp();
+    let header = URL_SAFE_NO_PAD.encode(json!({ "alg": "RS256", "typ": "JWT" }).to_string());
+    let claims = URL_SAFE_NO_PAD.encode(json!({
+        "iss": email,
+        "scope": GCS_SCOPE,
+        "aud": token_uri,
+        "iat": now,
+        "exp": now + GOOGLE_TOKEN_LIFETIME,
+    }).to_string());
+
+    let key = RsaPrivateKey::from_pkcs8_pem(private_key).map_err(|e| format!("invalid private_key: {}", e))?;
+    let signature = SigningKey::<Sha256>::new(key).sign(format!("{}.{}", header, claims).as_bytes());
+    let assertion = format!("{}.{}.{}", header, claims, URL_SAFE_NO_PAD.encode(signature.to_bytes()));
+
+    let request = client(TOKEN_TIMEOUT)?
+        .post(token_uri)
+        .form(&[("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"), ("assertion", &assertion)]);
+
+    Ok(json::<GoogleToken>(request)?.into_credentials())
+}
+
+fn google_file(path: &Path) -> Result<Credentials, String> {
+    let file = std::fs::read(path).map_err(|e| e.to_string())?;
+
+    match serde_json::from_slice(&file).map_err(|e| e.to_string())? {
+        GoogleKey::ServiceAccount { client_email, private_key, token_uri } => {
+            service_account(&client_email, &private_key, token_uri.as_deref().unwrap_or(GOOGLE_TOKEN_URI))
+        }
+        GoogleKey::AuthorizedUser { client_id, client_secret, refresh_token } => {
+            let request = client(TOKEN_TIMEOUT)?.post(GOOGLE_TOKEN_URI).form(&[
+                ("grant_type", "refresh_token"),
+                ("client_id", &client_id),
+                ("client_secret", &client_secret),
+                ("refresh_token", &refresh_token),
+            ]);
+
+            Ok(json::<GoogleToken>(request)?.into_credentials())
+        }
+    }
+}
+
+/// The token of the default service account from the metadata server of GCE, GKE or Cloud Run.
+fn gce_metadata() -> Result<Credentials, String> {
+    let host = variable("GCE_METADATA_HOST").unwrap_or(GCE_METADATA.to_string());
+    let request = client(METADATA_TIMEOUT)?
+        .get(format!("http://{}/computeMetadata/v1/instance/service-accounts/default/token", host))
+        .header("metadata-flavor", "Google");
+
+    Ok(json::<GoogleToken>(request)?.into_credentials())
+}
+
+/// The credentials for Cloud Storage.
+pub fn gcs() -> Result<Credentials, String> {
+    if let (Some(access_key), Some(secret_key)) = (variable("GCS_HMAC_ACCESS_KEY_ID"), variable("GCS_HMAC_SECRET")) {
+        return Ok(Credentials::key(access_key, secret_key, None));
+    }
+
+    let file = variable("GOOGLE_APPLICATION_CREDENTIALS").map(PathBuf::from).or_else(|| {
+        home().map(|home| home.join(".config/gcloud/application_default_credentials.json")).filter(|path| path.exists())
+    });
+
+    if let Some(file) = file {
+        return google_file(&file).map_err(|e| format!("{}: {}", file.display(), e));
+    }
+
+    gce_metadata().map_err(|e| format!("no Cloud Storage credentials in the environment or from gcloud, nor from the metadata server ({})", e))
+}
@@ -0,0 +1,191 @@
+//! Keeps the database up to date by importing the `--input` extract again every `--interval`, instead of a cron job
+//! around the importer. Every run is a child process with the same arguments, so a failed run leaves nothing behind
+//! and the previous data keeps being served.
+//!
+//! A SQLite `--db` is built next to itself, from a copy of the current file like `--baseline` unless `--fresh` is
+//! given, and renamed over it once the run succeeded. A Postgres `--db` is published by `--staging`.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::ArgMatches;
+use sea_orm::ConnectionTrait;
+use sqlx::sqlite::SqliteConnectOptions;
+use tokio::time::Instant;
+
+use crate::baseline::with_suffix;
+use crate::database;
+use crate::error::Error;
+use crate::notify;
+use crate::publish;
+
+/// Flags of the daemon itself, left out of the arguments of every run. The second is whether it takes a value.
+const DAEMON_FLAGS: [(&str, bool); 5] = [("--daemon", false), ("--interval", true), ("--db", true), ("--publish", true), ("--notify-url", true)];
+
+/// Parses a duration like `90s`, `30m`, `24h` or `7d`.
+pub fn parse_interval(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (count, unit) = value.split_at(split);
+    let count: u64 = count.parse().map_err(|_| format!("{:?} isn't a number followed by s, m, h or d", value))?;
+
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("{:?} isn't a number followed by s, m, h or d", value)),
+    };
+
+    if count == 0 {
+        return Err("the interval has to be longer than 0".to_string());
+    }
+
+    Ok(Duration::from_secs(count * seconds))
+}
+
+/// The arguments the daemon was started with, without its own flags.
+fn run_arguments() -> Vec<OsString> {
+    let mut arguments = Vec::new();
+    let mut skip_value = false;
+
+    for argument in std::env::args_os().skip(1) {
+        if skip_value {
+            skip_value = false;
+            continue;
+        }
+
+        let text = argument.to_string_lossy();
+        let flag = DAEMON_FLAGS.iter().find(|(flag, _)| text == *flag || text.starts_with(&format!("{}=", flag)));
+
+        match flag {
+            Some((flag, takes_value)) => skip_value = *takes_value && text == *flag,
+            None => arguments.push(argument),
+        }
+    }
+
+    arguments
+}
+
+/// Removes what an earlier run that didn't finish left of the file.
+fn remove_database(path: &Path) -> Result<(), Error> {
+    for path in [path.to_path_buf(), with_suffix(path, "-wal"), with_suffix(path, "-shm")] {
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(Error::Output(format!("failed to remove {}: {}", path.display(), e)));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves the write-ahead log into the file and renames it over the published one.
+async fn replace(next: &Path, target: &Path) -> Result<(), Error> {
+    let db = database::connect(&format!("sqlite://{}", next.display()), false, false).await.map_err(Error::Unreachable)?;
+    db.execute_unprepared("PRAGMA wal_checkpoint(TRUNCATE)").await?;
+    db.close().await?;
+
+    std::fs::rename(next, target)
+        .map_err(|e| Error::Output(format!("failed to rename {} to {}: {}", next.display(), target.display(), e)))?;
+
+    // Emptied by the checkpoint
+    remove_database(&with_suffix(next, "-wal"))?;
+
+    Ok(())
+}
+
+pub async fn run(db_uri: &str, matches: &ArgMatches) -> Result<(), Error> {
+    let interval = *matches.get_one::<Duration>("interval").expect("defaulted in clap");
+    let executable = std::env::current_exe().map_err(|e| Error::Usage(format!("failed to find the importer: {}", e)))?;
+    let arguments = run_arguments();
+    let notify_url = matches.get_one::<String>("notify-url");
+    let source = matches.get_one::<PathBuf>("input").map(PathBuf::as_path);
+
+    // Where SQLite databases are built before they're renamed over the --db
+    let target = if db_uri.starts_with("sqlite:") {
+        let path = SqliteConnectOptions::from_str(db_uri)
+            .map_err(|e| Error::Usage(format!("invalid --db: {}", e)))?
+            .get_filename()
+            .to_path_buf();
+
+        Some(path)
+    } else if matches.get_flag("staging") {
+        None
+    } else {
+        return Err(Error::Usage("--daemon requires a SQLite --db, or a Postgres one with --staging".to_string()));
+    };
+    let next = target.as_deref().map(|target| with_suffix(target, ".next"));
+
+    let mut cycle = 0;
+
+    loop {
+        cycle += 1;
+        let started = Instant::now();
+        let started_at = chrono::offset::Local::now();
+        let mut command = tokio::process::Command::new(&executable);
+        command.args(&arguments);
+
+        match (&target, &next) {
+            (Some(target), Some(next)) => {
+                remove_database(next)?;
+                command.arg("--db").arg(format!("sqlite://{}", next.display()));
+
+                if target.exists() && !matches.get_flag("fresh") {
+                    command.arg("--baseline").arg(target);
+                }
+            }
+            _ => {
+                command.arg("--db").arg(db_uri);
+            }
+        }
+
+        println!("Starting run {} at {}", cycle, started_at.format("%Y-%m-%d %H:%M:%S"));
+        let status = command.status().await.map_err(|e| Error::Command(format!("failed to start the import: {}", e).into()))?;
+
+        let mut published = None;
+
+        let outcome = match status.code() {
+            Some(0) => {
+                if let (Some(target), Some(next)) = (&target, &next) {
+                    replace(next, target).await?;
+                    println!("Published {}", target.display());
+                }
+
+                match matches.get_one::<String>("publish") {
+                    Some(url) => publish::run(db_uri, url, source, started_at).await
+                        .map(|url| published = Some(url))
+                        .map_err(|e| (e.exit_code(), e.to_string())),
+                    None => Ok(()),
+                }
+            }
+            code => {
+                if let Some(next) = &next {
+                    remove_database(next)?;
+                }
+
+                Err((code.unwrap_or(1), format!("run {} failed ({})", cycle, status)))
+            }
+        };
+
+        if let Some(url) = notify_url {
+            notify::send(url, &notify::summary(db_uri, source, published.as_deref(), started_at, &outcome).await).await;
+        }
+
+        match outcome {
+            // The arguments won't be any better next time
+            Err((2, _)) => return Err(Error::Usage(format!("run {} was refused, stopping the daemon", cycle))),
+            Err((_, message)) => println!("Warning: {}, keeping the previous data", message),
+            Ok(()) => {}
+        }
+
+        let next_run = started + interval;
+        let wait = next_run.saturating_duration_since(Instant::now());
+        let at = chrono::offset::Local::now() + chrono::Duration::from_std(wait).unwrap_or_default();
+        println!("Next run at {}", at.format("%Y-%m-%d %H:%M:%S"));
+        tokio::time::sleep_until(next_run).await;
+    }
+}
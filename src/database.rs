@@ -0,0 +1,413 @@
+use std::ffi::c_int;
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::thread::available_parallelism;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use log::LevelFilter;
+use libsqlite3_sys::{sqlite3_context, sqlite3_create_function_v2, sqlite3_result_double, sqlite3_result_null, sqlite3_value, sqlite3_value_double, sqlite3_value_type, SQLITE_DETERMINISTIC, SQLITE_NULL, SQLITE_OK, SQLITE_UTF8};
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, Condition, ConnectOptions, ConnectionTrait, Database, DatabaseConnection, DbBackend, DbErr, EntityTrait, Iterable, QueryFilter, QueryOrder, QuerySelect, RuntimeErr, Select, SqlxMySqlConnector, SqlxPostgresConnector, SqlxSqliteConnector, Statement};
+use sqlx::mysql::{MySqlConnectOptions, MySqlPoolOptions};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{ConnectOptions as _, Executor};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePool, SqlitePoolOptions, SqliteSynchronous};
+
+use crate::entities::*;
+use crate::spatial::haversine;
+
+/// 256 MiB, large enough to map a country sized artifact.
+const SQLITE_MMAP_SIZE: &str = "268435456";
+
+/// Page cache of 2 MiB per connection, negative sizes are in KiB.
+const SQLITE_LOW_MEMORY_CACHE_SIZE: &str = "-2048";
+
+const LOW_MEMORY_CONNECTIONS: u32 = 4;
+
+/// Makes every later transaction of a Postgres session read-only.
+pub const POSTGRES_READ_ONLY: &str = "SET SESSION CHARACTERISTICS AS TRANSACTION READ ONLY";
+
+/// Makes every later transaction of a MySQL session read-only.
+pub const MYSQL_READ_ONLY: &str = "SET SESSION TRANSACTION READ ONLY";
+
+/// Bound values SQLite allows in one statement.
+const SQLITE_MAX_VARIABLES: usize = 32766;
+
+/// Holds the passphrase SQLite databases are encrypted with, for builds with the `sqlcipher` feature.
+pub const KEY_VARIABLE: &str = "POSTCODE_DB_KEY";
+
+static POOL: OnceLock<PoolOptions> = OnceLock::new();
+
+/// How connections are pooled, from the command line. Settings that aren't given default per backend.
+#[derive(Clone, Copy)]
+pub struct PoolOptions {
+    pub max_connections: Option<u32>,
+    pub acquire_timeout: Option<Duration>,
+    /// Only for Postgres and MySQL, SQLite opens a file
+    pub connect_timeout: Option<Duration>,
+    /// Connections unused for this long are closed
+    pub idle_timeout: Option<Duration>,
+    /// Level SQL statements are logged at
+    pub sqlx_log: LevelFilter,
+    /// Batches written again after a [`transient`] error, over the whole import
+    pub write_retries: u32,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        Self { max_connections: None, acquire_timeout: None, connect_timeout: None, idle_timeout: None, sqlx_log: LevelFilter::Off, write_retries: 0 }
+    }
+}
+
+impl PoolOptions {
+    /// Writes to SQLite are serialized, more connections than twice the cores only wait on each other. A server is
+    /// shared with other clients, so imports stay well below the default limit of Postgres.
+    pub fn max_connections(&self, db_uri: &str, low_memory: bool) -> u32 {
+        match self.max_connections {
+            Some(max_connections) => max_connections,
+            None if low_memory => LOW_MEMORY_CONNECTIONS,
+            None if db_uri.starts_with("sqlite:") => available_parallelism().map_or(4, |cores| cores.get() as u32 * 2),
+            None => SERVER_CONNECTIONS,
+        }
+    }
+
+    fn acquire_timeout(&self) -> Duration {
+        self.acquire_timeout.unwrap_or(Duration::from_secs(10))
+    }
+}
+
+/// Connections to Postgres and MySQL when `--max-connections` isn't given.
+const SERVER_CONNECTIONS: u32 = 32;
+
+/// Server connections are closed after being unused this long, SQLite connections are kept.
+const SERVER_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Sets how every connection opened afterwards is pooled.
+pub fn init(options: PoolOptions) {
+    let _ = POOL.set(options);
+}
+
+pub fn pool_options() -> PoolOptions {
+    POOL.get().copied().unwrap_or_default()
+}
+
+/// Opens a database for importing and maintenance, creating a SQLite file and its directory when they don't exist.
+/// With `unsafe_fast` SQLite doesn't wait for writes to reach the disk and keeps its rollback journal in memory, call
+/// [`sync`] once the import is done. With `low_memory` the pool is kept small and SQLite uses a small page cache and
+/// spills sorts and temporary tables to files.
+pub async fn connect(db_uri: &str, unsafe_fast: bool, low_memory: bool) -> Result<DatabaseConnection, DbErr> {
+    let pool_options = pool_options();
+    let max_connections = pool_options.max_connections(db_uri, low_memory);
+
+    if db_uri.starts_with("sqlite:") {
+        let mut options = with_key(SqliteConnectOptions::from_str(db_uri).map_err(sqlx_error)?)?
+            .log_statements(pool_options.sqlx_log);
+
+        // sqlx only creates the file with `mode=rwc`, and never its directory
+        if let Some(filename) = missing_file(db_uri) {
+            if let Some(directory) = filename.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+                std::fs::create_dir_all(directory)
+                    .map_err(|e| DbErr::Conn(RuntimeErr::Internal(format!("failed to create {}: {}", directory.display(), e))))?;
+            }
+
+            options = options.create_if_missing(true);
+        }
+
+        if unsafe_fast {
+            options = options.synchronous(SqliteSynchronous::Off).journal_mode(SqliteJournalMode::Memory);
+        }
+
+        if low_memory {
+            options = options.pragma("cache_size", SQLITE_LOW_MEMORY_CACHE_SIZE).pragma("temp_store", "FILE");
+        }
+
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .acquire_timeout(pool_options.acquire_timeout())
+            .idle_timeout(pool_options.idle_timeout)
+            .after_connect(|connection, _| register_functions(connection))
+            .connect_with(options)
+            .await
+            .map_err(sqlx_error)?;
+
+        return Ok(SqlxSqliteConnector::from_sqlx_sqlite_pool(pool));
+    }
+
+    let mut db_opt = server_options(db_uri);
+    db_opt.max_connections(max_connections);
+
+    Database::connect(db_opt).await
+}
+
+/// Checks the scheme and syntax of a database URI before anything connects to it, so a typo is reported as such
+/// instead of as a failed connection.
+pub fn check_uri(uri: &str) -> Result<(), String> {
+    let scheme = uri.split_once(':').map_or(uri, |(scheme, _)| scheme);
+
+    let parsed = match scheme {
+        "sqlite" => SqliteConnectOptions::from_str(uri).map(drop),
+        "postgres" | "postgresql" => PgConnectOptions::from_str(uri).map(drop),
+        "mysql" => MySqlConnectOptions::from_str(uri).map(drop),
+        _ => return Err(format!("unsupported database `{}`, use sqlite://, postgres:// or mysql://", scheme)),
+    };
+
+    parsed.map_err(|e| format!("malformed {} URI: {}", scheme, e))
+}
+
+/// The file of a SQLite URI when it doesn't exist yet, never for in-memory databases.
+fn missing_file(db_uri: &str) -> Option<PathBuf> {
+    let in_memory = db_uri.split_once('?').is_some_and(|(_, query)| query.split('&').any(|parameter| parameter == "mode=memory"));
+    let filename = SqliteConnectOptions::from_str(db_uri).ok()?.get_filename();
+
+    (!in_memory && filename != Path::new(":memory:") && !filename.exists()).then(|| filename.to_path_buf())
+}
+
+/// Sets the SQLCipher key from [`KEY_VARIABLE`]. sqlx runs the `key` pragma before any other, as SQLCipher requires.
+/// Fails when the variable is set but the build can't encrypt, rather than silently writing a plain file.
+fn with_key(options: SqliteConnectOptions) -> Result<SqliteConnectOptions, DbErr> {
+    let Ok(key) = std::env::var(KEY_VARIABLE) else {
+        return Ok(options);
+    };
+
+    if !cfg!(feature = "sqlcipher") {
+        return Err(DbErr::Custom(format!("{} is set, but this build can't encrypt databases, build it with --features sqlcipher", KEY_VARIABLE)));
+    }
+
+    Ok(options.pragma("key", format!("'{}'", key.replace('\'', "''"))))
+}
+
+/// Flushes a SQLite database file to disk, for imports that ran with `unsafe_fast`.
+pub fn sync(db_uri: &str) -> std::io::Result<()> {
+    let options = SqliteConnectOptions::from_str(db_uri).map_err(std::io::Error::other)?;
+
+    // Windows only flushes handles that were opened for writing
+    OpenOptions::new().write(true).open(options.get_filename())?.sync_all()
+}
+
+/// Size of the database on disk in bytes, `None` for backends where it can't be determined.
+pub async fn size(db: &DatabaseConnection, db_uri: &str) -> Result<Option<u64>, DbErr> {
+    let sql = match db.get_database_backend() {
+        DbBackend::Sqlite => {
+            let filename = SqliteConnectOptions::from_str(db_uri).map_err(sqlx_error)?.get_filename().to_path_buf();
+
+            return Ok(std::fs::metadata(filename).ok().map(|metadata| metadata.len()));
+        }
+        DbBackend::Postgres => "SELECT pg_database_size(current_database())",
+        DbBackend::MySql => "SELECT CAST(SUM(data_length + index_length) AS SIGNED) FROM information_schema.tables WHERE table_schema = DATABASE()",
+    };
+
+    let size: Option<i64> = db.query_one(Statement::from_string(db.get_database_backend(), sql))
+        .await?
+        .map(|row| row.try_get_by_index(0))
+        .transpose()?;
+
+    Ok(size.map(|size| size as u64))
+}
+
+/// Opens a Postgres database where unqualified names refer to `schema`.
+pub async fn connect_to_schema(db_uri: &str, schema: &str) -> Result<DatabaseConnection, DbErr> {
+    let mut db_opt = server_options(db_uri);
+
+    db_opt.set_schema_search_path(schema);
+
+    Database::connect(db_opt).await
+}
+
+fn server_options(db_uri: &str) -> ConnectOptions {
+    let pool_options = pool_options();
+    let mut db_opt = ConnectOptions::new(db_uri);
+
+    db_opt.max_connections(pool_options.max_connections(db_uri, false))
+        .acquire_timeout(pool_options.acquire_timeout())
+        .connect_timeout(pool_options.connect_timeout.unwrap_or(Duration::from_secs(10)))
+        .idle_timeout(pool_options.idle_timeout.unwrap_or(SERVER_IDLE_TIMEOUT))
+        .sqlx_logging(pool_options.sqlx_log != LevelFilter::Off)
+        .sqlx_logging_level(pool_options.sqlx_log);
+
+    db_opt
+}
+
+/// Opens a database for lookups only. SQLite files are opened read-only, immutable and memory mapped, with a pool
+/// sized to twice the number of cores by default since readers never block each other.
+pub async fn connect_read_only(db_uri: &str) -> Result<DatabaseConnection, DbErr> {
+    if !db_uri.starts_with("sqlite:") {
+        return Database::connect(server_options(db_uri)).await;
+    }
+
+    Ok(SqlxSqliteConnector::from_sqlx_sqlite_pool(sqlite_read_only_pool(db_uri, true).await?))
+}
+
+/// Opens a database for statements that contain user input, like `export --where`. SQLite files are opened read-only
+/// and server sessions only allow read-only transactions. Unlike [`connect_read_only`] the file isn't immutable, so
+/// what an import is writing is seen.
+pub async fn connect_query_only(db_uri: &str) -> Result<DatabaseConnection, DbErr> {
+    let pool_options = pool_options();
+    let max_connections = pool_options.max_connections(db_uri, false);
+
+    if db_uri.starts_with("sqlite:") {
+        return Ok(SqlxSqliteConnector::from_sqlx_sqlite_pool(sqlite_read_only_pool(db_uri, false).await?));
+    }
+
+    if db_uri.starts_with("postgres") {
+        let pool = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .acquire_timeout(pool_options.acquire_timeout())
+            .after_connect(|connection, _| Box::pin(async move { connection.execute(POSTGRES_READ_ONLY).await.map(|_| ()) }))
+            .connect(db_uri)
+            .await
+            .map_err(sqlx_error)?;
+
+        return Ok(SqlxPostgresConnector::from_sqlx_postgres_pool(pool));
+    }
+
+    let pool = MySqlPoolOptions::new()
+        .max_connections(max_connections)
+        .acquire_timeout(pool_options.acquire_timeout())
+        .after_connect(|connection, _| Box::pin(async move { connection.execute(MYSQL_READ_ONLY).await.map(|_| ()) }))
+        .connect(db_uri)
+        .await
+        .map_err(sqlx_error)?;
+
+    Ok(SqlxMySqlConnector::from_sqlx_mysql_pool(pool))
+}
+
+/// The pool of [`connect_read_only`] for a SQLite file. An `immutable` file skips all locking, but changes written to
+/// it while it's open aren't seen.
+pub async fn sqlite_read_only_pool(db_uri: &str, immutable: bool) -> Result<SqlitePool, DbErr> {
+    let pool_options = pool_options();
+    let options = with_key(SqliteConnectOptions::from_str(db_uri).map_err(sqlx_error)?)?
+        .read_only(true)
+        .immutable(immutable)
+        .pragma("mmap_size", SQLITE_MMAP_SIZE)
+        .pragma("query_only", "true")
+        .log_statements(pool_options.sqlx_log);
+
+    if let Some(filename) = missing_file(db_uri) {
+        return Err(DbErr::Conn(RuntimeErr::Internal(format!("{} doesn't exist", filename.display()))));
+    }
+
+    let max_connections = pool_options.max_connections(db_uri, false);
+
+    SqlitePoolOptions::new()
+        .min_connections(max_connections / 2)
+        .max_connections(max_connections)
+        .acquire_timeout(pool_options.acquire_timeout())
+        .idle_timeout(pool_options.idle_timeout)
+        .after_connect(|connection, _| register_functions(connection))
+        .connect_with(options)
+        .await
+        .map_err(sqlx_error)
+}
+
+/// Matches the nodes that aren't soft-deleted or superseded by an external dataset.
+pub fn live() -> Condition {
+    Condition::all()
+        .add(node::Column::DeletedAt.is_null())
+        .add(node::Column::SupersededBy.is_null())
+}
+
+/// The nodes matching [`live`].
+pub fn live_nodes() -> Select<node::Entity> {
+    node::Entity::find().filter(live())
+}
+
+/// The number of pages of `limit` postcodes [`postcode_range`] goes through.
+pub async fn postcode_pages(db: &DatabaseConnection, limit: u64) -> Result<u64, DbErr> {
+    let count: Option<i64> = live_nodes()
+        .select_only()
+        .column_as(Expr::col(node::Column::Postcode).count_distinct(), "count")
+        .into_tuple()
+        .one(db)
+        .await?;
+
+    Ok((count.unwrap_or(0) as u64).div_ceil(limit))
+}
+
+/// The first and last of the next `limit` postcodes after `last`, for processing the nodes a page of postcodes at a
+/// time. SQLite can't write while a read is still open, so a long running stream isn't an option.
+pub async fn postcode_range(db: &DatabaseConnection, last: Option<&str>, limit: u64) -> Result<Option<(String, String)>, DbErr> {
+    let mut postcodes = live_nodes()
+        .select_only()
+        .column(node::Column::Postcode)
+        .distinct()
+        .order_by_asc(node::Column::Postcode)
+        .limit(limit);
+
+    if let Some(last) = last {
+        postcodes = postcodes.filter(node::Column::Postcode.gt(last));
+    }
+
+    let postcodes: Vec<String> = postcodes.into_tuple().all(db).await?;
+
+    Ok(postcodes.first().cloned().zip(postcodes.last().cloned()))
+}
+
+/// Whether a statement failed because the connection did rather than the statement itself, like when the server
+/// restarted or the network dropped, so it can succeed on a new connection.
+pub fn transient(e: &DbErr) -> bool {
+    let e = match e {
+        DbErr::ConnectionAcquire(_) => return true,
+        DbErr::Conn(RuntimeErr::SqlxError(e)) | DbErr::Exec(RuntimeErr::SqlxError(e)) | DbErr::Query(RuntimeErr::SqlxError(e)) => e,
+        _ => return false,
+    };
+
+    match e {
+        sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::Protocol(_) | sqlx::Error::PoolTimedOut => true,
+        // SQLSTATE connection exceptions, and Postgres shutting down or terminating the backend
+        sqlx::Error::Database(e) => e.code().is_some_and(|code| code.starts_with("08") || code.starts_with("57P0")),
+        _ => false,
+    }
+}
+
+fn sqlx_error(e: sqlx::Error) -> DbErr {
+    DbErr::Conn(RuntimeErr::SqlxError(e))
+}
+
+/// Registers `distance(lat1, lon1, lat2, lon2)`, the haversine distance in meters. The Postgres equivalent is
+/// created by a migration.
+fn register_functions(connection: &mut SqliteConnection) -> BoxFuture<'_, Result<(), sqlx::Error>> {
+    Box::pin(async move {
+        let mut handle = connection.lock_handle().await?;
+
+        let result = unsafe {
+            sqlite3_create_function_v2(
+                handle.as_raw_handle().as_ptr(),
+                c"distance".as_ptr(),
+                4,
+                SQLITE_UTF8 | SQLITE_DETERMINISTIC,
+                std::ptr::null_mut(),
+                Some(sqlite_distance),
+                None,
+                None,
+                None,
+            )
+        };
+
+        if result != SQLITE_OK {
+            return Err(sqlx::Error::Configuration(format!("failed to register distance function: {}", result).into()));
+        }
+
+        Ok(())
+    })
+}
+
+unsafe extern "C" fn sqlite_distance(context: *mut sqlite3_context, argc: c_int, argv: *mut *mut sqlite3_value) {
+    let args = std::slice::from_raw_parts(argv, argc as usize);
+
+    if args.iter().any(|arg| sqlite3_value_type(*arg) == SQLITE_NULL) {
+        sqlite3_result_null(context);
+        return;
+    }
+
+    let [lat1, lon1, lat2, lon2] = [0, 1, 2, 3].map(|i| sqlite3_value_double(args[i]));
+
+    sqlite3_result_double(context, haversine(lat1, lon1, lat2, lon2));
+}
+
+/// Rows of the entity per insert statement, keeps the number of bound values under SQLite's limit.
+pub fn insert_rows<E: EntityTrait>() -> usize {
+    SQLITE_MAX_VARIABLES / E::Column::iter().count()
+}
@@ -0,0 +1,153 @@
+//! Collapses postcodes whose addresses are all on one street into a single row at their average location.
+//!
+//! The nodes are streamed in postcode order and aggregated as they come in, only the collapsed rows are kept in
+//! memory. Aggregating in SQL instead needs a temporary table the size of the whole batch, which on large extracts
+//! blew up SQLite's temp store.
+
+use futures::TryStreamExt;
+use sea_orm::{ActiveValue, ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter, QueryOrder, StreamTrait};
+use sea_orm::sea_query::Expr;
+
+use crate::database::{insert_rows, live, live_nodes};
+use crate::entities::*;
+use crate::h3;
+use crate::plus_code;
+
+/// Postcodes per delete statement.
+const DELETE_POSTCODES: usize = 1024;
+
+/// The nodes of one postcode seen so far.
+struct Group {
+    /// The node with the best `source_rank`, then the lowest id, the collapsed row takes its id and fields
+    first: node::Model,
+    street: Option<String>,
+    single_street: bool,
+    /// The first QA note of the nodes, so the collapsed row stays flagged
+    qa_note: Option<String>,
+    lat: f64,
+    lon: f64,
+    count: usize,
+}
+
+impl Group {
+    fn new(node: node::Model) -> Self {
+        Self { street: node.street.clone(), single_street: node.street.is_some(), qa_note: node.qa_note.clone(), lat: node.lat, lon: node.lon, count: 1, first: node }
+    }
+
+    fn add(&mut self, node: node::Model) {
+        // An address without a street isn't known to be on the street of the others
+        if node.street != self.street {
+            self.single_street = false;
+        }
+
+        if self.qa_note.is_none() {
+            self.qa_note = node.qa_note;
+        }
+
+        self.lat += node.lat;
+        self.lon += node.lon;
+        self.count += 1;
+    }
+
+    /// The single row replacing the group, `None` when its addresses are on more than one street or without one.
+    fn collapse(self) -> Option<node::ActiveModel> {
+        if !self.single_street {
+            return None;
+        }
+
+        let count = self.count as f64;
+        let (lat, lon) = (self.lat / count, self.lon / count);
+
+        Some(node::ActiveModel {
+            id: ActiveValue::Set(self.first.id),
+            lat: ActiveValue::Set(lat),
+            lon: ActiveValue::Set(lon),
+            city: ActiveValue::Set(self.first.city),
+            country: ActiveValue::Set(self.first.country),
+            postcode: ActiveValue::Set(self.first.postcode),
+            province: ActiveValue::Set(self.first.province),
+            street: ActiveValue::Set(self.street),
+            house_number: ActiveValue::Set(None),
+            source: ActiveValue::Set(self.first.source),
+            source_date: ActiveValue::Set(self.first.source_date),
+            source_rank: ActiveValue::Set(self.first.source_rank),
+            plus_code: ActiveValue::Set(Some(plus_code::encode(lat, lon))),
+            grid_cell: ActiveValue::Set(self.first.grid_cell.map(|_| plus_code::grid_cell(lat, lon))),
+            h3: ActiveValue::Set(self.first.h3.and_then(|cell| h3::same_resolution(cell, lat, lon))),
+            updated_at: ActiveValue::Set(self.first.updated_at),
+            version: ActiveValue::Set(self.first.version),
+            qa_note: ActiveValue::Set(self.qa_note),
+            ..Default::default()
+        })
+    }
+}
+
+/// Collapses the postcodes from `first` up to and including `end` among the nodes matching the SQL `condition`.
+pub async fn collapse_single_street<C>(db: &C, condition: &str, first: &str, end: &str) -> Result<(), DbErr>
+where
+    C: ConnectionTrait + StreamTrait,
+{
+    let collapsed = collapsed(db, condition, first, end).await?;
+
+    replace(db, condition, collapsed).await
+}
+
+/// The rows the postcodes from `first` up to and including `end` collapse into, without changing anything. Ranges
+/// that don't overlap can be read at the same time.
+pub async fn collapsed<C>(db: &C, condition: &str, first: &str, end: &str) -> Result<Vec<node::ActiveModel>, DbErr>
+where
+    C: ConnectionTrait + StreamTrait,
+{
+    let mut collapsed = Vec::new();
+
+    let mut nodes = live_nodes()
+        .filter(Expr::cust(condition))
+        .filter(node::Column::Postcode.between(first, end))
+        .order_by_asc(node::Column::Postcode)
+        .order_by_asc(node::Column::SourceRank)
+        .order_by_asc(node::Column::Id)
+        .stream(db)
+        .await?;
+
+    let mut group: Option<Group> = None;
+
+    while let Some(node) = nodes.try_next().await? {
+        match &mut group {
+            Some(current) if current.first.postcode == node.postcode => current.add(node),
+            _ => {
+                collapsed.extend(group.replace(Group::new(node)).and_then(Group::collapse));
+            }
+        }
+    }
+
+    collapsed.extend(group.and_then(Group::collapse));
+
+    Ok(collapsed)
+}
+
+/// Replaces the nodes of every collapsed postcode with its collapsed row.
+pub async fn replace<C: ConnectionTrait>(db: &C, condition: &str, mut collapsed: Vec<node::ActiveModel>) -> Result<(), DbErr> {
+    let postcodes: Vec<String> = collapsed.iter()
+        .filter_map(|node| match &node.postcode {
+            ActiveValue::Set(postcode) => Some(postcode.clone()),
+            _ => None,
+        })
+        .collect();
+
+    for postcodes in postcodes.chunks(DELETE_POSTCODES) {
+        node::Entity::delete_many()
+            .filter(live())
+            .filter(Expr::cust(condition))
+            .filter(node::Column::Postcode.is_in(postcodes.iter().cloned()))
+            .exec(db)
+            .await?;
+    }
+
+    while !collapsed.is_empty() {
+        let rest = collapsed.split_off(collapsed.len().min(insert_rows::<node::Entity>()));
+        node::Entity::insert_many(collapsed).exec(db).await?;
+        collapsed = rest;
+    }
+
+    Ok(())
+}
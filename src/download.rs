@@ -0,0 +1,195 @@
+//! Streams the input from a URL or an `s3://` or `gs://` object. A dropped connection is resumed with a `Range`
+//! request from the last byte that arrived, so an hour long download doesn't start over. The XML parser only reads
+//! forward, so nothing is buffered to disk.
+
+use std::io::{self, Cursor, Read};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread;
+use std::time::Duration;
+
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::header::RANGE;
+use reqwest::{Method, StatusCode};
+
+use crate::storage::{Location, Storage};
+
+/// Attempts after a failure before giving up, reset whenever data arrives.
+const RETRIES: u32 = 5;
+const CHUNK_SIZE: usize = 1 << 20;
+/// Chunks downloaded ahead of the parser.
+const BUFFERED_CHUNKS: usize = 16;
+
+pub fn is_url(input: &str) -> bool {
+    input.starts_with("http://") || input.starts_with("https://")
+}
+
+/// Whether the input is downloaded rather than read from disk.
+pub fn is_remote(input: &str) -> bool {
+    is_url(input) || Location::parse(input).is_some()
+}
+
+/// Where the body comes from, objects are requested with signed requests.
+enum Source {
+    Url(String),
+    Object(Box<Storage>, Location),
+}
+
+impl Source {
+    fn new(url: &str, client: Client) -> Result<Self, String> {
+        match Location::parse(url) {
+            Some(location) => {
+                let location = location?;
+                Ok(Self::Object(Box::new(Storage::for_location(&location, client)?), location))
+            }
+            None => Ok(Self::Url(url.to_string())),
+        }
+    }
+
+    fn get(&self, client: &Client) -> RequestBuilder {
+        match self {
+            Self::Url(url) => client.get(url),
+            Self::Object(storage, location) => storage.request(Method::GET, location, &[]),
+        }
+    }
+}
+
+/// Why a transfer stopped before the end of the file.
+struct Failure {
+    message: String,
+    retry: bool,
+}
+
+impl Failure {
+    fn retry(message: impl ToString) -> Self {
+        Self { message: message.to_string(), retry: true }
+    }
+}
+
+/// Reads the body of a URL or object, downloaded on its own thread.
+pub struct Download {
+    chunks: Receiver<Result<Vec<u8>, String>>,
+    current: Cursor<Vec<u8>>,
+}
+
+impl Download {
+    pub fn start(url: String) -> Self {
+        let (sender, chunks) = sync_channel(BUFFERED_CHUNKS);
+
+        thread::spawn(move || {
+            if let Err(e) = fetch(&url, &sender) {
+                let _ = sender.send(Err(format!("{}: {}", url, e)));
+            }
+        });
+
+        Self { chunks, current: Cursor::new(Vec::new()) }
+    }
+}
+
+impl Read for Download {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.current.read(buf)?;
+
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+
+            match self.chunks.recv() {
+                Ok(Ok(chunk)) => self.current = Cursor::new(chunk),
+                Ok(Err(e)) => return Err(io::Error::other(e)),
+                // The download thread is done
+                Err(_) => return Ok(0),
+            }
+        }
+    }
+}
+
+fn fetch(url: &str, sender: &SyncSender<Result<Vec<u8>, String>>) -> Result<(), String> {
+    // The default timeout covers the whole body, which for an extract can take hours
+    let client = Client::builder()
+        .timeout(None)
+        .connect_timeout(Duration::from_secs(30))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let source = Source::new(url, client.clone())?;
+
+    let mut offset = 0;
+    let mut attempt = 0;
+
+    loop {
+        let before = offset;
+
+        let Err(failure) = transfer(&client, &source, &mut offset, sender) else {
+            return Ok(());
+        };
+
+        if offset > before {
+            attempt = 0;
+        }
+
+        if !failure.retry || attempt >= RETRIES {
+            return Err(failure.message);
+        }
+
+        attempt += 1;
+        let delay = Duration::from_secs(1 << attempt);
+
+        println!("Warning: download interrupted after {} bytes, retrying in {}s: {}", offset, delay.as_secs(), failure.message);
+        thread::sleep(delay);
+    }
+}
+
+/// Sends the body from `offset` onwards. Also returns normally when the parser stopped reading.
+fn transfer(client: &Client, source: &Source, offset: &mut u64, sender: &SyncSender<Result<Vec<u8>, String>>) -> Result<(), Failure> {
+    // Signed again for every attempt, the range isn't part of the signature
+    let mut request = source.get(client);
+
+    if *offset > 0 {
+        request = request.header(RANGE, format!("bytes={}-", offset));
+    }
+
+    let mut response = request.send().map_err(Failure::retry)?;
+    let status = response.status();
+
+    if status.is_client_error() {
+        return Err(Failure { message: format!("server responded with {}", status), retry: false });
+    }
+
+    if !status.is_success() {
+        return Err(Failure::retry(format!("server responded with {}", status)));
+    }
+
+    // Servers without range support send the whole file again
+    let mut skip = if status == StatusCode::PARTIAL_CONTENT { 0 } else { *offset };
+    let end = response.content_length().map(|length| length + *offset - skip);
+
+    loop {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let read = response.read(&mut chunk).map_err(Failure::retry)?;
+
+        if read == 0 {
+            return match end {
+                Some(end) if *offset < end => Err(Failure::retry(format!("connection closed at {} of {} bytes", offset, end))),
+                _ => Ok(()),
+            };
+        }
+
+        chunk.truncate(read);
+
+        if skip > 0 {
+            let skipped = skip.min(read as u64);
+            chunk.drain(..skipped as usize);
+            skip -= skipped;
+        }
+
+        if chunk.is_empty() {
+            continue;
+        }
+
+        *offset += chunk.len() as u64;
+
+        if sender.send(Ok(chunk)).is_err() {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,302 @@
+//! Elevation of every address, sampled from a directory of DEM tiles: SRTM `.hgt` files, named after their south west
+//! corner like `N52E005.hgt`, or GeoTIFFs in geographic coordinates like the Copernicus DEM. Tiles are read one at a
+//! time, and the addresses within each are looked up by their location.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use sea_orm::sea_query::{CaseStatement, Expr};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::tags::Tag;
+
+use crate::database::live_nodes;
+use crate::entities::*;
+use crate::error::Error;
+
+/// Addresses read and updated at a time.
+const PAGE_SIZE: u64 = 5000;
+/// Rows set in one UPDATE, every row is a parameter for its id and one for its elevation.
+const UPDATE_SIZE: usize = 500;
+
+/// The value of voids in SRTM tiles.
+const HGT_VOID: f32 = -32768.0;
+
+/// GeoTIFF keys for whether the raster is in geographic coordinates and whether a pixel is an area or a point.
+const MODEL_TYPE_KEY: u16 = 1024;
+const MODEL_TYPE_GEOGRAPHIC: u16 = 2;
+const RASTER_TYPE_KEY: u16 = 1025;
+const RASTER_PIXEL_IS_POINT: u16 = 2;
+
+#[derive(Clone, Copy)]
+enum Format {
+    Hgt,
+    GeoTiff,
+}
+
+/// Where the pixels of a tile are, in degrees.
+#[derive(Clone, Copy)]
+struct Georeference {
+    width: usize,
+    height: usize,
+    /// The center of the top left pixel
+    west: f64,
+    north: f64,
+    /// Degrees per pixel
+    dx: f64,
+    dy: f64,
+}
+
+impl Georeference {
+    /// min_lon, min_lat, max_lon, max_lat of the area the pixels cover.
+    fn bounds(&self) -> [f64; 4] {
+        [
+            self.west - self.dx / 2.0,
+            self.north - (self.height - 1) as f64 * self.dy - self.dy / 2.0,
+            self.west + (self.width - 1) as f64 * self.dx + self.dx / 2.0,
+            self.north + self.dy / 2.0,
+        ]
+    }
+}
+
+struct Tile {
+    path: PathBuf,
+    format: Format,
+    georeference: Georeference,
+}
+
+/// The elevations of a tile, row by row from the north.
+struct Grid {
+    georeference: Georeference,
+    values: Vec<f32>,
+    nodata: Option<f32>,
+}
+
+impl Grid {
+    /// The elevation in meters, interpolated between the four pixels around the location. Pixels without data are
+    /// left out, `None` when all four are.
+    fn sample(&self, lat: f64, lon: f64) -> Option<f64> {
+        let Georeference { width, height, west, north, dx, dy } = self.georeference;
+        let x = ((lon - west) / dx).clamp(0.0, (width - 1) as f64);
+        let y = ((north - lat) / dy).clamp(0.0, (height - 1) as f64);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+        let (tx, ty) = (x - x0 as f64, y - y0 as f64);
+
+        let corners = [
+            (x0, y0, (1.0 - tx) * (1.0 - ty)),
+            (x1, y0, tx * (1.0 - ty)),
+            (x0, y1, (1.0 - tx) * ty),
+            (x1, y1, tx * ty),
+        ];
+
+        let (sum, weights) = corners.iter()
+            .map(|&(x, y, weight)| (self.values[y * width + x], weight))
+            .filter(|(value, _)| !value.is_nan() && Some(*value) != self.nodata)
+            .fold((0.0, 0.0), |(sum, weights), (value, weight)| (sum + value as f64 * weight, weights + weight));
+
+        (weights > 0.0).then(|| sum / weights)
+    }
+}
+
+/// The south west corner of an SRTM tile from its name, like `N52E005` or `S23W044.SRTMGL1.hgt`.
+fn hgt_corner(name: &str) -> Option<(f64, f64)> {
+    let name = name.get(..7)?.to_uppercase();
+    let lat: f64 = name[1..3].parse().ok()?;
+    let lon: f64 = name[4..7].parse().ok()?;
+
+    let lat = match &name[..1] {
+        "N" => lat,
+        "S" => -lat,
+        _ => return None,
+    };
+    let lon = match &name[3..4] {
+        "E" => lon,
+        "W" => -lon,
+        _ => return None,
+    };
+
+    Some((lat, lon))
+}
+
+/// SRTM tiles are 1201 or 3601 samples square, the outer rows and columns overlap with the neighboring tiles.
+fn hgt_georeference(path: &Path) -> Result<Georeference, String> {
+    let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let (south, west) = hgt_corner(&name).ok_or_else(|| format!("{} isn't named after its corner, like N52E005.hgt", path.display()))?;
+    let bytes = std::fs::metadata(path).map_err(|e| format!("{}: {}", path.display(), e))?.len();
+    let size = ((bytes / 2) as f64).sqrt() as usize;
+
+    if size < 2 || (size * size * 2) as u64 != bytes {
+        return Err(format!("{} isn't a square grid of 16 bit samples", path.display()));
+    }
+
+    let step = 1.0 / (size - 1) as f64;
+
+    Ok(Georeference { width: size, height: size, west, north: south + 1.0, dx: step, dy: step })
+}
+
+fn open_tiff(path: &Path) -> Result<Decoder<BufReader<File>>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    Decoder::new(BufReader::new(file)).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// The value of a GeoTIFF key, `None` when the directory doesn't have it.
+fn geo_key(directory: &[u16], key: u16) -> Option<u16> {
+    // A header of four values, then the id, location, count and value of every key. Location 0 means the value is
+    // stored in place.
+    directory.get(4..)?
+        .chunks_exact(4)
+        .find(|entry| entry[0] == key && entry[1] == 0)
+        .map(|entry| entry[3])
+}
+
+fn geotiff_georeference(decoder: &mut Decoder<BufReader<File>>, path: &Path) -> Result<Georeference, String> {
+    let error = |e: tiff::TiffError| format!("{}: {}", path.display(), e);
+
+    let (width, height) = decoder.dimensions().map_err(error)?;
+    let scale = decoder.get_tag_f64_vec(Tag::ModelPixelScaleTag).map_err(error)?;
+    let tiepoint = decoder.get_tag_f64_vec(Tag::ModelTiepointTag).map_err(error)?;
+    let directory = decoder.get_tag_u16_vec(Tag::GeoKeyDirectoryTag).unwrap_or_default();
+
+    let (&[dx, dy, ..], &[i, j, _, x, y, ..]) = (&scale[..], &tiepoint[..]) else {
+        return Err(format!("{} has no pixel scale or tie point, it isn't a GeoTIFF", path.display()));
+    };
+
+    if geo_key(&directory, MODEL_TYPE_KEY).is_some_and(|model| model != MODEL_TYPE_GEOGRAPHIC) {
+        return Err(format!("{} isn't in geographic coordinates, reproject it to EPSG:4326", path.display()));
+    }
+
+    // The tie point is the corner of a pixel, unless pixels are points
+    let offset = match geo_key(&directory, RASTER_TYPE_KEY) {
+        Some(RASTER_PIXEL_IS_POINT) => 0.0,
+        _ => 0.5,
+    };
+
+    Ok(Georeference {
+        width: width as usize,
+        height: height as usize,
+        west: x + (offset - i) * dx,
+        north: y - (offset - j) * dy,
+        dx,
+        dy,
+    })
+}
+
+pub struct Dem {
+    tiles: Vec<Tile>,
+}
+
+impl Dem {
+    /// Finds the tiles in the directory and reads where they are, without reading their elevations yet.
+    pub fn open(dir: &Path) -> Result<Self, String> {
+        let entries = std::fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        let mut tiles = Vec::new();
+
+        for entry in entries {
+            let path = entry.map_err(|e| format!("{}: {}", dir.display(), e))?.path();
+            let extension = path.extension().map(|extension| extension.to_string_lossy().to_lowercase());
+
+            let (format, georeference) = match extension.as_deref() {
+                Some("hgt") => (Format::Hgt, hgt_georeference(&path)?),
+                Some("tif" | "tiff") => (Format::GeoTiff, geotiff_georeference(&mut open_tiff(&path)?, &path)?),
+                _ => continue,
+            };
+
+            tiles.push(Tile { path, format, georeference });
+        }
+
+        if tiles.is_empty() {
+            return Err(format!("{} has no .hgt or GeoTIFF tiles", dir.display()));
+        }
+
+        // Directory order differs between systems
+        tiles.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(Self { tiles })
+    }
+}
+
+fn read(tile: &Tile) -> Result<Grid, String> {
+    let path = &tile.path;
+
+    match tile.format {
+        Format::Hgt => {
+            let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let values = bytes.chunks_exact(2).map(|sample| i16::from_be_bytes([sample[0], sample[1]]) as f32).collect();
+
+            Ok(Grid { georeference: tile.georeference, values, nodata: Some(HGT_VOID) })
+        }
+        Format::GeoTiff => {
+            let mut decoder = open_tiff(path)?;
+            let nodata = decoder.get_tag_ascii_string(Tag::GdalNodata).ok().and_then(|nodata| nodata.trim().parse().ok());
+
+            let values = match decoder.read_image().map_err(|e| format!("{}: {}", path.display(), e))? {
+                DecodingResult::F32(values) => values,
+                DecodingResult::F64(values) => values.into_iter().map(|value| value as f32).collect(),
+                DecodingResult::I16(values) => values.into_iter().map(f32::from).collect(),
+                DecodingResult::U16(values) => values.into_iter().map(f32::from).collect(),
+                DecodingResult::I32(values) => values.into_iter().map(|value| value as f32).collect(),
+                _ => return Err(format!("{} doesn't have 16 bit, 32 bit or floating point elevations", path.display())),
+            };
+
+            if values.len() != tile.georeference.width * tile.georeference.height {
+                return Err(format!("{} has more than one band", path.display()));
+            }
+
+            Ok(Grid { georeference: tile.georeference, values, nodata })
+        }
+    }
+}
+
+/// Sets `elevation_m` of the live addresses within the tiles, returning how many were set.
+pub async fn annotate(db: &DatabaseConnection, dem: &Dem) -> Result<u64, Error> {
+    let mut annotated = 0;
+
+    for tile in &dem.tiles {
+        let [min_lon, min_lat, max_lon, max_lat] = tile.georeference.bounds();
+        let grid = read(tile).map_err(Error::Input)?;
+        let mut after_id = i64::MIN;
+
+        loop {
+            let nodes: Vec<(i64, f64, f64)> = live_nodes()
+                .select_only()
+                .columns([node::Column::Id, node::Column::Lat, node::Column::Lon])
+                .filter(node::Column::Lat.between(min_lat, max_lat))
+                .filter(node::Column::Lon.between(min_lon, max_lon))
+                .filter(node::Column::Id.gt(after_id))
+                .order_by_asc(node::Column::Id)
+                .limit(PAGE_SIZE)
+                .into_tuple()
+                .all(db)
+                .await?;
+
+            let Some(&(last_id, _, _)) = nodes.last() else {
+                break;
+            };
+
+            let elevations: Vec<(i64, f64)> = nodes.into_iter()
+                .filter_map(|(id, lat, lon)| grid.sample(lat, lon).map(|elevation| (id, elevation)))
+                .collect();
+
+            for elevations in elevations.chunks(UPDATE_SIZE) {
+                let case = elevations.iter().fold(CaseStatement::new(), |case, &(id, elevation)| {
+                    case.case(node::Column::Id.eq(id), Expr::value(elevation))
+                });
+
+                node::Entity::update_many()
+                    .col_expr(node::Column::ElevationM, case.into())
+                    .filter(node::Column::Id.is_in(elevations.iter().map(|(id, _)| *id)))
+                    .exec(db)
+                    .await?;
+
+                annotated += elevations.len() as u64;
+            }
+
+            after_id = last_id;
+        }
+    }
+
+    Ok(annotated)
+}
@@ -1,3 +1,16 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.12.4
 
+pub mod admin_area;
+pub mod api_key;
+pub mod deferred_index;
+pub mod import_lock;
+pub mod import_progress;
+pub mod import_run;
 pub mod node;
+pub mod poi_postcode;
+pub mod postcode_area;
+pub mod postcode_neighbors;
+pub mod postcode_rollup;
+pub mod postcode_timezone;
+pub mod quarantine;
+pub mod suggestion;
@@ -1,12 +1,13 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.12.4
 
 use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "node")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = true)]
-    pub id: u64,
+    pub id: i64,
     #[sea_orm(column_type = "Double")]
     pub lat: f64,
     #[sea_orm(column_type = "Double")]
@@ -17,14 +18,64 @@ pub struct Model {
     pub street: Option<String>,
     pub province: Option<String>,
     pub house_number: Option<String>,
+    /// The name of the building, used instead of or next to a house number in the UK and Ireland
+    pub house_name: Option<String>,
+    /// The house name case-folded and without punctuation, for lookups
+    pub house_name_normalized: Option<String>,
+    /// The postcode and house number lowercased and without spaces, like `1234ab12a`, for joins on exact addresses
+    pub search_key: Option<String>,
+    /// The part of the postcode that's stored separately, like the +4 of a US ZIP code
+    pub postcode_extension: Option<String>,
+    /// Apartment, suite or unit within the address
+    pub unit: Option<String>,
+    /// The part of a spaced postcode before the space: the GB outcode, CA forward sortation area or IE routing key
+    pub outcode: Option<String>,
+    /// The part of a spaced postcode after the space: the GB incode, CA local delivery unit or IE unique identifier
+    pub incode: Option<String>,
+    /// Block within a neighbourhood, used instead of streets in Japan
+    pub block_number: Option<String>,
+    pub neighbourhood: Option<String>,
+    pub quarter: Option<String>,
+    /// The `entrance` tag when the address is on an entrance, like `main` or `yes`
+    pub entrance: Option<String>,
     pub source: Option<String>,
     pub source_date: Option<Date>,
     pub updated_at: DateTime,
     pub version: i32,
+    /// When the element was deleted from OSM, the row is kept so consumers syncing from the database see the removal
+    pub deleted_at: Option<DateTime>,
+    /// The row from an external dataset that replaces this one, for addresses that are in both
+    pub superseded_by: Option<i64>,
+    /// Position of the source of the row in the precedence order, lower wins when duplicates are merged
+    pub source_rank: i32,
+    /// The 10 digit Open Location Code of the location, like `8FVC9G8F+6X`
+    pub plus_code: Option<String>,
+    /// The 11 digit plus code of the location, a cell of about 3 by 3 meters, when imported with `--grid-cells`
+    pub grid_cell: Option<String>,
+    /// The H3 cell of the location, when imported with `--h3-resolution`
+    pub h3: Option<i64>,
+    /// Every tag of the element as a JSON object, when imported with `--keep-raw-tags`
+    pub raw_tags: Option<Json>,
+    /// The `fixme` and `note` tags of the element, mappers use them to mark an address they're unsure of
+    pub qa_note: Option<String>,
+    /// Meters above sea level sampled from DEM tiles, when imported with `--dem`
+    #[sea_orm(column_type = "Double")]
+    pub elevation_m: Option<f64>,
+    /// The smallest administrative area the address is in, when imported with `--admin-areas`
+    pub admin_area_id: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(belongs_to = "super::admin_area::Entity", from = "Column::AdminAreaId", to = "super::admin_area::Column::Id")]
+    AdminArea,
+}
+
+impl Related<super::admin_area::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::AdminArea.def()
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
 
@@ -0,0 +1,67 @@
+//! Errors that end an import, each with its own exit code so scripts can tell a bad input file from a database
+//! that's down.
+
+use sea_orm::DbErr;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A subcommand like `export` or `query` failed.
+    #[error("{0}")]
+    Command(Box<dyn std::error::Error>),
+    /// Invalid arguments, or a profiles file or plugin that can't be loaded.
+    #[error("{0}")]
+    Usage(String),
+    /// The input can't be read or isn't valid OSM XML or PBF.
+    #[error("failed to read the input: {0}")]
+    Input(String),
+    /// The database can't be connected to.
+    #[error("failed to connect to the database: {0}")]
+    Unreachable(DbErr),
+    /// The database is reachable, but a statement failed.
+    #[error("database error: {0}")]
+    Database(DbErr),
+    /// Another import holds the lock on the database.
+    #[error("another import is running ({holder}, since {since}). Pass --force if it crashed")]
+    Locked { holder: String, since: String },
+    /// Parsed rows or a report couldn't be written.
+    #[error("failed to write output: {0}")]
+    Output(String),
+    /// An address failed validation with `--strict`, or more than `--max-rejected` did.
+    #[error("invalid address: {0}")]
+    Invalid(String),
+    /// The database lost more than `--max-drop` of its addresses since the previous import.
+    #[error("too many addresses dropped: {0}")]
+    Dropped(String),
+}
+
+impl Error {
+    /// The process exit code, see the README for the list.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Command(_) => 1,
+            Error::Usage(_) => 2,
+            Error::Input(_) => 3,
+            Error::Unreachable(_) => 4,
+            Error::Database(_) => 5,
+            Error::Locked { .. } => 6,
+            Error::Output(_) => 7,
+            Error::Invalid(_) => 8,
+            Error::Dropped(_) => 9,
+        }
+    }
+}
+
+impl From<DbErr> for Error {
+    fn from(e: DbErr) -> Self {
+        match e {
+            DbErr::Conn(_) | DbErr::ConnectionAcquire(_) => Error::Unreachable(e),
+            _ => Error::Database(e),
+        }
+    }
+}
+
+impl From<xml::reader::Error> for Error {
+    fn from(e: xml::reader::Error) -> Self {
+        Error::Input(e.to_string())
+    }
+}
@@ -0,0 +1,97 @@
+//! Writes the addresses as a point layer in an OGC GeoPackage.
+
+use std::error::Error;
+use std::path::Path;
+
+use futures::TryStreamExt;
+use sea_orm::{ConnectionTrait, DatabaseConnection, DbBackend, Select, Statement, TransactionTrait};
+
+use crate::entities::*;
+use crate::export::create_sqlite;
+
+const TABLE_NAME: &str = "addresses";
+const SRS_ID: i32 = 4326;
+
+const SCHEMA: [&str; 4] = [
+    "CREATE TABLE gpkg_spatial_ref_sys (srs_name TEXT NOT NULL, srs_id INTEGER NOT NULL PRIMARY KEY, organization TEXT NOT NULL, organization_coordsys_id INTEGER NOT NULL, definition TEXT NOT NULL, description TEXT)",
+    "CREATE TABLE gpkg_contents (table_name TEXT NOT NULL PRIMARY KEY, data_type TEXT NOT NULL, identifier TEXT UNIQUE, description TEXT DEFAULT '', last_change DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now')), min_x DOUBLE, min_y DOUBLE, max_x DOUBLE, max_y DOUBLE, srs_id INTEGER, CONSTRAINT fk_gc_r_srs_id FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys(srs_id))",
+    "CREATE TABLE gpkg_geometry_columns (table_name TEXT NOT NULL, column_name TEXT NOT NULL, geometry_type_name TEXT NOT NULL, srs_id INTEGER NOT NULL, z TINYINT NOT NULL, m TINYINT NOT NULL, CONSTRAINT pk_geom_cols PRIMARY KEY (table_name, column_name), CONSTRAINT fk_gc_tn FOREIGN KEY (table_name) REFERENCES gpkg_contents(table_name), CONSTRAINT fk_gc_srs FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys (srs_id))",
+    "CREATE TABLE addresses (fid INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL, geom POINT, osm_id INTEGER, postcode TEXT, house_number TEXT, street TEXT, city TEXT, province TEXT, country TEXT, source TEXT, updated_at DATETIME, version INTEGER)",
+];
+
+const SPATIAL_REF_SYS: &str = "INSERT INTO gpkg_spatial_ref_sys (srs_name, srs_id, organization, organization_coordsys_id, definition, description) VALUES \
+    ('Undefined cartesian SRS', -1, 'NONE', -1, 'undefined', 'undefined cartesian coordinate reference system'), \
+    ('Undefined geographic SRS', 0, 'NONE', 0, 'undefined', 'undefined geographic coordinate reference system'), \
+    ('WGS 84 geodetic', 4326, 'EPSG', 4326, 'GEOGCS[\"WGS 84\",DATUM[\"WGS_1984\",SPHEROID[\"WGS 84\",6378137,298.257223563,AUTHORITY[\"EPSG\",\"7030\"]],AUTHORITY[\"EPSG\",\"6326\"]],PRIMEM[\"Greenwich\",0,AUTHORITY[\"EPSG\",\"8901\"]],UNIT[\"degree\",0.0174532925199433,AUTHORITY[\"EPSG\",\"9122\"]],AUTHORITY[\"EPSG\",\"4326\"]]', 'longitude/latitude coordinates in decimal degrees on the WGS 84 spheroid')";
+
+pub async fn export(db: &DatabaseConnection, nodes: Select<node::Entity>, output: &Path) -> Result<(), Box<dyn Error>> {
+    let gpkg = create_sqlite(output).await?;
+
+    gpkg.execute_unprepared("PRAGMA application_id = 1196444487").await?;
+    gpkg.execute_unprepared("PRAGMA user_version = 10200").await?;
+
+    for statement in SCHEMA {
+        gpkg.execute_unprepared(statement).await?;
+    }
+
+    gpkg.execute_unprepared(SPATIAL_REF_SYS).await?;
+
+    let mut bounds = [f64::MAX, f64::MAX, f64::MIN, f64::MIN];
+    let transaction = gpkg.begin().await?;
+    let mut stream = nodes.stream(db).await?;
+
+    while let Some(model) = stream.try_next().await? {
+        bounds = [bounds[0].min(model.lon), bounds[1].min(model.lat), bounds[2].max(model.lon), bounds[3].max(model.lat)];
+
+        transaction.execute(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            "INSERT INTO addresses (geom, osm_id, postcode, house_number, street, city, province, country, source, updated_at, version) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            [
+                encode_point(model.lon, model.lat).into(),
+                model.id.into(),
+                model.postcode.into(),
+                model.house_number.into(),
+                model.street.into(),
+                model.city.into(),
+                model.province.into(),
+                model.country.into(),
+                model.source.into(),
+                model.updated_at.into(),
+                model.version.into(),
+            ],
+        )).await?;
+    }
+
+    transaction.commit().await?;
+
+    gpkg.execute(Statement::from_sql_and_values(
+        DbBackend::Sqlite,
+        "INSERT INTO gpkg_contents (table_name, data_type, identifier, description, min_x, min_y, max_x, max_y, srs_id) VALUES (?, 'features', ?, 'Addresses with postcodes', ?, ?, ?, ?, ?)",
+        [TABLE_NAME.into(), TABLE_NAME.into(), bounds[0].into(), bounds[1].into(), bounds[2].into(), bounds[3].into(), SRS_ID.into()],
+    )).await?;
+
+    gpkg.execute(Statement::from_sql_and_values(
+        DbBackend::Sqlite,
+        "INSERT INTO gpkg_geometry_columns (table_name, column_name, geometry_type_name, srs_id, z, m) VALUES (?, 'geom', 'POINT', ?, 0, 0)",
+        [TABLE_NAME.into(), SRS_ID.into()],
+    )).await?;
+
+    Ok(())
+}
+
+/// Encodes a point as a GeoPackage geometry blob: the `GP` header without envelope followed by little endian WKB.
+fn encode_point(x: f64, y: f64) -> Vec<u8> {
+    let mut blob = Vec::with_capacity(29);
+
+    blob.extend_from_slice(b"GP");
+    blob.push(0); // version
+    blob.push(0b0000_0001); // little endian, no envelope
+    blob.extend_from_slice(&SRS_ID.to_le_bytes());
+
+    blob.push(1); // little endian
+    blob.extend_from_slice(&1u32.to_le_bytes()); // wkbPoint
+    blob.extend_from_slice(&x.to_le_bytes());
+    blob.extend_from_slice(&y.to_le_bytes());
+
+    blob
+}
@@ -0,0 +1,103 @@
+use std::error::Error;
+use std::io::Write;
+
+use futures::TryStreamExt;
+use sea_orm::{ColumnTrait, ColumnType, DatabaseConnection, IdenStatic, Iterable, Select};
+use serde_json::json;
+
+use crate::entities::*;
+
+pub async fn export(db: &DatabaseConnection, nodes: Select<node::Entity>, mut output: impl Write) -> Result<(), Box<dyn Error>> {
+    let mut stream = nodes.stream(db).await?;
+
+    while let Some(model) = stream.try_next().await? {
+        serde_json::to_writer(&mut output, &model)?;
+        output.write_all(b"\n")?;
+    }
+
+    output.flush()?;
+
+    Ok(())
+}
+
+/// The JSON type a column is serialized as, and its format when JSON Schema has one.
+fn json_type(column_type: &ColumnType) -> (&'static str, Option<&'static str>) {
+    match column_type {
+        ColumnType::TinyInteger | ColumnType::SmallInteger | ColumnType::Integer | ColumnType::BigInteger => ("integer", None),
+        ColumnType::Float | ColumnType::Double => ("number", None),
+        ColumnType::Boolean => ("boolean", None),
+        ColumnType::Date => ("string", Some("date")),
+        ColumnType::Json | ColumnType::JsonBinary => ("object", None),
+        _ => ("string", None),
+    }
+}
+
+fn description(column: node::Column) -> Option<&'static str> {
+    Some(match column {
+        node::Column::Id => "OSM element id, negated for ways",
+        node::Column::Postcode => "Upper case postcode, formatted according to the country profile",
+        node::Column::HouseNumber => "null when the postcode covers a single street",
+        node::Column::HouseName => "The name of the building, used instead of or next to a house number in the UK and Ireland",
+        node::Column::HouseNameNormalized => "The house name case-folded and without punctuation, for lookups",
+        node::Column::SearchKey => "The postcode and house number lowercased and without spaces, like 1234ab12a",
+        node::Column::PostcodeExtension => "Stored apart from the postcode, the +4 of a US ZIP code",
+        node::Column::Outcode => "Part of the postcode before the space, for countries that split it",
+        node::Column::Incode => "Part of the postcode after the space, for countries that split it",
+        node::Column::BlockNumber => "Block within the neighbourhood, for addresses without a street",
+        node::Column::Entrance => "The entrance tag when the address is on an entrance",
+        node::Column::UpdatedAt | node::Column::DeletedAt => "ISO 8601 timestamp without timezone",
+        node::Column::Version => "OSM element version",
+        node::Column::SupersededBy => "The id of the row from an external dataset that replaces this one",
+        node::Column::SourceRank => "Position of the source of the row in the precedence order, lower wins",
+        node::Column::PlusCode => "The 10 digit Open Location Code of the location",
+        node::Column::GridCell => "The 11 digit plus code of the location, when imported with --grid-cells",
+        node::Column::H3 => "The H3 cell of the location, when imported with --h3-resolution",
+        node::Column::RawTags => "Every tag of the element, when imported with --keep-raw-tags",
+        node::Column::QaNote => "The fixme and note tags of the element",
+        node::Column::ElevationM => "Meters above sea level, when imported with --dem",
+        node::Column::AdminAreaId => "The smallest administrative area the address is in, when imported with --admin-areas",
+        _ => return None,
+    })
+}
+
+/// A JSON Schema of the exported rows, from the columns of the node table so it describes every field.
+pub fn write_schema(mut output: impl Write) -> Result<(), Box<dyn Error>> {
+    let mut properties = serde_json::Map::new();
+    let mut required = Vec::new();
+
+    for column in node::Column::iter() {
+        let definition = column.def();
+        let (kind, format) = json_type(definition.get_column_type());
+
+        let mut property = if definition.is_null() {
+            json!({ "type": [kind, "null"] })
+        } else {
+            required.push(column.as_str().to_string());
+            json!({ "type": kind })
+        };
+
+        if let Some(format) = format {
+            property["format"] = json!(format);
+        }
+
+        if let Some(description) = description(column) {
+            property["description"] = json!(description);
+        }
+
+        properties.insert(column.as_str().to_string(), property);
+    }
+
+    let schema = json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "node",
+        "description": "An address exported by the postcode DB generator",
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    });
+
+    serde_json::to_writer_pretty(&mut output, &schema)?;
+    output.write_all(b"\n")?;
+
+    Ok(())
+}
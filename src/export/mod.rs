@@ -0,0 +1,155 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use clap::{arg, value_parser, ArgAction, ArgMatches, Command};
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, QueryFilter, QuerySelect, RuntimeErr, Select, SqlxSqliteConnector};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+
+use crate::countries::parse_country;
+use crate::database::live_nodes;
+use crate::entities::*;
+use crate::query::{normalize_prefix, range_end, Page};
+
+mod gpkg;
+mod jsonl;
+mod shp;
+mod tiles;
+
+pub fn cli() -> Command {
+    Command::new("export")
+        .about("Exports the addresses in the database to another format")
+        .subcommand_required(true)
+        .arg(arg!(--bbox <BBOX> "Only addresses within min_lon,min_lat,max_lon,max_lat").value_parser(parse_bbox).allow_hyphen_values(true).global(true))
+        .arg(arg!(--country <COUNTRY> "Only addresses in this country, can be passed more than once").value_parser(parse_country).action(ArgAction::Append).global(true))
+        .arg(arg!(--prefix <PREFIX> "Only postcodes starting with this").global(true))
+        .arg(arg!(--where <CONDITION> "Only addresses matching this SQL condition on the node table, like \"city = 'Utrecht'\"").global(true))
+        .arg(arg!(--"after-id" <ID> "Only addresses with a higher id, to continue an earlier export").value_parser(value_parser!(i64)).allow_negative_numbers(true).global(true))
+        .arg(arg!(--limit <COUNT> "At most this many addresses, in order of id").value_parser(value_parser!(u64).range(1..)).global(true))
+        .subcommand(
+            Command::new("jsonl")
+                .about("One JSON object per address, for Elasticsearch/Logstash style ingestion")
+                .arg(arg!(--output <FILE> "Write to a file instead of stdout").value_parser(value_parser!(PathBuf)))
+                .arg(arg!(--schema <FILE> "Also write a JSON Schema describing the fields").value_parser(value_parser!(PathBuf)))
+        )
+        .subcommand(
+            Command::new("gpkg")
+                .about("GeoPackage with a point layer, for QGIS/ArcGIS")
+                .arg(arg!(--output <FILE> "GeoPackage file to write").required(true).value_parser(value_parser!(PathBuf)))
+        )
+        .subcommand(
+            Command::new("shp")
+                .about("ESRI Shapefile point layer with .prj and UTF-8 .cpg sidecars")
+                .arg(arg!(--output <FILE> "Shapefile to write, the sidecar files are placed next to it").required(true).value_parser(value_parser!(PathBuf)))
+        )
+        .subcommand(
+            Command::new("tiles")
+                .about("Address points as vector tiles in an MBTiles container")
+                .arg(arg!(--output <FILE> "MBTiles file to write").required(true).value_parser(value_parser!(PathBuf)))
+                .arg(arg!(--"min-zoom" <ZOOM>).default_value("12").value_parser(value_parser!(u8).range(0..=22)))
+                .arg(arg!(--"max-zoom" <ZOOM>).default_value("14").value_parser(value_parser!(u8).range(0..=22)))
+        )
+}
+
+/// A bounding box as min_lon,min_lat,max_lon,max_lat, the order of GeoJSON.
+fn parse_bbox(value: &str) -> Result<[f64; 4], String> {
+    let usage = || format!("{} isn't a bounding box like 4.7,52.2,5.1,52.5 (min_lon,min_lat,max_lon,max_lat)", value);
+
+    let coordinates: Vec<f64> = value.split(',').map(|coordinate| coordinate.trim().parse()).collect::<Result<_, _>>().map_err(|_| usage())?;
+    let [min_lon, min_lat, max_lon, max_lat] = coordinates[..] else {
+        return Err(usage());
+    };
+
+    if min_lon > max_lon || min_lat > max_lat || !(-90.0..=90.0).contains(&min_lat) || !(-90.0..=90.0).contains(&max_lat) {
+        return Err(usage());
+    }
+
+    Ok([min_lon, min_lat, max_lon, max_lat])
+}
+
+/// The addresses to export, all of them unless they're filtered with `--bbox`, `--country`, `--prefix` or `--where`.
+fn selection(matches: &ArgMatches) -> Select<node::Entity> {
+    let mut query = live_nodes();
+
+    if let Some([min_lon, min_lat, max_lon, max_lat]) = matches.get_one::<[f64; 4]>("bbox") {
+        query = query
+            .filter(node::Column::Lat.between(*min_lat, *max_lat))
+            .filter(node::Column::Lon.between(*min_lon, *max_lon));
+    }
+
+    if let Some(countries) = matches.get_many::<String>("country") {
+        query = query.filter(node::Column::Country.is_in(countries.cloned()));
+    }
+
+    if let Some(prefix) = matches.get_one::<String>("prefix") {
+        let prefix = normalize_prefix(prefix);
+        query = query.filter(node::Column::Postcode.gte(prefix.as_str())).filter(node::Column::Postcode.lt(range_end(&prefix)));
+    }
+
+    // Parenthesized, so an OR in it doesn't escape the other filters
+    if let Some(condition) = matches.get_one::<String>("where") {
+        query = query.filter(Expr::cust(format!("({})", condition)));
+    }
+
+    query
+}
+
+pub async fn run(db: &DatabaseConnection, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let page = Page {
+        after_id: matches.get_one::<i64>("after-id").copied(),
+        limit: matches.get_one::<u64>("limit").copied(),
+    };
+    let nodes = page.apply(selection(matches));
+
+    match matches.subcommand() {
+        Some(("jsonl", matches)) => {
+            if let Some(path) = matches.get_one::<PathBuf>("schema") {
+                jsonl::write_schema(File::create(path)?)?;
+            }
+
+            jsonl::export(db, nodes.clone(), output(matches)?).await?;
+        }
+        Some(("gpkg", matches)) => gpkg::export(db, nodes.clone(), matches.get_one::<PathBuf>("output").expect("required in clap")).await?,
+        Some(("shp", matches)) => shp::export(db, nodes.clone(), matches.get_one::<PathBuf>("output").expect("required in clap")).await?,
+        Some(("tiles", matches)) => {
+            let min_zoom = *matches.get_one::<u8>("min-zoom").expect("defaulted in clap");
+            let max_zoom = *matches.get_one::<u8>("max-zoom").expect("defaulted in clap");
+
+            tiles::export(db, nodes.clone(), matches.get_one::<PathBuf>("output").expect("required in clap"), min_zoom, max_zoom).await?
+        }
+        _ => unreachable!("subcommand is required"),
+    }
+
+    // A full page has an address at its last place. On stderr, as JSON lines can be written to stdout
+    if let Some(limit) = page.limit {
+        let last: Option<i64> = nodes.select_only().column(node::Column::Id).offset(limit - 1).limit(1).into_tuple().one(db).await?;
+
+        if let Some(last) = last {
+            eprintln!("Next page: --after-id {}", last);
+        }
+    }
+
+    Ok(())
+}
+
+fn output(matches: &ArgMatches) -> std::io::Result<Box<dyn Write>> {
+    Ok(match matches.get_one::<PathBuf>("output") {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(std::io::stdout())),
+    })
+}
+
+/// Creates an empty SQLite database at `path`, replacing any existing file.
+async fn create_sqlite(path: &Path) -> Result<DatabaseConnection, DbErr> {
+    if path.exists() {
+        std::fs::remove_file(path).map_err(|e| DbErr::Custom(e.to_string()))?;
+    }
+
+    // Built from the path rather than a URI, so Windows paths and characters like `?` and `#` aren't misread
+    let options = SqliteConnectOptions::new().filename(path).create_if_missing(true);
+    let pool = SqlitePool::connect_with(options).await.map_err(|e| DbErr::Conn(RuntimeErr::SqlxError(e)))?;
+
+    Ok(SqlxSqliteConnector::from_sqlx_sqlite_pool(pool))
+}
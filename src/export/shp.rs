@@ -0,0 +1,170 @@
+//! Writes the addresses as an ESRI Shapefile point layer (.shp, .shx, .dbf, .prj and .cpg).
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use chrono::Datelike;
+use futures::TryStreamExt;
+use sea_orm::{DatabaseConnection, Select};
+
+use crate::entities::*;
+
+const PRJ: &str = r#"GEOGCS["GCS_WGS_1984",DATUM["D_WGS_1984",SPHEROID["WGS_1984",6378137.0,298.257223563]],PRIMEM["Greenwich",0.0],UNIT["Degree",0.0174532925199433]]"#;
+const COLUMNS: [&str; 10] = ["osm_id", "postcode", "house_number", "street", "city", "province", "country", "source", "updated_at", "version"];
+const MAX_FIELD_LENGTH: usize = 254;
+const HEADER_LENGTH: u64 = 100;
+const POINT_CONTENT_LENGTH: i32 = 20;
+
+fn attributes(model: &node::Model) -> [Option<String>; 10] {
+    [
+        Some(model.id.to_string()),
+        Some(model.postcode.clone()),
+        model.house_number.clone(),
+        model.street.clone(),
+        model.city.clone(),
+        model.province.clone(),
+        model.country.clone(),
+        model.source.clone(),
+        Some(model.updated_at.to_string()),
+        Some(model.version.to_string()),
+    ]
+}
+
+pub async fn export(db: &DatabaseConnection, nodes: Select<node::Entity>, output: &Path) -> Result<(), Box<dyn Error>> {
+    // The dbf header needs the field widths up front, so measure them in a first pass
+    let mut widths = [1; COLUMNS.len()];
+    let mut count = 0u32;
+    let mut stream = nodes.clone().stream(db).await?;
+
+    while let Some(model) = stream.try_next().await? {
+        for (width, value) in widths.iter_mut().zip(attributes(&model)) {
+            *width = (*width).max(value.map_or(0, |value| value.len()).min(MAX_FIELD_LENGTH));
+        }
+
+        count += 1;
+    }
+
+    drop(stream);
+
+    std::fs::write(output.with_extension("prj"), PRJ)?;
+    std::fs::write(output.with_extension("cpg"), "UTF-8")?;
+
+    let mut shp = BufWriter::new(File::create(output.with_extension("shp"))?);
+    let mut shx = BufWriter::new(File::create(output.with_extension("shx"))?);
+    let mut dbf = BufWriter::new(File::create(output.with_extension("dbf"))?);
+
+    shp.write_all(&[0; HEADER_LENGTH as usize])?;
+    shx.write_all(&[0; HEADER_LENGTH as usize])?;
+    write_dbf_header(&mut dbf, count, &widths)?;
+
+    let mut bounds = [f64::MAX, f64::MAX, f64::MIN, f64::MIN];
+    let mut record = 0;
+    let mut stream = nodes.clone().stream(db).await?;
+
+    while let Some(model) = stream.try_next().await? {
+        record += 1;
+        bounds = [bounds[0].min(model.lon), bounds[1].min(model.lat), bounds[2].max(model.lon), bounds[3].max(model.lat)];
+
+        let offset = HEADER_LENGTH as i32 / 2 + (record - 1) * (4 + POINT_CONTENT_LENGTH / 2);
+        shx.write_all(&offset.to_be_bytes())?;
+        shx.write_all(&(POINT_CONTENT_LENGTH / 2).to_be_bytes())?;
+
+        shp.write_all(&record.to_be_bytes())?;
+        shp.write_all(&(POINT_CONTENT_LENGTH / 2).to_be_bytes())?;
+        shp.write_all(&1i32.to_le_bytes())?;
+        shp.write_all(&model.lon.to_le_bytes())?;
+        shp.write_all(&model.lat.to_le_bytes())?;
+
+        dbf.write_all(b" ")?;
+        for (width, value) in widths.iter().zip(attributes(&model)) {
+            let value = truncate(value.as_deref().unwrap_or_default(), *width);
+
+            dbf.write_all(value.as_bytes())?;
+            dbf.write_all(" ".repeat(width - value.len()).as_bytes())?;
+        }
+    }
+
+    dbf.write_all(&[0x1A])?;
+    dbf.flush()?;
+
+    let shp_length = HEADER_LENGTH as i32 / 2 + record * (4 + POINT_CONTENT_LENGTH / 2);
+    let shx_length = HEADER_LENGTH as i32 / 2 + record * 4;
+
+    write_shp_header(&mut shp, shp_length, bounds)?;
+    write_shp_header(&mut shx, shx_length, bounds)?;
+
+    Ok(())
+}
+
+fn write_shp_header(file: &mut BufWriter<File>, length_in_words: i32, bounds: [f64; 4]) -> std::io::Result<()> {
+    file.seek(SeekFrom::Start(0))?;
+
+    file.write_all(&9994i32.to_be_bytes())?;
+    file.write_all(&[0; 20])?;
+    file.write_all(&length_in_words.to_be_bytes())?;
+    file.write_all(&1000i32.to_le_bytes())?;
+    file.write_all(&1i32.to_le_bytes())?; // Point
+
+    for bound in bounds {
+        file.write_all(&bound.to_le_bytes())?;
+    }
+
+    file.write_all(&[0; 32])?; // Z and M ranges
+    file.flush()
+}
+
+fn write_dbf_header(file: &mut impl Write, count: u32, widths: &[usize]) -> std::io::Result<()> {
+    let today = chrono::offset::Local::now().date_naive();
+    let header_length = 32 + 32 * widths.len() as u16 + 1;
+    let record_length = 1 + widths.iter().sum::<usize>() as u16;
+
+    file.write_all(&[0x03, (today.year() - 1900) as u8, today.month() as u8, today.day() as u8])?;
+    file.write_all(&count.to_le_bytes())?;
+    file.write_all(&header_length.to_le_bytes())?;
+    file.write_all(&record_length.to_le_bytes())?;
+    file.write_all(&[0; 20])?;
+
+    for (name, width) in field_names(&COLUMNS).iter().zip(widths) {
+        let mut descriptor = [0u8; 32];
+
+        descriptor[..name.len()].copy_from_slice(name.as_bytes());
+        descriptor[11] = b'C';
+        descriptor[16] = *width as u8;
+
+        file.write_all(&descriptor)?;
+    }
+
+    file.write_all(&[0x0D])
+}
+
+/// DBF field names are limited to 10 characters, truncated names get a numeric suffix when they collide.
+fn field_names(columns: &[&str]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::with_capacity(columns.len());
+
+    for column in columns {
+        let mut name: String = column.chars().take(10).collect();
+        let mut suffix = 1;
+
+        while names.contains(&name) {
+            let suffix_str = suffix.to_string();
+            name = column.chars().take(10 - suffix_str.len()).collect::<String>() + &suffix_str;
+            suffix += 1;
+        }
+
+        names.push(name);
+    }
+
+    names
+}
+
+fn truncate(value: &str, max_length: usize) -> &str {
+    let mut end = value.len().min(max_length);
+
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    &value[..end]
+}
@@ -0,0 +1,195 @@
+//! Renders the address points into Mapbox Vector Tiles stored in an MBTiles container.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::f64::consts::PI;
+use std::io::Write;
+use std::path::Path;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use futures::TryStreamExt;
+use sea_orm::{ConnectionTrait, DatabaseConnection, DbBackend, Select, Statement};
+use serde_json::json;
+
+use crate::entities::*;
+use crate::export::create_sqlite;
+
+const LAYER_NAME: &str = "addresses";
+const EXTENT: u32 = 4096;
+const KEYS: [&str; 4] = ["postcode", "house_number", "street", "city"];
+
+struct Point {
+    id: i64,
+    x: u32,
+    y: u32,
+    values: [Option<String>; 4],
+}
+
+pub async fn export(db: &DatabaseConnection, nodes: Select<node::Entity>, output: &Path, min_zoom: u8, max_zoom: u8) -> Result<(), Box<dyn Error>> {
+    let mbtiles = create_sqlite(output).await?;
+    mbtiles.execute_unprepared("CREATE TABLE metadata (name TEXT, value TEXT)").await?;
+    mbtiles.execute_unprepared("CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB)").await?;
+    mbtiles.execute_unprepared("CREATE UNIQUE INDEX tile_index ON tiles (zoom_level, tile_column, tile_row)").await?;
+
+    let mut bounds = [f64::MAX, f64::MAX, f64::MIN, f64::MIN];
+
+    for zoom in min_zoom..=max_zoom {
+        println!("Rendering zoom level {}", zoom);
+
+        let mut tiles: HashMap<(u32, u32), Vec<Point>> = HashMap::new();
+        let mut stream = nodes.clone().stream(db).await?;
+
+        while let Some(model) = stream.try_next().await? {
+            bounds = [bounds[0].min(model.lon), bounds[1].min(model.lat), bounds[2].max(model.lon), bounds[3].max(model.lat)];
+
+            let (tile_x, tile_y, x, y) = project(model.lat, model.lon, zoom);
+
+            tiles.entry((tile_x, tile_y)).or_default().push(Point {
+                id: model.id,
+                x,
+                y,
+                values: [Some(model.postcode), model.house_number, model.street, model.city],
+            });
+        }
+
+        for ((tile_x, tile_y), points) in tiles {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&encode_tile(&points))?;
+
+            mbtiles.execute(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                "INSERT INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?, ?, ?, ?)",
+                [zoom.into(), tile_x.into(), ((1u32 << zoom) - 1 - tile_y).into(), encoder.finish()?.into()],
+            )).await?;
+        }
+    }
+
+    let vector_layers = json!({
+        "vector_layers": [{
+            "id": LAYER_NAME,
+            "minzoom": min_zoom,
+            "maxzoom": max_zoom,
+            "fields": KEYS.iter().map(|key| (key.to_string(), json!("String"))).collect::<serde_json::Map<_, _>>(),
+        }]
+    });
+
+    let metadata = [
+        ("name", LAYER_NAME.to_string()),
+        ("format", "pbf".to_string()),
+        ("type", "overlay".to_string()),
+        ("minzoom", min_zoom.to_string()),
+        ("maxzoom", max_zoom.to_string()),
+        ("bounds", format!("{},{},{},{}", bounds[0], bounds[1], bounds[2], bounds[3])),
+        ("center", format!("{},{},{}", (bounds[0] + bounds[2]) / 2.0, (bounds[1] + bounds[3]) / 2.0, min_zoom)),
+        ("json", vector_layers.to_string()),
+    ];
+
+    for (name, value) in metadata {
+        mbtiles.execute(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            "INSERT INTO metadata (name, value) VALUES (?, ?)",
+            [name.into(), value.into()],
+        )).await?;
+    }
+
+    Ok(())
+}
+
+/// Returns the XYZ tile containing the coordinate and its position inside that tile.
+fn project(lat: f64, lon: f64, zoom: u8) -> (u32, u32, u32, u32) {
+    let scale = (1u64 << zoom) as f64;
+    let lat = lat.clamp(-85.051_128_78, 85.051_128_78).to_radians();
+
+    let world_x = (lon + 180.0) / 360.0 * scale;
+    let world_y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * scale;
+
+    let tile_x = world_x.floor().clamp(0.0, scale - 1.0);
+    let tile_y = world_y.floor().clamp(0.0, scale - 1.0);
+
+    (
+        tile_x as u32,
+        tile_y as u32,
+        ((world_x - tile_x) * EXTENT as f64) as u32,
+        ((world_y - tile_y) * EXTENT as f64) as u32,
+    )
+}
+
+fn encode_tile(points: &[Point]) -> Vec<u8> {
+    let mut values: Vec<&str> = Vec::new();
+    let mut value_index: HashMap<&str, u32> = HashMap::new();
+    let mut layer = Vec::new();
+
+    write_varint_field(&mut layer, 15, 2);
+    write_bytes_field(&mut layer, 1, LAYER_NAME.as_bytes());
+
+    for point in points {
+        let mut tags = Vec::new();
+
+        for (key, value) in point.values.iter().enumerate() {
+            if let Some(value) = value {
+                let index = *value_index.entry(value).or_insert_with(|| {
+                    values.push(value);
+                    values.len() as u32 - 1
+                });
+
+                write_varint(&mut tags, key as u64);
+                write_varint(&mut tags, index as u64);
+            }
+        }
+
+        let mut geometry = Vec::new();
+        write_varint(&mut geometry, 1 | (1 << 3)); // MoveTo, one point
+        write_varint(&mut geometry, zigzag(point.x as i64));
+        write_varint(&mut geometry, zigzag(point.y as i64));
+
+        let mut feature = Vec::new();
+        write_varint_field(&mut feature, 1, point.id as u64);
+        write_bytes_field(&mut feature, 2, &tags);
+        write_varint_field(&mut feature, 3, 1); // POINT
+        write_bytes_field(&mut feature, 4, &geometry);
+
+        write_bytes_field(&mut layer, 2, &feature);
+    }
+
+    for key in KEYS {
+        write_bytes_field(&mut layer, 3, key.as_bytes());
+    }
+
+    for value in values {
+        let mut encoded = Vec::new();
+        write_bytes_field(&mut encoded, 1, value.as_bytes());
+        write_bytes_field(&mut layer, 4, &encoded);
+    }
+
+    write_varint_field(&mut layer, 5, EXTENT as u64);
+
+    let mut tile = Vec::new();
+    write_bytes_field(&mut tile, 3, &layer);
+
+    tile
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+
+    buffer.push(value as u8);
+}
+
+fn write_varint_field(buffer: &mut Vec<u8>, field: u64, value: u64) {
+    write_varint(buffer, field << 3);
+    write_varint(buffer, value);
+}
+
+fn write_bytes_field(buffer: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_varint(buffer, (field << 3) | 2);
+    write_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
@@ -0,0 +1,441 @@
+//! Loads open address datasets like OpenAddresses, the Dutch BAG or INSPIRE addresses next to the OSM data, and
+//! conflates the two.
+//!
+//! External rows are stored in `node` with ids from [`EXTERNAL_IDS`] up, derived from the source and the id of the
+//! record, so loading a newer version of a dataset updates its rows instead of adding them again. When an address is in
+//! both OSM and an external dataset, the row from the source with the worse precedence is marked as superseded and
+//! hidden from lookups, exports and postcode areas.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::{arg, value_parser, ArgAction, ArgMatches, Command};
+use h3o::Resolution;
+use md5::{Digest, Md5};
+use sea_orm::{ActiveValue, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder};
+use sea_orm::sea_query::Expr;
+use serde_json::Value;
+
+use crate::areas;
+use crate::autocomplete;
+use crate::batch::BatchInsert;
+use crate::database::{live_nodes, postcode_pages, postcode_range};
+use crate::entities::*;
+use crate::error::Error;
+use crate::h3;
+use crate::merge::{Conflict, MergePolicy};
+use crate::output::Output;
+use crate::plus_code;
+use crate::precedence::Precedence;
+use crate::profile;
+use crate::progress::Progress;
+use crate::spatial::rd_to_wgs84;
+
+/// The first id of rows from external datasets, far above any OSM id.
+pub const EXTERNAL_IDS: i64 = 1 << 60;
+
+const BATCH_SIZE: usize = 1024;
+const PENDING_WRITES: usize = 16;
+/// Postcodes per page of the conflation pass.
+const PAGE_SIZE: u64 = 512;
+
+/// The fields a record can have. `house_letter` is appended to the house number, `rd_x` and `rd_y` are Dutch RD New
+/// coordinates used when there's no `lat` and `lon`.
+const FIELDS: [&str; 12] = ["id", "lat", "lon", "rd_x", "rd_y", "postcode", "street", "house_number", "house_letter", "unit", "city", "province"];
+
+/// Defaults for a known dataset.
+struct Layout {
+    name: &'static str,
+    source: &'static str,
+    delimiter: char,
+    country: Option<&'static str>,
+    /// The column or property each field is read from
+    columns: &'static [(&'static str, &'static str)],
+}
+
+const LAYOUTS: [Layout; 2] = [
+    Layout {
+        name: "openaddresses",
+        source: "OpenAddresses",
+        delimiter: ',',
+        country: None,
+        columns: &[
+            ("id", "id"),
+            ("lat", "lat"),
+            ("lon", "lon"),
+            ("postcode", "postcode"),
+            ("street", "street"),
+            ("house_number", "number"),
+            ("unit", "unit"),
+            ("city", "city"),
+            ("province", "region"),
+        ],
+    },
+    // The CSV NLExtract derives from the BAG, one row per nummeraanduiding with the verblijfsobject, ligplaats or
+    // standplaats it belongs to. Secondary addresses of an object share its id, so rows are told apart by their
+    // nummeraanduiding when the export has it and by their address otherwise.
+    Layout {
+        name: "bag",
+        source: "BAG",
+        delimiter: ';',
+        country: Some("NL"),
+        columns: &[
+            ("id", "nummeraanduiding"),
+            ("lat", "lat"),
+            ("lon", "lon"),
+            ("rd_x", "x"),
+            ("rd_y", "y"),
+            ("postcode", "postcode"),
+            ("street", "openbareruimte"),
+            ("house_number", "huisnummer"),
+            ("house_letter", "huisletter"),
+            ("unit", "huisnummertoevoeging"),
+            ("city", "woonplaats"),
+            ("province", "provincie"),
+        ],
+    },
+];
+
+pub fn cli() -> Command {
+    Command::new("import-external")
+        .about("Loads an open address dataset like OpenAddresses, BAG or INSPIRE addresses from CSV or GeoJSON, and prefers it over OSM for addresses that are in both")
+        .arg(arg!(--input <FILE> "CSV or GeoJSON file, GeoJSON can also have one feature per line").required(true).value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--layout <LAYOUT> "The dataset the columns, delimiter, source and country default to").value_parser(LAYOUTS.map(|layout| layout.name)).default_value("openaddresses"))
+        .arg(arg!(--source <NAME> "Stored in the source column of every row, defaults to the name of the layout"))
+        .arg(arg!(--format <FORMAT> "Defaults to the extension of the input").value_parser(["csv", "geojson"]))
+        .arg(arg!(--column <FIELD_NAME> "Column or property a field is read from, like `house_number=huisnummer`").action(ArgAction::Append))
+        .arg(arg!(--delimiter <CHAR> "Delimiter of CSV files").value_parser(value_parser!(char)))
+        .arg(arg!(--country <ISO_CODE> "Country of the dataset, selects the rules its addresses are imported with").value_parser(crate::countries::parse_country))
+        .arg(arg!(--areas <STRATEGY> "How postcode areas are derived from the addresses").value_parser(areas::Strategy::NAMES).default_value("concave-hull"))
+        .arg(arg!(--"country-boundaries" <GEOJSON> "Country boundaries named by their ISO 3166-1 alpha-2 code, like Natural Earth's admin 0 countries, to clip Voronoi cells to").value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--"country-property" <NAME> "The property of --country-boundaries with the country code").default_value(areas::DEFAULT_PROPERTY).requires("country-boundaries"))
+}
+
+/// Which column or property each field is read from, compared ignoring case.
+fn field_names(layout: &Layout, matches: &ArgMatches) -> Result<HashMap<&'static str, String>, Error> {
+    let mut names: HashMap<&'static str, String> = layout.columns.iter().map(|(field, name)| (*field, name.to_string())).collect();
+
+    for spec in matches.get_many::<String>("column").into_iter().flatten() {
+        let (field, name) = spec.split_once('=')
+            .ok_or_else(|| Error::Usage(format!("expected field=name for --column, got {}", spec)))?;
+
+        let field = FIELDS.into_iter()
+            .find(|known| *known == field)
+            .ok_or_else(|| Error::Usage(format!("unknown field {} for --column", field)))?;
+
+        names.insert(field, name.to_lowercase());
+    }
+
+    Ok(names)
+}
+
+/// A record as field values, before it's turned into a row.
+type Record = HashMap<&'static str, String>;
+
+pub async fn run(db: Arc<DatabaseConnection>, matches: &ArgMatches) -> Result<(), Error> {
+    let layout_name = matches.get_one::<String>("layout").expect("defaulted in clap");
+    let layout = LAYOUTS.iter().find(|layout| layout.name == layout_name).expect("validated in clap");
+
+    let path = matches.get_one::<PathBuf>("input").expect("required in clap");
+    let source = matches.get_one::<String>("source").map_or(layout.source, String::as_str);
+    let country = matches.get_one::<String>("country").cloned().or(layout.country.map(str::to_string));
+    let names = field_names(layout, matches)?;
+    let precedence = Precedence::parse(matches.get_one::<String>("source-precedence").expect("defaulted in clap"))
+        .map_err(|e| Error::Usage(format!("invalid --source-precedence: {}", e)))?;
+    let borders = crate::country_boundaries(matches)?;
+
+    let format = match matches.get_one::<String>("format") {
+        Some(format) => format.clone(),
+        None => match path.extension().and_then(|extension| extension.to_str()) {
+            Some("csv") => "csv".to_string(),
+            Some("geojson" | "json" | "geojsonl") => "geojson".to_string(),
+            _ => return Err(Error::Usage(format!("can't tell the format of {}, pass --format", path.display()))),
+        },
+    };
+
+    let file = File::open(path).map_err(|e| Error::Input(format!("{}: {}", path.display(), e)))?;
+    let records: Box<dyn Iterator<Item = Result<Record, Error>>> = if format == "csv" {
+        let delimiter = matches.get_one::<char>("delimiter").copied().unwrap_or(layout.delimiter);
+        Box::new(csv_records(file, delimiter, &names)?)
+    } else {
+        geojson_records(file, &names)?
+    };
+
+    println!("Loading {}", path.display());
+
+    let output = Output::Database(db.clone(), Arc::new(MergePolicy::default()), Conflict::Update);
+    let mut batches = BatchInsert::new(output, BATCH_SIZE, PENDING_WRITES, None);
+    let now = chrono::offset::Local::now().naive_local();
+    let (mut loaded, mut skipped) = (0u64, 0u64);
+    let grid_cells = matches.get_flag("grid-cells");
+    let h3_resolution = h3::resolution(matches);
+
+    for record in records {
+        match to_node(record?, source, country.as_deref(), now, grid_cells, h3_resolution) {
+            Some(node) => {
+                batches.push(node).await.map_err(|e| Error::Output(e.to_string()))?;
+                loaded += 1;
+            }
+            None => skipped += 1,
+        }
+    }
+
+    batches.finish().await.map_err(|e| Error::Output(e.to_string()))?;
+    println!("Loaded {} addresses from {}, skipped {} without a location or valid address", loaded, source, skipped);
+
+    // Conflation picks the winner of every address by its rank
+    precedence.assign(db.as_ref()).await?;
+
+    println!("Conflating with OSM");
+    let superseded = conflate(db.as_ref()).await?;
+    println!("{} addresses superseded by another source", superseded);
+
+    println!("Building postcode areas");
+    let strategy = areas::Strategy::from_name(matches.get_one::<String>("areas").expect("defaulted in clap"))
+        .expect("validated in clap");
+    areas::build(db.as_ref(), strategy, borders.as_ref()).await?;
+
+    println!("Building autocomplete suggestions");
+    autocomplete::build(db.as_ref()).await?;
+
+    Ok(())
+}
+
+fn csv_records(input: impl Read + 'static, delimiter: char, names: &HashMap<&'static str, String>) -> Result<impl Iterator<Item = Result<Record, Error>>, Error> {
+    let delimiter = u8::try_from(delimiter).map_err(|_| Error::Usage("the CSV delimiter has to be a single byte".to_string()))?;
+
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(input);
+
+    let headers: Vec<String> = reader.headers()
+        .map_err(|e| Error::Input(e.to_string()))?
+        .iter()
+        .map(str::to_lowercase)
+        .collect();
+
+    let columns: Vec<(&'static str, usize)> = names.iter()
+        .filter_map(|(field, name)| headers.iter().position(|header| header == name).map(|column| (*field, column)))
+        .collect();
+
+    Ok(reader.into_records().map(move |record| {
+        let record = record.map_err(|e| Error::Input(e.to_string()))?;
+
+        Ok(columns.iter()
+            .filter_map(|(field, column)| record.get(*column).map(|value| (*field, value.to_string())))
+            .collect())
+    }))
+}
+
+/// Reads a FeatureCollection, or one feature per line like OpenAddresses writes them.
+fn geojson_records(input: impl Read + 'static, names: &HashMap<&'static str, String>) -> Result<Box<dyn Iterator<Item = Result<Record, Error>>>, Error> {
+    let mut reader = BufReader::new(input);
+    let mut first = String::new();
+    reader.read_line(&mut first).map_err(|e| Error::Input(e.to_string()))?;
+
+    let names = names.clone();
+
+    let line_per_feature = serde_json::from_str::<Value>(&first).is_ok_and(|value| value["type"] == "Feature");
+
+    if line_per_feature {
+        let lines = std::iter::once(Ok(first)).chain(reader.lines());
+
+        return Ok(Box::new(lines.filter(|line| !matches!(line, Ok(line) if line.trim().is_empty())).map(move |line| {
+            let line = line.map_err(|e| Error::Input(e.to_string()))?;
+            let feature = serde_json::from_str(&line).map_err(|e| Error::Input(e.to_string()))?;
+
+            Ok(feature_record(&feature, &names))
+        })));
+    }
+
+    let mut contents = first;
+    reader.read_to_string(&mut contents).map_err(|e| Error::Input(e.to_string()))?;
+
+    let collection: Value = serde_json::from_str(&contents).map_err(|e| Error::Input(e.to_string()))?;
+    let Some(Value::Array(features)) = collection.get("features").cloned() else {
+        return Err(Error::Input("expected a FeatureCollection or one feature per line".to_string()));
+    };
+
+    Ok(Box::new(features.into_iter().map(move |feature| Ok(feature_record(&feature, &names)))))
+}
+
+/// The properties of a feature and the coordinates of its point.
+fn feature_record(feature: &Value, names: &HashMap<&'static str, String>) -> Record {
+    let mut record: Record = HashMap::new();
+
+    if let Value::Object(properties) = &feature["properties"] {
+        for (field, name) in names {
+            let value = properties.iter().find(|(key, _)| key.to_lowercase() == *name).map(|(_, value)| value);
+
+            match value {
+                Some(Value::String(value)) => record.insert(field, value.clone()),
+                Some(Value::Number(value)) => record.insert(field, value.to_string()),
+                _ => None,
+            };
+        }
+    }
+
+    if feature["geometry"]["type"] == "Point" {
+        if let (Some(lon), Some(lat)) = (feature["geometry"]["coordinates"][0].as_f64(), feature["geometry"]["coordinates"][1].as_f64()) {
+            record.insert("lon", lon.to_string());
+            record.insert("lat", lat.to_string());
+        }
+    }
+
+    record
+}
+
+/// A stable id for a record, from its own id or otherwise its address.
+fn external_id(source: &str, record: &Record) -> i64 {
+    let key = match record.get("id").filter(|id| !id.is_empty()) {
+        Some(id) => id.clone(),
+        None => ["postcode", "street", "house_number", "unit"].map(|field| record.get(field).map_or("", String::as_str)).join("\0"),
+    };
+
+    let hash = Md5::new().chain_update(source).chain_update([0]).chain_update(key).finalize();
+    let hash = i64::from_le_bytes(hash[..8].try_into().expect("md5 hashes are 16 bytes"));
+
+    EXTERNAL_IDS + (hash & (EXTERNAL_IDS - 1))
+}
+
+/// The row for a record normalized by the country profile, `None` when it has no location or doesn't qualify.
+fn to_node(record: Record, source: &str, country: Option<&str>, now: chrono::NaiveDateTime, grid_cells: bool, h3_resolution: Option<Resolution>) -> Option<node::ActiveModel> {
+    let text = |field: &str| record.get(field).map(|value| value.trim().to_string()).filter(|value| !value.is_empty());
+    let number = |field: &str| text(field).and_then(|value| value.parse::<f64>().ok());
+
+    let (lat, lon) = match (number("lat"), number("lon"), number("rd_x"), number("rd_y")) {
+        (Some(lat), Some(lon), _, _) => (lat, lon),
+        (_, _, Some(x), Some(y)) => rd_to_wgs84(x, y),
+        _ => return None,
+    };
+
+    let house_number = match (text("house_number"), text("house_letter")) {
+        (Some(number), Some(letter)) => Some(number + &letter),
+        (number, _) => number,
+    };
+
+    let mut node = node::ActiveModel {
+        id: ActiveValue::Set(external_id(source, &record)),
+        lat: ActiveValue::Set(lat),
+        lon: ActiveValue::Set(lon),
+        city: ActiveValue::Set(text("city")),
+        country: ActiveValue::Set(country.map(str::to_string)),
+        postcode: ActiveValue::Set(text("postcode")?),
+        street: ActiveValue::Set(text("street")),
+        province: ActiveValue::Set(text("province")),
+        house_number: ActiveValue::Set(house_number),
+        house_name: ActiveValue::Set(None),
+        house_name_normalized: ActiveValue::Set(None),
+        search_key: ActiveValue::Set(None),
+        postcode_extension: ActiveValue::Set(None),
+        unit: ActiveValue::Set(text("unit")),
+        outcode: ActiveValue::Set(None),
+        incode: ActiveValue::Set(None),
+        block_number: ActiveValue::Set(None),
+        neighbourhood: ActiveValue::Set(None),
+        quarter: ActiveValue::Set(None),
+        entrance: ActiveValue::Set(None),
+        source: ActiveValue::Set(Some(source.to_string())),
+        source_date: ActiveValue::Set(None),
+        updated_at: ActiveValue::Set(now),
+        version: ActiveValue::Set(0),
+        deleted_at: ActiveValue::Set(None),
+        superseded_by: ActiveValue::Set(None),
+        source_rank: ActiveValue::Set(0),
+        plus_code: ActiveValue::Set(Some(plus_code::encode(lat, lon))),
+        grid_cell: ActiveValue::Set(grid_cells.then(|| plus_code::grid_cell(lat, lon))),
+        h3: ActiveValue::Set(h3_resolution.and_then(|resolution| h3::cell(lat, lon, resolution))),
+        raw_tags: ActiveValue::Set(None),
+        qa_note: ActiveValue::Set(None),
+        elevation_m: ActiveValue::Set(None),
+        admin_area_id: ActiveValue::Set(None),
+    };
+
+    profile::profiles().get(country).apply(&mut node).ok().map(|_| node)
+}
+
+/// Whether any external dataset has been loaded.
+pub async fn loaded(db: &DatabaseConnection) -> Result<bool, DbErr> {
+    Ok(node::Entity::find().filter(node::Column::Id.gte(EXTERNAL_IDS)).count(db).await? > 0)
+}
+
+/// Postcode, street ignoring case, house number and unit.
+type AddressKey = (String, Option<String>, String, Option<String>);
+
+/// Conflates OSM with the external datasets. When an address is in both, the rows from the side with the worse
+/// `source_rank` are marked as superseded by the best row, by default the OSM rows. Addresses match on their postcode,
+/// street, house number and unit, after normalization by the country profile. Duplicates within OSM or the external
+/// datasets are left to merging. Starts over on every run, so a changed precedence applies to every row. Returns the
+/// number of superseded rows.
+pub async fn conflate(db: &DatabaseConnection) -> Result<u64, DbErr> {
+    node::Entity::update_many()
+        .col_expr(node::Column::SupersededBy, Expr::value(Option::<i64>::None))
+        .filter(node::Column::SupersededBy.is_not_null())
+        .exec(db)
+        .await?;
+
+    let mut superseded = 0;
+    let mut last: Option<String> = None;
+    let mut progress = Progress::new("Conflating with external datasets", postcode_pages(db, PAGE_SIZE).await?);
+
+    while let Some((first, end)) = postcode_range(db, last.as_deref(), PAGE_SIZE).await? {
+        let nodes = live_nodes()
+            .filter(node::Column::Postcode.between(first.as_str(), end.as_str()))
+            .filter(node::Column::HouseNumber.is_not_null())
+            .order_by_asc(node::Column::Id)
+            .all(db)
+            .await?;
+
+        let mut addresses: HashMap<AddressKey, Vec<node::Model>> = HashMap::new();
+
+        for node in nodes {
+            addresses.entry(address_key(&node)).or_default().push(node);
+        }
+
+        let mut replaced: HashMap<i64, Vec<i64>> = HashMap::new();
+
+        for rows in addresses.into_values() {
+            // The best rank wins, external datasets on a tie, then the lowest id as the nodes are sorted by id
+            let Some(best) = rows.iter().min_by_key(|node| (node.source_rank, node.id < EXTERNAL_IDS)) else {
+                continue;
+            };
+            let best_is_external = best.id >= EXTERNAL_IDS;
+
+            let losers: Vec<i64> = rows.iter()
+                .filter(|node| (node.id >= EXTERNAL_IDS) != best_is_external)
+                .map(|node| node.id)
+                .collect();
+
+            if !losers.is_empty() {
+                replaced.entry(best.id).or_default().extend(losers);
+            }
+        }
+
+        for (by, ids) in replaced {
+            superseded += node::Entity::update_many()
+                .col_expr(node::Column::SupersededBy, Expr::value(by))
+                .filter(node::Column::Id.is_in(ids))
+                .exec(db)
+                .await?
+                .rows_affected;
+        }
+
+        last = Some(end);
+        progress.advance(1);
+    }
+
+    Ok(superseded)
+}
+
+fn address_key(node: &node::Model) -> AddressKey {
+    (
+        node.postcode.clone(),
+        node.street.as_ref().map(|street| street.to_lowercase()),
+        node.house_number.clone().unwrap_or_default(),
+        node.unit.clone(),
+    )
+}
@@ -0,0 +1,131 @@
+//! Repairs addresses already in the database, for mistakes in earlier imports that would otherwise take a full
+//! re-import to undo.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+
+use clap::{arg, value_parser, ArgMatches, Command};
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
+
+use crate::autocomplete;
+use crate::boundaries::Boundaries;
+use crate::countries::parse_country;
+use crate::entities::*;
+
+/// Addresses located and updated at a time.
+const PAGE_SIZE: u64 = 5000;
+
+pub fn cli() -> Command {
+    Command::new("fix")
+        .about("Repairs addresses already in the database")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("country")
+                .about("Sets the country of addresses imported without one, from a default or from the country boundary they're in")
+                .arg(arg!(--default <ISO_CODE> "Country of the addresses, or of those outside every boundary with --boundaries").value_parser(parse_country))
+                .arg(arg!(--boundaries <GEOJSON> "FeatureCollection of country polygons, like Natural Earth's admin 0 countries").value_parser(value_parser!(PathBuf)))
+                .arg(arg!(--property <NAME> "Property of the boundaries with the ISO 3166-1 code of the country").default_value("ISO_A2"))
+                .arg(arg!(--where <CONDITION> "SQL condition on the node table selecting the addresses to fix").default_value("country IS NULL"))
+                .group(clap::ArgGroup::new("from").args(["default", "boundaries"]).multiple(true).required(true))
+        )
+}
+
+pub async fn run(db: &DatabaseConnection, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    match matches.subcommand() {
+        Some(("country", matches)) => country(db, matches).await,
+        _ => unreachable!("subcommand_required in clap"),
+    }
+}
+
+async fn country(db: &DatabaseConnection, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let default = matches.get_one::<String>("default");
+    let condition = Expr::cust(format!("({})", matches.get_one::<String>("where").expect("defaulted in clap")));
+
+    let mut fixed = 0;
+
+    if let Some(path) = matches.get_one::<PathBuf>("boundaries") {
+        let boundaries = Boundaries::load(path, matches.get_one::<String>("property").expect("defaulted in clap"))?;
+
+        // Areas named by something other than a country code are reported once and left out
+        let mut codes: HashMap<String, Option<String>> = HashMap::new();
+        let mut outside = Vec::new();
+        let mut after_id = i64::MIN;
+
+        loop {
+            let rows: Vec<(i64, f64, f64)> = node::Entity::find()
+                .select_only()
+                .columns([node::Column::Id, node::Column::Lat, node::Column::Lon])
+                .filter(condition.clone())
+                .filter(node::Column::Id.gt(after_id))
+                .order_by_asc(node::Column::Id)
+                .limit(PAGE_SIZE)
+                .into_tuple()
+                .all(db)
+                .await?;
+
+            let Some(&(last_id, _, _)) = rows.last() else {
+                break;
+            };
+
+            let mut located: HashMap<String, Vec<i64>> = HashMap::new();
+
+            for (id, lat, lon) in rows {
+                let code = boundaries.locate(lat, lon).and_then(|name| {
+                    codes.entry(name.to_string())
+                        .or_insert_with(|| parse_country(name).inspect_err(|e| println!("Warning: skipping boundary {:?}: {}", name, e)).ok())
+                        .clone()
+                });
+
+                match code {
+                    Some(code) => located.entry(code).or_default().push(id),
+                    None => outside.push(id),
+                }
+            }
+
+            for (code, ids) in located {
+                fixed += set_country(db, &code, ids).await?;
+            }
+
+            after_id = last_id;
+        }
+
+        match default {
+            Some(default) => fixed += set_country(db, default, outside).await?,
+            None if !outside.is_empty() => println!("Warning: {} addresses aren't in any boundary, pass --default for them", outside.len()),
+            None => {}
+        }
+    } else if let Some(default) = default {
+        fixed = node::Entity::update_many()
+            .col_expr(node::Column::Country, Expr::value(default))
+            .filter(condition)
+            .exec(db)
+            .await?
+            .rows_affected;
+    }
+
+    println!("Set the country of {} addresses", fixed);
+
+    if fixed > 0 {
+        println!("Building autocomplete suggestions");
+        autocomplete::build(db).await?;
+    }
+
+    Ok(())
+}
+
+async fn set_country(db: &DatabaseConnection, code: &str, ids: Vec<i64>) -> Result<u64, Box<dyn Error>> {
+    let mut updated = 0;
+
+    for ids in ids.chunks(PAGE_SIZE as usize) {
+        updated += node::Entity::update_many()
+            .col_expr(node::Column::Country, Expr::value(code))
+            .filter(node::Column::Id.is_in(ids.iter().copied()))
+            .exec(db)
+            .await?
+            .rows_affected;
+    }
+
+    Ok(updated)
+}
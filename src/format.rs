@@ -0,0 +1,102 @@
+//! Renders addresses as the lines they're written on an envelope, in the order of their country.
+//!
+//! The layouts follow the templates of the OpenCage address-formatting project. A template is one line per line of the
+//! address with `{field}` placeholders, the layout of a country comes from its profile and can be replaced with
+//! `address_format` in `--profiles`. Placeholders of empty fields are dropped, along with the separators and lines they
+//! leave behind.
+
+use serde::Serialize;
+
+use crate::entities::*;
+use crate::profile::profiles;
+
+/// Street before the house number and the postcode before the city, the order of most of continental Europe.
+pub const DEFAULT: &str = "{house_name}\n{street} {house_number} {unit}\n{postcode} {city}";
+
+pub const FR: &str = "{house_name}\n{house_number} {street}\n{unit}\n{postcode} {city}";
+pub const GB: &str = "{unit}\n{house_name}\n{house_number} {street}\n{city}\n{postcode}";
+pub const IE: &str = "{unit}\n{house_name}\n{house_number} {street}\n{city}\n{province}\n{postcode}";
+/// Also used for CA, where the province is stored as its two letter code.
+pub const US: &str = "{house_name}\n{house_number} {street} {unit}\n{city}, {province} {postcode}-{postcode_extension}";
+pub const JP: &str = "〒{postcode}\n{province} {city} {quarter} {neighbourhood}\n{block_number}-{house_number}\n{house_name}";
+
+/// An address with its display string, as returned by lookups.
+#[derive(Serialize)]
+pub struct Formatted {
+    #[serde(flatten)]
+    pub node: node::Model,
+    pub formatted: String,
+}
+
+impl From<node::Model> for Formatted {
+    fn from(node: node::Model) -> Self {
+        Self { formatted: format_address(&node), node }
+    }
+}
+
+/// The address in the layout of its country, one line per line of the address.
+pub fn format_address(node: &node::Model) -> String {
+    render(&profiles().get(node.country.as_deref()).address_format, node)
+}
+
+fn field<'a>(node: &'a node::Model, name: &str) -> Option<&'a str> {
+    let value = match name {
+        "postcode" => return Some(&node.postcode),
+        "house_name" => &node.house_name,
+        "house_number" => &node.house_number,
+        "street" => &node.street,
+        "postcode_extension" => &node.postcode_extension,
+        "unit" => &node.unit,
+        "city" => &node.city,
+        "province" => &node.province,
+        "country" => &node.country,
+        "block_number" => &node.block_number,
+        "neighbourhood" => &node.neighbourhood,
+        "quarter" => &node.quarter,
+        _ => return None,
+    };
+
+    value.as_deref()
+}
+
+fn render(template: &str, node: &node::Model) -> String {
+    template.lines()
+        .map(|line| render_line(line, node))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Fills in one line. Separators around an empty placeholder are dropped, so `{city}, {province}` without a city
+/// becomes the province rather than `, ON`.
+fn render_line(line: &str, node: &node::Model) -> String {
+    let mut rendered = String::new();
+    let mut separator = String::new();
+    let mut rest = line;
+
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+
+        separator.push_str(&rest[..start]);
+
+        match field(node, &rest[start + 1..end]).map(str::trim).filter(|value| !value.is_empty()) {
+            Some(value) => {
+                // A separator before the first value is only kept when it's part of the layout, like `〒`
+                if !rendered.is_empty() || !separator.trim_matches(|c: char| c.is_whitespace() || ",-".contains(c)).is_empty() {
+                    rendered.push_str(&separator);
+                }
+
+                rendered.push_str(value);
+                separator.clear();
+            }
+            None if rendered.is_empty() => separator.clear(),
+            None => {}
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    rendered.split_whitespace().collect::<Vec<_>>().join(" ")
+}
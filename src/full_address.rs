@@ -0,0 +1,222 @@
+//! Recovers the parts of an address that are only tagged as one value: a free-form `addr:full`, or an `addr:street`
+//! with the house number in it. Without these the element would be skipped for lacking a postcode, street or house
+//! number. Tagged fields always win, only the missing ones are filled in.
+//!
+//! Builds with the `libpostal` feature parse with libpostal. Other builds, and builds whose libpostal models couldn't be
+//! loaded, use a heuristic that only knows where the house number goes in the street line of a country, set with
+//! `house_number_position` in its profile, and finds the postcode with the pattern of the profile. Countries without
+//! a position aren't parsed then.
+
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+use regex::Regex;
+use sea_orm::ActiveValue;
+
+use crate::entities::*;
+use crate::profile::{profiles, CountryProfile, HouseNumberPosition};
+
+/// A street line ending in its house number, `Dorpsstraat 12a` or `Hauptstraße 5-7`.
+static AFTER_STREET: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?<street>.*\p{L}.*?)\s+(?<number>[0-9]+\s?[a-zA-Z]?(?:\s?[-/]\s?[0-9a-zA-Z]+)?)$").unwrap()
+});
+
+/// A street line starting with its house number, `350 5th Avenue` or `12B rue de la Paix`.
+static BEFORE_STREET: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?<number>[0-9]+[a-zA-Z]?(?:[-/][0-9]+[a-zA-Z]?)?),?\s+(?<street>.*\p{L}.*)$").unwrap()
+});
+
+/// The streets of the addresses parsed so far that were tagged with their own house number, lowercased. A street line
+/// like `Plein 1944` can be a street named after a year, so its number is only split off when what's left is one of
+/// them.
+#[derive(Default)]
+pub struct KnownStreets(HashSet<String>);
+
+impl KnownStreets {
+    fn add(&mut self, street: &str) {
+        self.0.insert(street.to_lowercase());
+    }
+
+    /// Whether the house number split off a street line after the street leaves a street: one ending in a number of
+    /// its own, like `Plein 1944` of `Plein 1944 12`, or a known one.
+    fn confirms(&self, street: &str) -> bool {
+        street.split_whitespace().last().is_some_and(|word| word.starts_with(|c: char| c.is_ascii_digit()))
+            || self.0.contains(&street.to_lowercase())
+    }
+}
+
+/// The parts found in a value.
+#[derive(Default)]
+struct Parsed {
+    street: Option<String>,
+    house_number: Option<String>,
+    unit: Option<String>,
+    house_name: Option<String>,
+    postcode: Option<String>,
+    city: Option<String>,
+    province: Option<String>,
+}
+
+fn parse(value: &str, country: Option<&str>, streets: &KnownStreets) -> Option<Parsed> {
+    #[cfg(feature = "libpostal")]
+    if let Some(parsed) = parse_libpostal(value, country) {
+        return Some(parsed);
+    }
+
+    parse_heuristic(value, profiles().get(country), streets)
+}
+
+/// `None` when libpostal isn't available.
+#[cfg(feature = "libpostal")]
+fn parse_libpostal(value: &str, country: Option<&str>) -> Option<Parsed> {
+    let components = crate::libpostal::parse(value, country);
+
+    if components.is_empty() {
+        return None;
+    }
+
+    let mut parsed = Parsed::default();
+
+    for (label, component) in components {
+        let field = match label.as_str() {
+            "road" => &mut parsed.street,
+            "house_number" => &mut parsed.house_number,
+            "unit" => &mut parsed.unit,
+            "house" => &mut parsed.house_name,
+            "postcode" => &mut parsed.postcode,
+            "city" => &mut parsed.city,
+            "state" => &mut parsed.province,
+            _ => continue,
+        };
+
+        field.get_or_insert(component);
+    }
+
+    Some(parsed)
+}
+
+fn parse_heuristic(value: &str, profile: &CountryProfile, streets: &KnownStreets) -> Option<Parsed> {
+    let street_line = match profile.house_number_position {
+        HouseNumberPosition::None => return None,
+        HouseNumberPosition::AfterStreet => &*AFTER_STREET,
+        HouseNumberPosition::BeforeStreet => &*BEFORE_STREET,
+    };
+
+    let mut parsed = Parsed::default();
+    let mut rest = Vec::new();
+
+    for part in value.split([',', ';', '\n']).map(str::trim).filter(|part| !part.is_empty()) {
+        if parsed.postcode.is_none() {
+            if let Some((postcode, remainder)) = find_postcode(part, profile) {
+                parsed.postcode = Some(postcode);
+
+                // `1234 AB Utrecht` and `London SW1A 1AA` hold the city as well, `NY 10118` the state
+                if is_province(&remainder, profile) {
+                    parsed.province = Some(remainder);
+                } else if !remainder.is_empty() {
+                    parsed.city = Some(remainder);
+                }
+
+                continue;
+            }
+        }
+
+        if parsed.street.is_none() {
+            let captures = street_line.captures(part).filter(|captures| {
+                profile.house_number_position != HouseNumberPosition::AfterStreet || streets.confirms(captures["street"].trim())
+            });
+
+            if let Some(captures) = captures {
+                parsed.street = Some(captures["street"].trim().to_string());
+                parsed.house_number = Some(captures["number"].to_string());
+                continue;
+            }
+        }
+
+        rest.push(part);
+    }
+
+    // The part right after the street line is the city when the postcode didn't come with one
+    if parsed.city.is_none() && parsed.street.is_some() {
+        parsed.city = rest.first().map(|city| city.to_string());
+    }
+
+    Some(parsed)
+}
+
+fn is_province(value: &str, profile: &CountryProfile) -> bool {
+    profile.province_codes.iter().any(|(name, code)| name.eq_ignore_ascii_case(value) || code.eq_ignore_ascii_case(value))
+}
+
+/// The first run of one or two words of the part that the profile accepts as a postcode, with the words around it.
+/// Only for profiles with a postcode pattern, as any word is a postcode without one.
+fn find_postcode(part: &str, profile: &CountryProfile) -> Option<(String, String)> {
+    profile.postcode_pattern.as_ref()?;
+
+    let words: Vec<&str> = part.split_whitespace().collect();
+
+    for start in 0..words.len() {
+        for length in [2, 1] {
+            let Some(candidate) = words.get(start..start + length) else {
+                continue;
+            };
+
+            if let Some(postcode) = profile.normalize_postcode(&candidate.join(" ")) {
+                let remainder = [&words[..start], &words[start + length..]].concat().join(" ");
+
+                return Some((postcode, remainder));
+            }
+        }
+    }
+
+    None
+}
+
+fn fill(field: &mut ActiveValue<Option<String>>, value: Option<String>) {
+    if matches!(field, ActiveValue::Set(Some(_))) || value.is_none() {
+        return;
+    }
+
+    *field = ActiveValue::Set(value);
+}
+
+/// Fills in the missing fields of the node from its `addr:full`, and splits a house number off its street when it
+/// doesn't have one. Streets tagged along with a house number are added to `streets`.
+pub fn complete(node: &mut node::ActiveModel, full: Option<&str>, streets: &mut KnownStreets) {
+    let country = match &node.country {
+        ActiveValue::Set(country) => country.clone(),
+        _ => None,
+    };
+
+    let has_street = matches!(&node.street, ActiveValue::Set(Some(_)));
+    let has_house_number = matches!(&node.house_number, ActiveValue::Set(Some(_)));
+
+    if let (true, true, ActiveValue::Set(Some(street))) = (has_street, has_house_number, &node.street) {
+        streets.add(street);
+    }
+
+    if let (true, false, ActiveValue::Set(Some(street))) = (has_street, has_house_number, &node.street) {
+        // Only taken when both parts are found, a street that happens to end in a number is left alone
+        if let Some(Parsed { street: Some(street), house_number: Some(house_number), .. }) = parse(street, country.as_deref(), streets) {
+            node.street = ActiveValue::Set(Some(street));
+            node.house_number = ActiveValue::Set(Some(house_number));
+        }
+    }
+
+    let Some(parsed) = full.and_then(|full| parse(full, country.as_deref(), streets)) else {
+        return;
+    };
+
+    if !node.postcode.is_set() {
+        if let Some(postcode) = parsed.postcode {
+            node.postcode = ActiveValue::Set(postcode);
+        }
+    }
+
+    fill(&mut node.street, parsed.street);
+    fill(&mut node.house_number, parsed.house_number);
+    fill(&mut node.unit, parsed.unit);
+    fill(&mut node.house_name, parsed.house_name);
+    fill(&mut node.city, parsed.city);
+    fill(&mut node.province, parsed.province);
+}
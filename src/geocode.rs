@@ -0,0 +1,133 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{arg, value_parser, ArgMatches, Command};
+use sea_orm::DatabaseConnection;
+
+use crate::query::{lookup, Page};
+use crate::spatial::nearest;
+
+pub fn cli() -> Command {
+    Command::new("geocode")
+        .about("Appends lat/lon columns to a CSV of addresses by looking them up in the database")
+        .arg(arg!(--input <FILE> "CSV file with addresses").required(true).value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--output <FILE> "Write to a file instead of stdout").value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--"postcode-col" <COLUMN> "1-based index of the postcode column").required(true).value_parser(value_parser!(u64).range(1..)))
+        .arg(arg!(--"housenumber-col" <COLUMN> "1-based index of the house number column").value_parser(value_parser!(u64).range(1..)))
+        .arg(arg!(--"no-header" "The input does not start with a header row"))
+}
+
+pub fn reverse_cli() -> Command {
+    Command::new("reverse-geocode")
+        .about("Appends the nearest postcode and address to a CSV of coordinates")
+        .arg(arg!(--input <FILE> "CSV file with coordinates").required(true).value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--output <FILE> "Write to a file instead of stdout").value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--"lat-col" <COLUMN> "1-based index of the latitude column").required(true).value_parser(value_parser!(u64).range(1..)))
+        .arg(arg!(--"lon-col" <COLUMN> "1-based index of the longitude column").required(true).value_parser(value_parser!(u64).range(1..)))
+        .arg(arg!(--"no-header" "The input does not start with a header row"))
+}
+
+pub async fn run(db: &DatabaseConnection, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let postcode_col = *matches.get_one::<u64>("postcode-col").expect("required in clap") as usize - 1;
+    let house_number_col = matches.get_one::<u64>("housenumber-col").map(|col| *col as usize - 1);
+    let has_header = !matches.get_flag("no-header");
+
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(has_header)
+        .flexible(true)
+        .from_path(matches.get_one::<PathBuf>("input").expect("required in clap"))?;
+    let mut writer = csv_writer(matches.get_one::<PathBuf>("output"))?;
+
+    if has_header {
+        let mut header = reader.headers()?.clone();
+        header.push_field("lat");
+        header.push_field("lon");
+        writer.write_record(&header)?;
+    }
+
+    for record in reader.records() {
+        let mut record = record?;
+        let postcode = record.get(postcode_col).unwrap_or_default();
+        let house_number = house_number_col.and_then(|col| record.get(col)).filter(|value| !value.is_empty());
+
+        let found = if postcode.is_empty() {
+            None
+        } else {
+            lookup(db, postcode, house_number, None, Page::default()).await?.into_iter().next()
+        };
+
+        match found {
+            Some(model) => {
+                record.push_field(&model.lat.to_string());
+                record.push_field(&model.lon.to_string());
+            }
+            None => {
+                record.push_field("");
+                record.push_field("");
+            }
+        }
+
+        writer.write_record(&record)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+pub async fn run_reverse(db: &DatabaseConnection, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let lat_col = *matches.get_one::<u64>("lat-col").expect("required in clap") as usize - 1;
+    let lon_col = *matches.get_one::<u64>("lon-col").expect("required in clap") as usize - 1;
+    let has_header = !matches.get_flag("no-header");
+
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(has_header)
+        .flexible(true)
+        .from_path(matches.get_one::<PathBuf>("input").expect("required in clap"))?;
+    let mut writer = csv_writer(matches.get_one::<PathBuf>("output"))?;
+
+    if has_header {
+        let mut header = reader.headers()?.clone();
+        header.extend(["postcode", "street", "house_number", "city", "distance_m"]);
+        writer.write_record(&header)?;
+    }
+
+    for record in reader.records() {
+        let mut record = record?;
+        let lat = record.get(lat_col).and_then(|value| value.trim().parse::<f64>().ok());
+        let lon = record.get(lon_col).and_then(|value| value.trim().parse::<f64>().ok());
+
+        let found = match (lat, lon) {
+            (Some(lat), Some(lon)) => nearest(db, lat, lon).await?,
+            _ => None,
+        };
+
+        match found {
+            Some((model, distance)) => record.extend([
+                model.postcode,
+                model.street.unwrap_or_default(),
+                model.house_number.unwrap_or_default(),
+                model.city.unwrap_or_default(),
+                format!("{:.1}", distance),
+            ]),
+            None => record.extend(["", "", "", "", ""]),
+        }
+
+        writer.write_record(&record)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+pub fn csv_writer(output: Option<&PathBuf>) -> std::io::Result<csv::Writer<Box<dyn Write>>> {
+    let output: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(std::io::stdout()),
+    };
+
+    Ok(csv::WriterBuilder::new().flexible(true).from_writer(output))
+}
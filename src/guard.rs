@@ -0,0 +1,64 @@
+//! Guards against a database that lost a large share of its addresses since the previous import, like one built from
+//! a truncated download. Every import stores its number of live addresses in `import_run` to compare the next with.
+
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
+
+use crate::entities::*;
+use crate::error::Error;
+
+/// What happens when the addresses dropped by more than `--max-drop`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum OnDrop {
+    /// Fail the import, before staging tables are swapped in
+    Abort,
+    Warn,
+}
+
+impl OnDrop {
+    pub const NAMES: [&'static str; 2] = ["abort", "warn"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "abort" => Some(Self::Abort),
+            "warn" => Some(Self::Warn),
+            _ => None,
+        }
+    }
+}
+
+/// The number of addresses of the last import that stored it.
+pub async fn previous(db: &DatabaseConnection) -> Result<Option<u64>, DbErr> {
+    let addresses: Option<Option<i64>> = import_run::Entity::find()
+        .select_only()
+        .column(import_run::Column::Addresses)
+        .filter(import_run::Column::Addresses.is_not_null())
+        .order_by_desc(import_run::Column::Id)
+        .into_tuple()
+        .one(db)
+        .await?;
+
+    Ok(addresses.flatten().map(|addresses| addresses as u64))
+}
+
+/// Compares the addresses of this import with those of the previous one.
+pub fn check(previous: Option<u64>, addresses: u64, max_drop: f64, on_drop: OnDrop) -> Result<(), Error> {
+    let Some(previous) = previous.filter(|previous| *previous > 0) else {
+        return Ok(());
+    };
+
+    let dropped = previous.saturating_sub(addresses) as f64 * 100.0 / previous as f64;
+
+    if dropped <= max_drop {
+        return Ok(());
+    }
+
+    let message = format!("{} addresses left, {:.1}% less than the {} of the previous import, more than --max-drop {}%", addresses, dropped, previous, max_drop);
+
+    match on_drop {
+        OnDrop::Abort => Err(Error::Dropped(message)),
+        OnDrop::Warn => {
+            println!("Warning: {}", message);
+            Ok(())
+        }
+    }
+}
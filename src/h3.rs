@@ -0,0 +1,26 @@
+//! H3 cells, the hierarchical hexagons of https://h3geo.org, for heat maps and for joining against other data indexed
+//! the same way. With `--h3-resolution` addresses store the cell they're in, every coarser cell is a parent of it.
+
+use clap::ArgMatches;
+use h3o::{CellIndex, LatLng, Resolution};
+
+/// The `--h3-resolution` to store cells at, `None` to leave the column empty.
+pub fn resolution(matches: &ArgMatches) -> Option<Resolution> {
+    matches.get_one::<u8>("h3-resolution").map(|resolution| Resolution::try_from(*resolution).expect("validated in clap"))
+}
+
+/// The cell of a coordinate at `resolution`, as it's stored in the `h3` column. H3 indexes leave the highest bit
+/// unset, so they fit a signed 64 bit column.
+pub fn cell(lat: f64, lon: f64, resolution: Resolution) -> Option<i64> {
+    LatLng::new(lat, lon).ok().map(|location| u64::from(location.to_cell(resolution)) as i64)
+}
+
+/// The cell of a coordinate at the resolution of `stored`, for an address that moved.
+pub fn same_resolution(stored: i64, lat: f64, lon: f64) -> Option<i64> {
+    index(stored).and_then(|stored| cell(lat, lon, stored.resolution()))
+}
+
+/// A stored cell, `None` when the column holds something else.
+pub fn index(stored: i64) -> Option<CellIndex> {
+    CellIndex::try_from(stored as u64).ok()
+}
@@ -0,0 +1,78 @@
+//! The header of an OSM XML file, its `<osm>` root element and the `<bounds>` that follow it. It's stored in
+//! `import_run` after every import, to tell which extract a database was built from.
+
+use chrono::{DateTime, Utc};
+use sea_orm::{ActiveValue, DatabaseConnection, DbErr, EntityTrait};
+use sea_orm::prelude::DateTime as NaiveDateTime;
+use xml::attribute::OwnedAttribute;
+
+use crate::entities::*;
+use crate::error::Error;
+
+#[derive(Default, Debug, Clone)]
+pub struct Header {
+    pub generator: Option<String>,
+    /// The replication timestamp, when the data was last updated
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Minimum latitude and longitude, then maximum latitude and longitude
+    pub bounds: Option<(f64, f64, f64, f64)>,
+}
+
+fn attribute<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Option<&'a str> {
+    attributes.iter()
+        .find(|attribute| attribute.name.local_name == name)
+        .map(|attribute| attribute.value.as_str())
+}
+
+impl Header {
+    /// Reads the root element, refusing documents that aren't OSM data in a version this importer understands.
+    pub fn from_root(name: &str, attributes: &[OwnedAttribute]) -> Result<Self, Error> {
+        match name {
+            "osm" => {}
+            "osmChange" => return Err(Error::Input("osmChange files only hold the changes between two extracts, import a full extract instead".to_string())),
+            other => return Err(Error::Input(format!("expected an <osm> document, got <{}>", other))),
+        }
+
+        if let Some(version) = attribute(attributes, "version").filter(|version| *version != "0.6") {
+            return Err(Error::Input(format!("OSM XML version {} isn't supported, only 0.6 is", version)));
+        }
+
+        let timestamp = attribute(attributes, "timestamp")
+            .map(|timestamp| DateTime::parse_from_rfc3339(timestamp)
+                .map_err(|e| Error::Input(format!("invalid timestamp {}: {}", timestamp, e))))
+            .transpose()?
+            .map(|timestamp| timestamp.with_timezone(&Utc));
+
+        Ok(Self { generator: attribute(attributes, "generator").map(str::to_string), timestamp, bounds: None })
+    }
+
+    pub fn add_bounds(&mut self, attributes: &[OwnedAttribute]) {
+        let coordinate = |name: &str| attribute(attributes, name).and_then(|value| value.parse().ok());
+
+        if let (Some(min_lat), Some(min_lon), Some(max_lat), Some(max_lon)) = (coordinate("minlat"), coordinate("minlon"), coordinate("maxlat"), coordinate("maxlon")) {
+            self.bounds = Some((min_lat, min_lon, max_lat, max_lon));
+        }
+    }
+
+    /// Stores the header of a finished import along with where it was read from, `None` for stdin, and the number of
+    /// addresses it left in the database.
+    pub async fn record(&self, db: &DatabaseConnection, source: Option<String>, started_at: NaiveDateTime, addresses: u64) -> Result<(), DbErr> {
+        let bound = |pick: fn(&(f64, f64, f64, f64)) -> f64| ActiveValue::Set(self.bounds.as_ref().map(pick));
+
+        import_run::Entity::insert(import_run::ActiveModel {
+            id: ActiveValue::NotSet,
+            source: ActiveValue::Set(source),
+            generator: ActiveValue::Set(self.generator.clone()),
+            data_timestamp: ActiveValue::Set(self.timestamp.map(|timestamp| timestamp.naive_utc())),
+            min_lat: bound(|bounds| bounds.0),
+            min_lon: bound(|bounds| bounds.1),
+            max_lat: bound(|bounds| bounds.2),
+            max_lon: bound(|bounds| bounds.3),
+            started_at: ActiveValue::Set(started_at),
+            finished_at: ActiveValue::Set(chrono::offset::Local::now().naive_local()),
+            addresses: ActiveValue::Set(Some(addresses as i64)),
+        }).exec(db).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+//! Concave hulls for approximating an area from the points inside it.
+//!
+//! The convex hull is "dug" into: an edge is replaced by two edges through the closest point inside the hull when the
+//! edge is long compared to how far away that point is. Points are planar, project coordinates before calling these.
+
+pub type Point = (f64, f64);
+
+/// Lower values follow the points more closely, `f64::INFINITY` gives the convex hull.
+pub const DEFAULT_CONCAVITY: f64 = 2.0;
+
+fn cross(o: Point, a: Point, b: Point) -> f64 {
+    (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+}
+
+fn distance(a: Point, b: Point) -> f64 {
+    (a.0 - b.0).hypot(a.1 - b.1)
+}
+
+fn segment_distance(p: Point, a: Point, b: Point) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length = dx * dx + dy * dy;
+
+    if length == 0.0 {
+        return distance(p, a);
+    }
+
+    let t = (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length).clamp(0.0, 1.0);
+
+    distance(p, (a.0 + t * dx, a.1 + t * dy))
+}
+
+/// Whether the segments properly cross, touching at an end point doesn't count.
+fn segments_cross(a: Point, b: Point, c: Point, d: Point) -> bool {
+    let d1 = cross(c, d, a);
+    let d2 = cross(c, d, b);
+    let d3 = cross(a, b, c);
+    let d4 = cross(a, b, d);
+
+    ((d1 > 0.0 && d2 < 0.0) || (d1 < 0.0 && d2 > 0.0)) && ((d3 > 0.0 && d4 < 0.0) || (d3 < 0.0 && d4 > 0.0))
+}
+
+/// Indexes of the counter-clockwise convex hull, duplicate and collinear points are left out.
+pub fn convex_hull(points: &[Point]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..points.len()).collect();
+    order.sort_by(|&a, &b| points[a].partial_cmp(&points[b]).expect("coordinates are not NaN"));
+    order.dedup_by(|a, b| points[*a] == points[*b]);
+
+    if order.len() < 3 {
+        return order;
+    }
+
+    let mut hull: Vec<usize> = Vec::with_capacity(order.len() * 2);
+
+    for pass in [&order[..], &order.iter().rev().copied().collect::<Vec<_>>()[..]] {
+        let start = hull.len();
+
+        for &i in pass {
+            while hull.len() >= start + 2 && cross(points[hull[hull.len() - 2]], points[hull[hull.len() - 1]], points[i]) <= 0.0 {
+                hull.pop();
+            }
+
+            hull.push(i);
+        }
+
+        hull.pop();
+    }
+
+    hull
+}
+
+/// Indexes of the counter-clockwise concave hull. Returns fewer than 3 indexes when the points don't span an area.
+pub fn concave_hull(points: &[Point], concavity: f64) -> Vec<usize> {
+    let mut hull = convex_hull(points);
+
+    if hull.len() < 3 {
+        return hull;
+    }
+
+    let mut used = vec![false; points.len()];
+    hull.iter().for_each(|&i| used[i] = true);
+
+    // Only the first of a set of duplicates can be dug towards, hull points are sorted first so their duplicates go
+    let mut order: Vec<usize> = (0..points.len()).collect();
+    order.sort_by(|&a, &b| points[a].partial_cmp(&points[b]).expect("coordinates are not NaN").then(used[b].cmp(&used[a])));
+
+    for pair in order.windows(2) {
+        if points[pair[0]] == points[pair[1]] {
+            used[pair[1]] = true;
+        }
+    }
+
+    let mut edge = 0;
+
+    while edge < hull.len() {
+        let a = points[hull[edge]];
+        let b = points[hull[(edge + 1) % hull.len()]];
+
+        let closest = (0..points.len())
+            .filter(|&i| !used[i])
+            .map(|i| (i, segment_distance(points[i], a, b)))
+            .min_by(|x, y| x.1.total_cmp(&y.1));
+
+        let dig = closest.is_some_and(|(i, _)| {
+            let p = points[i];
+            let nearest_end = distance(p, a).min(distance(p, b));
+
+            nearest_end > 0.0
+                && distance(a, b) / nearest_end > concavity
+                && !(0..hull.len()).any(|other| {
+                    let c = points[hull[other]];
+                    let d = points[hull[(other + 1) % hull.len()]];
+
+                    segments_cross(a, p, c, d) || segments_cross(p, b, c, d)
+                })
+        });
+
+        match closest {
+            Some((i, _)) if dig => {
+                used[i] = true;
+                hull.insert(edge + 1, i);
+            }
+            _ => edge += 1,
+        }
+    }
+
+    hull
+}
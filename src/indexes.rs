@@ -0,0 +1,184 @@
+//! Drops the secondary indexes of `node` before a bulk import and creates them again once the rows are in, which is
+//! faster than keeping every index up to date row by row. The definitions are kept in `deferred_index` until their
+//! index exists again, so an import that stops in between still gets them back from the next run.
+//!
+//! Which indexes an artifact has at the end of an import is picked with an [`IndexProfile`].
+
+use futures::{stream, StreamExt, TryStreamExt};
+use sea_orm::sea_query::{Alias, Index, OnConflict};
+use sea_orm::{ActiveValue, ConnectionTrait, DatabaseConnection, DbBackend, DbErr, EntityTrait, PaginatorTrait, Statement, TransactionTrait};
+
+use crate::entities::*;
+
+/// Drops the indexes of `node`, except the primary key, and returns how many. MySQL doesn't expose the statements
+/// that create them, there nothing is dropped.
+pub async fn defer(db: &DatabaseConnection) -> Result<usize, DbErr> {
+    let backend = db.get_database_backend();
+
+    let sql = match backend {
+        // Indexes SQLite creates for constraints have no statement
+        DbBackend::Sqlite => "SELECT name, sql FROM sqlite_master WHERE type = 'index' AND tbl_name = 'node' AND sql IS NOT NULL",
+        DbBackend::Postgres => "SELECT indexname, indexdef FROM pg_indexes WHERE tablename = 'node' AND schemaname = current_schema() \
+            AND indexname NOT IN (SELECT conname FROM pg_constraint)",
+        DbBackend::MySql => return Ok(0),
+    };
+
+    let indexes: Vec<(String, String)> = db.query_all(Statement::from_string(backend, sql))
+        .await?
+        .iter()
+        .map(|row| Ok((row.try_get_by_index(0)?, row.try_get_by_index(1)?)))
+        .collect::<Result<_, DbErr>>()?;
+
+    let transaction = db.begin().await?;
+
+    for (name, definition) in &indexes {
+        deferred_index::Entity::insert(deferred_index::ActiveModel {
+            name: ActiveValue::Set(name.clone()),
+            definition: ActiveValue::Set(definition.clone()),
+        })
+            .on_conflict(OnConflict::column(deferred_index::Column::Name).update_column(deferred_index::Column::Definition).to_owned())
+            .exec(&transaction)
+            .await?;
+
+        transaction.execute_unprepared(&format!("DROP INDEX \"{}\"", name)).await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(indexes.len())
+}
+
+/// Whether there are indexes to create again.
+pub async fn deferred(db: &DatabaseConnection) -> Result<bool, DbErr> {
+    Ok(deferred_index::Entity::find().count(db).await? > 0)
+}
+
+/// Creates the deferred indexes, each in a transaction that also forgets its definition. Postgres builds them at the
+/// same time on their own connections, SQLite one after the other.
+pub async fn restore(db: &DatabaseConnection) -> Result<(), DbErr> {
+    let deferred = deferred_index::Entity::find().all(db).await?;
+    let at_once = if db.get_database_backend() == DbBackend::Sqlite { 1 } else { deferred.len().max(1) };
+
+    stream::iter(deferred)
+        .map(|index| async move {
+            let transaction = db.begin().await?;
+
+            transaction.execute_unprepared(&index.definition).await?;
+            deferred_index::Entity::delete_by_id(index.name).exec(&transaction).await?;
+
+            transaction.commit().await
+        })
+        .buffer_unordered(at_once)
+        .try_collect()
+        .await
+}
+
+/// The indexes lookups use, by name with their columns.
+const LOOKUP: [(&str, &[&str]); 9] = [
+    ("idx-postcode", &["postcode"]),
+    ("idx-house_number", &["house_number"]),
+    ("idx-lat-lon", &["lat", "lon"]),
+    ("idx-outcode", &["outcode"]),
+    ("idx-house-name", &["house_name_normalized"]),
+    ("idx-search-key", &["search_key"]),
+    ("idx-plus-code", &["plus_code"]),
+    ("idx-grid-cell", &["grid_cell"]),
+    ("idx-h3", &["h3"]),
+];
+
+/// Created by a migration on Postgres only, see `m20261016_000021_create_covering_lookup_index`.
+const COVERING: &str = "idx-postcode-house_number-covering";
+
+/// For queries that group or filter the whole table.
+const ANALYTICS: [(&str, &[&str]); 5] = [
+    ("idx-country", &["country"]),
+    ("idx-city", &["city"]),
+    ("idx-source", &["source"]),
+    ("idx-updated-at", &["updated_at"]),
+    ("idx-admin-area", &["admin_area_id"]),
+];
+
+/// Which indexes of `node` the artifact keeps.
+#[derive(Clone, Copy, PartialEq)]
+pub enum IndexProfile {
+    /// Only the postcode, for the smallest file that can still be looked up in.
+    Minimal,
+    /// Everything lookups, reverse geocoding and the server use.
+    Lookup,
+    /// The lookup indexes plus those for statistics over the whole table.
+    Analytics,
+}
+
+impl IndexProfile {
+    pub const NAMES: [&'static str; 3] = ["lookup", "minimal", "analytics"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "minimal" => Some(Self::Minimal),
+            "lookup" => Some(Self::Lookup),
+            "analytics" => Some(Self::Analytics),
+            _ => None,
+        }
+    }
+
+    fn indexes(self) -> Vec<(&'static str, &'static [&'static str])> {
+        match self {
+            Self::Minimal => LOOKUP[..1].to_vec(),
+            Self::Lookup => LOOKUP.to_vec(),
+            Self::Analytics => LOOKUP.iter().chain(&ANALYTICS).copied().collect(),
+        }
+    }
+}
+
+/// Names of the indexes of `node`, other than the primary key.
+async fn names(db: &DatabaseConnection) -> Result<Vec<String>, DbErr> {
+    let backend = db.get_database_backend();
+
+    let sql = match backend {
+        DbBackend::Sqlite => "SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'node' AND sql IS NOT NULL",
+        DbBackend::Postgres => "SELECT indexname FROM pg_indexes WHERE tablename = 'node' AND schemaname = current_schema() \
+            AND indexname NOT IN (SELECT conname FROM pg_constraint)",
+        DbBackend::MySql => "SELECT DISTINCT index_name FROM information_schema.statistics WHERE table_name = 'node' \
+            AND table_schema = DATABASE() AND index_name <> 'PRIMARY'",
+    };
+
+    db.query_all(Statement::from_string(backend, sql))
+        .await?
+        .iter()
+        .map(|row| row.try_get_by_index(0))
+        .collect()
+}
+
+/// Creates the indexes of the profile that are missing and drops the other indexes this importer knows of. Indexes
+/// added by hand are left alone.
+pub async fn apply(db: &DatabaseConnection, profile: IndexProfile) -> Result<(), DbErr> {
+    let backend = db.get_database_backend();
+    let existing = names(db).await?;
+    let wanted = profile.indexes();
+
+    let known = LOOKUP.iter().chain(&ANALYTICS).map(|(name, _)| *name);
+
+    for name in known.filter(|name| existing.iter().any(|existing| existing == name) && !wanted.iter().any(|(wanted, _)| wanted == name)) {
+        db.execute(backend.build(&Index::drop().name(name).table(node::Entity).to_owned())).await?;
+    }
+
+    for (name, columns) in wanted.into_iter().filter(|(name, _)| !existing.iter().any(|existing| existing == name)) {
+        let mut index = Index::create().name(name).table(node::Entity).to_owned();
+
+        for column in columns {
+            index.col(Alias::new(*column));
+        }
+
+        db.execute(backend.build(&index)).await?;
+    }
+
+    if backend == DbBackend::Postgres {
+        if profile == IndexProfile::Minimal {
+            db.execute_unprepared(&format!("DROP INDEX IF EXISTS \"{}\"", COVERING)).await?;
+        } else {
+            db.execute_unprepared(&format!("CREATE INDEX IF NOT EXISTS \"{}\" ON node (postcode, house_number) INCLUDE (lat, lon, street, city)", COVERING)).await?;
+        }
+    }
+
+    Ok(())
+}
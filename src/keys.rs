@@ -0,0 +1,89 @@
+use std::error::Error;
+
+use clap::{arg, value_parser, ArgMatches, Command};
+use sea_orm::{ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use sea_orm::sea_query::Expr;
+use sea_orm_migration::MigratorTrait;
+use sha2::{Digest, Sha256};
+
+use crate::entities::*;
+use crate::migrator::Migrator;
+use crate::table::print_table;
+
+pub fn cli() -> Command {
+    Command::new("keys")
+        .about("Manages the API keys accepted by `serve --require-api-key`")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("create")
+                .about("Creates a key and prints it, it can't be retrieved afterwards")
+                .arg(arg!(--name <NAME>).required(true))
+                .arg(arg!(--"rate-limit" <PER_SECOND> "Requests per second, defaults to the server's --rate-limit").value_parser(value_parser!(f64)))
+        )
+        .subcommand(Command::new("list").about("Lists all keys"))
+        .subcommand(
+            Command::new("revoke")
+                .about("Revokes a key")
+                .arg(arg!(--name <NAME>).required(true))
+        )
+}
+
+pub fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+pub async fn run(db: &DatabaseConnection, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    Migrator::up(db, None).await?;
+
+    let now = chrono::offset::Local::now().naive_local();
+
+    match matches.subcommand() {
+        Some(("create", matches)) => {
+            let key = hex::encode(rand::random::<[u8; 24]>());
+
+            api_key::Entity::insert(api_key::ActiveModel {
+                id: ActiveValue::NotSet,
+                name: ActiveValue::Set(matches.get_one::<String>("name").expect("required in clap").clone()),
+                key_hash: ActiveValue::Set(hash_key(&key)),
+                rate_limit: ActiveValue::Set(matches.get_one::<f64>("rate-limit").copied()),
+                created_at: ActiveValue::Set(now),
+                revoked_at: ActiveValue::Set(None),
+            }).exec(db).await?;
+
+            println!("{}", key);
+        }
+        Some(("list", _)) => {
+            let rows: Vec<Vec<String>> = api_key::Entity::find()
+                .order_by_asc(api_key::Column::Name)
+                .all(db)
+                .await?
+                .into_iter()
+                .map(|key| vec![
+                    key.name,
+                    key.rate_limit.map(|limit| limit.to_string()).unwrap_or_else(|| "default".to_string()),
+                    key.created_at.to_string(),
+                    key.revoked_at.map(|at| at.to_string()).unwrap_or_default(),
+                ])
+                .collect();
+
+            print_table(&["name", "rate_limit", "created_at", "revoked_at"], &rows);
+        }
+        Some(("revoke", matches)) => {
+            let name = matches.get_one::<String>("name").expect("required in clap");
+
+            let result = api_key::Entity::update_many()
+                .col_expr(api_key::Column::RevokedAt, Expr::value(now))
+                .filter(api_key::Column::Name.eq(name))
+                .filter(api_key::Column::RevokedAt.is_null())
+                .exec(db)
+                .await?;
+
+            if result.rows_affected == 0 {
+                return Err(format!("no active key named {}", name).into());
+            }
+        }
+        _ => unreachable!("subcommand is required"),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,84 @@
+//! Bindings to the address parser of libpostal, only built with the `libpostal` feature. The library and its models
+//! have to be installed, see https://github.com/openvenues/libpostal.
+
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::sync::{Mutex, OnceLock};
+
+#[repr(C)]
+struct ParserOptions {
+    language: *mut c_char,
+    country: *mut c_char,
+}
+
+#[repr(C)]
+struct ParserResponse {
+    num_components: usize,
+    components: *mut *mut c_char,
+    labels: *mut *mut c_char,
+}
+
+#[link(name = "postal")]
+extern "C" {
+    fn libpostal_setup() -> bool;
+    fn libpostal_setup_parser() -> bool;
+    fn libpostal_get_address_parser_default_options() -> ParserOptions;
+    fn libpostal_parse_address(address: *mut c_char, options: ParserOptions) -> *mut ParserResponse;
+    fn libpostal_address_parser_response_destroy(response: *mut ParserResponse);
+}
+
+/// Set up on the first address that needs it, loading the models takes a while and a few GB of memory. `None` when
+/// they couldn't be loaded. The parser isn't documented to be thread safe, so calls are serialized.
+static PARSER: OnceLock<Option<Mutex<()>>> = OnceLock::new();
+
+fn parser() -> Option<&'static Mutex<()>> {
+    PARSER.get_or_init(|| {
+        // SAFETY: called once, before any parsing
+        if unsafe { libpostal_setup() && libpostal_setup_parser() } {
+            Some(Mutex::new(()))
+        } else {
+            println!("Warning: failed to load the libpostal models, addr:full values won't be parsed");
+            None
+        }
+    }).as_ref()
+}
+
+/// The labelled components of a free-form address, like `("road", "Dorpsstraat")` and `("house_number", "12")`.
+/// `country` is an ISO code that helps the parser with ambiguous input. Empty when the models aren't available.
+pub fn parse(address: &str, country: Option<&str>) -> Vec<(String, String)> {
+    let (Some(parser), Ok(address)) = (parser(), CString::new(address)) else {
+        return Vec::new();
+    };
+
+    let country = country.and_then(|country| CString::new(country.to_lowercase()).ok());
+    let _guard = parser.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    // SAFETY: the strings outlive the call, libpostal doesn't write to them, and the response is read before it's
+    // destroyed
+    unsafe {
+        let mut options = libpostal_get_address_parser_default_options();
+
+        if let Some(country) = &country {
+            options.country = country.as_ptr() as *mut c_char;
+        }
+
+        let response = libpostal_parse_address(address.as_ptr() as *mut c_char, options);
+
+        if response.is_null() {
+            return Vec::new();
+        }
+
+        let components = (0..(*response).num_components)
+            .map(|i| {
+                let label = CStr::from_ptr(*(*response).labels.add(i)).to_string_lossy().into_owned();
+                let value = CStr::from_ptr(*(*response).components.add(i)).to_string_lossy().into_owned();
+
+                (label, value)
+            })
+            .collect();
+
+        libpostal_address_parser_response_destroy(response);
+
+        components
+    }
+}
@@ -0,0 +1,48 @@
+//! Keeps two importers from writing to the same database at once.
+//!
+//! The lock is a row in `import_lock`, inserting it fails while another importer holds it. An importer that crashed
+//! leaves its row behind, which `--force` takes over.
+
+use sea_orm::{ActiveValue, DatabaseConnection, DbErr, EntityTrait};
+
+use crate::entities::*;
+
+const NAME: &str = "import";
+
+/// Takes the import lock. Returns the lock that's in the way when another importer holds it, unless `force` is set.
+pub async fn acquire(db: &DatabaseConnection, force: bool) -> Result<Option<import_lock::Model>, DbErr> {
+    if force {
+        release(db).await?;
+    }
+
+    let lock = import_lock::ActiveModel {
+        name: ActiveValue::Set(NAME.to_string()),
+        holder: ActiveValue::Set(holder()),
+        acquired_at: ActiveValue::Set(chrono::offset::Local::now().naive_local()),
+    };
+
+    if import_lock::Entity::insert(lock).exec(db).await.is_ok() {
+        return Ok(None);
+    }
+
+    // The insert can also fail for other reasons, only report a lock when there is one
+    match import_lock::Entity::find_by_id(NAME).one(db).await? {
+        Some(held) => Ok(Some(held)),
+        None => Err(DbErr::Custom("failed to take the import lock".to_string())),
+    }
+}
+
+pub async fn release(db: &DatabaseConnection) -> Result<(), DbErr> {
+    import_lock::Entity::delete_by_id(NAME).exec(db).await?;
+
+    Ok(())
+}
+
+fn holder() -> String {
+    let host = std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .or_else(|_| std::fs::read_to_string("/etc/hostname").map(|host| host.trim().to_string()))
+        .unwrap_or_else(|_| "unknown host".to_string());
+
+    format!("pid {} on {}", std::process::id(), host)
+}
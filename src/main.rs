@@ -1,32 +1,184 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
 use std::default::Default;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::thread::available_parallelism;
 use std::time::Duration;
 
-use clap::{arg, Command};
-use futures::future::join_all;
-use sea_orm::{ActiveValue, ConnectionTrait, ConnectOptions, Database, DatabaseConnection, DbErr, EntityTrait, Iterable};
-use sea_orm::prelude::DateTime;
+use bzip2::read::MultiBzDecoder;
+use clap::parser::ValueSource;
+use clap::{arg, value_parser, ArgAction, Command};
+use flate2::read::MultiGzDecoder;
+use futures::{stream, StreamExt, TryStreamExt};
+use h3o::Resolution;
+use sea_orm::{ActiveValue, ConnectionTrait, DatabaseConnection, DbBackend, DbErr, EntityTrait, PaginatorTrait, TransactionTrait};
 use sea_orm::sea_query::OnConflict;
+use sea_orm::prelude::DateTime;
 use sea_orm_migration::MigratorTrait;
-use tokio;
-use tokio::io::BufReader;
+use tracing_subscriber::EnvFilter;
 use xml::attribute::OwnedAttribute;
 use xml::reader::{EventReader, ParserConfig2, XmlEvent};
 use regex::Regex;
+use log::LevelFilter;
 
+use crate::baseline::Baseline;
+use crate::batch::{BatchInsert, Upsert};
+use crate::boundaries::Boundaries;
+use crate::database::PoolOptions;
 use crate::entities::*;
+use crate::download::Download;
+use crate::elevation::Dem;
+use crate::error::Error;
+use crate::full_address::KnownStreets;
+use crate::guard::OnDrop;
+use crate::header::Header;
+use crate::indexes::IndexProfile;
+use crate::merge::{Conflict, MergePolicy};
 use crate::migrator::Migrator;
+use crate::output::{ElasticOutput, Output, Preview};
+use crate::plugin::Plugin;
+use crate::precedence::Precedence;
+use crate::profile::{CountryProfile, Profiles, Rejection};
+use crate::progress::Progress;
+use crate::rejections::{MaxRejected, Rejections};
+use crate::street::Dictionaries;
+use crate::timings::Timings;
+use crate::verify::Md5Reader;
+use crate::pbf::Pbf;
+use crate::ways::NodeIndex;
 
 mod migrator;
 mod entities;
+mod error;
+mod admin;
+mod areas;
+mod autocomplete;
+mod baseline;
+mod batch;
+mod boundaries;
+mod cluster;
+mod countries;
+mod credentials;
+mod daemon;
+mod database;
+mod dedup;
+mod download;
+mod elevation;
+mod export;
+mod external;
+mod fix;
+mod format;
+mod full_address;
+mod geocode;
+mod guard;
+mod h3;
+mod header;
+mod hull;
+mod indexes;
+mod keys;
+#[cfg(feature = "libpostal")]
+mod libpostal;
+mod lock;
+mod merge;
+mod metrics;
+mod notify;
+mod output;
+mod package;
+mod pbf;
+mod plugin;
+mod poi;
+mod precedence;
+mod profile;
+mod publish;
+mod rejections;
+mod plus_code;
+mod progress;
+mod query;
+mod rollups;
+mod schema;
+mod serve;
+mod spatial;
+mod staging;
+mod storage;
+mod street;
+mod survey;
+mod table;
+mod timezones;
+mod timings;
+mod verify;
+mod voronoi;
+mod ways;
 
 fn cli() -> Command {
     Command::new("OSM postcode data importer")
-        .about("Parses OSM XML metadata file and extracts postcodes to be stored in a database\npipe the xml into stdin to process it. You can use tools like `pv` to monitor progress.")
+        .about("Parses OSM XML metadata file and extracts postcodes to be stored in a database\npipe the xml into stdin or pass it with --input to process it. You can use tools like `pv` to monitor progress.")
         // .arg(arg!(--xml <XML>))
         .arg(arg!(--fresh))
-        .arg(arg!(--db <DATABASE_URI>).default_value("sqlite://output.db"))
+        .arg(arg!(--"commit-every" <ROWS> "Number of parsed rows written per transaction").value_parser(value_parser!(u64).range(1..)).default_value("1024"))
+        .arg(arg!(--"unsafe-fast" "Skip syncing SQLite writes to disk until the import is done. Much faster, but a crash or power loss halfway can corrupt the database"))
+        .arg(arg!(--"timings-json" <PATH> "Also write the time, throughput, memory and database growth of every phase to this file").value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--force "Import even when the database says another import is running, for when an earlier import crashed").global(true))
+        .arg(arg!(--resume "Continue processing after an interrupted import, without reading any input").conflicts_with("fresh"))
+        .arg(arg!(--"low-memory" "Use smaller batches, fewer connections and temporary files instead of memory, for devices like a Raspberry Pi"))
+        .arg(arg!(--staging "Import into a staging schema and swap it in once done, so a live Postgres database keeps serving the previous data until then"))
+        .arg(arg!(--preview <COUNT> "Only parse until this many addresses qualify, print them and exit without writing to the database").value_parser(value_parser!(u64).range(1..)).conflicts_with_all(["resume", "staging", "fresh"]))
+        .arg(arg!(--baseline <SQLITE_DB> "Build the new SQLite --db from a copy of this earlier artifact, only processing the elements that changed since").value_parser(value_parser!(PathBuf)).conflicts_with_all(["fresh", "resume", "staging", "preview"]))
+        .arg(arg!(--"defer-indexes" "Drop the indexes of the nodes while parsing and create them once the rows are in, which is faster for large imports into an empty database"))
+        .arg(arg!(--"index-profile" <PROFILE> "Indexes the database keeps: lookup for everything lookups use, minimal for only the postcode, analytics for also country, city, source and update time").value_parser(IndexProfile::NAMES).default_value("lookup"))
+        .arg(arg!(--"verify-md5" "Check the --input against the md5 published next to it as <input>.md5").requires("input"))
+        .arg(arg!(--"max-age" <DAYS> "Refuse extracts whose timestamp is older than this many days").value_parser(value_parser!(u64)))
+        .arg(arg!(--daemon "Keep running and import the --input again every --interval, publishing every database that imports without errors").requires("input").conflicts_with_all(["resume", "preview", "baseline"]))
+        .arg(arg!(--interval <DURATION> "Time between the starts of --daemon runs, like 30m, 24h or 7d").value_parser(daemon::parse_interval).default_value("24h").requires("daemon"))
+        .arg(arg!(--publish <URL> "Upload the SQLite database to s3://bucket/key or gs://bucket/key once imported, compressed when the key ends in .zst. {date} is replaced with the day of the import"))
+        .arg(arg!(--"notify-url" <URL> "POST a JSON summary of the import to this URL once it succeeded or failed, with the SHA-256 of a SQLite database"))
+        .arg(arg!(--input <OSM_FILE> "Read the OSM XML from this file, URL or s3:// or gs:// object instead of stdin, decompressing .bz2 and .gz, or read a local .osm.pbf file").value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--db <DATABASE_URI>).value_parser(parse_db_uri).default_value("sqlite://output.db").global(true))
+        .arg(arg!(--"max-connections" <COUNT> "Connections kept to the database, defaults to twice the cores for SQLite and 32 for Postgres and MySQL").value_parser(value_parser!(u32).range(1..)).global(true))
+        .arg(arg!(--"acquire-timeout" <SECONDS> "How long to wait for a free connection before failing").value_parser(value_parser!(u64)).default_value("10").global(true))
+        .arg(arg!(--"connect-timeout" <SECONDS> "How long to wait for Postgres or MySQL to accept a connection").value_parser(value_parser!(u64)).default_value("10").global(true))
+        .arg(arg!(--"idle-timeout" <SECONDS> "Close connections unused for this long, defaults to 600 for Postgres and MySQL and never for SQLite").value_parser(value_parser!(u64)).global(true))
+        .arg(arg!(--"write-retries" <COUNT> "How many times batches are written again after losing the connection to the database, over the whole import").value_parser(value_parser!(u32)).default_value("10").global(true))
+        .arg(arg!(--"sqlx-log" <LEVEL> "Log every SQL statement to stderr at this level").value_parser(["off", "error", "warn", "info", "debug", "trace"]).default_value("off").global(true))
+        .arg(arg!(--plugin <WASM> "WASM module used to transform every element").value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--"metrics-listen" <ADDRESS> "Expose Prometheus metrics on this address while importing").value_parser(value_parser!(SocketAddr)))
+        .arg(arg!(--"merge-policy" <COLUMN_POLICY> "How a re-imported element is combined with the stored row, like `city=non-null`. Policies are newest (default), non-null and longest").action(ArgAction::Append))
+        .arg(arg!(--"on-conflict" <POLICY> "What happens to elements that are already stored: update them with --merge-policy, ignore them or fail the import").value_parser(Conflict::NAMES).default_value("update"))
+        .arg(arg!(--"poi-postcodes" "Also import the postcodes of named places without a full address, like shops, into poi_postcode"))
+        .arg(arg!(--"grid-cells" "Also store the 11 digit plus code of every address, a stable reference to a cell of about 3 by 3 meters").global(true))
+        .arg(arg!(--"h3-resolution" <RES> "Also store the H3 cell of every address at this resolution, from 0 (continents) to 15 (about a square meter)").value_parser(value_parser!(u8).range(0..=15)).global(true))
+        .arg(arg!(--"keep-raw-tags" "Also store every tag of each address as JSON in raw_tags, to debug how a row was normalized. Makes the database a lot larger"))
+        .arg(arg!(--strict "Fail the import on an address without a country, with a postcode that isn't valid in its country or with an unknown province, instead of skipping it or importing it as is"))
+        .arg(arg!(--"max-rejected" <PERCENT> "Fail the staged import before it's swapped in when more than this percentage of the addresses doesn't validate, or of those rejected for one reason like `postcode=1`, to tell a broken extract from the usual mistakes").value_parser(MaxRejected::parse).action(ArgAction::Append).requires("staging"))
+        .arg(arg!(--quarantine "Keep the addresses that don't validate in the quarantine table with their tags and why, instead of dropping them"))
+        .arg(arg!(--"max-drop" <PERCENT> "Check that the database didn't lose more than this percentage of its addresses since the previous import, to catch a truncated download").value_parser(parse_percentage))
+        .arg(arg!(--"on-drop" <ACTION> "What happens when the addresses dropped by more than --max-drop: fail the import before staging tables are swapped in, or only warn").value_parser(OnDrop::NAMES).default_value("abort").requires("max-drop"))
+        .arg(arg!(--ways "Also import addresses tagged on ways, like buildings. Keeps the location of every node in memory"))
+        .arg(arg!(--"admin-areas" "Also import administrative boundaries into admin_area and link every address to the smallest one it's in. Keeps the location of every node and the nodes of every way in memory"))
+        .arg(arg!(--country <ISO_CODE> "Country of elements without an addr:country tag, selects the rules they're imported with").value_parser(countries::parse_country))
+        .arg(arg!(--profiles <JSON> "Additional country profiles").value_parser(value_parser!(PathBuf)).global(true))
+        .arg(arg!(--"street-abbreviations" <JSON> "Additional or replaced dictionaries of abbreviated street types, by language").value_parser(value_parser!(PathBuf)).global(true))
+        .arg(arg!(--"source-precedence" <SOURCES> "Which source wins when duplicates are merged: node, way, external or the --source of an external dataset, best first").default_value(precedence::DEFAULT).global(true))
+        .arg(arg!(--"merge-distance" <METERS> "Merge nodes with the same address within this distance of each other, merging is off by default").value_parser(value_parser!(f64)).default_value("0"))
+        .arg(arg!(--areas <STRATEGY> "How postcode areas are derived from the addresses").value_parser(areas::Strategy::NAMES).default_value("concave-hull"))
+        .arg(arg!(--"country-boundaries" <GEOJSON> "Country boundaries named by their ISO 3166-1 alpha-2 code, like Natural Earth's admin 0 countries, to clip Voronoi cells to").value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--"country-property" <NAME> "The property of --country-boundaries with the country code").default_value(areas::DEFAULT_PROPERTY).requires("country-boundaries"))
+        .arg(arg!(--dem <DIR> "Directory of SRTM .hgt or GeoTIFF elevation tiles, like the Copernicus DEM, to fill the elevation_m of every address from").value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--timezones <GEOJSON> "Timezone boundaries with a tzid property, like timezone-boundary-builder's combined.json, to fill postcode_timezone from").value_parser(value_parser!(PathBuf)))
+        .subcommand(export::cli())
+        .subcommand(query::cli())
+        .subcommand(geocode::cli())
+        .subcommand(geocode::reverse_cli())
+        .subcommand(serve::cli())
+        .subcommand(package::cli())
+        .subcommand(fix::cli())
+        .subcommand(keys::cli())
+        .subcommand(survey::cli())
+        .subcommand(schema::cli())
+        .subcommand(external::cli())
 }
 
 async fn build_db(db: Arc<DatabaseConnection>, fresh: bool) -> Result<(), DbErr> {
@@ -49,198 +201,910 @@ async fn build_db(db: Arc<DatabaseConnection>, fresh: bool) -> Result<(), DbErr>
     Ok(())
 }
 
-fn node_ready(node: &node::ActiveModel) -> bool {
-    node.id.is_set() && node.postcode.is_set() && node.street.is_set()
+/// A database, or an Elasticsearch index to import into.
+fn parse_db_uri(value: &str) -> Result<String, String> {
+    if value.starts_with("elastic://") || value.starts_with("elastics://") {
+        return match ElasticOutput::from_uri(value) {
+            Some(_) => Ok(value.to_string()),
+            None => Err("Elasticsearch URIs look like elastic://host:port/index".to_string()),
+        };
+    }
+
+    database::check_uri(value).map(|_| value.to_string())
+}
+
+fn parse_percentage(value: &str) -> Result<f64, String> {
+    match value.trim_end_matches('%').parse::<f64>() {
+        Ok(percentage) if (0.0..=100.0).contains(&percentage) => Ok(percentage),
+        _ => Err(format!("{} isn't a percentage between 0 and 100", value)),
+    }
+}
+
+/// Normalizes a postcode entered in a lookup, which isn't tied to a country.
+pub fn normalize_postcode(postcode: &str) -> String {
+    profile::profiles().normalize_any(postcode)
+}
+
+fn apply_plugin(plugin: &mut Plugin, node: &mut node::ActiveModel, tags: &BTreeMap<String, String>) -> bool {
+    let (ActiveValue::Set(lat), ActiveValue::Set(lon)) = (&node.lat, &node.lon) else {
+        return false;
+    };
+
+    let address = match plugin.transform(tags, *lat, *lon) {
+        Ok(Some(address)) => address,
+        Ok(None) => return false,
+        Err(e) => {
+            println!("Warning: plugin failed on node {:?}: {}", node.id, e);
+            return false;
+        }
+    };
+
+    if let Some(postcode) = address.postcode {
+        node.postcode = ActiveValue::Set(postcode);
+    }
+
+    if address.city.is_some() { node.city = ActiveValue::Set(address.city); }
+    if address.country.is_some() { node.country = ActiveValue::Set(address.country); }
+    if address.street.is_some() { node.street = ActiveValue::Set(address.street); }
+    if address.house_number.is_some() { node.house_number = ActiveValue::Set(address.house_number); }
+    if address.province.is_some() { node.province = ActiveValue::Set(address.province); }
+
+    true
+}
+
+/// How elements are finished into rows.
+#[derive(Clone, Copy)]
+struct FinishOptions {
+    /// Import addresses tagged on ways, located by their nodes
+    ways: bool,
+    grid_cells: bool,
+    h3_resolution: Option<Resolution>,
+    /// Store every tag of the element, for debugging the normalization
+    keep_raw_tags: bool,
+    /// Fail on addresses that would otherwise be rejected or imported as they are
+    strict: bool,
+}
+
+/// Fails `--strict` imports on an address without a country, with a postcode that doesn't match its country or with a
+/// province that doesn't map to a code.
+fn check_strict(node: &node::ActiveModel, country: Option<&str>, profile: &CountryProfile) -> Result<(), Error> {
+    let ActiveValue::Set(postcode) = &node.postcode else {
+        return Ok(());
+    };
+
+    let element = match node.id {
+        ActiveValue::Set(id) if id < 0 => format!("way {}", -id),
+        ActiveValue::Set(id) => format!("node {}", id),
+        _ => "an element without id".to_string(),
+    };
+
+    let Some(country) = country else {
+        return Err(Error::Invalid(format!("{} with postcode {:?} has no addr:country and there's no --country", element, postcode)));
+    };
+
+    if profile.normalize_postcode(postcode).is_none() {
+        return Err(Error::Invalid(format!("{} has postcode {:?}, which isn't valid in {}", element, postcode, country)));
+    }
+
+    if let ActiveValue::Set(Some(province)) = &node.province {
+        if !profile.maps_province(province) {
+            return Err(Error::Invalid(format!("{} has province {:?}, which isn't one of the provinces of {}", element, province, country)));
+        }
+    }
+
+    Ok(())
+}
+
+/// What became of an element.
+enum Finished {
+    Row(node::ActiveModel),
+    /// An address with a postcode that didn't validate
+    Rejected(node::ActiveModel, Rejection),
+    /// Not an address
+    Skipped,
+}
+
+fn finish_node(plugin: &mut Option<Plugin>, mut node: node::ActiveModel, tags: &BTreeMap<String, String>, options: FinishOptions) -> Result<Finished, Error> {
+    let had_postcode = node.postcode.is_set();
+    let reject = |node, rejection| if had_postcode { Finished::Rejected(node, rejection) } else { Finished::Skipped };
+
+    if let Some(plugin) = plugin {
+        if !apply_plugin(plugin, &mut node, tags) {
+            return Ok(reject(node, Rejection::Plugin));
+        }
+    }
+
+    let country = match &node.country {
+        ActiveValue::Set(country) => country.clone(),
+        _ => None,
+    };
+    let profile = profile::profiles().get(country.as_deref());
+
+    if options.strict {
+        check_strict(&node, country.as_deref(), profile)?;
+    }
+
+    if let Err(rejection) = profile.apply(&mut node) {
+        return Ok(reject(node, rejection));
+    }
+
+    if let (ActiveValue::Set(lat), ActiveValue::Set(lon)) = (&node.lat, &node.lon) {
+        node.plus_code = ActiveValue::Set(Some(plus_code::encode(*lat, *lon)));
+
+        if options.grid_cells {
+            node.grid_cell = ActiveValue::Set(Some(plus_code::grid_cell(*lat, *lon)));
+        }
+
+        if let Some(resolution) = options.h3_resolution {
+            node.h3 = ActiveValue::Set(h3::cell(*lat, *lon, resolution));
+        }
+    }
+
+    if options.keep_raw_tags {
+        node.raw_tags = ActiveValue::Set(Some(serde_json::json!(tags)));
+    }
+
+    Ok(Finished::Row(node))
+}
+
+/// Finishes a node, or a way when `way_refs` is set, once its address is completed. Ways are only imported when
+/// there's a node index to locate them.
+fn finish_element(plugin: &mut Option<Plugin>, mut node: node::ActiveModel, tags: &BTreeMap<String, String>, way_refs: Option<&[i64]>, node_index: &mut Option<NodeIndex>, options: FinishOptions) -> Result<Finished, Error> {
+    // The nodes are also kept for admin areas
+    let Some(index) = node_index.as_mut().filter(|_| options.ways) else {
+        return if way_refs.is_none() { finish_node(plugin, node, tags, options) } else { Ok(Finished::Skipped) };
+    };
+
+    match way_refs {
+        Some(refs) => {
+            let Some((lat, lon, at_entrance)) = index.locate(refs) else {
+                return Ok(Finished::Skipped);
+            };
+
+            node.lat = ActiveValue::Set(lat);
+            node.lon = ActiveValue::Set(lon);
+
+            if at_entrance {
+                node.entrance = ActiveValue::Set(Some("main".to_string()));
+            }
+        }
+        None => {
+            if let (ActiveValue::Set(id), ActiveValue::Set(Some(entrance))) = (&node.id, &node.entrance) {
+                if entrance == "main" {
+                    index.mark_main_entrance(*id);
+                }
+            }
+        }
+    }
+
+    finish_node(plugin, node, tags, options)
+}
+
+fn new_element(attribute_map: &ParsedAttributeMap, now: DateTime, country: Option<String>, province: Option<String>) -> node::ActiveModel {
+    node::ActiveModel {
+        id: attribute_map.id.map_or(ActiveValue::NotSet, ActiveValue::Set),
+        lat: attribute_map.lat.map_or(ActiveValue::NotSet, ActiveValue::Set),
+        lon: attribute_map.lon.map_or(ActiveValue::NotSet, ActiveValue::Set),
+        version: attribute_map.version.map_or(ActiveValue::NotSet, ActiveValue::Set),
+        updated_at: attribute_map.timestamp.map_or(ActiveValue::Set(now), ActiveValue::Set),
+        city: ActiveValue::Set(None),
+        country: ActiveValue::Set(country),
+        postcode: ActiveValue::NotSet,
+        house_number: ActiveValue::Set(None),
+        house_name: ActiveValue::Set(None),
+        house_name_normalized: ActiveValue::Set(None),
+        search_key: ActiveValue::Set(None),
+        postcode_extension: ActiveValue::Set(None),
+        unit: ActiveValue::Set(None),
+        outcode: ActiveValue::Set(None),
+        incode: ActiveValue::Set(None),
+        block_number: ActiveValue::Set(None),
+        neighbourhood: ActiveValue::Set(None),
+        quarter: ActiveValue::Set(None),
+        entrance: ActiveValue::Set(None),
+        street: ActiveValue::Set(None),
+        province: ActiveValue::Set(province),
+        source: ActiveValue::Set(None),
+        source_date: ActiveValue::Set(None),
+        deleted_at: ActiveValue::Set(None),
+        superseded_by: ActiveValue::Set(None),
+        source_rank: ActiveValue::Set(0),
+        plus_code: ActiveValue::Set(None),
+        grid_cell: ActiveValue::Set(None),
+        h3: ActiveValue::Set(None),
+        raw_tags: ActiveValue::Set(None),
+        qa_note: ActiveValue::Set(None),
+        elevation_m: ActiveValue::Set(None),
+        admin_area_id: ActiveValue::Set(None),
+    }
+}
+
+fn parse_attributes(attributes: &[OwnedAttribute]) -> Result<ParsedAttributeMap, Error> {
+    let mut parsed = ParsedAttributeMap::default();
+    let invalid = |name: &str, value: &str| Error::Input(format!("invalid {} attribute: {}", name, value));
+
+    for OwnedAttribute { name, value } in attributes {
+        match name.local_name.to_string().as_str() {
+            "id" => {parsed.id = Some(value.parse().map_err(|_| invalid("id", value))?)},
+            "lat" => {parsed.lat = Some(value.parse().map_err(|_| invalid("lat", value))?)},
+            "lon" => {parsed.lon = Some(value.parse().map_err(|_| invalid("lon", value))?)},
+            "version" => {parsed.version = Some(value.parse().map_err(|_| invalid("version", value))?)},
+            "timestamp" => {parsed.timestamp = Some(DateTime::from_str(&value.to_string()).unwrap_or_default())},
+            "visible" => {parsed.visible = Some(value == "true")},
+            _ => {},
+            // v => {println!("Warning: skipped node key: {}", v);}
+        }
+    }
+
+    Ok(parsed)
 }
 
 #[derive(Debug, Clone)]
 enum ParsedElementEvent {
+    /// The root element of XML
+    Header(Header),
+    Bounds(Vec<OwnedAttribute>),
     Node(ParsedAttributeMap),
+    Way(ParsedAttributeMap),
+    Relation(ParsedAttributeMap),
+    NodeRef(i64),
+    /// The type, id and role of a member of a relation
+    Member(String, i64, String),
     Tag(String, String),
+    /// The location of the way, already resolved from its nodes, and whether that's its main entrance
+    WayLocation(f64, f64, bool),
 }
 unsafe impl Send for ParsedElementEvent {}
 
 #[derive(Default, Debug, Clone, Copy)]
 struct ParsedAttributeMap {
-    id: Option<u64>,
+    id: Option<i64>,
     lat: Option<f64>,
     lon: Option<f64>,
     version: Option<i32>,
     timestamp: Option<DateTime>,
+    visible: Option<bool>,
 }
 
-async fn parse_file(db: Arc<DatabaseConnection>) -> std::io::Result<()> {
-    // let parser = match path {
-    //     Some(path) => EventReader::new(BufReader::new(File::open(path)?)),
-    //     None => {
-    //         let stdin = io::stdin();
-    //
-    //         EventReader::new(stdin.lock())
-    //     }
-    // };
-    let now = chrono::offset::Local::now().naive_local();
-    let re_addr = Regex::new("^addr:").unwrap();
+/// The id and version of an element whose last version is deleted.
+fn deleted_version(node: &node::ActiveModel) -> Option<(i64, i32)> {
+    match (&node.id, &node.version) {
+        (ActiveValue::Set(id), ActiveValue::Set(version)) => Some((*id, *version)),
+        _ => None,
+    }
+}
+
+/// Whether the baseline already has a row from this version of the element.
+fn unchanged(baseline: &mut Option<Baseline>, node: &node::ActiveModel) -> bool {
+    match (baseline, deleted_version(node)) {
+        (Some(baseline), Some((id, version))) => baseline.unchanged(id, version),
+        _ => false,
+    }
+}
+
+/// Marks a written or deleted row as still in the extract of a differential build.
+fn seen(baseline: &mut Option<Baseline>, id: i64) {
+    if let Some(baseline) = baseline {
+        baseline.seen(id);
+    }
+}
+
+/// How the input is parsed and written.
+struct ParseOptions {
+    default_country: Option<String>,
+    commit_every: usize,
+    pending_writes: usize,
+    max_age: Option<chrono::Duration>,
+    /// Where the postcodes of named places without a full address are written, when importing them
+    poi_postcodes: Option<Arc<DatabaseConnection>>,
+    /// Where the areas of administrative boundaries are written, when importing them
+    admin_areas: Option<Arc<DatabaseConnection>>,
+    finish: FinishOptions,
+    /// Fail when more than these percentages of the addresses are rejected
+    max_rejected: Vec<MaxRejected>,
+    /// Where rejected addresses are kept, when quarantining them
+    quarantine: Option<Arc<DatabaseConnection>>,
+    /// The artifact a differential build starts from
+    baseline: Option<Baseline>,
+}
+
+/// The `--country-boundaries` to clip Voronoi cells to, named by the `--country-property` of every feature.
+fn country_boundaries(matches: &clap::ArgMatches) -> Result<Option<Boundaries>, Error> {
+    let property = matches.get_one::<String>("country-property").expect("defaulted in clap");
+
+    matches.get_one::<PathBuf>("country-boundaries")
+        .map(|path| Boundaries::load(path, property))
+        .transpose()
+        .map_err(|e| Error::Usage(format!("invalid --country-boundaries: {}", e)))
+}
+
+/// What's imported: OSM XML, read as it streams in, or a PBF file, which is read more than once. The reader of a PBF
+/// is the one the import itself reads from.
+enum Input {
+    Xml(Box<dyn Read>),
+    Pbf(Pbf, Box<dyn Read>),
+}
+
+/// The input to import, the `--input` file or URL or otherwise stdin. Inputs ending in `.bz2` or `.gz` are
+/// decompressed, after checking the published checksum of the compressed file when `verify_md5` is set.
+fn input(matches: &clap::ArgMatches, verify_md5: bool) -> Result<Input, Error> {
+    let Some(path) = matches.get_one::<PathBuf>("input") else {
+        return Ok(Input::Xml(Box::new(std::io::stdin())));
+    };
+
+    let remote = path.to_str().filter(|path| download::is_remote(path));
+    let pbf = path.extension().is_some_and(|extension| extension == "pbf");
+
+    if let (true, Some(url)) = (pbf, remote) {
+        return Err(Error::Usage(format!("PBF files are read more than once, download {} first", url)));
+    }
+
+    let reader: Box<dyn Read> = match remote {
+        Some(url) => Box::new(Download::start(url.to_string())),
+        None => Box::new(File::open(path).map_err(|e| Error::Input(format!("{}: {}", path.display(), e)))?),
+    };
+    let reader: Box<dyn Read> = if verify_md5 {
+        Box::new(Md5Reader::new(reader, verify::published_md5(path)?))
+    } else {
+        reader
+    };
 
+    if pbf {
+        return Ok(Input::Pbf(Pbf::open(path)?, reader));
+    }
+
+    Ok(Input::Xml(match path.extension().and_then(|extension| extension.to_str()) {
+        Some("bz2") => Box::new(MultiBzDecoder::new(reader)),
+        Some("gz") => Box::new(MultiGzDecoder::new(reader)),
+        _ => reader,
+    }))
+}
+
+/// The events of OSM XML, starting with its root element. Members of relations are only read when `members` is set.
+fn xml_events(input: Box<dyn Read>, members: bool) -> impl Iterator<Item = Result<ParsedElementEvent, Error>> {
     let parser_config = ParserConfig2::new()
         .trim_whitespace(true)
         .ignore_comments(true)
         .cdata_to_characters(false);
 
-    let parser_buffer = std::io::BufReader::with_capacity(10_000_000, std::io::stdin());
+    let parser_buffer = std::io::BufReader::with_capacity(10_000_000, input);
     let parser = EventReader::new_with_config(parser_buffer, parser_config);
+    let mut root = true;
+
+    parser.into_iter().filter_map(move |raw_event| {
+        let (name, attributes) = match raw_event {
+            Ok(XmlEvent::StartElement { name, attributes, .. }) => (name, attributes),
+            Ok(_) => return None,
+            Err(e) => return Some(Err(e.into())),
+        };
+
+        if std::mem::take(&mut root) {
+            return Some(Header::from_root(&name.local_name, &attributes).map(ParsedElementEvent::Header));
+        }
+
+        Some(Ok(match name.to_string().as_str() {
+            "node" => return Some(parse_attributes(&attributes).map(ParsedElementEvent::Node)),
+            "way" => return Some(parse_attributes(&attributes).map(ParsedElementEvent::Way)),
+            "relation" => return Some(parse_attributes(&attributes).map(ParsedElementEvent::Relation)),
+            "member" if members => {
+                let attribute = |key: &str| attributes.iter().find(|attribute| attribute.name.local_name == key).map(|attribute| attribute.value.clone());
+
+                let (Some(kind), Some(Ok(id))) = (attribute("type"), attribute("ref").map(|id| id.parse())) else {
+                    return None;
+                };
+
+                ParsedElementEvent::Member(kind, id, attribute("role").unwrap_or_default())
+            },
+            "bounds" => ParsedElementEvent::Bounds(attributes),
+            "nd" => {
+                let node_ref = attributes.iter()
+                    .find(|attribute| attribute.name.local_name == "ref")
+                    .and_then(|attribute| attribute.value.parse().ok())?;
+
+                ParsedElementEvent::NodeRef(node_ref)
+            },
+            "tag" => {
+                let mut tag_key = None;
+                let mut tag_value = None;
+
+                for OwnedAttribute{name, value} in &attributes {
+                    match name.local_name.to_string().as_str() {
+                        "k" => tag_key = Some(value.clone()),
+                        "v" => tag_value = Some(value.clone()),
+                        v => {println!("Warning: malformed tag key: {}", v);}
+                    };
+                }
+
+                ParsedElementEvent::Tag(tag_key?, tag_value?)
+            },
+            _ => return None,
+        }))
+    })
+}
+
+/// Counts a rejected element and quarantines it when that's enabled, returning the row of an element that wasn't.
+async fn reject(finished: Finished, rejections: &mut Rejections, quarantined: &mut Option<BatchInsert<Upsert<quarantine::ActiveModel>>>, tags: &BTreeMap<String, String>, now: DateTime) -> Result<Option<node::ActiveModel>, Error> {
+    match finished {
+        Finished::Row(node) => Ok(Some(node)),
+        Finished::Rejected(node, rejection) => {
+            rejections.add(rejection);
+
+            if let (Some(quarantined), Some(row)) = (quarantined, rejections::quarantine_row(&node, rejection, tags, now)) {
+                quarantined.push(row).await?;
+            }
+
+            Ok(None)
+        }
+        Finished::Skipped => Ok(None),
+    }
+}
+
+/// Parses the input into the output, returning the header of the file.
+async fn parse_file(input: Input, output: Output, mut plugin: Option<Plugin>, options: ParseOptions) -> Result<Header, Error> {
+    let ParseOptions { default_country, commit_every, pending_writes, max_age, poi_postcodes, admin_areas, finish, max_rejected, quarantine, mut baseline } = options;
+    let now = chrono::offset::Local::now().naive_local();
+    let re_addr = Regex::new("^addr:").unwrap();
 
     let mut current_node: node::ActiveModel = Default::default();
+    let mut current_tags = BTreeMap::new();
+    let mut current_name = None;
+    let mut current_full = None;
+
+    // Previews are written as a single batch, to keep the order of the file
+    let flush_interval = output.limit().is_none().then_some(FLUSH_INTERVAL);
+    let mut batches = BatchInsert::new(output.clone(), commit_every, pending_writes, flush_interval);
+    let mut poi_batches = poi_postcodes.map(|db| {
+        let on_conflict = OnConflict::column(poi_postcode::Column::Id).update_columns([poi_postcode::Column::Name, poi_postcode::Column::Postcode, poi_postcode::Column::Lat, poi_postcode::Column::Lon]).to_owned();
+
+        BatchInsert::new(Upsert::new(db.as_ref().clone(), Some(on_conflict)), commit_every, pending_writes, flush_interval)
+    });
+    let mut pois = 0;
+
+    let mut rejections = Rejections::default();
+    let mut quarantined = match quarantine {
+        Some(db) => {
+            // Only the rejections of the latest import are kept
+            quarantine::Entity::delete_many().exec(db.as_ref()).await?;
 
-    const BUFFER_SIZE: usize = 1024;
-    let mut buffer = Vec::with_capacity(BUFFER_SIZE);
-    let mut futures = Vec::new();
+            let on_conflict = OnConflict::column(quarantine::Column::Id).update_columns([quarantine::Column::Reason, quarantine::Column::Country, quarantine::Column::Postcode, quarantine::Column::Lat, quarantine::Column::Lon, quarantine::Column::Tags, quarantine::Column::QuarantinedAt]).to_owned();
+
+            Some(BatchInsert::new(Upsert::new(db.as_ref().clone(), Some(on_conflict)), commit_every, pending_writes, flush_interval))
+        }
+        None => None,
+    };
 
     let mut current_province = None;
-    let mut current_country = None;
-
-    for raw_event in parser {
-        if let Ok(XmlEvent::StartElement { name, attributes, .. }) = raw_event {
-            if buffer.len() >= BUFFER_SIZE {
-                let my_db = db.clone();
-
-                let future = async move {
-                    node::Entity::insert_many(buffer)
-                        .on_conflict(
-                            OnConflict::column(node::Column::Id)
-                                .update_columns(node::Column::iter())
-                                .to_owned()
-                        ).exec::<DatabaseConnection>(my_db.as_ref()).await
-                };
+    let mut current_country = default_country;
+
+    // Set while inside a way, with the ids of its nodes
+    let mut current_way: Option<Vec<i64>> = None;
+    // Set once the way is located, by the PBF reader
+    let mut current_located = false;
+    let mut admin = admin_areas.as_ref().map(|_| admin::Collector::default());
+
+    // Rows that qualified, for outputs with a limit
+    let mut finished = 0;
+    // Read from the root element of XML, or the header block of PBF
+    let mut header = None;
+    // Deleted versions in full history files are `visible="false"`
+    let mut current_visible = true;
+
+    // Streets tagged with a house number, to tell which street lines end in their house number
+    let mut known_streets = KnownStreets::default();
 
-                futures.push(tokio::spawn(future));
+    // XML is located as it streams past, PBF ways arrive located from an index of their nodes built beforehand
+    let mut node_index = None;
+    let mut pbf_index = None;
 
-                buffer = Vec::with_capacity(BUFFER_SIZE);
+    let events: Box<dyn Iterator<Item = Result<ParsedElementEvent, Error>>> = match input {
+        Input::Xml(input) => {
+            node_index = (finish.ways || admin_areas.is_some()).then(NodeIndex::default);
+
+            Box::new(xml_events(input, admin_areas.is_some()))
+        }
+        Input::Pbf(pbf, reader) => {
+            if let Some(max_age) = max_age {
+                verify::check_freshness(pbf.header.timestamp, max_age)?;
+            }
+
+            header = Some(pbf.header.clone());
+
+            let ways = match (admin_areas.is_some(), finish.ways, plugin.is_some()) {
+                (true, _, _) => Some(pbf::Ways::All),
+                (false, true, true) => Some(pbf::Ways::Tagged),
+                (false, true, false) => Some(pbf::Ways::WithPostcode),
+                (false, false, _) => None,
+            };
+
+            if let Some(ways) = ways {
+                println!("Indexing the nodes of ways");
+                pbf_index = Some(Arc::new(pbf.index(ways)?));
             }
 
-            if futures.len() >= 128 {
-                println!("Draining write queue...");
-                join_all(futures.drain(..)).await;
+            let selection = pbf::Selection { locate: finish.ways, members: admin_areas.is_some() };
+
+            Box::new(pbf.events(reader, pbf_index.clone(), selection))
+        }
+    };
+
+    for event in events {
+        let event = event?;
+
+        match event {
+            ParsedElementEvent::Header(root) => {
+                if let Some(max_age) = max_age {
+                    verify::check_freshness(root.timestamp, max_age)?;
+                }
 
-                futures = Vec::new();
+                header = Some(root);
             }
+            ParsedElementEvent::Bounds(attributes) => {
+                if let Some(header) = &mut header {
+                    header.add_bounds(&attributes);
+                }
+            }
+            ParsedElementEvent::Node(_) | ParsedElementEvent::Way(_) | ParsedElementEvent::Relation(_) => {
+                metrics::PARSED_ELEMENTS.inc();
+
+                if let Some(admin) = &mut admin {
+                    if let (Some(refs), ActiveValue::Set(id)) = (&current_way, &current_node.id) {
+                        admin.add_way(-id, refs);
+                    }
+
+                    admin.end_element(current_visible);
+                }
 
-            let event = match name.to_string().as_str() {
-                "node" => ParsedElementEvent::Node({
-                    let mut parsed = ParsedAttributeMap::default();
-
-                    for OwnedAttribute { name, value } in &attributes {
-                        match name.local_name.to_string().as_str() {
-                            "id" => {parsed.id = Some(value.parse().unwrap())},
-                            "lat" => {parsed.lat = Some(value.parse().unwrap())},
-                            "lon" => {parsed.lon = Some(value.parse().unwrap())},
-                            "version" => {parsed.version = Some(value.parse().unwrap())},
-                            "timestamp" => {parsed.timestamp = Some(DateTime::from_str(&value.to_string()).unwrap_or_default())},
-                            _ => {},
-                            // v => {println!("Warning: skipped node key: {}", v);}
+                let next_id = match &event {
+                    ParsedElementEvent::Node(attribute_map) => attribute_map.id,
+                    ParsedElementEvent::Way(attribute_map) => attribute_map.id.map(|id| -id),
+                    _ => None,
+                };
+
+                // Full history files list every version of an element in order, only the last one counts
+                let superseded = matches!(current_node.id, ActiveValue::Set(id) if Some(id) == next_id);
+
+                // Elements the baseline already has at this version are skipped like superseded ones
+                if superseded || (current_visible && unchanged(&mut baseline, &current_node)) {
+                    current_node = Default::default();
+                } else if !current_visible {
+                    if let Some(key) = deleted_version(&std::mem::take(&mut current_node)) {
+                        seen(&mut baseline, key.0);
+
+                        if let Some(poi_batches) = &mut poi_batches {
+                            poi_batches.delete(key.0).await?;
                         }
+
+                        batches.delete(key).await.map_err(write_error)?;
                     }
+                } else {
+                    let poi = poi_batches.as_ref().and_then(|_| poi::from_element(&current_node, current_name.as_deref()));
 
-                    parsed
-                }),
-                "tag" => {
-                    let mut tag_key = None;
-                    let mut tag_value = None;
-
-                    for OwnedAttribute{name, value} in &attributes {
-                        match name.local_name.to_string().as_str() {
-                            "k" => tag_key = Some(value.clone()),
-                            "v" => tag_value = Some(value.clone()),
-                            v => {println!("Warning: malformed tag key: {}", v);}
-                        };
+                    full_address::complete(&mut current_node, current_full.as_deref(), &mut known_streets);
+
+                    let finished_element = finish_element(&mut plugin, std::mem::take(&mut current_node), &current_tags, current_way.as_deref().filter(|_| !current_located), &mut node_index, finish)?;
+
+                    if let Some(node) = reject(finished_element, &mut rejections, &mut quarantined, &current_tags, now).await? {
+                        if let ActiveValue::Set(id) = node.id {
+                            seen(&mut baseline, id);
+                        }
+
+                        batches.push(node).await.map_err(write_error)?;
+                        finished += 1;
+                    } else if let (Some(poi_batches), Some(poi)) = (&mut poi_batches, poi) {
+                        poi_batches.push(poi).await?;
+                        pois += 1;
                     }
+                }
+
+                current_tags.clear();
+                current_name = None;
+                current_full = None;
+
+                // Previews stop at the element after the last row they show
+                if output.limit().is_some_and(|limit| finished >= limit) {
+                    break;
+                }
+
+                current_visible = match &event {
+                    ParsedElementEvent::Node(attribute_map) | ParsedElementEvent::Way(attribute_map) | ParsedElementEvent::Relation(attribute_map) => attribute_map.visible != Some(false),
+                    _ => true,
+                };
+
+                current_located = false;
+                (current_node, current_way) = match event {
+                    ParsedElementEvent::Node(attribute_map) => {
+                        if let (Some(index), Some(id), Some(lat), Some(lon)) = (&mut node_index, attribute_map.id, attribute_map.lat, attribute_map.lon) {
+                            index.add(id, lat, lon);
+                        }
 
-                    if tag_key.is_none() || tag_value.is_none() {
-                        continue;
+                        (new_element(&attribute_map, now, current_country.clone(), current_province.clone()), None)
                     }
+                    // Way ids overlap with node ids, they're stored negated
+                    ParsedElementEvent::Way(attribute_map) => {
+                        let attribute_map = ParsedAttributeMap { id: attribute_map.id.map(|id| -id), ..attribute_map };
 
-                    ParsedElementEvent::Tag(tag_key.unwrap(), tag_value.unwrap())
-                },
-                _ => continue,
-            };
+                        (new_element(&attribute_map, now, current_country.clone(), current_province.clone()), Some(Vec::new()))
+                    }
+                    ParsedElementEvent::Relation(attribute_map) => {
+                        if let (Some(admin), Some(id)) = (&mut admin, attribute_map.id) {
+                            admin.start_relation(id);
+                        }
 
-            match event {
-                ParsedElementEvent::Node(attribute_map) => {
-                    if node_ready(&current_node) {
-                        buffer.push(current_node);
+                        (Default::default(), None)
                     }
+                    _ => (Default::default(), None),
+                };
+            }
+            ParsedElementEvent::NodeRef(node_ref) => {
+                if let Some(refs) = &mut current_way {
+                    refs.push(node_ref);
+                }
+            }
+            ParsedElementEvent::Member(kind, id, role) => {
+                if let Some(admin) = &mut admin {
+                    admin.member(&kind, id, &role);
+                }
+            }
+            ParsedElementEvent::Tag(tag_key, value) => {
+                if let Some(admin) = &mut admin {
+                    admin.tag(&tag_key, &value);
+                }
 
-                    current_node = node::ActiveModel {
-                        id: attribute_map.id.map_or(ActiveValue::NotSet, |attr| ActiveValue::Set(attr)),
-                        lat: attribute_map.lat.map_or(ActiveValue::NotSet, |attr| ActiveValue::Set(attr)),
-                        lon: attribute_map.lon.map_or(ActiveValue::NotSet, |attr| ActiveValue::Set(attr)),
-                        version: attribute_map.version.map_or(ActiveValue::NotSet, |attr| ActiveValue::Set(attr)),
-                        updated_at: attribute_map.timestamp.map_or(ActiveValue::Set(now.clone()), |attr| ActiveValue::Set(attr)),
-                        city: ActiveValue::Set(None),
-                        country: ActiveValue::Set(current_country.clone()),
-                        postcode: ActiveValue::NotSet,
-                        house_number: ActiveValue::Set(None),
-                        street: ActiveValue::Set(None),
-                        province: ActiveValue::Set(current_province.clone()),
-                        source: ActiveValue::Set(None),
-                        source_date: ActiveValue::Set(None),
-                    };
+                if plugin.is_some() || finish.keep_raw_tags || quarantined.is_some() {
+                    current_tags.insert(tag_key.clone(), value.clone());
+                }
+
+                if tag_key == "name" {
+                    current_name = Some(value.clone());
                 }
-                ParsedElementEvent::Tag(tag_key, value) => {
-                    match re_addr.replace(tag_key.as_str(), "").to_string().as_str() {
-                        "city" => current_node.city = ActiveValue::Set(Some(value.to_string())),
-                        "country" => {
-                            current_country = Some(value.to_string());
-
-                            current_node.country = ActiveValue::Set(current_country.clone())
-                        },
-                        "housenumber" => current_node.house_number = ActiveValue::Set(Some(value.to_string().to_uppercase())),
-                        "postcode" => current_node.postcode = ActiveValue::Set(value.to_string().to_uppercase().replace(" ", "")),
-                        "street" => current_node.street = ActiveValue::Set(Some(value.to_string())),
-                        "province" => {
-                            current_province = Some(value.to_string());
-
-                            current_node.province = ActiveValue::Set(current_province.clone());
-                        },
-                        "source" => current_node.source = ActiveValue::Set(Some(value.clone())),
-                        // "source:date" => current_node.source_date = find_attr("v", &attributes).map_or(ActiveValue::NotSet, |attr| ActiveValue::Set(attr.value.parse().unwrap())),
-                        _ => (),
+
+                match re_addr.replace(tag_key.as_str(), "").to_string().as_str() {
+                    "city" => current_node.city = ActiveValue::Set(Some(value.to_string())),
+                    "country" => {
+                        current_country = Some(value.to_string());
+
+                        current_node.country = ActiveValue::Set(current_country.clone())
+                    },
+                    "housenumber" => current_node.house_number = ActiveValue::Set(Some(value.to_string())),
+                    "full" => current_full = Some(value.clone()),
+                    "housename" => current_node.house_name = ActiveValue::Set(Some(value.to_string())),
+                    "postcode" => current_node.postcode = ActiveValue::Set(value.to_string()),
+                    "street" => current_node.street = ActiveValue::Set(Some(value.to_string())),
+                    "unit" => current_node.unit = ActiveValue::Set(Some(value.to_string())),
+                    "block_number" => current_node.block_number = ActiveValue::Set(Some(value.to_string())),
+                    "neighbourhood" => current_node.neighbourhood = ActiveValue::Set(Some(value.to_string())),
+                    "quarter" => current_node.quarter = ActiveValue::Set(Some(value.to_string())),
+                    "entrance" => current_node.entrance = ActiveValue::Set(Some(value.to_string()).filter(|value| value != "no")),
+                    "province" | "state" => {
+                        current_province = Some(value.to_string());
+
+                        current_node.province = ActiveValue::Set(current_province.clone());
+                    },
+                    "source" => current_node.source = ActiveValue::Set(Some(value.clone())),
+                    "fixme" | "FIXME" | "note" => {
+                        let note = match &current_node.qa_note {
+                            ActiveValue::Set(Some(note)) => format!("{}; {}", note, value),
+                            _ => value.clone(),
+                        };
+
+                        current_node.qa_note = ActiveValue::Set(Some(note));
                     }
+                    // "source:date" => current_node.source_date = find_attr("v", &attributes).map_or(ActiveValue::NotSet, |attr| ActiveValue::Set(attr.value.parse().unwrap())),
+                    _ => (),
                 }
             }
+            ParsedElementEvent::WayLocation(lat, lon, at_entrance) => {
+                current_node.lat = ActiveValue::Set(lat);
+                current_node.lon = ActiveValue::Set(lon);
+
+                if at_entrance {
+                    current_node.entrance = ActiveValue::Set(Some("main".to_string()));
+                }
+
+                current_located = true;
+            }
+        }
+    }
+
+    if let Some(admin) = &mut admin {
+        if let (Some(refs), ActiveValue::Set(id)) = (&current_way, &current_node.id) {
+            admin.add_way(-id, refs);
+        }
+
+        admin.end_element(current_visible);
+    }
+
+    if current_visible && unchanged(&mut baseline, &current_node) {
+        // Already stored at this version
+    } else if !current_visible {
+        if let Some(key) = deleted_version(&current_node) {
+            seen(&mut baseline, key.0);
+
+            if let Some(poi_batches) = &mut poi_batches {
+                poi_batches.delete(key.0).await?;
+            }
+
+            batches.delete(key).await.map_err(write_error)?;
+        }
+    } else {
+        let poi = poi_batches.as_ref().and_then(|_| poi::from_element(&current_node, current_name.as_deref()));
+
+        full_address::complete(&mut current_node, current_full.as_deref(), &mut known_streets);
+
+        let finished_element = finish_element(&mut plugin, current_node, &current_tags, current_way.as_deref().filter(|_| !current_located), &mut node_index, finish)?;
+
+        if let Some(node) = reject(finished_element, &mut rejections, &mut quarantined, &current_tags, now).await? {
+            if let ActiveValue::Set(id) = node.id {
+                seen(&mut baseline, id);
+            }
+
+            batches.push(node).await.map_err(write_error)?;
+            finished += 1;
+        } else if let (Some(poi_batches), Some(poi)) = (&mut poi_batches, poi) {
+            poi_batches.push(poi).await?;
+            pois += 1;
         }
     }
 
-    if node_ready(&current_node) {
-        buffer.push(current_node);
+    rejections.report(finished as u64);
+
+    // Only staged imports have thresholds, which are checked before the staging tables are swapped in
+    if let Some(exceeded) = rejections.exceeded(&max_rejected, finished as u64) {
+        return Err(Error::Invalid(exceeded));
     }
 
     println!("Waiting for writes to finish...");
-    node::Entity::insert_many(buffer.drain(..))
-        .on_conflict(OnConflict::column(node::Column::Id).update_columns(node::Column::iter()).to_owned())
-        .exec(db.as_ref())
-        .await
-        .unwrap();
+    let written = batches.flush_and_wait().await;
+    let failed = written.iter().filter(|written| written.result.is_err()).count();
+    let (rows, deleted) = written.iter()
+        .filter(|written| written.result.is_ok())
+        .fold((0, 0), |(rows, deleted), written| (rows + written.rows, deleted + written.deleted));
+    let batch_count = written.len() - failed;
 
-    join_all(futures.drain(..)).await;
+    if let Some(e) = written.into_iter().find_map(|written| written.result.err()) {
+        if failed > 1 {
+            println!("Warning: {} batches failed to write, reporting the first", failed);
+        }
 
-    Ok(())
+        return Err(write_error(e));
+    }
+
+    println!("Wrote {} rows and {} deletions in {} batches", rows, deleted, batch_count);
+
+    if let Some(poi_batches) = poi_batches {
+        poi_batches.finish().await?;
+        println!("Wrote {} POI postcodes", pois);
+    }
+
+    if let Some(quarantined) = quarantined {
+        quarantined.finish().await?;
+        println!("Quarantined {} rejected addresses", rejections.total());
+    }
+
+    if let (Some(admin), Some(db)) = (admin, admin_areas) {
+        // The events are done with the PBF index by now
+        let mut index = node_index.or_else(|| pbf_index.and_then(Arc::into_inner)).expect("kept for admin areas");
+        let (areas, broken) = admin.finish(&mut index);
+
+        if broken > 0 {
+            println!("Warning: {} boundaries aren't complete in the extract and were left out", broken);
+        }
+
+        println!("Writing {} admin areas", areas.len());
+        admin::write(db.as_ref(), areas).await?;
+    }
+
+    if let (Some(baseline), Output::Database(db, ..)) = (baseline, &output) {
+        let (skipped, removed) = baseline.finish(db.as_ref()).await?;
+        metrics::DELETED_ROWS.inc_by(removed);
+        println!("Skipped {} elements unchanged since the baseline, removed {} that are gone", skipped, removed);
+    }
+
+    Ok(header.unwrap_or_default())
+}
+
+fn write_error(e: Box<dyn std::error::Error + Send + Sync>) -> Error {
+    match e.downcast::<DbErr>() {
+        Ok(e) => Error::from(*e),
+        Err(e) => Error::Output(e.to_string()),
+    }
 }
 
-async fn process_data(db: Arc<DatabaseConnection>) -> Result<(), DbErr> {
-    println!("Build uniq table");
-    db.execute_unprepared("CREATE TABLE node_uniq AS SELECT id, AVG(lat) as lat, AVG(lon) as lon, city, country, postcode, province, street, source, source_date, updated_at, version FROM node GROUP BY postcode HAVING count(distinct street) = 1").await?;
+/// Postcodes per batch of the processing phase.
+const PROCESS_BATCH_SIZE: u64 = 1024;
+
+/// Parsed rows are written after waiting this long, also when the batch isn't full yet.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Batches being written at the same time while parsing.
+const PENDING_WRITES: usize = 128;
+
+/// Smaller batches and fewer writes in flight for `--low-memory`.
+const LOW_MEMORY_COMMIT_EVERY: usize = 256;
+const LOW_MEMORY_PENDING_WRITES: usize = 4;
+const LOW_MEMORY_PROCESS_BATCH_SIZE: u64 = 128;
+
+/// Most batches of the processing phase worked on at once, each on its own connection.
+const MAX_PROCESS_WORKERS: usize = 8;
+
+/// Key of the processing phase in the `import_progress` table.
+const PROCESS_PHASE: &str = "process";
+
+/// Collapses postcodes whose addresses are all on a single street into one row without a house number. Works through
+/// the postcodes in batches, each in its own transaction that also records the last postcode done, so an interrupted
+/// run picks up where it stopped when `resume` is set. The batches are ranges of postcodes rather than ids as every
+/// node of a postcode has to be in the same batch. Up to `workers` batches are processed at once.
+async fn process_data(db: Arc<DatabaseConnection>, resume: bool, batch_size: u64, workers: usize) -> Result<(), DbErr> {
+    let condition = profile::profiles().single_street_condition();
+
+    if !resume {
+        import_progress::Entity::delete_by_id(PROCESS_PHASE).exec(db.as_ref()).await?;
+    }
+
+    let mut last = import_progress::Entity::find_by_id(PROCESS_PHASE)
+        .one(db.as_ref())
+        .await?
+        .map(|progress| progress.last_postcode);
+
+    if let Some(last) = &last {
+        println!("Resuming after postcode {}", last);
+    }
+
+    // Collapsing removes and adds nodes, so the batches are fixed up front
+    let mut ranges = Vec::new();
+
+    while let Some((first, end)) = database::postcode_range(db.as_ref(), last.as_deref(), batch_size).await? {
+        last = Some(end.clone());
+        ranges.push((first, end));
+    }
+
+    let mut progress = Progress::new("Processing data", ranges.len() as u64);
+    let (db, condition) = (db.as_ref(), condition.as_str());
+
+    // Servers collapse every range in its own transaction. SQLite has a single writer, so there the ranges are only
+    // read at the same time and written one after the other
+    let parallel_writes = db.get_database_backend() != DbBackend::Sqlite;
+
+    // Buffered keeps the order of the ranges, so the progress only moves past ranges that are done
+    let mut done = stream::iter(ranges)
+        .map(|(first, end)| async move {
+            if !parallel_writes {
+                let collapsed = dedup::collapsed(db, condition, &first, &end).await?;
+
+                return Ok((end, Some(collapsed)));
+            }
 
-    println!("Index uniq table");
-    db.execute_unprepared("CREATE INDEX idx_node_uniq_postcode ON node_uniq(postcode)").await?;
+            let transaction = db.begin().await?;
+            dedup::collapse_single_street(&transaction, condition, &first, &end).await?;
+            transaction.commit().await?;
 
-    println!("Remove duplicates");
-    db.execute_unprepared("DELETE FROM node WHERE postcode IN (SELECT postcode FROM node_uniq)").await?;
+            Ok::<_, DbErr>((end, None))
+        })
+        .buffered(workers);
 
-    println!("Re-insert normalized unique postcodes");
-    db.execute_unprepared("INSERT INTO node (id, lat, lon, city, country, postcode, province, street, house_number, source, source_date, updated_at, version) SELECT id, lat, lon, city, country, postcode, province, street, null, source, source_date, updated_at, version FROM node_uniq").await?;
+    while let Some((end, collapsed)) = done.try_next().await? {
+        let transaction = db.begin().await?;
 
-    println!("Cleanup, removing node_uniq");
-    db.execute_unprepared("DROP TABLE node_uniq").await?;
+        if let Some(collapsed) = collapsed {
+            dedup::replace(&transaction, condition, collapsed).await?;
+        }
+
+        // Ranges committed in parallel before an interruption are collapsed again when resuming, which leaves
+        // collapsed rows as they are
+        import_progress::Entity::insert(import_progress::ActiveModel {
+            phase: ActiveValue::Set(PROCESS_PHASE.to_string()),
+            last_postcode: ActiveValue::Set(end),
+            updated_at: ActiveValue::Set(chrono::offset::Local::now().naive_local()),
+        })
+            .on_conflict(OnConflict::column(import_progress::Column::Phase).update_columns([import_progress::Column::LastPostcode, import_progress::Column::UpdatedAt]).to_owned())
+            .exec(&transaction)
+            .await?;
+
+        transaction.commit().await?;
+        progress.advance(1);
+    }
+
+    import_progress::Entity::delete_by_id(PROCESS_PHASE).exec(db).await?;
 
     Ok(())
 }
@@ -248,22 +1112,451 @@ async fn process_data(db: Arc<DatabaseConnection>) -> Result<(), DbErr> {
 #[tokio::main]
 async fn main() {
     let matches = cli().get_matches();
+    let started_at = chrono::offset::Local::now();
+    let mut result = run(&matches).await;
+
+    // A daemon publishes and notifies after every run instead
+    let import = matches.subcommand().is_none() && !matches.get_flag("daemon");
+    let db_uri = matches.get_one::<String>("db").expect("defaulted in clap");
+    let source = matches.get_one::<PathBuf>("input").map(PathBuf::as_path);
+    let mut published = None;
+
+    if let (true, Ok(()), Some(url)) = (import, &result, matches.get_one::<String>("publish")) {
+        match publish::run(db_uri, url, source, started_at).await {
+            Ok(url) => published = Some(url),
+            Err(e) => result = Err(e),
+        }
+    }
+
+    if let Err(e) = &result {
+        eprintln!("Error: {}", e);
+    }
+
+    if let (true, Some(url)) = (import, matches.get_one::<String>("notify-url")) {
+        let outcome = result.as_ref().map(|_| ()).map_err(|e| (e.exit_code(), e.to_string()));
+
+        notify::send(url, &notify::summary(db_uri, source, published.as_deref(), started_at, &outcome).await).await;
+    }
+
+    if let Err(e) = result {
+        std::process::exit(e.exit_code());
+    }
+}
+
+async fn run(matches: &clap::ArgMatches) -> Result<(), Error> {
     let db_uri = matches.get_one::<String>("db").expect("defaulted in clap");
+    let default_country = matches.get_one::<String>("country").cloned();
+    let low_memory = matches.get_flag("low-memory");
+    let mut commit_every = *matches.get_one::<u64>("commit-every").expect("defaulted in clap") as usize;
+    let mut pending_writes = PENDING_WRITES;
+    let mut process_batch_size = PROCESS_BATCH_SIZE;
+
+    if low_memory {
+        commit_every = commit_every.min(LOW_MEMORY_COMMIT_EVERY);
+        pending_writes = LOW_MEMORY_PENDING_WRITES;
+        process_batch_size = LOW_MEMORY_PROCESS_BATCH_SIZE;
+    }
+
+    let pool_options = PoolOptions {
+        max_connections: matches.get_one::<u32>("max-connections").copied(),
+        acquire_timeout: matches.get_one::<u64>("acquire-timeout").map(|seconds| Duration::from_secs(*seconds)),
+        connect_timeout: matches.get_one::<u64>("connect-timeout").map(|seconds| Duration::from_secs(*seconds)),
+        idle_timeout: matches.get_one::<u64>("idle-timeout").map(|seconds| Duration::from_secs(*seconds)),
+        sqlx_log: matches.get_one::<String>("sqlx-log").expect("defaulted in clap").parse().expect("validated in clap"),
+        write_retries: *matches.get_one::<u32>("write-retries").expect("defaulted in clap"),
+    };
+
+    if pool_options.sqlx_log != LevelFilter::Off {
+        tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::new(format!("sqlx={}", pool_options.sqlx_log)))
+            .with_writer(std::io::stderr)
+            .init();
+    }
+
+    database::init(pool_options);
+
+    // Every write holds a connection, more than the pool has would only wait for one
+    let max_connections = pool_options.max_connections(db_uri, low_memory) as usize;
+    pending_writes = pending_writes.min(max_connections);
+
+    if let Some(path) = matches.get_one::<PathBuf>("profiles") {
+        profile::init(Profiles::load(path).map_err(|e| Error::Usage(format!("failed to load profiles: {}", e)))?);
+    }
+    if let Some(path) = matches.get_one::<PathBuf>("street-abbreviations") {
+        street::init(Dictionaries::load(path).map_err(|e| Error::Usage(format!("failed to load street abbreviations: {}", e)))?);
+    }
+    let plugin = matches.get_one::<PathBuf>("plugin")
+        .map(|path| Plugin::load(path).map_err(|e| Error::Usage(format!("failed to load plugin: {}", e))))
+        .transpose()?;
+
+    if let Some(("survey", matches)) = matches.subcommand() {
+        let Input::Xml(input) = input(matches, false)? else {
+            return Err(Error::Usage("survey only reads OSM XML".to_string()));
+        };
+
+        return survey::run(input, *matches.get_one::<usize>("top").expect("defaulted in clap"));
+    }
+
+    if let Some(("schema", matches)) = matches.subcommand() {
+        return schema::run(db_uri, matches);
+    }
+
+    if let Some(("serve", matches)) = matches.subcommand() {
+        return serve::run(db_uri, matches).await.map_err(Error::Command);
+    }
+
+    if let Some(("query", query)) = matches.subcommand() {
+        if let Some(("sql", matches)) = query.subcommand() {
+            let sql = matches.get_one::<String>("SQL").expect("required in clap");
+
+            return query::sql::run(db_uri, sql, matches.get_one::<String>("format").expect("defaulted in clap")).await.map_err(Error::Command);
+        }
+    }
+
+    if let (None, Some(url)) = (matches.subcommand(), matches.get_one::<String>("publish")) {
+        publish::check(db_uri, url)?;
+    }
 
-    let mut db_opt = ConnectOptions::new(db_uri);
+    // Every run is a child process that handles the other flags
+    if matches.subcommand().is_none() && matches.get_flag("daemon") {
+        return daemon::run(db_uri, matches).await;
+    }
+
+    if let Some(listen) = matches.get_one::<SocketAddr>("metrics-listen") {
+        metrics::spawn_server(*listen);
+    }
+
+    let options = ParseOptions {
+        default_country,
+        commit_every,
+        pending_writes,
+        max_age: matches.get_one::<u64>("max-age").map(|days| chrono::Duration::days(*days as i64)),
+        poi_postcodes: None,
+        admin_areas: None,
+        finish: FinishOptions {
+            ways: matches.get_flag("ways"),
+            grid_cells: matches.get_flag("grid-cells"),
+            h3_resolution: h3::resolution(matches),
+            keep_raw_tags: matches.get_flag("keep-raw-tags"),
+            strict: matches.get_flag("strict"),
+        },
+        max_rejected: matches.get_many::<MaxRejected>("max-rejected").into_iter().flatten().copied().collect(),
+        quarantine: None,
+        baseline: None,
+    };
+    let verify_md5 = matches.get_flag("verify-md5");
+
+    if let (None, Some(&count)) = (matches.subcommand(), matches.get_one::<u64>("preview")) {
+        let preview = Arc::new(Preview::new(count as usize));
+
+        // A single batch, so the rows stay in the order of the file
+        let options = ParseOptions { commit_every: count as usize, ..options };
+        parse_file(input(matches, verify_md5)?, Output::Preview(preview.clone()), plugin, options).await?;
+
+        return query::print_models(&preview.rows(), "table").map_err(Error::Command);
+    }
+
+    if let (None, Some(elastic)) = (matches.subcommand(), ElasticOutput::from_uri(db_uri)) {
+        if matches.get_flag("poi-postcodes") {
+            println!("Warning: --poi-postcodes only affects databases");
+        }
+
+        if matches.value_source("on-conflict") == Some(ValueSource::CommandLine) {
+            println!("Warning: --on-conflict only affects databases, Elasticsearch documents are always replaced");
+        }
+
+        println!("Parsing file");
+        return parse_file(input(matches, verify_md5)?, Output::Elastic(Arc::new(elastic)), plugin, options).await.map(|_| ());
+    }
+
+    let unsafe_fast = matches.get_flag("unsafe-fast");
+
+    if unsafe_fast && !db_uri.starts_with("sqlite:") {
+        println!("Warning: --unsafe-fast only affects SQLite databases");
+    }
+
+    // Without the write-ahead log, SQLite readers hold up the writer
+    let process_workers = if low_memory || unsafe_fast {
+        1
+    } else {
+        available_parallelism().map_or(4, |cores| cores.get()).min(MAX_PROCESS_WORKERS).min(max_connections)
+    };
+
+    let baseline = matches.subcommand().is_none().then(|| matches.get_one::<PathBuf>("baseline")).flatten();
+
+    if let Some(baseline) = baseline {
+        println!("Copying the baseline");
+        baseline::copy(baseline, db_uri)?;
+    }
+
+    // `--where` is inserted into the query as it is
+    if let Some(("export", matches)) = matches.subcommand() {
+        let db = database::connect_query_only(db_uri).await.map_err(Error::Unreachable)?;
+        return export::run(&db, matches).await.map_err(Error::Command);
+    }
 
-    db_opt.max_connections(128)
-        .acquire_timeout(Duration::from_secs(10))
-        .connect_timeout(Duration::from_secs(10));
+    let db = Arc::new(database::connect(db_uri, unsafe_fast, low_memory).await.map_err(Error::Unreachable)?);
 
-    let db = Arc::new(Database::connect(db_opt).await.unwrap());
+    match matches.subcommand() {
+        Some(("query", matches)) => return query::run(db.as_ref(), matches).await.map_err(Error::Command),
+        Some(("geocode", matches)) => return geocode::run(db.as_ref(), matches).await.map_err(Error::Command),
+        Some(("reverse-geocode", matches)) => return geocode::run_reverse(db.as_ref(), matches).await.map_err(Error::Command),
+        Some(("keys", matches)) => return keys::run(db.as_ref(), matches).await.map_err(Error::Command),
+        Some(("package", matches)) => return package::run(db.as_ref(), db_uri, matches).await.map_err(Error::Command),
+        Some(("fix", matches)) => {
+            build_db(db.clone(), false).await?;
+
+            if let Some(held) = lock::acquire(db.as_ref(), matches.get_flag("force")).await? {
+                return Err(Error::Locked { holder: held.holder, since: held.acquired_at.to_string() });
+            }
+
+            let result = fix::run(db.as_ref(), matches).await.map_err(Error::Command);
+            lock::release(db.as_ref()).await?;
+
+            return result;
+        }
+        Some(("import-external", matches)) => {
+            build_db(db.clone(), false).await?;
+
+            if let Some(held) = lock::acquire(db.as_ref(), matches.get_flag("force")).await? {
+                return Err(Error::Locked { holder: held.holder, since: held.acquired_at.to_string() });
+            }
+
+            let result = external::run(db.clone(), matches).await;
+            lock::release(db.as_ref()).await?;
+
+            return result;
+        }
+        _ => {}
+    }
+
+    let staged = matches.get_flag("staging");
+    let resume = matches.get_flag("resume");
+
+    if staged && db.get_database_backend() != DbBackend::Postgres {
+        return Err(Error::Usage("--staging requires a Postgres database".to_string()));
+    }
+
+    let policy = MergePolicy::parse(matches.get_many::<String>("merge-policy").into_iter().flatten())
+        .map_err(|e| Error::Usage(format!("invalid --merge-policy: {}", e)))?;
+    let conflict = Conflict::from_name(matches.get_one::<String>("on-conflict").expect("defaulted in clap"))
+        .expect("validated in clap");
+
+    if conflict != Conflict::Update && matches.contains_id("merge-policy") {
+        return Err(Error::Usage("--merge-policy only applies with --on-conflict update".to_string()));
+    }
+
+    let timezones = matches.get_one::<PathBuf>("timezones")
+        .map(|path| Boundaries::load(path, timezones::PROPERTY))
+        .transpose()
+        .map_err(|e| Error::Usage(format!("invalid --timezones: {}", e)))?;
+    let borders = country_boundaries(matches)?;
+    let dem = matches.get_one::<PathBuf>("dem")
+        .map(|dir| Dem::open(dir))
+        .transpose()
+        .map_err(|e| Error::Usage(format!("invalid --dem: {}", e)))?;
+    let precedence = Precedence::parse(matches.get_one::<String>("source-precedence").expect("defaulted in clap"))
+        .map_err(|e| Error::Usage(format!("invalid --source-precedence: {}", e)))?;
+    let input = input(matches, verify_md5)?;
+    let mut timings = Timings::new(db_uri);
+    let started_at = chrono::offset::Local::now().naive_local();
 
     println!("Building database");
-    build_db(db.clone(), matches.get_flag("fresh")).await.unwrap();
+    let phase = timings.start(db.as_ref(), "build").await?;
+    build_db(db.clone(), false).await?;
+
+    if let Some(held) = lock::acquire(db.as_ref(), matches.get_flag("force")).await? {
+        return Err(Error::Locked { holder: held.holder, since: held.acquired_at.to_string() });
+    }
+
+    // Read before --fresh drops the earlier runs
+    let previous_addresses = guard::previous(db.as_ref()).await?;
+    let on_drop = OnDrop::from_name(matches.get_one::<String>("on-drop").expect("defaulted in clap")).expect("validated in clap");
+
+    // Released even when the import fails, so the next run doesn't need --force
+    let result: Result<(), Error> = async {
+        // Only recreated once the lock is ours, which is then dropped along with the other tables
+        if matches.get_flag("fresh") && !staged {
+            build_db(db.clone(), true).await?;
+            lock::acquire(db.as_ref(), true).await?;
+        }
+
+        let import_db = if staged && resume {
+            Arc::new(staging::connect(db_uri, db.as_ref()).await?)
+        } else if staged {
+            println!("Preparing staging tables");
+            Arc::new(staging::prepare(db_uri, db.as_ref(), matches.get_flag("fresh")).await?)
+        } else {
+            db.clone()
+        };
+        timings.finish(db.as_ref(), phase, None).await?;
+
+        if matches.get_flag("defer-indexes") && !resume {
+            if import_db.get_database_backend() == DbBackend::MySql {
+                println!("Warning: --defer-indexes only affects SQLite and Postgres databases");
+            }
+
+            let deferred = indexes::defer(import_db.as_ref()).await?;
+            println!("Deferred {} indexes until the rows are in", deferred);
+        }
+
+        // Not known when resuming, the file was read by the import that was interrupted
+        let mut header = None;
+        let mut unchanged_since_baseline = false;
+
+        if !resume {
+            println!("Parsing file");
+            let phase = timings.start(db.as_ref(), "parse").await?;
+            let inserted = metrics::INSERTED_ROWS.get();
+            let deleted = metrics::DELETED_ROWS.get();
+            let loaded = match baseline {
+                Some(_) => Some(Baseline::load(import_db.as_ref()).await?),
+                None => None,
+            };
+            let options = ParseOptions {
+                poi_postcodes: matches.get_flag("poi-postcodes").then(|| import_db.clone()),
+                quarantine: matches.get_flag("quarantine").then(|| import_db.clone()),
+                admin_areas: matches.get_flag("admin-areas").then(|| import_db.clone()),
+                baseline: loaded,
+                ..options
+            };
+            header = Some(parse_file(input, Output::Database(import_db.clone(), Arc::new(policy), conflict), plugin, options).await?);
+            timings.finish(db.as_ref(), phase, Some(metrics::INSERTED_ROWS.get() - inserted)).await?;
+            unchanged_since_baseline = baseline.is_some() && metrics::INSERTED_ROWS.get() == inserted && metrics::DELETED_ROWS.get() == deleted;
+        }
+
+        // Also brings back the indexes of an earlier import that stopped while they were deferred
+        if indexes::deferred(import_db.as_ref()).await? {
+            println!("Creating indexes");
+            let phase = timings.start(db.as_ref(), "index").await?;
+            indexes::restore(import_db.as_ref()).await?;
+            timings.finish(db.as_ref(), phase, None).await?;
+        }
+
+        if unchanged_since_baseline {
+            println!("Nothing changed since the baseline, skipping the processing");
+        }
+
+        if !resume && !unchanged_since_baseline {
+            // Conflation and merging duplicates pick the winner of every address by its rank
+            precedence.assign(import_db.as_ref()).await?;
+
+            // Before merging duplicates, which would otherwise merge an external row into the OSM one
+            if external::loaded(import_db.as_ref()).await? {
+                println!("Conflating with external datasets");
+                let phase = timings.start(db.as_ref(), "conflate").await?;
+                let nodes = database::live_nodes().count(import_db.as_ref()).await?;
+                let superseded = external::conflate(import_db.as_ref()).await?;
+                println!("{} addresses superseded by another source", superseded);
+                timings.finish(db.as_ref(), phase, Some(nodes)).await?;
+            }
+
+            let merge_distance = *matches.get_one::<f64>("merge-distance").expect("defaulted in clap");
+
+            if merge_distance > 0.0 {
+                println!("Merging duplicate addresses");
+                let phase = timings.start(db.as_ref(), "merge").await?;
+                let nodes = database::live_nodes().count(import_db.as_ref()).await?;
+                let merged = cluster::merge(import_db.as_ref(), merge_distance).await?;
+                println!("Merged away {} duplicates", merged);
+                timings.finish(db.as_ref(), phase, Some(nodes)).await?;
+            }
+
+            println!("Building postcode areas");
+            let strategy = areas::Strategy::from_name(matches.get_one::<String>("areas").expect("defaulted in clap"))
+                .expect("validated in clap");
+            let phase = timings.start(db.as_ref(), "areas").await?;
+            let nodes = database::live_nodes().count(import_db.as_ref()).await?;
+            areas::build(import_db.as_ref(), strategy, borders.as_ref()).await?;
+            timings.finish(db.as_ref(), phase, Some(nodes)).await?;
+
+            // Before single-street postcodes are collapsed, which would count them as a single address, like rollups
+            println!("Building autocomplete suggestions");
+            let phase = timings.start(db.as_ref(), "suggest").await?;
+            autocomplete::build(import_db.as_ref()).await?;
+            timings.finish(db.as_ref(), phase, Some(nodes)).await?;
+
+            println!("Rolling up postcodes");
+            let phase = timings.start(db.as_ref(), "rollups").await?;
+            let rollups = rollups::build(import_db.as_ref()).await?;
+            println!("Wrote {} postcode rollups", rollups);
+            timings.finish(db.as_ref(), phase, Some(nodes)).await?;
+
+            if let Some(timezones) = &timezones {
+                println!("Building postcode timezones");
+                let phase = timings.start(db.as_ref(), "timezones").await?;
+                let outside = timezones::build(import_db.as_ref(), timezones).await?;
+
+                if outside > 0 {
+                    println!("Warning: {} postcodes aren't in any timezone", outside);
+                }
+
+                timings.finish(db.as_ref(), phase, Some(nodes)).await?;
+            }
+        }
+
+        if !unchanged_since_baseline {
+            println!("Processing data");
+            let phase = timings.start(db.as_ref(), "process").await?;
+            let nodes = database::live_nodes().count(import_db.as_ref()).await?;
+            process_data(import_db.clone(), resume, process_batch_size, process_workers).await?;
+            timings.finish(db.as_ref(), phase, Some(nodes)).await?;
+        }
+
+        // After processing, which replaces collapsed rows
+        if matches.get_flag("admin-areas") {
+            println!("Resolving admin areas");
+            let phase = timings.start(db.as_ref(), "admin").await?;
+            let nodes = database::live_nodes().count(import_db.as_ref()).await?;
+            let resolved = admin::resolve(import_db.as_ref()).await?;
+            println!("{} addresses are in an admin area", resolved);
+            timings.finish(db.as_ref(), phase, Some(nodes)).await?;
+        }
+
+        if let Some(dem) = &dem {
+            println!("Sampling elevations");
+            let phase = timings.start(db.as_ref(), "elevation").await?;
+            let nodes = database::live_nodes().count(import_db.as_ref()).await?;
+            let annotated = elevation::annotate(import_db.as_ref(), dem).await?;
+            println!("Sampled the elevation of {} addresses", annotated);
+            timings.finish(db.as_ref(), phase, Some(nodes)).await?;
+        }
+
+        let index_profile = IndexProfile::from_name(matches.get_one::<String>("index-profile").expect("defaulted in clap"))
+            .expect("validated in clap");
+        indexes::apply(import_db.as_ref(), index_profile).await?;
+
+        let addresses = database::live_nodes().count(import_db.as_ref()).await?;
+
+        if let Some(max_drop) = matches.get_one::<f64>("max-drop") {
+            guard::check(previous_addresses, addresses, *max_drop, on_drop)?;
+        }
+
+        if staged {
+            println!("Swapping in staging tables");
+            staging::swap(db.as_ref()).await?;
+        }
+
+        if let Some(header) = header {
+            let source = matches.get_one::<PathBuf>("input").map(|path| path.display().to_string());
+            header.record(db.as_ref(), source, started_at, addresses).await?;
+        }
+
+        if unsafe_fast {
+            println!("Syncing database to disk");
+            database::sync(db_uri).map_err(|e| Error::Output(format!("failed to sync the database: {}", e)))?;
+        }
+
+        timings.print();
+
+        if let Some(path) = matches.get_one::<PathBuf>("timings-json") {
+            timings.write_json(path).map_err(|e| Error::Output(format!("{}: {}", path.display(), e)))?;
+        }
+
+        Ok(())
+    }.await;
 
-    println!("Parsing file");
-    parse_file(db.clone()).await.unwrap();
+    lock::release(db.as_ref()).await?;
 
-    println!("Processing data");
-    process_data(db.clone()).await.unwrap();
+    result
 }
@@ -0,0 +1,193 @@
+//! Per column policies for combining a re-imported node with the row that's already stored.
+
+use std::collections::HashMap;
+
+use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, IdenStatic, Iterable, ModelTrait, QueryFilter, Value};
+
+use crate::entities::*;
+use crate::profile::{profiles, search_key};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Policy {
+    /// The value of the element with the highest OSM version, the incoming one on a tie
+    Newest,
+    /// Whichever value isn't null, the newest when both are set
+    NonNull,
+    /// The longest text, the newest when both are as long
+    Longest,
+}
+
+impl Policy {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "newest" => Some(Self::Newest),
+            "non-null" => Some(Self::NonNull),
+            "longest" => Some(Self::Longest),
+            _ => None,
+        }
+    }
+}
+
+/// What happens when an imported element is already stored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Conflict {
+    /// Combine it with the stored row according to the [`MergePolicy`]
+    Update,
+    /// Keep the stored row, without reading it first
+    Ignore,
+    /// Fail the import
+    Error,
+}
+
+impl Conflict {
+    pub const NAMES: [&'static str; 3] = ["update", "ignore", "error"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "update" => Some(Self::Update),
+            "ignore" => Some(Self::Ignore),
+            "error" => Some(Self::Error),
+            _ => None,
+        }
+    }
+}
+
+/// The policy for each column, [`Policy::Newest`] unless configured otherwise.
+#[derive(Default)]
+pub struct MergePolicy {
+    columns: HashMap<String, Policy>,
+}
+
+impl MergePolicy {
+    /// Parses `column=policy` pairs, like `city=non-null`.
+    pub fn parse<'a>(specs: impl IntoIterator<Item = &'a String>) -> Result<Self, String> {
+        let mut columns = HashMap::new();
+
+        for spec in specs {
+            let (column, policy) = spec.split_once('=')
+                .ok_or_else(|| format!("expected column=policy, got {}", spec))?;
+
+            if !node::Column::iter().any(|known| known.as_str() == column) {
+                return Err(format!("unknown column {}", column));
+            }
+
+            let policy = Policy::from_name(policy)
+                .ok_or_else(|| format!("unknown policy {}, expected newest, non-null or longest", policy))?;
+
+            columns.insert(column.to_string(), policy);
+        }
+
+        Ok(Self { columns })
+    }
+
+    fn policy(&self, column: &node::Column) -> Policy {
+        self.columns.get(column.as_str()).copied().unwrap_or(Policy::Newest)
+    }
+
+    /// Merges the batch with the rows already stored under the same ids, in place so a failed batch stays whole.
+    pub async fn apply(&self, db: &DatabaseConnection, batch: &mut [node::ActiveModel]) -> Result<(), DbErr> {
+        let ids: Vec<i64> = batch.iter()
+            .filter_map(|node| match &node.id {
+                ActiveValue::Set(id) => Some(*id),
+                _ => None,
+            })
+            .collect();
+
+        let mut existing: HashMap<i64, node::Model> = node::Entity::find()
+            .filter(node::Column::Id.is_in(ids))
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|model| (model.id, model))
+            .collect();
+
+        for node in batch {
+            let stored = match &node.id {
+                ActiveValue::Set(id) => existing.remove(id),
+                _ => None,
+            };
+
+            if let Some(stored) = stored {
+                self.merge(&stored, node);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn merge(&self, stored: &node::Model, incoming: &mut node::ActiveModel) {
+        let incoming_is_newer = match &incoming.version {
+            ActiveValue::Set(version) => *version >= stored.version,
+            _ => true,
+        };
+
+        // Only set when the element has one, like the parser does
+        let extension = match &incoming.postcode_extension {
+            ActiveValue::Set(extension) => extension.clone(),
+            _ => None,
+        };
+        let mut postcode_incoming = true;
+
+        for column in node::Column::iter() {
+            let current = stored.get(column);
+
+            let ActiveValue::Set(value) = incoming.get(column) else {
+                postcode_incoming &= !matches!(column, node::Column::Postcode);
+                incoming.set(column, current);
+                continue;
+            };
+
+            let keep_incoming = match self.policy(&column) {
+                Policy::Newest => incoming_is_newer,
+                Policy::NonNull => match (is_null(&value), is_null(&current)) {
+                    (false, true) => true,
+                    (true, false) => false,
+                    _ => incoming_is_newer,
+                },
+                Policy::Longest => match text_length(&value).cmp(&text_length(&current)) {
+                    std::cmp::Ordering::Greater => true,
+                    std::cmp::Ordering::Less => false,
+                    std::cmp::Ordering::Equal => incoming_is_newer,
+                },
+            };
+
+            if !keep_incoming {
+                postcode_incoming &= !matches!(column, node::Column::Postcode);
+                incoming.set(column, current);
+            }
+        }
+
+        // The parts of the postcode go with the postcode that was kept, split by the profile of the merged country
+        if let ActiveValue::Set(postcode) = &incoming.postcode {
+            let country = match &incoming.country {
+                ActiveValue::Set(country) => country.as_deref(),
+                _ => None,
+            };
+            let (outcode, incode) = profiles().get(country).outcode_incode(postcode).unzip();
+
+            incoming.outcode = ActiveValue::Set(outcode);
+            incoming.incode = ActiveValue::Set(incode);
+            incoming.postcode_extension = ActiveValue::Set(if postcode_incoming { extension } else { stored.postcode_extension.clone() });
+        }
+
+        // The postcode and house number can each come from a different row
+        if let (ActiveValue::Set(postcode), ActiveValue::Set(house_number)) = (&incoming.postcode, &incoming.house_number) {
+            incoming.search_key = ActiveValue::Set(search_key(postcode, house_number.as_deref()));
+        }
+    }
+}
+
+fn is_null(value: &Value) -> bool {
+    matches!(
+        value,
+        Value::Bool(None) | Value::Int(None) | Value::BigInt(None) | Value::Double(None) | Value::String(None)
+            | Value::ChronoDate(None) | Value::ChronoDateTime(None) | Value::Json(None)
+    )
+}
+
+fn text_length(value: &Value) -> usize {
+    match value {
+        Value::String(Some(text)) => text.chars().count(),
+        _ => 0,
+    }
+}
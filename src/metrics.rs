@@ -0,0 +1,64 @@
+//! Prometheus metrics for the importer and the lookup server.
+
+use std::net::SocketAddr;
+use std::sync::LazyLock;
+
+use axum::Router;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use prometheus::{register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec, Encoder, Histogram, HistogramVec, IntCounter, IntCounterVec, TextEncoder};
+
+pub static PARSED_ELEMENTS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("postcode_parsed_elements_total", "OSM elements read from the input").unwrap()
+});
+
+pub static INSERTED_ROWS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("postcode_inserted_rows_total", "Rows written to the output").unwrap()
+});
+
+pub static DELETED_ROWS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("postcode_deleted_rows_total", "Rows removed because their element was deleted").unwrap()
+});
+
+pub static REJECTED_ROWS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("postcode_rejected_rows_total", "Elements with a postcode that were not written", &["reason"]).unwrap()
+});
+
+pub static BATCH_DURATION: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!("postcode_batch_duration_seconds", "Time spent writing a batch", vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]).unwrap()
+});
+
+pub static QUERY_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!("postcode_query_duration_seconds", "Time spent answering a lookup", &["endpoint"], vec![0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0]).unwrap()
+});
+
+/// Registers every metric up front so they are reported as zero instead of missing before first use.
+pub fn register() {
+    LazyLock::force(&PARSED_ELEMENTS);
+    LazyLock::force(&INSERTED_ROWS);
+    LazyLock::force(&DELETED_ROWS);
+    LazyLock::force(&REJECTED_ROWS);
+    LazyLock::force(&BATCH_DURATION);
+    LazyLock::force(&QUERY_DURATION);
+}
+
+pub async fn metrics_handler() -> impl IntoResponse {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder.encode(&prometheus::gather(), &mut buffer).expect("metrics are encodable");
+
+    ([(CONTENT_TYPE, encoder.format_type().to_string())], buffer)
+}
+
+/// Exposes `/metrics` on a separate listener while importing.
+pub fn spawn_server(listen: SocketAddr) {
+    register();
+
+    tokio::spawn(async move {
+        let app = Router::new().route("/metrics", get(metrics_handler));
+        let listener = tokio::net::TcpListener::bind(listen).await.expect("failed to bind metrics listener");
+
+        axum::serve(listener, app).await.expect("metrics server failed");
+    });
+}
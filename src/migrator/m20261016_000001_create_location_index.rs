@@ -0,0 +1,22 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20231101_000000_create_nodes_table::Node;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000001_create_location_index"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.create_index(Index::create().if_not_exists().name("idx-lat-lon").table(Node::Table).col(Node::Lat).col(Node::Lon).to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_index(Index::drop().name("idx-lat-lon").table(Node::Table).to_owned()).await
+    }
+}
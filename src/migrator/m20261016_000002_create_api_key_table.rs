@@ -0,0 +1,48 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000002_create_api_key_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.create_table(Table::create()
+            .table(ApiKey::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(ApiKey::Id)
+                    .integer()
+                    .not_null()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(ApiKey::Name).string().not_null().unique_key())
+            .col(ColumnDef::new(ApiKey::KeyHash).string().not_null().unique_key())
+            .col(ColumnDef::new(ApiKey::RateLimit).double())
+            .col(ColumnDef::new(ApiKey::CreatedAt).date_time().not_null())
+            .col(ColumnDef::new(ApiKey::RevokedAt).date_time())
+            .to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ApiKey::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum ApiKey {
+    Table,
+    Id,
+    Name,
+    KeyHash,
+    RateLimit,
+    CreatedAt,
+    RevokedAt,
+}
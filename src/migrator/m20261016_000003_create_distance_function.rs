@@ -0,0 +1,42 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{ConnectionTrait, DbBackend};
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000003_create_distance_function"
+    }
+}
+
+// SQLite registers the function on every connection instead, see `database::register_functions`.
+const POSTGRES_UP: &str = "CREATE OR REPLACE FUNCTION distance(lat1 double precision, lon1 double precision, lat2 double precision, lon2 double precision) \
+    RETURNS double precision AS $$ \
+        SELECT 2 * 6371008.8 * asin(sqrt(power(sin(radians(lat2 - lat1) / 2), 2) + cos(radians(lat1)) * cos(radians(lat2)) * power(sin(radians(lon2 - lon1) / 2), 2))) \
+    $$ LANGUAGE sql IMMUTABLE STRICT PARALLEL SAFE";
+
+const MYSQL_UP: &str = "CREATE FUNCTION distance(lat1 DOUBLE, lon1 DOUBLE, lat2 DOUBLE, lon2 DOUBLE) RETURNS DOUBLE DETERMINISTIC \
+    RETURN 2 * 6371008.8 * ASIN(SQRT(POW(SIN(RADIANS(lat2 - lat1) / 2), 2) + COS(RADIANS(lat1)) * COS(RADIANS(lat2)) * POW(SIN(RADIANS(lon2 - lon1) / 2), 2)))";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        match db.get_database_backend() {
+            DbBackend::Postgres => db.execute_unprepared(POSTGRES_UP).await.map(|_| ()),
+            DbBackend::MySql => db.execute_unprepared(MYSQL_UP).await.map(|_| ()),
+            DbBackend::Sqlite => Ok(()),
+        }
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        match db.get_database_backend() {
+            DbBackend::Postgres => db.execute_unprepared("DROP FUNCTION IF EXISTS distance(double precision, double precision, double precision, double precision)").await.map(|_| ()),
+            DbBackend::MySql => db.execute_unprepared("DROP FUNCTION IF EXISTS distance").await.map(|_| ()),
+            DbBackend::Sqlite => Ok(()),
+        }
+    }
+}
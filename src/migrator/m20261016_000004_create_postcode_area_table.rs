@@ -0,0 +1,43 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000004_create_postcode_area_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.create_table(Table::create()
+            .table(PostcodeArea::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(PostcodeArea::Postcode)
+                    .string()
+                    .not_null()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(PostcodeArea::PointCount).integer().not_null())
+            .col(ColumnDef::new(PostcodeArea::Wkt).text().not_null())
+            .col(ColumnDef::new(PostcodeArea::Geojson).text().not_null())
+            .to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PostcodeArea::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum PostcodeArea {
+    Table,
+    Postcode,
+    PointCount,
+    Wkt,
+    Geojson,
+}
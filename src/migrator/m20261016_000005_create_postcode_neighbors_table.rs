@@ -0,0 +1,39 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000005_create_postcode_neighbors_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.create_table(Table::create()
+            .table(PostcodeNeighbors::Table)
+            .if_not_exists()
+            .col(ColumnDef::new(PostcodeNeighbors::Postcode).string().not_null())
+            .col(ColumnDef::new(PostcodeNeighbors::Neighbor).string().not_null())
+            .col(ColumnDef::new(PostcodeNeighbors::BorderLength).double().not_null())
+            .col(ColumnDef::new(PostcodeNeighbors::Distance).double().not_null())
+            .primary_key(Index::create().col(PostcodeNeighbors::Postcode).col(PostcodeNeighbors::Neighbor))
+            .to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PostcodeNeighbors::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum PostcodeNeighbors {
+    Table,
+    Postcode,
+    Neighbor,
+    BorderLength,
+    Distance,
+}
@@ -0,0 +1,31 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20231101_000000_create_nodes_table::Node;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000006_add_us_address_columns"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // SQLite can only add one column per statement
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.alter_table(Table::alter().table(Node::Table).add_column(ColumnDef::new(Columns::PostcodeExtension).string()).to_owned()).await?;
+        manager.alter_table(Table::alter().table(Node::Table).add_column(ColumnDef::new(Columns::Unit).string()).to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.alter_table(Table::alter().table(Node::Table).drop_column(Columns::PostcodeExtension).to_owned()).await?;
+        manager.alter_table(Table::alter().table(Node::Table).drop_column(Columns::Unit).to_owned()).await
+    }
+}
+
+#[derive(Iden)]
+enum Columns {
+    PostcodeExtension,
+    Unit,
+}
@@ -0,0 +1,34 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20231101_000000_create_nodes_table::Node;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000007_add_outcode_columns"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // SQLite can only add one column per statement
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.alter_table(Table::alter().table(Node::Table).add_column(ColumnDef::new(Columns::Outcode).string()).to_owned()).await?;
+        manager.alter_table(Table::alter().table(Node::Table).add_column(ColumnDef::new(Columns::Incode).string()).to_owned()).await?;
+
+        manager.create_index(Index::create().if_not_exists().name("idx-outcode").table(Node::Table).col(Columns::Outcode).to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_index(Index::drop().name("idx-outcode").table(Node::Table).to_owned()).await?;
+        manager.alter_table(Table::alter().table(Node::Table).drop_column(Columns::Outcode).to_owned()).await?;
+        manager.alter_table(Table::alter().table(Node::Table).drop_column(Columns::Incode).to_owned()).await
+    }
+}
+
+#[derive(Iden)]
+enum Columns {
+    Outcode,
+    Incode,
+}
@@ -0,0 +1,34 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20231101_000000_create_nodes_table::Node;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000008_add_japanese_address_columns"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // SQLite can only add one column per statement
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.alter_table(Table::alter().table(Node::Table).add_column(ColumnDef::new(Columns::BlockNumber).string()).to_owned()).await?;
+        manager.alter_table(Table::alter().table(Node::Table).add_column(ColumnDef::new(Columns::Neighbourhood).string()).to_owned()).await?;
+        manager.alter_table(Table::alter().table(Node::Table).add_column(ColumnDef::new(Columns::Quarter).string()).to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.alter_table(Table::alter().table(Node::Table).drop_column(Columns::BlockNumber).to_owned()).await?;
+        manager.alter_table(Table::alter().table(Node::Table).drop_column(Columns::Neighbourhood).to_owned()).await?;
+        manager.alter_table(Table::alter().table(Node::Table).drop_column(Columns::Quarter).to_owned()).await
+    }
+}
+
+#[derive(Iden)]
+enum Columns {
+    BlockNumber,
+    Neighbourhood,
+    Quarter,
+}
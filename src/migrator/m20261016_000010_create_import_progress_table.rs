@@ -0,0 +1,41 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000010_create_import_progress_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.create_table(Table::create()
+            .table(ImportProgress::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(ImportProgress::Phase)
+                    .string()
+                    .not_null()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(ImportProgress::LastPostcode).string().not_null())
+            .col(ColumnDef::new(ImportProgress::UpdatedAt).date_time().not_null())
+            .to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ImportProgress::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum ImportProgress {
+    Table,
+    Phase,
+    LastPostcode,
+    UpdatedAt,
+}
@@ -0,0 +1,41 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000011_create_import_lock_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.create_table(Table::create()
+            .table(ImportLock::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(ImportLock::Name)
+                    .string()
+                    .not_null()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(ImportLock::Holder).string().not_null())
+            .col(ColumnDef::new(ImportLock::AcquiredAt).date_time().not_null())
+            .to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ImportLock::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum ImportLock {
+    Table,
+    Name,
+    Holder,
+    AcquiredAt,
+}
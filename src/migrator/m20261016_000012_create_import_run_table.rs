@@ -0,0 +1,56 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000012_create_import_run_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.create_table(Table::create()
+            .table(ImportRun::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(ImportRun::Id)
+                    .integer()
+                    .not_null()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(ImportRun::Source).string())
+            .col(ColumnDef::new(ImportRun::Generator).string())
+            .col(ColumnDef::new(ImportRun::DataTimestamp).date_time())
+            .col(ColumnDef::new(ImportRun::MinLat).double())
+            .col(ColumnDef::new(ImportRun::MinLon).double())
+            .col(ColumnDef::new(ImportRun::MaxLat).double())
+            .col(ColumnDef::new(ImportRun::MaxLon).double())
+            .col(ColumnDef::new(ImportRun::StartedAt).date_time().not_null())
+            .col(ColumnDef::new(ImportRun::FinishedAt).date_time().not_null())
+            .to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ImportRun::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum ImportRun {
+    Table,
+    Id,
+    Source,
+    Generator,
+    DataTimestamp,
+    MinLat,
+    MinLon,
+    MaxLat,
+    MaxLon,
+    StartedAt,
+    FinishedAt,
+}
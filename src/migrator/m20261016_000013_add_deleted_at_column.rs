@@ -0,0 +1,27 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20231101_000000_create_nodes_table::Node;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000013_add_deleted_at_column"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.alter_table(Table::alter().table(Node::Table).add_column(ColumnDef::new(Columns::DeletedAt).date_time()).to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.alter_table(Table::alter().table(Node::Table).drop_column(Columns::DeletedAt).to_owned()).await
+    }
+}
+
+#[derive(Iden)]
+enum Columns {
+    DeletedAt,
+}
@@ -0,0 +1,33 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20231101_000000_create_nodes_table::Node;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000014_add_house_name_columns"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // SQLite can only add one column per statement
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.alter_table(Table::alter().table(Node::Table).add_column(ColumnDef::new(Columns::HouseName).string()).to_owned()).await?;
+        manager.alter_table(Table::alter().table(Node::Table).add_column(ColumnDef::new(Columns::HouseNameNormalized).string()).to_owned()).await?;
+        manager.create_index(Index::create().if_not_exists().name("idx-house-name").table(Node::Table).col(Columns::HouseNameNormalized).to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_index(Index::drop().name("idx-house-name").table(Node::Table).to_owned()).await?;
+        manager.alter_table(Table::alter().table(Node::Table).drop_column(Columns::HouseNameNormalized).to_owned()).await?;
+        manager.alter_table(Table::alter().table(Node::Table).drop_column(Columns::HouseName).to_owned()).await
+    }
+}
+
+#[derive(Iden)]
+enum Columns {
+    HouseName,
+    HouseNameNormalized,
+}
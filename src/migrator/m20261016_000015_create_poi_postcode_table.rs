@@ -0,0 +1,47 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000015_create_poi_postcode_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.create_table(Table::create()
+            .table(PoiPostcode::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(PoiPostcode::Id)
+                    .big_integer()
+                    .not_null()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(PoiPostcode::Name).string().not_null())
+            .col(ColumnDef::new(PoiPostcode::Postcode).string().not_null())
+            .col(ColumnDef::new(PoiPostcode::Lat).double().not_null())
+            .col(ColumnDef::new(PoiPostcode::Lon).double().not_null())
+            .to_owned()).await?;
+
+        manager.create_index(Index::create().if_not_exists().name("idx-poi-postcode").table(PoiPostcode::Table).col(PoiPostcode::Postcode).to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PoiPostcode::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum PoiPostcode {
+    Table,
+    Id,
+    Name,
+    Postcode,
+    Lat,
+    Lon,
+}
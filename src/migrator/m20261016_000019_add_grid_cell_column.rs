@@ -0,0 +1,29 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20231101_000000_create_nodes_table::Node;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000019_add_grid_cell_column"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.alter_table(Table::alter().table(Node::Table).add_column(ColumnDef::new(Columns::GridCell).string()).to_owned()).await?;
+        manager.create_index(Index::create().if_not_exists().name("idx-grid-cell").table(Node::Table).col(Columns::GridCell).to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_index(Index::drop().name("idx-grid-cell").table(Node::Table).to_owned()).await?;
+        manager.alter_table(Table::alter().table(Node::Table).drop_column(Columns::GridCell).to_owned()).await
+    }
+}
+
+#[derive(Iden)]
+enum Columns {
+    GridCell,
+}
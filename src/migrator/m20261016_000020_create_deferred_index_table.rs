@@ -0,0 +1,39 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000020_create_deferred_index_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.create_table(Table::create()
+            .table(DeferredIndex::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(DeferredIndex::Name)
+                    .string()
+                    .not_null()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(DeferredIndex::Definition).text().not_null())
+            .to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(DeferredIndex::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum DeferredIndex {
+    Table,
+    Name,
+    Definition,
+}
@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{ConnectionTrait, DbBackend};
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000021_create_covering_lookup_index"
+    }
+}
+
+// Lookups by postcode and house number are answered from the index alone. SQLite and MySQL can't include columns
+// that aren't part of the key, there the lookup reads the rows.
+const POSTGRES_UP: &str = "CREATE INDEX IF NOT EXISTS \"idx-postcode-house_number-covering\" ON node (postcode, house_number) \
+    INCLUDE (lat, lon, street, city)";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        match db.get_database_backend() {
+            DbBackend::Postgres => db.execute_unprepared(POSTGRES_UP).await.map(|_| ()),
+            DbBackend::MySql | DbBackend::Sqlite => Ok(()),
+        }
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        match db.get_database_backend() {
+            DbBackend::Postgres => db.execute_unprepared("DROP INDEX IF EXISTS \"idx-postcode-house_number-covering\"").await.map(|_| ()),
+            DbBackend::MySql | DbBackend::Sqlite => Ok(()),
+        }
+    }
+}
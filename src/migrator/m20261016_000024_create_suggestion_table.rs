@@ -0,0 +1,69 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{ConnectionTrait, DbBackend};
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000024_create_suggestion_table"
+    }
+}
+
+// Every backend has its own full text search. SQLite keeps it in an FTS5 table that reads the text from `suggestion`
+// and has to be rebuilt once the suggestions are, see `autocomplete::build`.
+const SQLITE_UP: &str = "CREATE VIRTUAL TABLE IF NOT EXISTS suggestion_fts USING fts5(search_text, content='suggestion', content_rowid='id')";
+
+const POSTGRES_UP: &str = "CREATE INDEX IF NOT EXISTS \"idx-suggestion-search\" ON suggestion USING GIN (to_tsvector('simple', search_text))";
+
+const MYSQL_UP: &str = "CREATE FULLTEXT INDEX `idx-suggestion-search` ON suggestion (search_text)";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.create_table(Table::create()
+            .table(Suggestion::Table)
+            .if_not_exists()
+            .col(ColumnDef::new(Suggestion::Id).big_integer().not_null().auto_increment().primary_key())
+            .col(ColumnDef::new(Suggestion::Street).string().not_null())
+            .col(ColumnDef::new(Suggestion::City).string())
+            .col(ColumnDef::new(Suggestion::Postcode).string().not_null())
+            .col(ColumnDef::new(Suggestion::Country).string())
+            .col(ColumnDef::new(Suggestion::Addresses).integer().not_null())
+            .col(ColumnDef::new(Suggestion::Lat).double().not_null())
+            .col(ColumnDef::new(Suggestion::Lon).double().not_null())
+            .col(ColumnDef::new(Suggestion::SearchText).text().not_null())
+            .to_owned()).await?;
+
+        let db = manager.get_connection();
+
+        match db.get_database_backend() {
+            DbBackend::Sqlite => db.execute_unprepared(SQLITE_UP).await.map(|_| ()),
+            DbBackend::Postgres => db.execute_unprepared(POSTGRES_UP).await.map(|_| ()),
+            DbBackend::MySql => db.execute_unprepared(MYSQL_UP).await.map(|_| ()),
+        }
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        if db.get_database_backend() == DbBackend::Sqlite {
+            db.execute_unprepared("DROP TABLE IF EXISTS suggestion_fts").await?;
+        }
+
+        manager.drop_table(Table::drop().table(Suggestion::Table).to_owned()).await
+    }
+}
+
+#[derive(Iden)]
+enum Suggestion {
+    Table,
+    Id,
+    Street,
+    City,
+    Postcode,
+    Country,
+    Addresses,
+    Lat,
+    Lon,
+    SearchText,
+}
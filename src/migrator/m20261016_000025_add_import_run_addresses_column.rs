@@ -0,0 +1,27 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20261016_000012_create_import_run_table::ImportRun;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000025_add_import_run_addresses_column"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.alter_table(Table::alter().table(ImportRun::Table).add_column(ColumnDef::new(Columns::Addresses).big_integer()).to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.alter_table(Table::alter().table(ImportRun::Table).drop_column(Columns::Addresses).to_owned()).await
+    }
+}
+
+#[derive(Iden)]
+enum Columns {
+    Addresses,
+}
@@ -0,0 +1,38 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000026_create_postcode_timezone_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.create_table(Table::create()
+            .table(PostcodeTimezone::Table)
+            .if_not_exists()
+            .col(ColumnDef::new(PostcodeTimezone::Postcode).string().not_null().primary_key())
+            .col(ColumnDef::new(PostcodeTimezone::Timezone).string().not_null())
+            .col(ColumnDef::new(PostcodeTimezone::Lat).double().not_null())
+            .col(ColumnDef::new(PostcodeTimezone::Lon).double().not_null())
+            .to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PostcodeTimezone::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum PostcodeTimezone {
+    Table,
+    Postcode,
+    Timezone,
+    Lat,
+    Lon,
+}
@@ -0,0 +1,59 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20231101_000000_create_nodes_table::Node;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000028_create_admin_area_table"
+    }
+}
+
+// `node.admin_area_id` and `admin_area.parent_id` have no foreign key constraints, the areas are replaced as a whole
+// by every import that reads them while the addresses still point at the previous ones.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.create_table(Table::create()
+            .table(AdminArea::Table)
+            .if_not_exists()
+            .col(ColumnDef::new(AdminArea::Id).big_integer().not_null().primary_key())
+            .col(ColumnDef::new(AdminArea::Name).string().not_null())
+            .col(ColumnDef::new(AdminArea::AdminLevel).integer().not_null())
+            .col(ColumnDef::new(AdminArea::ParentId).big_integer())
+            .col(ColumnDef::new(AdminArea::Lat).double().not_null())
+            .col(ColumnDef::new(AdminArea::Lon).double().not_null())
+            .col(ColumnDef::new(AdminArea::Geojson).text().not_null())
+            .to_owned()).await?;
+
+        manager.create_index(Index::create().if_not_exists().name("idx-admin-area-parent").table(AdminArea::Table).col(AdminArea::ParentId).to_owned()).await?;
+
+        manager.alter_table(Table::alter().table(Node::Table).add_column(ColumnDef::new(Columns::AdminAreaId).big_integer()).to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.alter_table(Table::alter().table(Node::Table).drop_column(Columns::AdminAreaId).to_owned()).await?;
+
+        manager
+            .drop_table(Table::drop().table(AdminArea::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum AdminArea {
+    Table,
+    Id,
+    Name,
+    AdminLevel,
+    ParentId,
+    Lat,
+    Lon,
+    Geojson,
+}
+
+#[derive(Iden)]
+enum Columns {
+    AdminAreaId,
+}
@@ -0,0 +1,47 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000029_create_postcode_rollup_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.create_table(Table::create()
+            .table(PostcodeRollup::Table)
+            .if_not_exists()
+            .col(ColumnDef::new(PostcodeRollup::Country).string().not_null())
+            .col(ColumnDef::new(PostcodeRollup::Level).string().not_null())
+            .col(ColumnDef::new(PostcodeRollup::Code).string().not_null())
+            .col(ColumnDef::new(PostcodeRollup::Parent).string())
+            .col(ColumnDef::new(PostcodeRollup::Postcodes).integer().not_null())
+            .col(ColumnDef::new(PostcodeRollup::Addresses).integer().not_null())
+            .col(ColumnDef::new(PostcodeRollup::Lat).double().not_null())
+            .col(ColumnDef::new(PostcodeRollup::Lon).double().not_null())
+            .primary_key(Index::create().col(PostcodeRollup::Country).col(PostcodeRollup::Level).col(PostcodeRollup::Code))
+            .to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PostcodeRollup::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum PostcodeRollup {
+    Table,
+    Country,
+    Level,
+    Code,
+    Parent,
+    Postcodes,
+    Addresses,
+    Lat,
+    Lon,
+}
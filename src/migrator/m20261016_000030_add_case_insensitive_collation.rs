@@ -0,0 +1,100 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{ConnectionTrait, DbBackend, Statement, TransactionTrait};
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000030_add_case_insensitive_collation"
+    }
+}
+
+// Street, city and postcode compare case insensitively, so `WHERE city = 'utrecht'` finds `Utrecht`. SQLite can't
+// change the collation of a column, the table is rebuilt from its own definition with `COLLATE NOCASE` added. Postgres
+// uses a nondeterministic ICU collation, which `LIKE` doesn't support on these columns before Postgres 18. MySQL's
+// default collations already ignore case.
+const COLUMNS: [&str; 3] = ["street", "city", "postcode"];
+
+const POSTGRES_ICU: &str = "SELECT 1 FROM pg_collation WHERE collprovider = 'i' AND current_setting('server_encoding') = 'UTF8' LIMIT 1";
+
+const POSTGRES_COLLATION: &str = "CREATE COLLATION IF NOT EXISTS nocase (provider = icu, locale = 'und-u-ks-level2', deterministic = false)";
+
+/// Rebuilds the SQLite `node` table with its definition changed by `change`, keeping its rows and indexes. In a single
+/// transaction, other connections of the pool would still see the dropped table when renaming.
+async fn rebuild_sqlite_node<C: TransactionTrait>(connection: &C, change: impl Fn(&str) -> String) -> Result<(), DbErr> {
+    let db = connection.begin().await?;
+    let schema = |sql: &str| Statement::from_string(DbBackend::Sqlite, sql);
+
+    let table: String = db.query_one(schema("SELECT sql FROM sqlite_master WHERE type = 'table' AND name = 'node'"))
+        .await?
+        .ok_or_else(|| DbErr::Custom("no node table".to_string()))?
+        .try_get_by_index(0)?;
+
+    // Primary keys have automatic indexes without a definition
+    let indexes: Vec<String> = db.query_all(schema("SELECT sql FROM sqlite_master WHERE type = 'index' AND tbl_name = 'node' AND sql IS NOT NULL"))
+        .await?
+        .iter()
+        .map(|row| row.try_get_by_index(0))
+        .collect::<Result<_, _>>()?;
+
+    let rebuilt = change(&table).replacen("\"node\"", "\"node_rebuilt\"", 1);
+
+    db.execute_unprepared(&rebuilt).await?;
+    db.execute_unprepared("INSERT INTO node_rebuilt SELECT * FROM node").await?;
+    db.execute_unprepared("DROP TABLE node").await?;
+    db.execute_unprepared("ALTER TABLE node_rebuilt RENAME TO node").await?;
+
+    for index in indexes {
+        db.execute_unprepared(&index).await?;
+    }
+
+    db.commit().await
+}
+
+/// The definition of a text column in a SQLite `CREATE TABLE`, like `"street" text`.
+fn sqlite_column(column: &str) -> String {
+    format!("\"{}\" text", column)
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        match db.get_database_backend() {
+            DbBackend::Sqlite => rebuild_sqlite_node(db, |table| {
+                COLUMNS.iter().fold(table.to_string(), |table, column| {
+                    table.replacen(&sqlite_column(column), &format!("{} COLLATE NOCASE", sqlite_column(column)), 1)
+                })
+            }).await,
+            DbBackend::Postgres => {
+                // Servers built without ICU, or databases in an encoding it doesn't support, keep comparing case sensitively
+                let icu = db.query_one(Statement::from_string(DbBackend::Postgres, POSTGRES_ICU)).await?;
+
+                if icu.is_none() {
+                    return Ok(());
+                }
+
+                db.execute_unprepared(POSTGRES_COLLATION).await?;
+
+                let columns: Vec<String> = COLUMNS.iter().map(|column| format!("ALTER COLUMN {} TYPE varchar COLLATE nocase", column)).collect();
+                db.execute_unprepared(&format!("ALTER TABLE node {}", columns.join(", "))).await.map(|_| ())
+            }
+            DbBackend::MySql => Ok(()),
+        }
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        match db.get_database_backend() {
+            DbBackend::Sqlite => rebuild_sqlite_node(db, |table| table.replace(" COLLATE NOCASE", "")).await,
+            DbBackend::Postgres => {
+                let columns: Vec<String> = COLUMNS.iter().map(|column| format!("ALTER COLUMN {} TYPE varchar COLLATE \"default\"", column)).collect();
+                db.execute_unprepared(&format!("ALTER TABLE node {}", columns.join(", "))).await?;
+                db.execute_unprepared("DROP COLLATION IF EXISTS nocase").await.map(|_| ())
+            }
+            DbBackend::MySql => Ok(()),
+        }
+    }
+}
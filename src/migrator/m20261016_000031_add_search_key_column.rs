@@ -0,0 +1,67 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{ConnectionTrait, DbBackend, Statement};
+
+use super::m20231101_000000_create_nodes_table::Node;
+use crate::profile::search_key;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000031_add_search_key_column"
+    }
+}
+
+// Stored rows get their key here, it's set on import from then on. The SQL functions only lowercase ASCII and
+// `REPLACE` only removes spaces, so they key the rows of printable ASCII and the others are keyed with
+// `profile::search_key`, like imported rows are.
+const SQLITE_BACKFILL: &str = "UPDATE node SET search_key = LOWER(REPLACE(postcode || house_number, ' ', '')) WHERE house_number IS NOT NULL AND postcode || house_number NOT GLOB '*[^ -~]*'";
+
+// The case-insensitive collation of the postcode doesn't support `REPLACE` or regular expressions
+const POSTGRES_BACKFILL: &str = "UPDATE node SET search_key = LOWER(REPLACE((postcode || house_number) COLLATE \"C\", ' ', '')) WHERE house_number IS NOT NULL AND (postcode || house_number) COLLATE \"C\" !~ '[^ -~]'";
+
+const MYSQL_BACKFILL: &str = "UPDATE node SET search_key = LOWER(REPLACE(CONCAT(postcode, house_number), ' ', '')) WHERE house_number IS NOT NULL AND CONCAT(postcode, house_number) NOT REGEXP '[^ -~]'";
+
+const UNKEYED: &str = "SELECT id, postcode, house_number FROM node WHERE house_number IS NOT NULL AND search_key IS NULL";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.alter_table(Table::alter().table(Node::Table).add_column(ColumnDef::new(Columns::SearchKey).string()).to_owned()).await?;
+
+        let db = manager.get_connection();
+
+        match db.get_database_backend() {
+            DbBackend::Sqlite => db.execute_unprepared(SQLITE_BACKFILL).await?,
+            DbBackend::Postgres => db.execute_unprepared(POSTGRES_BACKFILL).await?,
+            DbBackend::MySql => db.execute_unprepared(MYSQL_BACKFILL).await?,
+        };
+
+        for row in db.query_all(Statement::from_string(db.get_database_backend(), UNKEYED)).await? {
+            let id: i64 = row.try_get("", "id")?;
+            let postcode: String = row.try_get("", "postcode")?;
+            let house_number: String = row.try_get("", "house_number")?;
+
+            manager.exec_stmt(
+                Query::update()
+                    .table(Node::Table)
+                    .value(Columns::SearchKey, search_key(&postcode, Some(&house_number)))
+                    .and_where(Expr::col(Node::Id).eq(id))
+                    .to_owned(),
+            ).await?;
+        }
+
+        // Not unique, addresses mapped twice or with units share a key
+        manager.create_index(Index::create().if_not_exists().name("idx-search-key").table(Node::Table).col(Columns::SearchKey).to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_index(Index::drop().name("idx-search-key").table(Node::Table).to_owned()).await?;
+        manager.alter_table(Table::alter().table(Node::Table).drop_column(Columns::SearchKey).to_owned()).await
+    }
+}
+
+#[derive(Iden)]
+enum Columns {
+    SearchKey,
+}
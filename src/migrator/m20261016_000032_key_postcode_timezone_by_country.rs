@@ -0,0 +1,51 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000032_key_postcode_timezone_by_country"
+    }
+}
+
+// The same postcode in two countries had a single timezone, looked up at the center of the addresses of both. The
+// table only holds what `--timezones` derives from the addresses, so it's recreated empty instead of guessing the
+// country of the old rows, the next import with `--timezones` fills it again.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table(Table::drop().table(PostcodeTimezone::Table).to_owned()).await?;
+
+        manager.create_table(Table::create()
+            .table(PostcodeTimezone::Table)
+            .col(ColumnDef::new(PostcodeTimezone::Country).string().not_null())
+            .col(ColumnDef::new(PostcodeTimezone::Postcode).string().not_null())
+            .col(ColumnDef::new(PostcodeTimezone::Timezone).string().not_null())
+            .col(ColumnDef::new(PostcodeTimezone::Lat).double().not_null())
+            .col(ColumnDef::new(PostcodeTimezone::Lon).double().not_null())
+            .primary_key(Index::create().col(PostcodeTimezone::Country).col(PostcodeTimezone::Postcode))
+            .to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table(Table::drop().table(PostcodeTimezone::Table).to_owned()).await?;
+
+        manager.create_table(Table::create()
+            .table(PostcodeTimezone::Table)
+            .col(ColumnDef::new(PostcodeTimezone::Postcode).string().not_null().primary_key())
+            .col(ColumnDef::new(PostcodeTimezone::Timezone).string().not_null())
+            .col(ColumnDef::new(PostcodeTimezone::Lat).double().not_null())
+            .col(ColumnDef::new(PostcodeTimezone::Lon).double().not_null())
+            .to_owned()).await
+    }
+}
+
+#[derive(Iden)]
+enum PostcodeTimezone {
+    Table,
+    Country,
+    Postcode,
+    Timezone,
+    Lat,
+    Lon,
+}
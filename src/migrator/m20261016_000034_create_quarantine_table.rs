@@ -0,0 +1,48 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20261016_000034_create_quarantine_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.create_table(Table::create()
+            .table(Quarantine::Table)
+            .if_not_exists()
+            .col(ColumnDef::new(Quarantine::Id).big_integer().not_null().primary_key())
+            .col(ColumnDef::new(Quarantine::Reason).string().not_null())
+            .col(ColumnDef::new(Quarantine::Country).string())
+            .col(ColumnDef::new(Quarantine::Postcode).string())
+            .col(ColumnDef::new(Quarantine::Lat).double())
+            .col(ColumnDef::new(Quarantine::Lon).double())
+            .col(ColumnDef::new(Quarantine::Tags).json().not_null())
+            .col(ColumnDef::new(Quarantine::QuarantinedAt).date_time().not_null())
+            .to_owned()).await?;
+
+        manager.create_index(Index::create().if_not_exists().name("idx-quarantine-reason").table(Quarantine::Table).col(Quarantine::Reason).to_owned()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Quarantine::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Quarantine {
+    Table,
+    Id,
+    Reason,
+    Country,
+    Postcode,
+    Lat,
+    Lon,
+    Tags,
+    QuarantinedAt,
+}
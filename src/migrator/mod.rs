@@ -1,7 +1,43 @@
+use sea_orm::EntityTrait;
 use sea_orm_migration::prelude::*;
+use sea_orm_migration::seaql_migrations;
 use sea_orm_migration::MigratorTrait;
 
 mod m20231101_000000_create_nodes_table;
+mod m20261016_000001_create_location_index;
+mod m20261016_000002_create_api_key_table;
+mod m20261016_000003_create_distance_function;
+mod m20261016_000004_create_postcode_area_table;
+mod m20261016_000005_create_postcode_neighbors_table;
+mod m20261016_000006_add_us_address_columns;
+mod m20261016_000007_add_outcode_columns;
+mod m20261016_000008_add_japanese_address_columns;
+mod m20261016_000009_add_entrance_column;
+mod m20261016_000010_create_import_progress_table;
+mod m20261016_000011_create_import_lock_table;
+mod m20261016_000012_create_import_run_table;
+mod m20261016_000013_add_deleted_at_column;
+mod m20261016_000014_add_house_name_columns;
+mod m20261016_000015_create_poi_postcode_table;
+mod m20261016_000016_add_superseded_by_column;
+mod m20261016_000017_add_source_rank_column;
+mod m20261016_000018_add_plus_code_column;
+mod m20261016_000019_add_grid_cell_column;
+mod m20261016_000020_create_deferred_index_table;
+mod m20261016_000021_create_covering_lookup_index;
+mod m20261016_000022_add_raw_tags_column;
+mod m20261016_000023_add_qa_note_column;
+mod m20261016_000024_create_suggestion_table;
+mod m20261016_000025_add_import_run_addresses_column;
+mod m20261016_000026_create_postcode_timezone_table;
+mod m20261016_000027_add_elevation_column;
+mod m20261016_000028_create_admin_area_table;
+mod m20261016_000029_create_postcode_rollup_table;
+mod m20261016_000030_add_case_insensitive_collation;
+mod m20261016_000031_add_search_key_column;
+mod m20261016_000032_key_postcode_timezone_by_country;
+mod m20261016_000033_add_h3_column;
+mod m20261016_000034_create_quarantine_table;
 
 pub struct Migrator;
 
@@ -10,6 +46,56 @@ impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
         vec![
             Box::new(m20231101_000000_create_nodes_table::Migration),
+            Box::new(m20261016_000001_create_location_index::Migration),
+            Box::new(m20261016_000002_create_api_key_table::Migration),
+            Box::new(m20261016_000003_create_distance_function::Migration),
+            Box::new(m20261016_000004_create_postcode_area_table::Migration),
+            Box::new(m20261016_000005_create_postcode_neighbors_table::Migration),
+            Box::new(m20261016_000006_add_us_address_columns::Migration),
+            Box::new(m20261016_000007_add_outcode_columns::Migration),
+            Box::new(m20261016_000008_add_japanese_address_columns::Migration),
+            Box::new(m20261016_000009_add_entrance_column::Migration),
+            Box::new(m20261016_000010_create_import_progress_table::Migration),
+            Box::new(m20261016_000011_create_import_lock_table::Migration),
+            Box::new(m20261016_000012_create_import_run_table::Migration),
+            Box::new(m20261016_000013_add_deleted_at_column::Migration),
+            Box::new(m20261016_000014_add_house_name_columns::Migration),
+            Box::new(m20261016_000015_create_poi_postcode_table::Migration),
+            Box::new(m20261016_000016_add_superseded_by_column::Migration),
+            Box::new(m20261016_000017_add_source_rank_column::Migration),
+            Box::new(m20261016_000018_add_plus_code_column::Migration),
+            Box::new(m20261016_000019_add_grid_cell_column::Migration),
+            Box::new(m20261016_000020_create_deferred_index_table::Migration),
+            Box::new(m20261016_000021_create_covering_lookup_index::Migration),
+            Box::new(m20261016_000022_add_raw_tags_column::Migration),
+            Box::new(m20261016_000023_add_qa_note_column::Migration),
+            Box::new(m20261016_000024_create_suggestion_table::Migration),
+            Box::new(m20261016_000025_add_import_run_addresses_column::Migration),
+            Box::new(m20261016_000026_create_postcode_timezone_table::Migration),
+            Box::new(m20261016_000027_add_elevation_column::Migration),
+            Box::new(m20261016_000028_create_admin_area_table::Migration),
+            Box::new(m20261016_000029_create_postcode_rollup_table::Migration),
+            Box::new(m20261016_000030_add_case_insensitive_collation::Migration),
+            Box::new(m20261016_000031_add_search_key_column::Migration),
+            Box::new(m20261016_000032_key_postcode_timezone_by_country::Migration),
+            Box::new(m20261016_000033_add_h3_column::Migration),
+            Box::new(m20261016_000034_create_quarantine_table::Migration),
         ]
     }
-}
\ No newline at end of file
+}
+/// Names of the migrations that haven't been applied yet. Unlike `Migrator::get_pending_migrations` this doesn't
+/// try to create the migrations table, so it works on read-only connections.
+pub async fn pending_migrations<C: ConnectionTrait>(db: &C) -> Result<Vec<String>, DbErr> {
+    let applied: Vec<String> = seaql_migrations::Entity::find()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|migration| migration.version)
+        .collect();
+
+    Ok(Migrator::migrations()
+        .iter()
+        .map(|migration| migration.name().to_string())
+        .filter(|name| !applied.contains(name))
+        .collect())
+}
@@ -0,0 +1,91 @@
+//! Tells other systems an import finished by POSTing a JSON summary to `--notify-url`, so they can pick up the new
+//! database without polling for it. A notification that can't be delivered is only a warning, the import itself
+//! already succeeded or failed.
+
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use sea_orm::{ConnectionTrait, PaginatorTrait};
+use serde_json::{json, Value};
+use sqlx::sqlite::SqliteConnectOptions;
+
+use crate::database::{self, live_nodes};
+use crate::package::sha256;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How an import ended, the exit code and message of the error when it failed.
+pub type Outcome = Result<(), (i32, String)>;
+
+/// The live addresses in the database, with the write-ahead log of a SQLite file moved into it so it can be hashed.
+pub async fn addresses(db_uri: &str) -> Result<u64, sea_orm::DbErr> {
+    let db = database::connect(db_uri, false, false).await?;
+
+    if db_uri.starts_with("sqlite:") {
+        db.execute_unprepared("PRAGMA wal_checkpoint(TRUNCATE)").await?;
+    }
+
+    let addresses = live_nodes().count(&db).await?;
+    db.close().await?;
+
+    Ok(addresses)
+}
+
+/// The file, size and SHA-256 of a SQLite database, `None` for servers.
+fn artifact(db_uri: &str) -> Option<Value> {
+    let path = SqliteConnectOptions::from_str(db_uri).ok()?.get_filename().to_path_buf();
+    let bytes = std::fs::metadata(&path).ok()?.len();
+
+    Some(json!({
+        "path": path.display().to_string(),
+        "bytes": bytes,
+        "sha256": sha256(&path).ok(),
+    }))
+}
+
+/// The summary of an import of `source` into `db_uri`, and the URL it was published to. The database is only described
+/// when the import succeeded.
+pub async fn summary(db_uri: &str, source: Option<&Path>, published: Option<&str>, started_at: DateTime<Local>, outcome: &Outcome) -> Value {
+    let mut summary = json!({
+        "status": if outcome.is_ok() { "succeeded" } else { "failed" },
+        "exit_code": outcome.as_ref().err().map_or(0, |(code, _)| *code),
+        "error": outcome.as_ref().err().map(|(_, message)| message),
+        "source": source.map(|source| source.display().to_string()),
+        "started_at": started_at.to_rfc3339(),
+        "finished_at": Local::now().to_rfc3339(),
+        "addresses": null,
+        "artifact": null,
+        "published": published,
+    });
+
+    if outcome.is_ok() {
+        match addresses(db_uri).await {
+            Ok(addresses) => summary["addresses"] = json!(addresses),
+            Err(e) => println!("Warning: failed to count the addresses for the notification: {}", e),
+        }
+
+        if db_uri.starts_with("sqlite:") {
+            summary["artifact"] = json!(artifact(db_uri));
+        }
+    }
+
+    summary
+}
+
+/// POSTs the summary to the URL, printing a warning when that fails.
+pub async fn send(url: &str, summary: &Value) {
+    let response = reqwest::Client::new()
+        .post(url)
+        .timeout(TIMEOUT)
+        .json(summary)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+
+    match response {
+        Ok(_) => println!("Notified {}", url),
+        Err(e) => println!("Warning: failed to notify {}: {}", url, e),
+    }
+}
@@ -0,0 +1,308 @@
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use sea_orm::{ActiveValue, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait, Iterable, QueryFilter, QueryTrait, TransactionTrait, TryIntoModel};
+use sea_orm::sea_query::{Expr, OnConflict, UpdateStatement};
+use serde_json::json;
+
+use crate::batch::{Failed, Sink, Statements};
+use crate::database;
+use crate::entities::*;
+use crate::merge::{Conflict, MergePolicy};
+use crate::metrics;
+
+pub type OutputResult = Result<(), Box<dyn Error + Send + Sync>>;
+
+/// Deleted elements per update statement.
+const DELETE_ROWS: usize = 1024;
+
+/// Where parsed nodes are written to.
+#[derive(Clone)]
+pub enum Output {
+    Database(Arc<DatabaseConnection>, Arc<MergePolicy>, Conflict),
+    Elastic(Arc<ElasticOutput>),
+    Preview(Arc<Preview>),
+}
+
+impl Output {
+    /// The number of rows after which parsing can stop, for previews.
+    pub fn limit(&self) -> Option<usize> {
+        match self {
+            Output::Preview(preview) => Some(preview.limit),
+            _ => None,
+        }
+    }
+}
+
+impl Sink for Output {
+    type Row = node::ActiveModel;
+    /// The id and the version that deleted the element
+    type Key = (i64, i32);
+    type Error = Box<dyn Error + Send + Sync>;
+    type Batch = Pending;
+
+    fn id(row: &node::ActiveModel) -> Option<i64> {
+        match row.id {
+            ActiveValue::Set(id) => Some(id),
+            _ => None,
+        }
+    }
+
+    fn transient(e: &Self::Error) -> bool {
+        match e.downcast_ref::<DbErr>() {
+            Some(e) => database::transient(e),
+            None => e.downcast_ref::<reqwest::Error>().is_some_and(|e| e.is_connect() || e.is_timeout()),
+        }
+    }
+
+    fn batch(&self, batch: Vec<node::ActiveModel>, deleted: Vec<(i64, i32)>) -> Pending {
+        Pending::Nodes(batch, deleted)
+    }
+
+    /// Writes a batch of nodes and removes the deleted elements, in a single transaction for databases.
+    async fn write(&self, batch: Pending) -> Result<(), Failed<Self>> {
+        let _timer = metrics::BATCH_DURATION.start_timer();
+
+        let batch = match (self, batch) {
+            (_, Pending::Nodes(batch, deleted)) if batch.is_empty() && deleted.is_empty() => return Ok(()),
+            (Output::Database(db, policy, conflict), Pending::Nodes(batch, deleted)) => transaction(db, policy, *conflict, batch, deleted).await?,
+            (Output::Elastic(elastic), Pending::Nodes(batch, deleted)) => match elastic.bulk_body(batch, deleted) {
+                Some(bulk) => bulk,
+                // Every node in the batch can have been skipped
+                None => return Ok(()),
+            },
+            (Output::Preview(preview), Pending::Nodes(batch, _)) => {
+                metrics::INSERTED_ROWS.inc_by(batch.len() as u64);
+                preview.collect(batch);
+                return Ok(());
+            }
+            (_, prepared) => prepared,
+        };
+
+        let written = match (self, &batch) {
+            (Output::Database(db, ..), Pending::Transaction { rows, statements, deletions }) => {
+                commit(db, statements, deletions).await.map(|()| *rows).map_err(Into::into)
+            }
+            (Output::Elastic(elastic), Pending::Bulk { rows, body }) => elastic.bulk_index(body.clone()).await.map(|()| *rows),
+            _ => unreachable!("a batch is prepared by the output it's written to"),
+        };
+
+        match written {
+            Ok(rows) => {
+                metrics::INSERTED_ROWS.inc_by(rows);
+                Ok(())
+            }
+            Err(error) => Err(Failed { error, batch }),
+        }
+    }
+}
+
+/// A batch of nodes on its way to an [`Output`], handed back as far as it got when writing it failed.
+pub enum Pending {
+    /// The nodes and the id and version of the deleted elements, as they were collected
+    Nodes(Vec<node::ActiveModel>, Vec<(i64, i32)>),
+    /// The statements writing the nodes and the ones marking deleted rows, run in a single transaction
+    Transaction { rows: u64, statements: Statements, deletions: Vec<UpdateStatement> },
+    /// The body of a _bulk request
+    Bulk { rows: u64, body: Bytes },
+}
+
+/// Turns the nodes into the statements of a transaction, after merging them with the stored rows for updates.
+async fn transaction(db: &DatabaseConnection, policy: &MergePolicy, conflict: Conflict, mut batch: Vec<node::ActiveModel>, deleted: Vec<(i64, i32)>) -> Result<Pending, Failed<Output>> {
+    let rows = batch.len() as u64;
+
+    // Only updates need the stored rows
+    if conflict == Conflict::Update {
+        for chunk in batch.chunks_mut(database::insert_rows::<node::Entity>()) {
+            if let Err(e) = policy.apply(db, chunk).await {
+                return Err(Failed { error: e.into(), batch: Pending::Nodes(batch, deleted) });
+            }
+        }
+    }
+
+    let on_conflict = match conflict {
+        Conflict::Update => Some(OnConflict::column(node::Column::Id).update_columns(node::Column::iter()).to_owned()),
+        Conflict::Ignore => Some(OnConflict::column(node::Column::Id).do_nothing().to_owned()),
+        Conflict::Error => None,
+    };
+
+    let mut statements: Statements = Vec::new();
+
+    while !batch.is_empty() {
+        let rest = batch.split_off(batch.len().min(database::insert_rows::<node::Entity>()));
+
+        // A deleted element that's added again is stored as new, which the upsert does in update mode
+        if conflict != Conflict::Update {
+            let ids: Vec<i64> = batch.iter().filter_map(Output::id).collect();
+
+            statements.push(Box::new(node::Entity::delete_many()
+                .filter(node::Column::Id.is_in(ids))
+                .filter(node::Column::DeletedAt.is_not_null())
+                .into_query()));
+        }
+
+        let mut insert = node::Entity::insert_many(batch);
+
+        if let Some(on_conflict) = &on_conflict {
+            insert = insert.on_conflict(on_conflict.clone());
+        }
+
+        // Without returning, as ignoring every row of a statement isn't an error
+        statements.push(Box::new(insert.into_query()));
+        batch = rest;
+    }
+
+    // Rows are only marked as deleted, so consumers syncing from the database see the removal. Rows stored from a
+    // newer version than the deletion stay, like updates do
+    let deleted_at = chrono::offset::Local::now().naive_local();
+
+    let deletions = deleted.chunks(DELETE_ROWS)
+        .map(|deleted| node::Entity::update_many()
+            .col_expr(node::Column::DeletedAt, Expr::value(deleted_at))
+            .filter(node::Column::DeletedAt.is_null())
+            .filter(deleted.iter().fold(Condition::any(), |condition, (id, version)| condition.add(
+                node::Column::Id.eq(*id).and(node::Column::Version.lte(*version))
+            )))
+            .into_query())
+        .collect();
+
+    Ok(Pending::Transaction { rows, statements, deletions })
+}
+
+async fn commit(db: &DatabaseConnection, statements: &Statements, deletions: &[UpdateStatement]) -> Result<(), DbErr> {
+    let transaction = db.begin().await?;
+    let backend = transaction.get_database_backend();
+
+    for statement in statements {
+        transaction.execute(statement.build(&backend)).await?;
+    }
+
+    for deletion in deletions {
+        let removed = transaction.execute(backend.build(deletion)).await?;
+
+        metrics::DELETED_ROWS.inc_by(removed.rows_affected());
+    }
+
+    transaction.commit().await
+}
+
+/// Keeps the first rows in memory to show them instead of writing anything.
+pub struct Preview {
+    limit: usize,
+    rows: Mutex<Vec<node::Model>>,
+}
+
+impl Preview {
+    pub fn new(limit: usize) -> Self {
+        Self { limit, rows: Mutex::new(Vec::with_capacity(limit)) }
+    }
+
+    fn collect(&self, batch: Vec<node::ActiveModel>) {
+        let mut rows = self.rows.lock().expect("preview lock poisoned");
+        let room = self.limit.saturating_sub(rows.len());
+
+        rows.extend(batch.into_iter().filter_map(|node| node.try_into_model().ok()).take(room));
+    }
+
+    pub fn rows(&self) -> Vec<node::Model> {
+        self.rows.lock().expect("preview lock poisoned").clone()
+    }
+}
+
+/// Bulk indexes nodes into an Elasticsearch/OpenSearch index.
+pub struct ElasticOutput {
+    client: reqwest::Client,
+    base_url: String,
+    index: String,
+}
+
+impl ElasticOutput {
+    /// Parses `elastic://host:port/index`, use `elastics://` for https.
+    pub fn from_uri(uri: &str) -> Option<Self> {
+        let (scheme, rest) = uri.split_once("://")?;
+        let scheme = match scheme {
+            "elastic" => "http",
+            "elastics" => "https",
+            _ => return None,
+        };
+        let (host, index) = rest.trim_end_matches('/').rsplit_once('/')?;
+
+        Some(Self {
+            client: reqwest::Client::new(),
+            base_url: format!("{}://{}", scheme, host),
+            index: index.to_string(),
+        })
+    }
+
+    /// The _bulk request indexing the nodes and deleting the deleted elements, `None` when there's nothing to send as an
+    /// empty body is refused by the _bulk API. Documents carry their OSM version as an external version, so like in a
+    /// database an older version doesn't replace or delete a document stored from a newer one.
+    fn bulk_body(&self, batch: Vec<node::ActiveModel>, deleted: Vec<(i64, i32)>) -> Option<Pending> {
+        let mut rows = 0;
+        let mut body = String::new();
+
+        for (id, version) in deleted {
+            body.push_str(&json!({ "delete": { "_index": self.index, "_id": id, "version": version, "version_type": "external_gte" } }).to_string());
+            body.push('\n');
+        }
+
+        for node in batch {
+            let model = match node.try_into_model() {
+                Ok(model) => model,
+                Err(e) => {
+                    println!("Warning: skipped incomplete node: {}", e);
+                    continue;
+                }
+            };
+
+            body.push_str(&json!({ "index": { "_index": self.index, "_id": model.id, "version": model.version, "version_type": "external_gte" } }).to_string());
+            body.push('\n');
+            body.push_str(&serde_json::to_string(&model).expect("a node serializes to JSON"));
+            body.push('\n');
+            rows += 1;
+        }
+
+        (!body.is_empty()).then(|| Pending::Bulk { rows, body: Bytes::from(body) })
+    }
+
+    /// Sends a _bulk request, which fails when any of its documents was rejected.
+    async fn bulk_index(&self, body: Bytes) -> OutputResult {
+        let response: serde_json::Value = self.client
+            .post(format!("{}/_bulk", self.base_url))
+            .header("Content-Type", "application/x-ndjson")
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let Some(errors) = response["errors"].as_bool() else {
+            return Err(format!("unexpected _bulk response: {}", response).into());
+        };
+
+        // Every item has a single action, index or delete, with an error when it failed. A version conflict is a document
+        // stored from a newer version, which is kept like it is in a database
+        if errors {
+            let failed: Vec<&serde_json::Value> = response["items"].as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|item| item.as_object()?.values().next()?.get("error"))
+                .filter(|error| error["type"] != "version_conflict_engine_exception")
+                .collect();
+
+            if failed.is_empty() {
+                return Ok(());
+            }
+
+            return Err(format!(
+                "{} documents failed to index, the first with {}",
+                failed.len(),
+                failed.first().map_or("no error in the response".to_string(), |error| error.to_string()),
+            ).into());
+        }
+
+        Ok(())
+    }
+}
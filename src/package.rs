@@ -0,0 +1,149 @@
+//! Describes a finished SQLite artifact in a `manifest.json`: how many rows it has, the area it covers, the extract it
+//! was built from and the SHA-256 of the file. Signed with an Ed25519 key, apps that download the artifact can check
+//! both that it's complete and who built it before loading it.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use clap::{arg, value_parser, ArgMatches, Command};
+use ed25519_dalek::pkcs8::DecodePrivateKey;
+use ed25519_dalek::{Signer, SigningKey};
+use sea_orm::sea_query::{Expr, Func, SimpleExpr};
+use sea_orm::{ConnectionTrait, DatabaseConnection, EntityTrait, FromQueryResult, PaginatorTrait, QueryOrder, QuerySelect};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::sqlite::SqliteConnectOptions;
+
+use crate::database::live_nodes;
+use crate::entities::*;
+
+pub fn cli() -> Command {
+    Command::new("package")
+        .about("Writes a manifest.json with the row counts, bounding box, extract and SHA-256 of a SQLite database, optionally signed")
+        .arg(arg!(--output <FILE> "Manifest to write, the signature goes next to it with .sig appended").default_value("manifest.json").value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--"signing-key" <PEM> "Ed25519 private key in PKCS#8 PEM, like `openssl genpkey -algorithm ed25519` writes").value_parser(value_parser!(PathBuf)))
+}
+
+#[derive(FromQueryResult)]
+struct Bounds {
+    min_lat: Option<f64>,
+    min_lon: Option<f64>,
+    max_lat: Option<f64>,
+    max_lon: Option<f64>,
+}
+
+pub fn sha256(path: &Path) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 20];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+
+        if read == 0 {
+            return Ok(hex::encode(hasher.finalize()));
+        }
+
+        hasher.update(&buffer[..read]);
+    }
+}
+
+pub async fn run(db: &DatabaseConnection, db_uri: &str, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    if !db_uri.starts_with("sqlite:") {
+        return Err("package describes a SQLite database file, --db is a server".into());
+    }
+
+    let output = matches.get_one::<PathBuf>("output").expect("defaulted in clap");
+    let signing_key = matches.get_one::<PathBuf>("signing-key")
+        .map(|path| {
+            let pem = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+            SigningKey::from_pkcs8_pem(&pem).map_err(|e| format!("{} isn't an Ed25519 private key: {}", path.display(), e))
+        })
+        .transpose()?;
+
+    let bounds = live_nodes()
+        .select_only()
+        .column_as(Expr::col(node::Column::Lat).min(), "min_lat")
+        .column_as(Expr::col(node::Column::Lon).min(), "min_lon")
+        .column_as(Expr::col(node::Column::Lat).max(), "max_lat")
+        .column_as(Expr::col(node::Column::Lon).max(), "max_lon")
+        .into_model::<Bounds>()
+        .one(db)
+        .await?;
+    let bbox = match bounds {
+        Some(Bounds { min_lat: Some(min_lat), min_lon: Some(min_lon), max_lat: Some(max_lat), max_lon: Some(max_lon) }) => json!([min_lon, min_lat, max_lon, max_lat]),
+        _ => json!(null),
+    };
+
+    let postcodes: Option<i64> = live_nodes()
+        .select_only()
+        .column_as(SimpleExpr::from(Func::count_distinct(Expr::col(node::Column::Postcode))), "postcodes")
+        .into_tuple()
+        .one(db)
+        .await?;
+
+    let run = import_run::Entity::find()
+        .order_by_desc(import_run::Column::Id)
+        .one(db)
+        .await?;
+
+    let rows = json!({
+        "node": live_nodes().count(db).await?,
+        "admin_area": admin_area::Entity::find().count(db).await?,
+        "postcode_area": postcode_area::Entity::find().count(db).await?,
+        "postcode_neighbors": postcode_neighbors::Entity::find().count(db).await?,
+        "postcode_rollup": postcode_rollup::Entity::find().count(db).await?,
+        "postcode_timezone": postcode_timezone::Entity::find().count(db).await?,
+        "suggestion": suggestion::Entity::find().count(db).await?,
+        "poi_postcode": poi_postcode::Entity::find().count(db).await?,
+    });
+
+    // Everything has to be in the main file before it's hashed
+    db.execute_unprepared("PRAGMA wal_checkpoint(TRUNCATE)").await?;
+
+    let path = SqliteConnectOptions::from_str(db_uri)?.get_filename().to_path_buf();
+    let bytes = std::fs::metadata(&path)?.len();
+
+    let mut manifest = json!({
+        "generator": format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        "created_at": chrono::Utc::now().to_rfc3339(),
+        "database": {
+            "file": path.file_name().map(|name| name.to_string_lossy().to_string()),
+            "bytes": bytes,
+            "sha256": sha256(&path)?,
+        },
+        "rows": rows,
+        "postcodes": postcodes.unwrap_or(0),
+        "bbox": bbox,
+        "extract": run.map(|run| json!({
+            "source": run.source,
+            "generator": run.generator,
+            "timestamp": run.data_timestamp.map(|timestamp| timestamp.and_utc().to_rfc3339()),
+            "imported_at": run.finished_at,
+        })),
+    });
+
+    if let Some(signing_key) = &signing_key {
+        manifest["public_key"] = json!(hex::encode(signing_key.verifying_key().as_bytes()));
+    }
+
+    // The signature is over these exact bytes, the file mustn't be reformatted
+    let contents = serde_json::to_string_pretty(&manifest)? + "\n";
+    std::fs::write(output, &contents).map_err(|e| format!("{}: {}", output.display(), e))?;
+    println!("Wrote {}", output.display());
+
+    if let Some(signing_key) = signing_key {
+        let mut signature_path = output.as_os_str().to_owned();
+        signature_path.push(".sig");
+
+        std::fs::write(&signature_path, signing_key.sign(contents.as_bytes()).to_bytes())
+            .map_err(|e| format!("{}: {}", Path::new(&signature_path).display(), e))?;
+        println!("Wrote {}", Path::new(&signature_path).display());
+    }
+
+    Ok(())
+}
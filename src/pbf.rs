@@ -0,0 +1,766 @@
+//! OSM PBF input, read as the same events as XML.
+//!
+//! Unlike XML the file is read more than once. With `--ways` or `--admin-areas` a first scan collects the ids of the
+//! nodes that the ways to locate reference, a second keeps the locations of just those nodes, and the import then
+//! locates every way from that index. Extracts are sorted by type and id, so all nodes come before the first way and
+//! the nodes of every block are merged against the sorted ids rather than looked up one by one. Blobs are
+//! decompressed and decoded on every core a batch at a time, ways are located within the same per blob work, and the
+//! events are handed on in the order of the file.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use flate2::read::ZlibDecoder;
+use rayon::prelude::*;
+use sea_orm::prelude::DateTime as NaiveDateTime;
+
+use crate::error::Error;
+use crate::header::Header;
+use crate::metrics;
+use crate::ways::NodeIndex;
+use crate::{ParsedAttributeMap, ParsedElementEvent};
+
+/// Blobs decoded at once for every thread.
+const BLOBS_PER_THREAD: usize = 4;
+
+/// The largest blob header and blob the format allows.
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+const MAX_BLOB_SIZE: usize = 32 * 1024 * 1024;
+
+/// Features a file can require that this reader understands, others are refused.
+const FEATURES: [&str; 3] = ["OsmSchema-V0.6", "DenseNodes", "HistoricalInformation"];
+
+/// Decoding happens on other threads, so its errors are messages that become [`Error::Input`] once they're back.
+fn invalid(message: impl std::fmt::Display) -> String {
+    format!("invalid PBF: {}", message)
+}
+
+/// A field of a protobuf message. The format only uses varints and length delimited fields.
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+fn varint(data: &mut &[u8]) -> Result<u64, String> {
+    let mut value = 0;
+
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first().ok_or_else(|| invalid("truncated varint"))?;
+        *data = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(invalid("varint longer than 64 bits"))
+}
+
+fn zigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+/// The fields of a message as their number and value, fixed size fields are skipped.
+struct Fields<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn field(&mut self) -> Result<Option<(u64, Field<'a>)>, String> {
+        while !self.data.is_empty() {
+            let key = varint(&mut self.data)?;
+
+            let skip = match key & 7 {
+                0 => return Ok(Some((key >> 3, Field::Varint(varint(&mut self.data)?)))),
+                2 => {
+                    let length = varint(&mut self.data)? as usize;
+
+                    if length > self.data.len() {
+                        return Err(invalid("truncated field"));
+                    }
+
+                    let (bytes, rest) = self.data.split_at(length);
+                    self.data = rest;
+
+                    return Ok(Some((key >> 3, Field::Bytes(bytes))));
+                }
+                1 => 8,
+                5 => 4,
+                other => return Err(invalid(format!("unknown wire type {}", other))),
+            };
+
+            self.data = self.data.get(skip..).ok_or_else(|| invalid("truncated field"))?;
+        }
+
+        Ok(None)
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u64, Field<'a>), String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let field = self.field();
+
+        // Nothing after a malformed field can be trusted
+        if field.is_err() {
+            self.data = &[];
+        }
+
+        field.transpose()
+    }
+}
+
+/// The values of a packed repeated field.
+fn packed(mut data: &[u8]) -> Result<Vec<u64>, String> {
+    let mut values = Vec::new();
+
+    while !data.is_empty() {
+        values.push(varint(&mut data)?);
+    }
+
+    Ok(values)
+}
+
+/// Delta coded, zigzag encoded values like ids and coordinates.
+fn deltas(data: &[u8]) -> Result<Vec<i64>, String> {
+    let mut last = 0i64;
+
+    Ok(packed(data)?.into_iter().map(|delta| {
+        last = last.wrapping_add(zigzag(delta));
+        last
+    }).collect())
+}
+
+/// A blob as it's stored in the file, decompressed on one of the threads.
+struct Blob {
+    kind: String,
+    data: Vec<u8>,
+}
+
+impl Blob {
+    fn decompress(&self) -> Result<Vec<u8>, String> {
+        let (mut raw, mut zlib, mut zstd, mut size) = (None, None, None, 0);
+
+        for field in Fields::new(&self.data) {
+            match field? {
+                (1, Field::Bytes(bytes)) => raw = Some(bytes),
+                (2, Field::Varint(value)) => size = value as usize,
+                (3, Field::Bytes(bytes)) => zlib = Some(bytes),
+                (7, Field::Bytes(bytes)) => zstd = Some(bytes),
+                (4..=6, _) => return Err(invalid("only uncompressed, zlib and zstd compressed blobs are supported")),
+                _ => {}
+            }
+        }
+
+        if size > MAX_BLOB_SIZE {
+            return Err(invalid(format!("blob of {} bytes, more than the format allows", size)));
+        }
+
+        let mut data = Vec::with_capacity(size);
+
+        match (raw, zlib, zstd) {
+            (Some(raw), _, _) => data.extend_from_slice(raw),
+            (_, Some(zlib), _) => {
+                ZlibDecoder::new(zlib).take(MAX_BLOB_SIZE as u64).read_to_end(&mut data).map_err(invalid)?;
+            }
+            (_, _, Some(zstd)) => {
+                zstd::Decoder::new(zstd).map_err(invalid)?.take(MAX_BLOB_SIZE as u64).read_to_end(&mut data).map_err(invalid)?;
+            }
+            _ => return Err(invalid("blob without data")),
+        }
+
+        Ok(data)
+    }
+
+    /// The elements of a data blob, other blobs have none.
+    fn elements(&self) -> Result<Vec<Element>, String> {
+        if self.kind != "OSMData" {
+            return Ok(Vec::new());
+        }
+
+        elements(&self.decompress()?)
+    }
+}
+
+/// Reads the blobs of a file one after another.
+struct Blobs<R> {
+    reader: R,
+}
+
+impl<R: Read> Blobs<R> {
+    fn new(reader: R) -> Self {
+        Self { reader }
+    }
+
+    fn read(&mut self, size: usize) -> Result<Vec<u8>, Error> {
+        let mut data = vec![0; size];
+        self.reader.read_exact(&mut data).map_err(|e| Error::Input(e.to_string()))?;
+
+        Ok(data)
+    }
+
+    fn next(&mut self) -> Result<Option<Blob>, Error> {
+        let mut size = [0; 4];
+
+        // The file ends between blobs
+        match self.reader.read(&mut size[..1]) {
+            Ok(0) => return Ok(None),
+            Ok(_) => self.reader.read_exact(&mut size[1..]).map_err(|e| Error::Input(e.to_string()))?,
+            Err(e) if e.kind() == ErrorKind::Interrupted => return self.next(),
+            Err(e) => return Err(Error::Input(e.to_string())),
+        }
+
+        let size = u32::from_be_bytes(size) as usize;
+
+        if size > MAX_HEADER_SIZE {
+            return Err(Error::Input(invalid(format!("blob header of {} bytes, more than the format allows", size))));
+        }
+
+        let (mut kind, mut data_size) = (None, None);
+
+        for field in Fields::new(&self.read(size)?) {
+            match field.map_err(Error::Input)? {
+                (1, Field::Bytes(bytes)) => kind = Some(String::from_utf8_lossy(bytes).into_owned()),
+                (3, Field::Varint(value)) => data_size = Some(value as usize),
+                _ => {}
+            }
+        }
+
+        let (Some(kind), Some(data_size)) = (kind, data_size) else {
+            return Err(Error::Input(invalid("blob header without a type or size")));
+        };
+
+        if data_size > MAX_BLOB_SIZE {
+            return Err(Error::Input(invalid(format!("blob of {} bytes, more than the format allows", data_size))));
+        }
+
+        Ok(Some(Blob { kind, data: self.read(data_size)? }))
+    }
+
+    /// The next blobs, enough to keep every thread busy. Empty at the end of the file.
+    fn batch(&mut self) -> Result<Vec<Blob>, Error> {
+        let mut batch = Vec::new();
+
+        while batch.len() < rayon::current_num_threads() * BLOBS_PER_THREAD {
+            match self.next()? {
+                Some(blob) => batch.push(blob),
+                None => break,
+            }
+        }
+
+        Ok(batch)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Node,
+    Way,
+    Relation,
+}
+
+/// An element of a data block, with the nodes of a way and the type, id and role of the members of a relation.
+struct Element {
+    kind: Kind,
+    attributes: ParsedAttributeMap,
+    tags: Vec<(String, String)>,
+    refs: Vec<i64>,
+    members: Vec<(String, i64, String)>,
+}
+
+impl Element {
+    fn new(kind: Kind) -> Self {
+        Self { kind, attributes: ParsedAttributeMap::default(), tags: Vec::new(), refs: Vec::new(), members: Vec::new() }
+    }
+
+    fn has_tag(&self, key: &str, value: Option<&str>) -> bool {
+        self.tags.iter().any(|(k, v)| k.strip_prefix("addr:").unwrap_or(k) == key && value.is_none_or(|value| v == value))
+    }
+}
+
+/// The string table and the units of a block.
+struct Block {
+    strings: Vec<String>,
+    granularity: i64,
+    lat_offset: i64,
+    lon_offset: i64,
+    date_granularity: i64,
+}
+
+impl Block {
+    fn string(&self, index: u64) -> Result<String, String> {
+        self.strings.get(index as usize).cloned().ok_or_else(|| invalid(format!("string {} isn't in the table", index)))
+    }
+
+    fn tags(&self, keys: &[u64], values: &[u64]) -> Result<Vec<(String, String)>, String> {
+        if keys.len() != values.len() {
+            return Err(invalid("tags with a different number of keys and values"));
+        }
+
+        keys.iter().zip(values).map(|(key, value)| Ok((self.string(*key)?, self.string(*value)?))).collect()
+    }
+
+    fn lat(&self, lat: i64) -> f64 {
+        (self.lat_offset + self.granularity * lat) as f64 / 1e9
+    }
+
+    fn lon(&self, lon: i64) -> f64 {
+        (self.lon_offset + self.granularity * lon) as f64 / 1e9
+    }
+
+    fn timestamp(&self, timestamp: i64) -> Option<NaiveDateTime> {
+        DateTime::from_timestamp_millis(timestamp.saturating_mul(self.date_granularity)).map(|timestamp| timestamp.naive_utc())
+    }
+
+    /// Sets the version, timestamp and visibility of an element from its info.
+    fn info(&self, data: &[u8], attributes: &mut ParsedAttributeMap) -> Result<(), String> {
+        for field in Fields::new(data) {
+            match field? {
+                (1, Field::Varint(version)) => attributes.version = Some(version as i32),
+                (2, Field::Varint(timestamp)) => attributes.timestamp = self.timestamp(timestamp as i64),
+                (6, Field::Varint(visible)) => attributes.visible = Some(visible != 0),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A node, way or relation, which only differ in how their id, location and references are stored.
+    fn element(&self, kind: Kind, data: &[u8]) -> Result<Element, String> {
+        let mut element = Element::new(kind);
+        let (mut keys, mut values, mut lat, mut lon) = (Vec::new(), Vec::new(), None, None);
+        let (mut roles, mut types) = (Vec::new(), Vec::new());
+
+        for field in Fields::new(data) {
+            match (kind, field?) {
+                (Kind::Node, (1, Field::Varint(id))) => element.attributes.id = Some(zigzag(id)),
+                (_, (1, Field::Varint(id))) => element.attributes.id = Some(id as i64),
+                (_, (2, Field::Bytes(bytes))) => keys = packed(bytes)?,
+                (_, (3, Field::Bytes(bytes))) => values = packed(bytes)?,
+                (_, (4, Field::Bytes(info))) => self.info(info, &mut element.attributes)?,
+                (Kind::Node, (8, Field::Varint(value))) => lat = Some(self.lat(zigzag(value))),
+                (Kind::Node, (9, Field::Varint(value))) => lon = Some(self.lon(zigzag(value))),
+                (Kind::Way, (8, Field::Bytes(bytes))) => element.refs = deltas(bytes)?,
+                (Kind::Relation, (8, Field::Bytes(bytes))) => roles = packed(bytes)?,
+                (Kind::Relation, (9, Field::Bytes(bytes))) => element.refs = deltas(bytes)?,
+                (Kind::Relation, (10, Field::Bytes(bytes))) => types = packed(bytes)?,
+                _ => {}
+            }
+        }
+
+        element.tags = self.tags(&keys, &values)?;
+
+        // Deleted versions in history files have no location, like in XML
+        if element.attributes.visible != Some(false) {
+            (element.attributes.lat, element.attributes.lon) = (lat, lon);
+        }
+
+        if kind == Kind::Relation {
+            if roles.len() != element.refs.len() || types.len() != element.refs.len() {
+                return Err(invalid("relation with a different number of members, roles and types"));
+            }
+
+            element.members = std::mem::take(&mut element.refs)
+                .into_iter()
+                .zip(roles.iter().zip(&types))
+                .map(|(id, (role, kind))| {
+                    let kind = match kind {
+                        0 => "node",
+                        1 => "way",
+                        _ => "relation",
+                    };
+
+                    Ok((kind.to_string(), id, self.string(*role)?))
+                })
+                .collect::<Result<_, String>>()?;
+        }
+
+        Ok(element)
+    }
+
+    /// Dense nodes store every attribute as a column of deltas, and the tags of all nodes in one list where each node
+    /// ends with a 0.
+    fn dense_nodes(&self, data: &[u8], elements: &mut Vec<Element>) -> Result<(), String> {
+        let (mut ids, mut lats, mut lons, mut tags) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        let (mut versions, mut timestamps, mut visible) = (Vec::new(), Vec::new(), Vec::new());
+
+        for field in Fields::new(data) {
+            match field? {
+                (1, Field::Bytes(bytes)) => ids = deltas(bytes)?,
+                (5, Field::Bytes(info)) => {
+                    for field in Fields::new(info) {
+                        match field? {
+                            (1, Field::Bytes(bytes)) => versions = packed(bytes)?,
+                            (2, Field::Bytes(bytes)) => timestamps = deltas(bytes)?,
+                            (6, Field::Bytes(bytes)) => visible = packed(bytes)?,
+                            _ => {}
+                        }
+                    }
+                }
+                (8, Field::Bytes(bytes)) => lats = deltas(bytes)?,
+                (9, Field::Bytes(bytes)) => lons = deltas(bytes)?,
+                (10, Field::Bytes(bytes)) => tags = packed(bytes)?,
+                _ => {}
+            }
+        }
+
+        if lats.len() != ids.len() || lons.len() != ids.len() {
+            return Err(invalid("dense nodes with a different number of ids and coordinates"));
+        }
+
+        let mut tags = tags.into_iter();
+
+        for (i, &id) in ids.iter().enumerate() {
+            let mut element = Element::new(Kind::Node);
+            element.attributes.id = Some(id);
+            element.attributes.version = versions.get(i).map(|version| *version as i32);
+            element.attributes.timestamp = timestamps.get(i).and_then(|timestamp| self.timestamp(*timestamp));
+            element.attributes.visible = visible.get(i).map(|visible| *visible != 0);
+
+            if element.attributes.visible != Some(false) {
+                element.attributes.lat = Some(self.lat(lats[i]));
+                element.attributes.lon = Some(self.lon(lons[i]));
+            }
+
+            // Blocks without any tags leave the list out entirely
+            while let Some(key) = tags.next().filter(|key| *key != 0) {
+                let value = tags.next().ok_or_else(|| invalid("dense node tag without a value"))?;
+                element.tags.push((self.string(key)?, self.string(value)?));
+            }
+
+            elements.push(element);
+        }
+
+        Ok(())
+    }
+}
+
+/// The elements of a decompressed primitive block, in order.
+fn elements(data: &[u8]) -> Result<Vec<Element>, String> {
+    let mut block = Block { strings: Vec::new(), granularity: 100, lat_offset: 0, lon_offset: 0, date_granularity: 1000 };
+    let mut groups = Vec::new();
+
+    for field in Fields::new(data) {
+        match field? {
+            (1, Field::Bytes(table)) => {
+                for field in Fields::new(table) {
+                    if let (1, Field::Bytes(string)) = field? {
+                        block.strings.push(String::from_utf8_lossy(string).into_owned());
+                    }
+                }
+            }
+            (2, Field::Bytes(group)) => groups.push(group),
+            (17, Field::Varint(value)) => block.granularity = value as i64,
+            (18, Field::Varint(value)) => block.date_granularity = value as i64,
+            (19, Field::Varint(value)) => block.lat_offset = value as i64,
+            (20, Field::Varint(value)) => block.lon_offset = value as i64,
+            _ => {}
+        }
+    }
+
+    let mut elements = Vec::new();
+
+    for group in groups {
+        for field in Fields::new(group) {
+            match field? {
+                (1, Field::Bytes(node)) => elements.push(block.element(Kind::Node, node)?),
+                (2, Field::Bytes(dense)) => block.dense_nodes(dense, &mut elements)?,
+                (3, Field::Bytes(way)) => elements.push(block.element(Kind::Way, way)?),
+                (4, Field::Bytes(relation)) => elements.push(block.element(Kind::Relation, relation)?),
+                _ => {}
+            }
+        }
+    }
+
+    Ok(elements)
+}
+
+/// Reads the header block, refusing files that need features this reader doesn't have. The flag is set for full
+/// history files.
+fn header(blob: &Blob) -> Result<(Header, bool), String> {
+    if blob.kind != "OSMHeader" {
+        return Err(invalid(format!("expected the file to start with an OSMHeader blob, got {}", blob.kind)));
+    }
+
+    let mut header = Header::default();
+    let mut history = false;
+
+    for field in Fields::new(&blob.decompress()?) {
+        match field? {
+            (1, Field::Bytes(bbox)) => {
+                let mut sides = [0i64; 4];
+
+                for field in Fields::new(bbox) {
+                    if let (side @ 1..=4, Field::Varint(value)) = field? {
+                        sides[side as usize - 1] = zigzag(value);
+                    }
+                }
+
+                // Left, right, top and bottom in nanodegrees
+                let [left, right, top, bottom] = sides.map(|side| side as f64 / 1e9);
+                header.bounds = Some((bottom, left, top, right));
+            }
+            (4, Field::Bytes(feature)) => {
+                let feature = String::from_utf8_lossy(feature);
+
+                if !FEATURES.contains(&feature.as_ref()) {
+                    return Err(format!("the PBF requires {}, which isn't supported", feature));
+                }
+
+                history |= feature == "HistoricalInformation";
+            }
+            (16, Field::Bytes(program)) => header.generator = Some(String::from_utf8_lossy(program).into_owned()),
+            (32, Field::Varint(timestamp)) => header.timestamp = DateTime::<Utc>::from_timestamp(timestamp as i64, 0),
+            _ => {}
+        }
+    }
+
+    Ok((header, history))
+}
+
+/// Which ways are located, and so whose nodes the index keeps.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Ways {
+    /// Ways with a postcode, the only ones that can become addresses
+    WithPostcode,
+    /// Every way with tags, as a plugin can add the postcode
+    Tagged,
+    /// Every way, admin boundaries are made of untagged ways
+    All,
+}
+
+impl Ways {
+    fn wants(self, element: &Element) -> bool {
+        match self {
+            Ways::WithPostcode => element.has_tag("postcode", None),
+            Ways::Tagged => !element.tags.is_empty(),
+            Ways::All => true,
+        }
+    }
+}
+
+/// The wanted nodes of a block and which of them are main entrances. Both are sorted by id, so the nodes are merged
+/// against the ids from where the first one would be.
+fn wanted_nodes(wanted: &[i64], elements: Vec<Element>) -> (Vec<(i64, f64, f64)>, Vec<i64>) {
+    let (mut nodes, mut entrances) = (Vec::new(), Vec::new());
+    let (mut cursor, mut previous) = (0, i64::MAX);
+
+    for element in elements.into_iter().filter(|element| element.kind == Kind::Node) {
+        let (Some(id), Some(lat), Some(lon)) = (element.attributes.id, element.attributes.lat, element.attributes.lon) else {
+            continue;
+        };
+
+        // Only the first node of a block, or a node out of order in a hand made file, is searched for
+        if id < previous {
+            cursor = wanted.partition_point(|wanted| *wanted < id);
+        }
+
+        previous = id;
+
+        while cursor < wanted.len() && wanted[cursor] < id {
+            cursor += 1;
+        }
+
+        if wanted.get(cursor) == Some(&id) {
+            nodes.push((id, lat, lon));
+
+            if element.has_tag("entrance", Some("main")) {
+                entrances.push(id);
+            }
+        }
+    }
+
+    (nodes, entrances)
+}
+
+/// What the import needs from every element.
+#[derive(Clone, Copy)]
+pub struct Selection {
+    /// Ways are located from the index, rather than from their nodes as the import goes
+    pub locate: bool,
+    /// The nodes of ways and members of relations are passed on, for admin areas
+    pub members: bool,
+}
+
+pub struct Pbf {
+    path: PathBuf,
+    pub header: Header,
+    /// Every version of every element is passed on, as a version without tags replaces the one before it
+    history: bool,
+}
+
+impl Pbf {
+    /// Opens the file and reads its header.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let mut blobs = Blobs::new(BufReader::new(File::open(path).map_err(|e| Error::Input(format!("{}: {}", path.display(), e)))?));
+        let blob = blobs.next()?.ok_or_else(|| Error::Input(invalid("the file is empty")))?;
+        let (header, history) = header(&blob).map_err(Error::Input)?;
+
+        Ok(Self { path: path.to_path_buf(), header, history })
+    }
+
+    /// Decodes every blob of the file on all threads, handing the results on in order.
+    fn scan<T: Send>(&self, decode: impl Fn(Vec<Element>) -> T + Sync, mut consume: impl FnMut(T)) -> Result<(), Error> {
+        let mut blobs = Blobs::new(BufReader::new(File::open(&self.path).map_err(|e| Error::Input(format!("{}: {}", self.path.display(), e)))?));
+
+        loop {
+            let batch = blobs.batch()?;
+
+            if batch.is_empty() {
+                return Ok(());
+            }
+
+            let decoded = batch.par_iter()
+                .map(|blob| blob.elements().map(&decode))
+                .collect::<Result<Vec<T>, String>>()
+                .map_err(Error::Input)?;
+
+            decoded.into_iter().for_each(&mut consume);
+        }
+    }
+
+    /// The locations of the nodes of the ways to locate, from a scan of the ways and then one of the nodes.
+    pub fn index(&self, ways: Ways) -> Result<NodeIndex, Error> {
+        let mut wanted = Vec::new();
+
+        self.scan(
+            |elements| elements.into_iter()
+                .filter(|element| element.kind == Kind::Way && ways.wants(element))
+                .flat_map(|element| element.refs)
+                .collect::<Vec<i64>>(),
+            |refs| wanted.extend(refs),
+        )?;
+
+        wanted.par_sort_unstable();
+        wanted.dedup();
+
+        let mut index = NodeIndex::default();
+
+        self.scan(|elements| wanted_nodes(&wanted, elements), |(nodes, entrances)| {
+            for (id, lat, lon) in nodes {
+                index.add(id, lat, lon);
+            }
+
+            for id in entrances {
+                index.mark_main_entrance(id);
+            }
+        })?;
+
+        index.sort();
+
+        Ok(index)
+    }
+
+    /// The elements of the file from `reader` as events, with every way to locate followed by its location. Elements
+    /// that can't become a row, like nodes without tags, are left out.
+    pub(crate) fn events(&self, reader: Box<dyn Read>, index: Option<Arc<NodeIndex>>, selection: Selection) -> Events {
+        Events { blobs: Blobs::new(BufReader::new(reader)), pending: VecDeque::new(), index, selection, history: self.history, done: false }
+    }
+}
+
+pub(crate) struct Events {
+    blobs: Blobs<BufReader<Box<dyn Read>>>,
+    pending: VecDeque<ParsedElementEvent>,
+    index: Option<Arc<NodeIndex>>,
+    selection: Selection,
+    history: bool,
+    done: bool,
+}
+
+impl Events {
+    /// Decodes the next batch of blobs, `false` at the end of the file.
+    fn fill(&mut self) -> Result<bool, Error> {
+        let batch = self.blobs.batch()?;
+
+        if batch.is_empty() {
+            return Ok(false);
+        }
+
+        let (index, selection, history) = (self.index.as_deref(), self.selection, self.history);
+
+        let decoded = batch.par_iter()
+            .map(|blob| Ok(to_events(blob.elements()?, index, selection, history)))
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(Error::Input)?;
+
+        self.pending.extend(decoded.into_iter().flatten());
+
+        Ok(true)
+    }
+}
+
+impl Iterator for Events {
+    type Item = Result<ParsedElementEvent, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() && !self.done {
+            match self.fill() {
+                Ok(more) => self.done = !more,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+/// The events of the elements of a block, as XML would have them.
+fn to_events(elements: Vec<Element>, index: Option<&NodeIndex>, selection: Selection, history: bool) -> Vec<ParsedElementEvent> {
+    let mut events = Vec::new();
+    let mut skipped = 0;
+
+    for element in elements {
+        let deleted = element.attributes.visible == Some(false);
+
+        let wanted = history || deleted || match element.kind {
+            Kind::Node => !element.tags.is_empty(),
+            Kind::Way => !element.tags.is_empty() || selection.members,
+            Kind::Relation => selection.members,
+        };
+
+        if !wanted {
+            skipped += 1;
+            continue;
+        }
+
+        let location = match (element.kind, index) {
+            (Kind::Way, Some(index)) if selection.locate && !deleted => index.find(&element.refs),
+            _ => None,
+        };
+
+        events.push(match element.kind {
+            Kind::Node => ParsedElementEvent::Node(element.attributes),
+            Kind::Way => ParsedElementEvent::Way(element.attributes),
+            Kind::Relation => ParsedElementEvent::Relation(element.attributes),
+        });
+        events.extend(element.tags.into_iter().map(|(key, value)| ParsedElementEvent::Tag(key, value)));
+
+        if selection.members {
+            events.extend(element.refs.into_iter().map(ParsedElementEvent::NodeRef));
+            events.extend(element.members.into_iter().map(|(kind, id, role)| ParsedElementEvent::Member(kind, id, role)));
+        }
+
+        if let Some((lat, lon, at_entrance)) = location {
+            events.push(ParsedElementEvent::WayLocation(lat, lon, at_entrance));
+        }
+    }
+
+    // Counted like the elements that are passed on
+    metrics::PARSED_ELEMENTS.inc_by(skipped);
+
+    events
+}
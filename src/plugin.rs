@@ -0,0 +1,76 @@
+//! WASM element transform plugins.
+//!
+//! A plugin is a WASM module exporting its `memory` together with:
+//!
+//!  - `alloc(len: i32) -> i32` returning a pointer to `len` writable bytes
+//!  - `transform(tags_ptr: i32, tags_len: i32, lat: f64, lon: f64) -> i64`
+//!
+//! The tags of an element are passed in as a JSON object. `transform` returns
+//! `(ptr << 32) | len` pointing at a JSON encoded [`Address`], or `0` to drop
+//! the element.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use wasmi::{Engine, Instance, Linker, Memory, Module, Store, TypedFunc};
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Address {
+    pub city: Option<String>,
+    pub country: Option<String>,
+    pub postcode: Option<String>,
+    pub street: Option<String>,
+    pub house_number: Option<String>,
+    pub province: Option<String>,
+}
+
+pub struct Plugin {
+    store: Store<()>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    transform: TypedFunc<(i32, i32, f64, f64), i64>,
+}
+
+impl Plugin {
+    pub fn load(path: &Path) -> Result<Self, wasmi::Error> {
+        let wasm = std::fs::read(path).map_err(|e| wasmi::Error::new(e.to_string()))?;
+
+        let engine = Engine::default();
+        let module = Module::new(&engine, &wasm)?;
+        let mut store = Store::new(&engine, ());
+        let instance: Instance = Linker::<()>::new(&engine)
+            .instantiate(&mut store, &module)?
+            .start(&mut store)?;
+
+        let memory = instance.get_memory(&store, "memory")
+            .ok_or_else(|| wasmi::Error::new("plugin does not export `memory`"))?;
+        let alloc = instance.get_typed_func(&store, "alloc")?;
+        let transform = instance.get_typed_func(&store, "transform")?;
+
+        Ok(Self { store, memory, alloc, transform })
+    }
+
+    pub fn transform(&mut self, tags: &BTreeMap<String, String>, lat: f64, lon: f64) -> Result<Option<Address>, wasmi::Error> {
+        let input = serde_json::to_vec(tags).map_err(|e| wasmi::Error::new(e.to_string()))?;
+
+        let input_ptr = self.alloc.call(&mut self.store, input.len() as i32)?;
+        self.memory.write(&mut self.store, input_ptr as usize, &input)?;
+
+        let packed = self.transform.call(&mut self.store, (input_ptr, input.len() as i32, lat, lon))?;
+
+        if packed == 0 {
+            return Ok(None);
+        }
+
+        let output_ptr = (packed >> 32) as u32 as usize;
+        let output_len = packed as u32 as usize;
+        let mut output = vec![0; output_len];
+        self.memory.read(&self.store, output_ptr, &mut output)?;
+
+        serde_json::from_slice(&output)
+            .map(Some)
+            .map_err(|e| wasmi::Error::new(e.to_string()))
+    }
+}
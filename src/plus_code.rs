@@ -0,0 +1,106 @@
+//! Open Location Codes, better known as plus codes, which identify a place without needing a postcode or street.
+//!
+//! Addresses store the standard 10 digit code like `8FVC9G8F+6X`, a cell of about 14 by 14 meters. With
+//! `--grid-cells` they also get the 11 digit code of their grid cell of about 3 by 3 meters, like `8FVC9G8F+6XQ`. See
+//! https://github.com/google/open-location-code for the specification.
+
+const ALPHABET: &[u8; 20] = b"23456789CFGHJMPQRVWX";
+const SEPARATOR: char = '+';
+const SEPARATOR_POSITION: usize = 8;
+const PADDING: char = '0';
+/// Digits of the codes that are stored, after which the cell is divided into rows and columns instead of pairs.
+const CODE_LENGTH: usize = 10;
+const GRID_CELL_LENGTH: usize = 11;
+const GRID_ROWS: i64 = 5;
+const GRID_COLUMNS: i64 = 4;
+
+/// Cells per degree of latitude and of longitude at the precision of a 10 digit code.
+const CELLS_PER_DEGREE: f64 = 8000.0;
+
+/// The 10 digit plus code of a coordinate.
+pub fn encode(lat: f64, lon: f64) -> String {
+    encode_with_length(lat, lon, CODE_LENGTH)
+}
+
+/// The 11 digit plus code of a coordinate, its grid cell of about 3 by 3 meters.
+pub fn grid_cell(lat: f64, lon: f64) -> String {
+    encode_with_length(lat, lon, GRID_CELL_LENGTH)
+}
+
+/// Codes of 10 digits or more, as only those are stored.
+fn encode_with_length(lat: f64, lon: f64, length: usize) -> String {
+    let grid_digits = (length - CODE_LENGTH) as u32;
+    let lat_per_degree = CELLS_PER_DEGREE * GRID_ROWS.pow(grid_digits) as f64;
+    let lon_per_degree = CELLS_PER_DEGREE * GRID_COLUMNS.pow(grid_digits) as f64;
+
+    // The north pole belongs to the cell below it, longitudes wrap around
+    let mut lat = (((lat.clamp(-90.0, 90.0) + 90.0) * lat_per_degree).floor() as i64).min((180.0 * lat_per_degree) as i64 - 1);
+    let mut lon = (((lon + 180.0) * lon_per_degree).floor() as i64).rem_euclid((360.0 * lon_per_degree) as i64);
+
+    let mut digits = vec![0u8; length];
+
+    for digit in (CODE_LENGTH..length).rev() {
+        digits[digit] = ALPHABET[((lat % GRID_ROWS) * GRID_COLUMNS + lon % GRID_COLUMNS) as usize];
+        lat /= GRID_ROWS;
+        lon /= GRID_COLUMNS;
+    }
+
+    for pair in (0..CODE_LENGTH / 2).rev() {
+        digits[pair * 2] = ALPHABET[(lat % 20) as usize];
+        digits[pair * 2 + 1] = ALPHABET[(lon % 20) as usize];
+        lat /= 20;
+        lon /= 20;
+    }
+
+    let mut code: String = digits.iter().map(|&digit| digit as char).collect();
+    code.insert(SEPARATOR_POSITION, SEPARATOR);
+    code
+}
+
+/// The start of the stored codes within the area of a full plus code. A code as precise as the stored ones or more
+/// matches its 14 meter cell, a shorter code padded with zeros like `8FVC0000+` matches every cell within it. Short
+/// codes that leave out the first digits, like `9G8F+6X Zurich`, aren't supported as they need a reference location.
+pub fn prefix(code: &str) -> Result<String, String> {
+    let code = code.trim().to_uppercase();
+    let invalid = |reason: &str| Err(format!("{} isn't a full plus code: {}", code, reason));
+
+    let Some((before, after)) = code.split_once(SEPARATOR) else {
+        return invalid("it has no +");
+    };
+
+    if before.len() != SEPARATOR_POSITION {
+        return invalid("it needs 8 digits before the +, shortened codes aren't supported");
+    }
+
+    let digits = before.trim_end_matches(PADDING);
+
+    if digits.len() < 2 || digits.len() % 2 != 0 || digits.contains(PADDING) {
+        return invalid("padding has to follow an even number of digits");
+    }
+
+    if digits.len() < SEPARATOR_POSITION && !after.is_empty() {
+        return invalid("padded codes can't have digits after the +");
+    }
+
+    if after.len() == 1 {
+        return invalid("it needs at least 2 digits after the +");
+    }
+
+    if !digits.chars().chain(after.chars()).all(|c| c.is_ascii() && ALPHABET.contains(&(c as u8))) {
+        return invalid("it has characters that aren't plus code digits");
+    }
+
+    // The first digit is latitude up to 180 degrees, the second longitude up to 360
+    if ALPHABET.iter().position(|&c| c == digits.as_bytes()[0]).is_some_and(|i| i > 8)
+        || ALPHABET.iter().position(|&c| c == digits.as_bytes()[1]).is_some_and(|i| i > 17) {
+        return invalid("it's outside the range of coordinates");
+    }
+
+    if digits.len() < SEPARATOR_POSITION {
+        return Ok(digits.to_string());
+    }
+
+    let after: String = after.chars().take(CODE_LENGTH - SEPARATOR_POSITION).collect();
+
+    Ok(format!("{}{}{}", digits, SEPARATOR, after))
+}
@@ -0,0 +1,28 @@
+//! Shops, amenities and other named places that only have an `addr:postcode`. They aren't addresses, but in poorly
+//! mapped areas they're often the only hint of where a postcode is.
+
+use sea_orm::ActiveValue;
+
+use crate::entities::*;
+use crate::profile;
+
+/// The postcode of a named element that isn't imported as an address, normalized by the profile of its country.
+/// `None` for elements without a name, location or valid postcode.
+pub fn from_element(node: &node::ActiveModel, name: Option<&str>) -> Option<poi_postcode::ActiveModel> {
+    let (ActiveValue::Set(id), ActiveValue::Set(lat), ActiveValue::Set(lon), ActiveValue::Set(postcode)) = (&node.id, &node.lat, &node.lon, &node.postcode) else {
+        return None;
+    };
+
+    let country = match &node.country {
+        ActiveValue::Set(country) => country.as_deref(),
+        _ => None,
+    };
+
+    Some(poi_postcode::ActiveModel {
+        id: ActiveValue::Set(*id),
+        name: ActiveValue::Set(name?.to_string()),
+        postcode: ActiveValue::Set(profile::profiles().get(country).normalize_postcode(postcode)?),
+        lat: ActiveValue::Set(*lat),
+        lon: ActiveValue::Set(*lon),
+    })
+}
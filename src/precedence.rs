@@ -0,0 +1,89 @@
+//! Which source wins when the same address comes from more than one: OSM nodes, OSM ways or an external dataset.
+//!
+//! Every row gets the position of its source in the precedence order as `source_rank`, lower is better. Merging
+//! duplicates keeps the row with the best rank, so the result doesn't depend on the order rows were imported in.
+
+use sea_orm::{ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QuerySelect};
+use sea_orm::sea_query::Expr;
+
+use crate::entities::*;
+use crate::external::EXTERNAL_IDS;
+
+/// External datasets first, then addresses tagged on nodes, then on ways.
+pub const DEFAULT: &str = "external,node,way";
+
+/// The sources in order of preference. Besides `node`, `way` and `external` an entry can be the `--source` of an
+/// external dataset, to rank it over others.
+pub struct Precedence {
+    sources: Vec<String>,
+}
+
+impl Precedence {
+    /// Parses a comma separated list like `BAG,external,node,way`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let sources: Vec<String> = spec.split(',').map(|source| source.trim().to_string()).collect();
+
+        if sources.iter().any(String::is_empty) {
+            return Err(format!("empty source in {}", spec));
+        }
+
+        Ok(Self { sources })
+    }
+
+    fn condition(source: &str) -> Condition {
+        match source {
+            "node" => Condition::all().add(node::Column::Id.gt(0)).add(node::Column::Id.lt(EXTERNAL_IDS)),
+            "way" => Condition::all().add(node::Column::Id.lt(0)),
+            "external" => Condition::all().add(node::Column::Id.gte(EXTERNAL_IDS)),
+            name => Condition::all().add(node::Column::Id.gte(EXTERNAL_IDS)).add(node::Column::Source.eq(name)),
+        }
+    }
+
+    /// Whether the rows come from more than one source, external datasets each counting as their own.
+    async fn mixed(db: &DatabaseConnection) -> Result<bool, DbErr> {
+        let mut sources = 0;
+
+        for source in ["node", "way"] {
+            if node::Entity::find().filter(Self::condition(source)).one(db).await?.is_some() {
+                sources += 1;
+            }
+        }
+
+        sources += node::Entity::find()
+            .select_only()
+            .column(node::Column::Source)
+            .filter(Self::condition("external"))
+            .distinct()
+            .limit(2)
+            .into_tuple::<Option<String>>()
+            .all(db)
+            .await?
+            .len();
+
+        Ok(sources > 1)
+    }
+
+    /// Sets the `source_rank` of every row. Rows from sources that aren't listed rank after the listed ones. Rows
+    /// from a single source would all get the same rank, then the table is left as it is.
+    pub async fn assign(&self, db: &DatabaseConnection) -> Result<(), DbErr> {
+        if !Self::mixed(db).await? {
+            return Ok(());
+        }
+
+        node::Entity::update_many()
+            .col_expr(node::Column::SourceRank, Expr::value(self.sources.len() as i32))
+            .exec(db)
+            .await?;
+
+        // In reverse, so the first entry that matches a row decides its rank
+        for (rank, source) in self.sources.iter().enumerate().rev() {
+            node::Entity::update_many()
+                .col_expr(node::Column::SourceRank, Expr::value(rank as i32))
+                .filter(Self::condition(source))
+                .exec(db)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,565 @@
+//! Per country rules for cleaning up and validating addresses.
+//!
+//! A profile is selected by the `addr:country` of an element, or `--country` when it has none. Countries without a
+//! profile use the defaults. Profiles can be added or replaced with a JSON file passed to `--profiles`:
+//!
+//! ```json
+//! {
+//!     "BE": { "postcode_pattern": "^[1-9][0-9]{3}$", "required": ["street", "house_number"], "dedup": "none" },
+//!     "CA": { "normalization": "spaced", "province_codes": { "Ontario": "ON", "Quebec": "QC" } },
+//!     "NL": { "postcode_hierarchy": [{ "name": "wijk", "pattern": "^[0-9]{4}" }, { "name": "region", "pattern": "^[0-9]{2}" }] },
+//!     "AT": { "language": "de" }
+//! }
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use regex::Regex;
+use sea_orm::ActiveValue;
+use serde::{Deserialize, Deserializer};
+
+use crate::entities::*;
+use crate::format;
+use crate::street;
+
+static PROFILES: OnceLock<Profiles> = OnceLock::new();
+
+/// The format published by Royal Mail, including the letters that aren't used in each position.
+const GB_POSTCODE: &str = "^(GIR 0AA|([A-PR-UWYZ][0-9][0-9]?|[A-PR-UWYZ][A-HK-Y][0-9][0-9]?|[A-PR-UWYZ][0-9][A-HJKPSTUW]|[A-PR-UWYZ][A-HK-Y][0-9][ABEHMNPRVWXY]) [0-9][ABD-HJLNP-UW-Z]{2})$";
+
+/// Forward sortation area and local delivery unit, `D`, `F`, `I`, `O`, `Q` and `U` aren't used.
+const CA_POSTCODE: &str = "^[ABCEGHJ-NPRSTVXY][0-9][ABCEGHJ-NPRSTV-Z] [0-9][ABCEGHJ-NPRSTV-Z][0-9]$";
+
+/// Eircode routing key and unique identifier.
+const IE_POSTCODE: &str = "^([AC-FHKNPRTV-Y][0-9]{2}|D6W) [0-9AC-FHKNPRTV-Y]{4}$";
+
+/// Sector, district and area, `SW1A 1AA` is in sector `SW1A 1`, district `SW1A` and area `SW`.
+const GB_HIERARCHY: [(&str, &str); 3] = [("sector", "^[A-Z0-9]+ [0-9]"), ("district", "^[A-Z0-9]+"), ("area", "^[A-Z]+")];
+
+/// Leitregion and Leitzone, the first two and the first digit of the Postleitzahl.
+const DE_HIERARCHY: [(&str, &str); 2] = [("leitregion", "^[0-9]{2}"), ("leitzone", "^[0-9]")];
+
+const CA_PROVINCES: [(&str, &str); 13] = [
+    ("Alberta", "AB"), ("British Columbia", "BC"), ("Manitoba", "MB"), ("New Brunswick", "NB"),
+    ("Newfoundland and Labrador", "NL"), ("Northwest Territories", "NT"), ("Nova Scotia", "NS"), ("Nunavut", "NU"),
+    ("Ontario", "ON"), ("Prince Edward Island", "PE"), ("Quebec", "QC"), ("Saskatchewan", "SK"), ("Yukon", "YT"),
+];
+
+/// USPS codes of the states, districts and territories.
+const US_STATES: [(&str, &str); 56] = [
+    ("Alabama", "AL"), ("Alaska", "AK"), ("Arizona", "AZ"), ("Arkansas", "AR"), ("California", "CA"),
+    ("Colorado", "CO"), ("Connecticut", "CT"), ("Delaware", "DE"), ("District of Columbia", "DC"), ("Florida", "FL"),
+    ("Georgia", "GA"), ("Hawaii", "HI"), ("Idaho", "ID"), ("Illinois", "IL"), ("Indiana", "IN"),
+    ("Iowa", "IA"), ("Kansas", "KS"), ("Kentucky", "KY"), ("Louisiana", "LA"), ("Maine", "ME"),
+    ("Maryland", "MD"), ("Massachusetts", "MA"), ("Michigan", "MI"), ("Minnesota", "MN"), ("Mississippi", "MS"),
+    ("Missouri", "MO"), ("Montana", "MT"), ("Nebraska", "NE"), ("Nevada", "NV"), ("New Hampshire", "NH"),
+    ("New Jersey", "NJ"), ("New Mexico", "NM"), ("New York", "NY"), ("North Carolina", "NC"), ("North Dakota", "ND"),
+    ("Ohio", "OH"), ("Oklahoma", "OK"), ("Oregon", "OR"), ("Pennsylvania", "PA"), ("Rhode Island", "RI"),
+    ("South Carolina", "SC"), ("South Dakota", "SD"), ("Tennessee", "TN"), ("Texas", "TX"), ("Utah", "UT"),
+    ("Vermont", "VT"), ("Virginia", "VA"), ("Washington", "WA"), ("West Virginia", "WV"), ("Wisconsin", "WI"),
+    ("Wyoming", "WY"), ("American Samoa", "AS"), ("Guam", "GU"), ("Northern Mariana Islands", "MP"), ("Puerto Rico", "PR"),
+    ("U.S. Virgin Islands", "VI"),
+];
+
+/// How a postcode is written after uppercasing and removing whitespace.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Normalization {
+    /// `1234 ab` becomes `1234AB`
+    #[default]
+    Compact,
+    /// A space before the last `inward_length` characters, `sw1a1aa` becomes `SW1A 1AA`
+    Spaced,
+    /// A hyphen before the last four characters, `1000001` becomes `100-0001`
+    Hyphenated,
+    /// The first five digits of a ZIP+4 code, the other four are stored as the extension
+    ZipPlusFour,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HouseNumberStyle {
+    /// `12a` becomes `12A`
+    #[default]
+    Uppercase,
+    /// Whitespace is removed as well, `12 a` becomes `12A`
+    Compact,
+}
+
+/// Where the house number goes in a street line, for splitting it off `addr:street` and parsing `addr:full` without
+/// libpostal.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HouseNumberPosition {
+    /// Values aren't parsed
+    #[default]
+    None,
+    /// `Dorpsstraat 12a`
+    AfterStreet,
+    /// `350 5th Avenue`
+    BeforeStreet,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Street,
+    HouseNumber,
+    City,
+    BlockNumber,
+}
+
+impl Field {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Street => "street",
+            Self::HouseNumber => "house_number",
+            Self::City => "city",
+            Self::BlockNumber => "block_number",
+        }
+    }
+}
+
+/// Why an address with a postcode isn't imported, by the name `--max-rejected` and the `quarantine` table use.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rejection {
+    /// Dropped by the `--plugin`
+    Plugin,
+    /// The postcode isn't valid in its country
+    Postcode,
+    /// An element without an id
+    Id,
+    /// Without a field its country requires
+    Missing(Field),
+}
+
+impl Rejection {
+    pub const NAMES: [&'static str; 7] = ["plugin", "postcode", "id", "street", "house_number", "city", "block_number"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Plugin => "plugin",
+            Self::Postcode => "postcode",
+            Self::Id => "id",
+            Self::Missing(field) => field.name(),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "plugin" => Some(Self::Plugin),
+            "postcode" => Some(Self::Postcode),
+            "id" => Some(Self::Id),
+            "street" => Some(Self::Missing(Field::Street)),
+            "house_number" => Some(Self::Missing(Field::HouseNumber)),
+            "city" => Some(Self::Missing(Field::City)),
+            "block_number" => Some(Self::Missing(Field::BlockNumber)),
+            _ => None,
+        }
+    }
+}
+
+/// How addresses are reduced after importing.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dedup {
+    /// Keep every address
+    None,
+    /// Postcodes covering a single street are reduced to one address at their center
+    #[default]
+    SingleStreet,
+}
+
+/// A coarser level of postcodes, like the GB postcode district, that addresses are rolled up into.
+#[derive(Clone, Deserialize)]
+pub struct PostcodeLevel {
+    pub name: String,
+    /// Matched against the normalized postcode, the first group or else the whole match is the code at this level
+    #[serde(deserialize_with = "deserialize_required_pattern")]
+    pub pattern: Regex,
+}
+
+impl PostcodeLevel {
+    fn new(name: &str, pattern: &str) -> Self {
+        Self { name: name.to_string(), pattern: Regex::new(pattern).expect("built-in patterns are valid") }
+    }
+
+    /// The code of the postcode at this level, `None` when the pattern doesn't match.
+    pub fn code(&self, postcode: &str) -> Option<String> {
+        let captures = self.pattern.captures(postcode)?;
+
+        captures.get(1).or_else(|| captures.get(0)).map(|code| code.as_str().to_string())
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct CountryProfile {
+    /// Matched against the normalized postcode, elements that don't match are skipped
+    #[serde(deserialize_with = "deserialize_pattern")]
+    pub postcode_pattern: Option<Regex>,
+    pub normalization: Normalization,
+    pub required: Vec<Field>,
+    pub house_number: HouseNumberStyle,
+    pub dedup: Dedup,
+    /// Store the parts before and after the space of a spaced postcode in `outcode` and `incode`
+    pub split_outcode: bool,
+    /// Characters after the space of a spaced postcode
+    pub inward_length: usize,
+    /// Province names mapped to their code, matched case insensitively
+    pub province_codes: HashMap<String, String>,
+    /// How addresses are written, see [`crate::format`]
+    pub address_format: String,
+    pub house_number_position: HouseNumberPosition,
+    /// Coarser levels of postcodes rolled up into `postcode_rollup`, from fine to coarse
+    pub postcode_hierarchy: Vec<PostcodeLevel>,
+    /// Whose dictionary expands abbreviated street types, see [`crate::street`]
+    pub language: Option<String>,
+}
+
+/// Accepts any postcode, the rules countries without a profile are imported with.
+impl Default for CountryProfile {
+    fn default() -> Self {
+        Self {
+            postcode_pattern: None,
+            normalization: Normalization::Compact,
+            required: vec![Field::Street],
+            house_number: HouseNumberStyle::Uppercase,
+            dedup: Dedup::SingleStreet,
+            split_outcode: false,
+            inward_length: 3,
+            province_codes: HashMap::new(),
+            address_format: format::DEFAULT.to_string(),
+            house_number_position: HouseNumberPosition::None,
+            postcode_hierarchy: Vec::new(),
+            language: None,
+        }
+    }
+}
+
+fn deserialize_pattern<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Regex>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|pattern| Regex::new(&pattern).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+fn deserialize_required_pattern<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+    Regex::new(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+impl CountryProfile {
+    fn new(pattern: &str, normalization: Normalization, required: &[Field], house_number: HouseNumberStyle, dedup: Dedup) -> Self {
+        Self {
+            postcode_pattern: Some(Regex::new(pattern).expect("built-in patterns are valid")),
+            normalization,
+            required: required.to_vec(),
+            house_number,
+            dedup,
+            split_outcode: false,
+            inward_length: 3,
+            province_codes: HashMap::new(),
+            address_format: format::DEFAULT.to_string(),
+            house_number_position: HouseNumberPosition::None,
+            postcode_hierarchy: Vec::new(),
+            language: None,
+        }
+    }
+
+    fn with_postcode_pattern(mut self, pattern: &str) -> Self {
+        self.postcode_pattern = Some(Regex::new(pattern).expect("built-in patterns are valid"));
+        self
+    }
+
+    fn with_split_outcode(mut self) -> Self {
+        self.split_outcode = true;
+        self
+    }
+
+    fn with_inward_length(mut self, length: usize) -> Self {
+        self.inward_length = length;
+        self
+    }
+
+    fn with_address_format(mut self, template: &str) -> Self {
+        self.address_format = template.to_string();
+        self
+    }
+
+    fn with_house_number_position(mut self, position: HouseNumberPosition) -> Self {
+        self.house_number_position = position;
+        self
+    }
+
+    fn with_postcode_hierarchy(mut self, levels: &[(&str, &str)]) -> Self {
+        self.postcode_hierarchy = levels.iter().map(|(name, pattern)| PostcodeLevel::new(name, pattern)).collect();
+        self
+    }
+
+    fn with_language(mut self, language: &str) -> Self {
+        self.language = Some(language.to_string());
+        self
+    }
+
+    fn with_province_codes(mut self, codes: &[(&str, &str)]) -> Self {
+        self.province_codes = codes.iter().map(|(name, code)| (name.to_string(), code.to_string())).collect();
+        self
+    }
+
+    /// Splits off the part of the postcode that's stored separately.
+    fn split_postcode(&self, postcode: &str) -> (String, Option<String>) {
+        let compact: String = postcode.split_whitespace().collect::<String>().to_uppercase();
+
+        if self.normalization != Normalization::ZipPlusFour {
+            return (compact, None);
+        }
+
+        let digits = compact.replace('-', "");
+
+        if digits.len() == 9 && digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            return (digits[..5].to_string(), Some(digits[5..].to_string()));
+        }
+
+        (compact, None)
+    }
+
+    /// The code of a province name or code, `None` when it isn't one of the province codes.
+    fn province_code(&self, province: &str) -> Option<&String> {
+        let province = province.trim();
+
+        self.province_codes.iter()
+            .find(|(name, code)| name.eq_ignore_ascii_case(province) || code.eq_ignore_ascii_case(province))
+            .map(|(_, code)| code)
+    }
+
+    pub fn normalize_province(&self, province: &str) -> String {
+        self.province_code(province).cloned().unwrap_or_else(|| province.trim().to_string())
+    }
+
+    /// Whether the province maps to a code, always for countries without province codes.
+    pub fn maps_province(&self, province: &str) -> bool {
+        self.province_codes.is_empty() || self.province_code(province).is_some()
+    }
+
+    /// The normalized postcode, or `None` when it doesn't match the pattern of the country.
+    pub fn normalize_postcode(&self, postcode: &str) -> Option<String> {
+        let (mut normalized, _) = self.split_postcode(postcode);
+
+        if self.normalization == Normalization::Spaced && normalized.len() > self.inward_length && normalized.is_char_boundary(normalized.len() - self.inward_length) {
+            normalized.insert(normalized.len() - self.inward_length, ' ');
+        }
+
+        if self.normalization == Normalization::Hyphenated {
+            // Full width digits and the postal mark are common in Japanese data
+            normalized = normalized.chars()
+                .filter(|c| !matches!(c, '-' | '－' | '〒'))
+                .map(|c| match c {
+                    '０'..='９' => char::from_u32(c as u32 - '０' as u32 + '0' as u32).expect("digits map to digits"),
+                    _ => c,
+                })
+                .collect();
+
+            if normalized.len() > 4 && normalized.is_char_boundary(normalized.len() - 4) {
+                normalized.insert(normalized.len() - 4, '-');
+            }
+        }
+
+        match &self.postcode_pattern {
+            Some(pattern) if !pattern.is_match(&normalized) => None,
+            _ => Some(normalized),
+        }
+    }
+
+    /// The parts before and after the space of a normalized postcode, for profiles that store them.
+    pub fn outcode_incode(&self, postcode: &str) -> Option<(String, String)> {
+        postcode.split_once(' ')
+            .filter(|_| self.split_outcode)
+            .map(|(outcode, incode)| (outcode.to_string(), incode.to_string()))
+    }
+
+    pub fn normalize_house_number(&self, house_number: &str) -> String {
+        match self.house_number {
+            HouseNumberStyle::Uppercase => house_number.trim().to_uppercase(),
+            HouseNumberStyle::Compact => house_number.split_whitespace().collect::<String>().to_uppercase(),
+        }
+    }
+
+    /// The first required field the node doesn't have.
+    fn missing(&self, node: &node::ActiveModel) -> Option<Field> {
+        self.required.iter().copied().find(|field| !match field {
+            Field::Street => matches!(&node.street, ActiveValue::Set(Some(_))),
+            Field::HouseNumber => matches!(&node.house_number, ActiveValue::Set(Some(_))),
+            Field::City => matches!(&node.city, ActiveValue::Set(Some(_))),
+            Field::BlockNumber => matches!(&node.block_number, ActiveValue::Set(Some(_))),
+        })
+    }
+
+    /// Normalizes the node in place, returns why it should be skipped otherwise.
+    pub fn apply(&self, node: &mut node::ActiveModel) -> Result<(), Rejection> {
+        let ActiveValue::Set(postcode) = &node.postcode else {
+            return Err(Rejection::Postcode);
+        };
+
+        let extension = self.split_postcode(postcode).1;
+
+        let Some(postcode) = self.normalize_postcode(postcode) else {
+            return Err(Rejection::Postcode);
+        };
+
+        if let Some((outcode, incode)) = self.outcode_incode(&postcode) {
+            node.outcode = ActiveValue::Set(Some(outcode));
+            node.incode = ActiveValue::Set(Some(incode));
+        }
+
+        node.postcode = ActiveValue::Set(postcode);
+
+        if extension.is_some() {
+            node.postcode_extension = ActiveValue::Set(extension);
+        }
+
+        if let ActiveValue::Set(Some(province)) = &node.province {
+            node.province = ActiveValue::Set(Some(self.normalize_province(province)));
+        }
+
+        if let (ActiveValue::Set(Some(street)), Some(dictionary)) = (&node.street, self.language.as_deref().and_then(|language| street::dictionaries().get(language))) {
+            node.street = ActiveValue::Set(Some(dictionary.expand(street)));
+        }
+
+        if let ActiveValue::Set(Some(house_number)) = &node.house_number {
+            node.house_number = ActiveValue::Set(Some(self.normalize_house_number(house_number)));
+        }
+
+        if let ActiveValue::Set(Some(house_name)) = &node.house_name {
+            node.house_name_normalized = ActiveValue::Set(Some(normalize_house_name(house_name)));
+        }
+
+        if let (ActiveValue::Set(postcode), ActiveValue::Set(house_number)) = (&node.postcode, &node.house_number) {
+            node.search_key = ActiveValue::Set(search_key(postcode, house_number.as_deref()));
+        }
+
+        if !node.id.is_set() {
+            return Err(Rejection::Id);
+        }
+
+        self.missing(node).map_or(Ok(()), |field| Err(Rejection::Missing(field)))
+    }
+}
+
+pub struct Profiles {
+    default: CountryProfile,
+    countries: BTreeMap<String, CountryProfile>,
+}
+
+impl Profiles {
+    /// The profiles shipped with the importer.
+    pub fn builtin() -> Self {
+        let countries = BTreeMap::from([
+            ("NL".to_string(), CountryProfile::new("^[1-9][0-9]{3}[A-Z]{2}$", Normalization::Compact, &[Field::Street], HouseNumberStyle::Compact, Dedup::SingleStreet).with_house_number_position(HouseNumberPosition::AfterStreet).with_language("nl")),
+            ("DE".to_string(), CountryProfile::new("^[0-9]{5}$", Normalization::Compact, &[Field::Street], HouseNumberStyle::Compact, Dedup::None).with_house_number_position(HouseNumberPosition::AfterStreet).with_postcode_hierarchy(&DE_HIERARCHY).with_language("de")),
+            ("CA".to_string(), CountryProfile::new(CA_POSTCODE, Normalization::Spaced, &[Field::Street], HouseNumberStyle::Uppercase, Dedup::None).with_split_outcode().with_province_codes(&CA_PROVINCES).with_address_format(format::US).with_house_number_position(HouseNumberPosition::BeforeStreet).with_language("en")),
+            ("GB".to_string(), CountryProfile::new(GB_POSTCODE, Normalization::Spaced, &[Field::Street], HouseNumberStyle::Uppercase, Dedup::None).with_split_outcode().with_address_format(format::GB).with_house_number_position(HouseNumberPosition::BeforeStreet).with_postcode_hierarchy(&GB_HIERARCHY).with_language("en")),
+            ("FR".to_string(), CountryProfile::new("^[0-9]{5}$", Normalization::Compact, &[Field::Street, Field::City], HouseNumberStyle::Uppercase, Dedup::None).with_address_format(format::FR).with_house_number_position(HouseNumberPosition::BeforeStreet).with_language("fr")),
+            ("US".to_string(), CountryProfile::new("^[0-9]{5}$", Normalization::ZipPlusFour, &[Field::Street, Field::HouseNumber], HouseNumberStyle::Uppercase, Dedup::None).with_province_codes(&US_STATES).with_address_format(format::US).with_house_number_position(HouseNumberPosition::BeforeStreet).with_language("en")),
+            ("IE".to_string(), CountryProfile::new(IE_POSTCODE, Normalization::Spaced, &[], HouseNumberStyle::Uppercase, Dedup::None).with_split_outcode().with_inward_length(4).with_address_format(format::IE).with_house_number_position(HouseNumberPosition::BeforeStreet).with_language("en")),
+            // Japanese addresses are numbered by block within a neighbourhood and rarely have a street
+            ("JP".to_string(), CountryProfile::new("^[0-9]{3}-[0-9]{4}$", Normalization::Hyphenated, &[], HouseNumberStyle::Compact, Dedup::None).with_address_format(format::JP)),
+            // Only the postcode pattern and street types, the fields are those of countries without a profile
+            ("RU".to_string(), CountryProfile::default().with_postcode_pattern("^[0-9]{6}$").with_language("ru")),
+        ]);
+
+        Self { default: CountryProfile::default(), countries }
+    }
+
+    /// The built-in profiles, with those in the file added or replaced.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let overrides: HashMap<String, CountryProfile> = serde_json::from_slice(&std::fs::read(path)?)?;
+
+        let mut profiles = Self::builtin();
+        profiles.countries.extend(overrides.into_iter().map(|(code, profile)| (code.to_uppercase(), profile)));
+
+        Ok(profiles)
+    }
+
+    pub fn get(&self, country: Option<&str>) -> &CountryProfile {
+        country
+            .and_then(|country| self.countries.get(&country.to_uppercase()))
+            .unwrap_or(&self.default)
+    }
+
+    /// The profile of the first country whose pattern matches a postcode of an unknown country, as entered in a
+    /// lookup. Profiles without a pattern would match anything, so the default is used when none matches.
+    pub fn matching(&self, postcode: &str) -> &CountryProfile {
+        self.countries.values()
+            .filter(|profile| profile.postcode_pattern.is_some())
+            .find(|profile| profile.normalize_postcode(postcode).is_some())
+            .unwrap_or(&self.default)
+    }
+
+    /// Normalizes a postcode of an unknown country the way its [`matching`](Self::matching) profile would.
+    pub fn normalize_any(&self, postcode: &str) -> String {
+        self.matching(postcode).normalize_postcode(postcode).unwrap_or_else(|| postcode.to_string())
+    }
+
+    /// The countries whose profile has a postcode hierarchy.
+    pub fn hierarchical_countries(&self) -> Vec<&str> {
+        self.countries.iter()
+            .filter(|(_, profile)| !profile.postcode_hierarchy.is_empty())
+            .map(|(code, _)| code.as_str())
+            .collect()
+    }
+
+    /// SQL condition on the `node` table selecting the rows whose country uses [`Dedup::SingleStreet`].
+    pub fn single_street_condition(&self) -> String {
+        let (dedup, keep): (Vec<_>, Vec<_>) = self.countries.iter()
+            .partition(|(_, profile)| profile.dedup == Dedup::SingleStreet);
+
+        let list = |codes: Vec<(&String, &CountryProfile)>| codes.iter()
+            .map(|(code, _)| format!("'{}'", code.replace('\'', "''")))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        // The default applies to rows without a country and all countries without a profile
+        if self.default.dedup == Dedup::SingleStreet {
+            if keep.is_empty() {
+                return "1 = 1".to_string();
+            }
+
+            format!("(country IS NULL OR UPPER(country) NOT IN ({}))", list(keep))
+        } else {
+            if dedup.is_empty() {
+                return "1 = 0".to_string();
+            }
+
+            format!("UPPER(country) IN ({})", list(dedup))
+        }
+    }
+}
+
+/// Sets the profiles used by [`profiles`], only the first call has any effect.
+pub fn init(profiles: Profiles) {
+    let _ = PROFILES.set(profiles);
+}
+
+pub fn profiles() -> &'static Profiles {
+    PROFILES.get_or_init(Profiles::builtin)
+}
+
+/// Case-folds a house name and drops its punctuation, so `St. John's House` and `st johns house` match. The same in
+/// every country, as lookups don't know the country.
+pub fn normalize_house_name(house_name: &str) -> String {
+    house_name.split_whitespace()
+        .map(|word| word.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect::<String>())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The normalized postcode and house number lowercased and without whitespace, like `1234ab12a`, the key other
+/// databases join addresses on. `None` without a house number.
+pub fn search_key(postcode: &str, house_number: Option<&str>) -> Option<String> {
+    let house_number = house_number?;
+
+    Some(postcode.chars().chain(house_number.chars()).filter(|c| !c.is_whitespace()).flat_map(char::to_lowercase).collect())
+}
@@ -0,0 +1,67 @@
+//! Progress of the phases after parsing, which page through the postcodes and would otherwise run for minutes without
+//! printing anything.
+
+use std::time::{Duration, Instant};
+
+/// Minimum time between two reports.
+const INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct Progress {
+    label: &'static str,
+    total: u64,
+    done: u64,
+    started: Instant,
+    reported: Instant,
+}
+
+impl Progress {
+    pub fn new(label: &'static str, total: u64) -> Self {
+        let now = Instant::now();
+
+        Self { label, total, done: 0, started: now, reported: now }
+    }
+
+    /// Marks `count` more steps as done. Prints the progress with an estimate of the time left at most every few
+    /// seconds, and once the last step is done.
+    pub fn advance(&mut self, count: u64) {
+        self.done += count;
+
+        let now = Instant::now();
+
+        if self.done < self.total && now.duration_since(self.reported) < INTERVAL {
+            return;
+        }
+
+        self.reported = now;
+
+        let elapsed = now.duration_since(self.started);
+
+        if self.done >= self.total {
+            println!("{}: {} batches done in {}", self.label, self.done, format_duration(elapsed));
+            return;
+        }
+
+        let remaining = elapsed.mul_f64((self.total - self.done) as f64 / self.done.max(1) as f64);
+
+        println!(
+            "{}: {}/{} batches ({:.0}%), about {} left",
+            self.label,
+            self.done,
+            self.total,
+            self.done as f64 / self.total as f64 * 100.0,
+            format_duration(remaining),
+        );
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+
+    if seconds >= 3600 {
+        format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60)
+    } else if seconds >= 60 {
+        format!("{}m {:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
@@ -0,0 +1,171 @@
+//! Uploads the finished SQLite database to S3 or Cloud Storage with `--publish`, compressed with zstd when the key ends
+//! in `.zst`. `{date}` in the key is replaced with the day the import started. `latest.json` next to the upload is
+//! replaced last and points at it, so downloaders find the newest database without listing the bucket.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use reqwest::blocking::{Body, Client};
+use reqwest::Method;
+use serde_json::json;
+use sqlx::sqlite::SqliteConnectOptions;
+
+use crate::baseline::with_suffix;
+use crate::error::Error;
+use crate::notify;
+use crate::package::sha256;
+use crate::storage::{Location, Storage};
+
+/// Slower than the default level, but the database is compressed once and downloaded many times.
+const ZSTD_LEVEL: i32 = 12;
+const LATEST: &str = "latest.json";
+
+/// What the object is described by, besides its key.
+struct Metadata {
+    sha256: String,
+    addresses: u64,
+    source: Option<String>,
+    created_at: String,
+}
+
+/// Header values have to be visible ASCII.
+fn header_value(value: &str) -> String {
+    value.chars().map(|c| if c.is_ascii_graphic() || c == ' ' { c } else { '_' }).collect()
+}
+
+fn upload(storage: &Storage, location: &Location, path: &Path, content_type: &str, metadata: &Metadata) -> Result<(), String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let bytes = file.metadata().map_err(|e| format!("{}: {}", path.display(), e))?.len();
+
+    let mut headers = vec![
+        ("x-amz-meta-sha256".to_string(), metadata.sha256.clone()),
+        ("x-amz-meta-created-at".to_string(), metadata.created_at.clone()),
+        ("x-amz-meta-generator".to_string(), format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))),
+        ("x-amz-meta-addresses".to_string(), metadata.addresses.to_string()),
+    ];
+
+    if let Some(source) = &metadata.source {
+        headers.push(("x-amz-meta-source".to_string(), header_value(source)));
+    }
+
+    storage.request(Method::PUT, location, &headers)
+        .header("content-type", content_type)
+        .body(Body::sized(file, bytes))
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| format!("failed to upload {}: {}", location.url(), e))?;
+
+    Ok(())
+}
+
+fn compress(path: &Path, target: &Path) -> Result<(), String> {
+    let error = |e: std::io::Error| format!("failed to compress {}: {}", path.display(), e);
+    let input = BufReader::new(File::open(path).map_err(error)?);
+    let output = File::create(target).map_err(error)?;
+
+    zstd::stream::copy_encode(input, output, ZSTD_LEVEL).map_err(error)
+}
+
+fn publish(database: PathBuf, location: Location, metadata: Metadata) -> Result<(), String> {
+    // Large databases take longer than the default timeout to send
+    let client = Client::builder()
+        .timeout(None)
+        .connect_timeout(Duration::from_secs(30))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let storage = Storage::for_location(&location, client)?;
+
+    let compressed = location.key.ends_with(".zst").then(|| with_suffix(&database, ".zst"));
+
+    let (upload_path, content_type) = match &compressed {
+        Some(compressed) => {
+            println!("Compressing {}", database.display());
+            compress(&database, compressed)?;
+            (compressed.as_path(), "application/zstd")
+        }
+        None => (database.as_path(), "application/vnd.sqlite3"),
+    };
+
+    let result = (|| {
+        println!("Uploading to {}", location.url());
+        upload(&storage, &location, upload_path, content_type, &metadata)?;
+
+        let bytes = std::fs::metadata(upload_path).map_err(|e| format!("{}: {}", upload_path.display(), e))?.len();
+        let latest = json!({
+            "url": location.url(),
+            "key": location.key,
+            "bytes": bytes,
+            "sha256": sha256(upload_path).map_err(|e| format!("{}: {}", upload_path.display(), e))?,
+            "compression": compressed.as_ref().map(|_| "zstd"),
+            "database_sha256": metadata.sha256,
+            "addresses": metadata.addresses,
+            "source": metadata.source,
+            "created_at": metadata.created_at,
+        });
+
+        let key = match location.key.rsplit_once('/') {
+            Some((directory, _)) => format!("{}/{}", directory, LATEST),
+            None => LATEST.to_string(),
+        };
+        let pointer = location.with_key(key);
+
+        storage.request(Method::PUT, &pointer, &[])
+            .header("content-type", "application/json")
+            .header("cache-control", "no-cache")
+            .body(serde_json::to_vec_pretty(&latest).map_err(|e| e.to_string())?)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("failed to update {}: {}", pointer.url(), e))?;
+
+        println!("Published {}", location.url());
+        Ok(())
+    })();
+
+    if let Some(compressed) = &compressed {
+        let _ = std::fs::remove_file(compressed);
+    }
+
+    result
+}
+
+/// Checks the `--publish` URL and `--db` before importing, rather than throwing away a finished import.
+pub fn check(db_uri: &str, url: &str) -> Result<Location, Error> {
+    if !db_uri.starts_with("sqlite:") {
+        return Err(Error::Usage("--publish uploads a SQLite database file, --db is a server".to_string()));
+    }
+
+    Location::parse(url)
+        .unwrap_or_else(|| Err(format!("{} isn't an s3:// or gs:// URL", url)))
+        .map_err(Error::Usage)
+}
+
+/// Uploads the SQLite database of `db_uri` to the `--publish` URL, returning the URL of the upload.
+pub async fn run(db_uri: &str, url: &str, source: Option<&Path>, started_at: DateTime<Local>) -> Result<String, Error> {
+    let url = url.replace("{date}", &started_at.format("%Y-%m-%d").to_string());
+    let location = check(db_uri, &url)?;
+
+    let database = SqliteConnectOptions::from_str(db_uri)
+        .map_err(|e| Error::Usage(format!("invalid --db: {}", e)))?
+        .get_filename()
+        .to_path_buf();
+
+    // Also moves the write-ahead log into the file
+    let addresses = notify::addresses(db_uri).await.map_err(Error::Database)?;
+    let metadata = Metadata {
+        sha256: sha256(&database).map_err(|e| Error::Output(format!("{}: {}", database.display(), e)))?,
+        addresses,
+        source: source.map(|source| source.display().to_string()),
+        created_at: started_at.to_rfc3339(),
+    };
+
+    tokio::task::spawn_blocking(move || publish(database, location, metadata))
+        .await
+        .expect("the upload doesn't panic")
+        .map_err(Error::Output)?;
+
+    Ok(url)
+}
@@ -0,0 +1,109 @@
+//! Address density per H3 cell, for heat maps and for joining against other data indexed the same way.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use h3o::{CellIndex, Resolution};
+use sea_orm::{ColumnTrait, DatabaseConnection, QueryFilter, QuerySelect};
+use sea_orm::sea_query::{Expr, Func, SimpleExpr};
+use serde::Serialize;
+
+use crate::database::live_nodes;
+use crate::entities::*;
+use crate::h3::index;
+use crate::query::Ranked;
+use crate::table::print_table;
+
+#[derive(Serialize)]
+struct Cell {
+    /// H3 index of the cell in hexadecimal, like `881969b4a5fffff`
+    cell: String,
+    addresses: i64,
+    postcodes: usize,
+    /// Center of the addresses in the cell
+    lat: f64,
+    lon: f64,
+    per_km2: f64,
+}
+
+#[derive(Default)]
+struct Sums<'a> {
+    addresses: i64,
+    postcodes: HashSet<&'a str>,
+    lat: f64,
+    lon: f64,
+}
+
+/// Counts the addresses per cell at `resolution`, the parents of the cells stored with `--h3-resolution`. Densest
+/// first, at most `limit` after the cursor `after`.
+pub async fn run(db: &DatabaseConnection, resolution: Resolution, after: Option<&Ranked>, limit: Option<u64>, format: &str) -> Result<(), Box<dyn Error>> {
+    let stored: Vec<(i64, String, i64, f64, f64)> = live_nodes()
+        .select_only()
+        .column(node::Column::H3)
+        .column(node::Column::Postcode)
+        .column_as(node::Column::Id.count(), "addresses")
+        .column_as(SimpleExpr::from(Func::sum(Expr::col(node::Column::Lat))), "lat")
+        .column_as(SimpleExpr::from(Func::sum(Expr::col(node::Column::Lon))), "lon")
+        .filter(node::Column::H3.is_not_null())
+        .group_by(node::Column::H3)
+        .group_by(node::Column::Postcode)
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let mut sums: HashMap<CellIndex, Sums> = HashMap::new();
+    let mut finer = 0;
+
+    for (cell, postcode, addresses, lat, lon) in &stored {
+        let Some(parent) = index(*cell).and_then(|cell| cell.parent(resolution)) else {
+            finer += addresses;
+            continue;
+        };
+
+        let sums = sums.entry(parent).or_default();
+        sums.addresses += addresses;
+        sums.postcodes.insert(postcode);
+        sums.lat += lat;
+        sums.lon += lon;
+    }
+
+    if finer > 0 {
+        println!("Warning: {} addresses were imported with an --h3-resolution coarser than {}", finer, resolution);
+    }
+
+    let cells: Vec<Cell> = sums.into_iter()
+        .map(|(cell, sums)| Cell {
+            cell: cell.to_string(),
+            addresses: sums.addresses,
+            postcodes: sums.postcodes.len(),
+            lat: sums.lat / sums.addresses as f64,
+            lon: sums.lon / sums.addresses as f64,
+            per_km2: sums.addresses as f64 / cell.area_km2(),
+        })
+        .collect();
+
+    let (cells, next) = Ranked::page(cells, after, limit, |cell| (cell.addresses as f64, cell.cell.as_str()));
+
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&cells)?);
+        Ranked::print_next(next);
+        return Ok(());
+    }
+
+    let rows: Vec<Vec<String>> = cells.iter()
+        .map(|cell| vec![
+            cell.cell.clone(),
+            cell.addresses.to_string(),
+            cell.postcodes.to_string(),
+            format!("{:.5}", cell.lat),
+            format!("{:.5}", cell.lon),
+            format!("{:.1}", cell.per_km2),
+        ])
+        .collect();
+
+    print_table(&["cell", "addresses", "postcodes", "lat", "lon", "per_km2"], &rows);
+
+    Ranked::print_next(next);
+
+    Ok(())
+}
@@ -0,0 +1,244 @@
+use std::error::Error;
+
+use clap::{arg, value_parser, ArgMatches, Command};
+use h3o::Resolution;
+use sea_orm::{ColumnTrait, Condition, DatabaseConnection, DbErr, QueryFilter};
+use sea_orm::sea_query::{Expr, Func};
+
+use crate::database::live_nodes;
+use crate::entities::*;
+use crate::format::{format_address, Formatted};
+use crate::normalize_postcode;
+use crate::plus_code;
+use crate::profile::{normalize_house_name, profiles};
+use crate::spatial::{nearest_n, Nearby, MAX_NEAREST_LIMIT};
+use crate::table::print_table;
+
+mod h3;
+mod page;
+mod prefix;
+pub mod sql;
+mod stats;
+
+pub use page::{Key, Page, Ranked};
+pub use prefix::{centroid_cursor, normalize_prefix, postcode_centroids, postcodes_with_prefix, range_end, PostcodeCursor};
+
+pub fn cli() -> Command {
+    Command::new("query")
+        .about("Queries an existing database")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("lookup")
+                .about("Looks up the addresses for a postcode and optional house number or name, or for a plus code")
+                .arg(arg!(--postcode <POSTCODE>).required_unless_present_any(["pluscode", "prefix"]))
+                .arg(arg!(--pluscode <PLUS_CODE> "Full plus code like 8FVC9G8F+6X, or a padded area like 8FVC9G00+").conflicts_with_all(["postcode", "housenumber", "housename"]))
+                .arg(arg!(--prefix <PREFIX> "List the postcodes starting with this, each with its number of addresses and their center").conflicts_with_all(["postcode", "pluscode", "housenumber", "housename"]))
+                .arg(arg!(--housenumber <HOUSE_NUMBER>))
+                .arg(arg!(--housename <HOUSE_NAME> "Name of the building, matched ignoring case and punctuation"))
+                .arg(arg!(--format <FORMAT> "`address` prints every address as it's written on an envelope in its country").value_parser(["table", "json", "address"]).default_value("table"))
+                .arg(arg!(--"after-id" <ID> "Only addresses with a higher id, the cursor printed after a full page").value_parser(value_parser!(i64)).allow_negative_numbers(true).conflicts_with("prefix"))
+                .arg(arg!(--after <CURSOR> "Only postcodes after this country and postcode, the cursor printed after a full page of --prefix").value_parser(PostcodeCursor::parse).requires("prefix"))
+                .arg(arg!(--limit <COUNT> "At most this many addresses or postcodes").value_parser(value_parser!(u64).range(1..)))
+        )
+        .subcommand(
+            Command::new("nearest")
+                .about("Lists the addresses closest to a coordinate with their distance and bearing")
+                .arg(arg!(--lat <LAT>).required(true).value_parser(value_parser!(f64)).allow_negative_numbers(true))
+                .arg(arg!(--lon <LON>).required(true).value_parser(value_parser!(f64)).allow_negative_numbers(true))
+                .arg(arg!(--limit <COUNT>).default_value("10").value_parser(value_parser!(u64).range(..=MAX_NEAREST_LIMIT)))
+                .arg(arg!(--"after-id" <ID> "Only addresses further away than this one, the cursor printed after a full page").value_parser(value_parser!(i64)).allow_negative_numbers(true))
+                .arg(arg!(--format <FORMAT>).value_parser(["table", "json"]).default_value("table"))
+        )
+        .subcommand(
+            Command::new("sql")
+                .about("Runs a SELECT on a read-only connection, for when there's no database client at hand")
+                .arg(arg!(<SQL> "A single statement, like \"SELECT country, COUNT(*) FROM node GROUP BY country\""))
+                .arg(arg!(--format <FORMAT>).value_parser(["table", "csv", "json"]).default_value("table"))
+        )
+        .subcommand(
+            Command::new("stats")
+                .about("Counts the addresses and postcodes every source contributes: OSM nodes, OSM ways and each external dataset")
+                .arg(arg!(--format <FORMAT>).value_parser(["table", "json"]).default_value("table"))
+                .arg(arg!(--after <CURSOR> "Only sources after this one, the cursor printed after a full page").value_parser(Ranked::parse))
+                .arg(arg!(--limit <COUNT> "Only list the largest sources").value_parser(value_parser!(u64).range(1..)))
+                .subcommand(
+                    Command::new("h3")
+                        .about("Counts the addresses per H3 cell, densest first, from the cells stored with --h3-resolution")
+                        .arg(arg!(--resolution <RES> "Resolution of the cells, at most the --h3-resolution of the import").required(true).value_parser(value_parser!(u8).range(0..=15)))
+                        .arg(arg!(--after <CURSOR> "Only cells after this one, the cursor printed after a full page").value_parser(Ranked::parse))
+                        .arg(arg!(--limit <COUNT> "Only list the densest cells").value_parser(value_parser!(u64).range(1..)))
+                        .arg(arg!(--format <FORMAT>).value_parser(["table", "json"]).default_value("table"))
+                )
+        )
+}
+
+pub async fn run(db: &DatabaseConnection, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    match matches.subcommand() {
+        Some(("lookup", matches)) => {
+            let limit = matches.get_one::<u64>("limit").copied();
+
+            if let Some(prefix) = matches.get_one::<String>("prefix") {
+                let centroids = postcodes_with_prefix(db, prefix, matches.get_one::<PostcodeCursor>("after"), limit).await?;
+
+                prefix::print_centroids(&centroids, matches.get_one::<String>("format").expect("defaulted in clap"))?;
+
+                if let Some(last) = centroids.last().filter(|_| limit.is_some_and(|limit| centroids.len() as u64 >= limit)) {
+                    eprintln!("Next page: --after '{}'", centroid_cursor(last));
+                }
+
+                return Ok(());
+            }
+
+            let page = Page { after_id: matches.get_one::<i64>("after-id").copied(), limit };
+
+            let models = match matches.get_one::<String>("pluscode") {
+                Some(code) => lookup_plus_code(db, &plus_code::prefix(code)?, page).await?,
+                None => {
+                    let postcode = matches.get_one::<String>("postcode").expect("required in clap without --pluscode or --prefix");
+                    let house_number = matches.get_one::<String>("housenumber");
+                    let house_name = matches.get_one::<String>("housename");
+
+                    lookup(db, postcode, house_number.map(String::as_str), house_name.map(String::as_str), page).await?
+                }
+            };
+
+            print_models(&models, matches.get_one::<String>("format").expect("defaulted in clap"))?;
+
+            // On stderr, so the output itself can still be piped
+            if let Some(next) = page.next(&models) {
+                eprintln!("Next page: --after-id {}", next);
+            }
+
+            Ok(())
+        }
+        Some(("nearest", matches)) => {
+            let lat = *matches.get_one::<f64>("lat").expect("required in clap");
+            let lon = *matches.get_one::<f64>("lon").expect("required in clap");
+            let limit = *matches.get_one::<u64>("limit").expect("defaulted in clap");
+            let found = nearest_n(db, lat, lon, limit, matches.get_one::<i64>("after-id").copied()).await?;
+
+            print_nearby(&found, matches.get_one::<String>("format").expect("defaulted in clap"))?;
+
+            if let Some(last) = found.last().filter(|_| found.len() as u64 >= limit) {
+                eprintln!("Next page: --after-id {}", last.node.id);
+            }
+
+            Ok(())
+        }
+        Some(("stats", matches)) => match matches.subcommand() {
+            Some(("h3", matches)) => {
+                let resolution = matches.get_one::<u8>("resolution").expect("required in clap");
+                let resolution = Resolution::try_from(*resolution).expect("validated in clap");
+                let after = matches.get_one::<Ranked>("after");
+                let limit = matches.get_one::<u64>("limit").copied();
+
+                h3::run(db, resolution, after, limit, matches.get_one::<String>("format").expect("defaulted in clap")).await
+            }
+            _ => {
+                let after = matches.get_one::<Ranked>("after");
+                let limit = matches.get_one::<u64>("limit").copied();
+
+                stats::run(db, after, limit, matches.get_one::<String>("format").expect("defaulted in clap")).await
+            }
+        },
+        Some(("sql", _)) => unreachable!("run before connecting, see sql::run"),
+        _ => unreachable!("subcommand is required"),
+    }
+}
+
+/// Finds the addresses for a postcode. Postcodes that only cover a single street are stored without a house
+/// number, so those rows match any house number or name. Exact house number and name matches are returned first, also
+/// when the lookup is paged.
+pub async fn lookup(db: &DatabaseConnection, postcode: &str, house_number: Option<&str>, house_name: Option<&str>, page: Page) -> Result<Vec<node::Model>, DbErr> {
+    let mut query = live_nodes()
+        .filter(node::Column::Postcode.eq(normalize_postcode(postcode)));
+
+    if let Some(house_number) = house_number {
+        // Stored the way the country of the postcode writes them, `12 a` is `12A` in NL
+        let house_number = profiles().matching(postcode).normalize_house_number(house_number);
+
+        query = query.filter(
+            Condition::any()
+                .add(node::Column::HouseNumber.eq(house_number))
+                .add(node::Column::HouseNumber.is_null())
+        );
+    }
+
+    if let Some(house_name) = house_name {
+        query = query.filter(
+            Condition::any()
+                .add(node::Column::HouseNameNormalized.eq(normalize_house_name(house_name)))
+                .add(node::Column::HouseNumber.is_null().and(node::Column::HouseName.is_null()))
+        );
+    }
+
+    // Keys without NULLs, a cursor can't be compared to them
+    let exact_first = [
+        Key::new(Expr::case(Expr::col(node::Column::HouseNameNormalized).is_null(), 1).finally(0)),
+        Key::new(Expr::case(Expr::col(node::Column::HouseNumber).is_null(), 1).finally(0)),
+        Key::new(Func::coalesce([Expr::col(node::Column::HouseNumber).into(), Expr::val("").into()])),
+    ];
+
+    page.apply_ordered(query, &exact_first).all(db).await
+}
+
+/// Finds the addresses whose plus code starts with `prefix`, see [`plus_code::prefix`]. A large area can have
+/// millions, page through them with `page`.
+pub async fn lookup_plus_code(db: &DatabaseConnection, prefix: &str, page: Page) -> Result<Vec<node::Model>, DbErr> {
+    let query = live_nodes().filter(node::Column::PlusCode.starts_with(prefix));
+
+    page.apply_ordered(query, &[Key::new(Expr::col(node::Column::PlusCode))]).all(db).await
+}
+
+pub fn print_models(models: &[node::Model], format: &str) -> Result<(), Box<dyn Error>> {
+    if format == "json" {
+        let formatted: Vec<Formatted> = models.iter().cloned().map(Formatted::from).collect();
+        println!("{}", serde_json::to_string_pretty(&formatted)?);
+        return Ok(());
+    }
+
+    if format == "address" {
+        let addresses: Vec<String> = models.iter().map(format_address).collect();
+        println!("{}", addresses.join("\n\n"));
+        return Ok(());
+    }
+
+    let rows: Vec<Vec<String>> = models.iter()
+        .map(|model| vec![
+            model.lat.to_string(),
+            model.lon.to_string(),
+            model.city.clone().unwrap_or_default(),
+            model.country.clone().unwrap_or_default(),
+            model.postcode.clone(),
+            model.province.clone().unwrap_or_default(),
+            model.street.clone().unwrap_or_default(),
+            model.house_number.clone().unwrap_or_default(),
+        ])
+        .collect();
+
+    print_table(&["lat", "lon", "city", "country", "postcode", "province", "street", "house_number"], &rows);
+
+    Ok(())
+}
+
+fn print_nearby(found: &[Nearby], format: &str) -> Result<(), Box<dyn Error>> {
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(found)?);
+        return Ok(());
+    }
+
+    let rows: Vec<Vec<String>> = found.iter()
+        .map(|nearby| vec![
+            format!("{:.1}", nearby.distance),
+            format!("{:.0}", nearby.bearing),
+            nearby.node.postcode.clone(),
+            nearby.node.street.clone().unwrap_or_default(),
+            nearby.node.house_number.clone().unwrap_or_default(),
+            nearby.node.city.clone().unwrap_or_default(),
+        ])
+        .collect();
+
+    print_table(&["distance_m", "bearing", "postcode", "street", "house_number", "city"], &rows);
+
+    Ok(())
+}
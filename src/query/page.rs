@@ -0,0 +1,133 @@
+//! Keyset pagination. Every listing has a fixed order that ends in a unique key, and a page continues after the last
+//! row of the previous one in that order instead of skipping rows, so paging through millions of rows stays as fast as
+//! the first page and doesn't skip or repeat rows when rows are added or removed in between. The order is the same
+//! whether a listing is paged or not.
+//!
+//! Addresses are paged with [`Page`], by the id of the last one. Listings sorted in memory, like statistics and
+//! suggestions, are paged with [`Ranked`].
+
+use sea_orm::{ColumnTrait, Condition, Order, QueryFilter, QueryOrder, QuerySelect, Select};
+use sea_orm::sea_query::{Expr, Query, SimpleExpr};
+
+use crate::entities::*;
+
+/// An expression on the columns of the node table that addresses are ordered by, before their id. Columns are left
+/// unqualified, so the expression also works on the row of the cursor.
+pub struct Key(SimpleExpr);
+
+impl Key {
+    pub fn new(expr: impl Into<SimpleExpr>) -> Self {
+        Self(expr.into())
+    }
+
+    /// The value of the key for the address with the id, computed by the database the same way as for ordering.
+    fn of(&self, id: i64) -> SimpleExpr {
+        let row = Query::select()
+            .expr(self.0.clone())
+            .from(node::Entity)
+            .and_where(Expr::col(node::Column::Id).eq(id))
+            .to_owned();
+
+        SimpleExpr::SubQuery(None, Box::new(row.into_sub_query_statement()))
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Page {
+    /// Only the addresses after the one with this id
+    pub after_id: Option<i64>,
+    pub limit: Option<u64>,
+}
+
+impl Page {
+    /// Orders the addresses by id.
+    pub fn apply(&self, query: Select<node::Entity>) -> Select<node::Entity> {
+        self.apply_ordered(query, &[])
+    }
+
+    /// Orders the addresses by the keys and then by id. With a cursor, only the addresses after it in that order are
+    /// kept. When the address of the cursor is gone, so is the rest of a listing ordered by keys.
+    pub fn apply_ordered(&self, mut query: Select<node::Entity>, keys: &[Key]) -> Select<node::Entity> {
+        if let Some(after_id) = self.after_id {
+            // k1 > c1 OR (k1 = c1 AND (k2 > c2 OR (k2 = c2 AND id > after_id)))
+            let mut after = Condition::all().add(node::Column::Id.gt(after_id));
+
+            for key in keys.iter().rev() {
+                after = Condition::any()
+                    .add(Expr::expr(key.0.clone()).gt(key.of(after_id)))
+                    .add(Condition::all().add(Expr::expr(key.0.clone()).eq(key.of(after_id))).add(after));
+            }
+
+            query = query.filter(after);
+        }
+
+        for key in keys {
+            query = query.order_by(key.0.clone(), Order::Asc);
+        }
+
+        query.order_by_asc(node::Column::Id).limit(self.limit)
+    }
+
+    /// The cursor of the next page, `None` when this page wasn't full and so was the last one.
+    pub fn next(&self, models: &[node::Model]) -> Option<i64> {
+        match self.limit {
+            Some(limit) if models.len() as u64 >= limit => models.last().map(|model| model.id),
+            _ => None,
+        }
+    }
+}
+
+/// The cursor of a listing sorted by a number, highest first, and then by a name that's unique within the listing: the
+/// number and name of the last item of the previous page, like `1523,881969b4a5fffff`.
+#[derive(Clone)]
+pub struct Ranked {
+    number: f64,
+    name: String,
+}
+
+impl Ranked {
+    pub fn parse(cursor: &str) -> Result<Self, String> {
+        cursor.split_once(',')
+            .and_then(|(number, name)| Some(Self { number: number.parse().ok()?, name: name.to_string() }))
+            .ok_or_else(|| format!("{} isn't a cursor like 1523,881969b4a5fffff", cursor))
+    }
+
+    /// Prints the cursor of the next page on stderr, so the output itself can still be piped.
+    pub fn print_next(next: Option<String>) {
+        if let Some(next) = next {
+            eprintln!("Next page: --after '{}'", next);
+        }
+    }
+
+    /// Sorts the items by the number and name of their `key`, and keeps at most `limit` of them after the cursor. Also
+    /// returns the cursor of the next page, when this one is full.
+    pub fn page<T>(mut items: Vec<T>, after: Option<&Ranked>, limit: Option<u64>, key: impl Fn(&T) -> (f64, &str)) -> (Vec<T>, Option<String>) {
+        items.sort_by(|a, b| {
+            let ((a_number, a_name), (b_number, b_name)) = (key(a), key(b));
+
+            b_number.total_cmp(&a_number).then_with(|| a_name.cmp(b_name))
+        });
+
+        let start = after.map_or(0, |after| items.partition_point(|item| {
+            let (number, name) = key(item);
+
+            number > after.number || (number == after.number && name <= after.name.as_str())
+        }));
+
+        let mut items = items.split_off(start);
+
+        if let Some(limit) = limit {
+            items.truncate(limit as usize);
+        }
+
+        let next = items.last()
+            .filter(|_| limit.is_some_and(|limit| items.len() as u64 >= limit))
+            .map(|item| {
+                let (number, name) = key(item);
+
+                format!("{},{}", number, name)
+            });
+
+        (items, next)
+    }
+}
@@ -0,0 +1,146 @@
+//! Postcodes starting with a prefix, each as the center of its addresses, for interfaces that drill down from a
+//! region to a single postcode.
+
+use std::error::Error;
+
+use sea_orm::{ColumnTrait, Condition, DatabaseConnection, DbErr, FromQueryResult, QueryFilter, QueryOrder, QuerySelect, Select};
+use sea_orm::sea_query::{Expr, Func, SimpleExpr};
+use serde::Serialize;
+
+use crate::database::live_nodes;
+use crate::entities::*;
+use crate::normalize_postcode;
+use crate::table::print_table;
+
+#[derive(Serialize, FromQueryResult)]
+pub struct Centroid {
+    pub postcode: String,
+    pub country: Option<String>,
+    pub addresses: i64,
+    /// Center of the addresses with the postcode
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// A string after every string starting with `prefix`, so the prefix can be looked up as a range on the postcode
+/// index. `LIKE 'prefix%'` doesn't use the index in SQLite and fails on the case-insensitive postcode column in
+/// Postgres. U+FFFF sorts after every other character both byte-wise and in ICU collations, which reserve it for this.
+pub fn range_end(prefix: &str) -> String {
+    format!("{}\u{FFFF}", prefix)
+}
+
+/// A prefix as it's compared to the stored postcodes, uppercased and without a trailing `*`. Unlike a whole postcode its
+/// whitespace is kept as a single space, as a partial postcode like `SW1A 1` can't be matched to a country pattern.
+pub fn normalize_prefix(prefix: &str) -> String {
+    prefix.trim_end_matches(|c: char| c == '*' || c.is_whitespace())
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_uppercase()
+}
+
+/// The postcodes of every country, each as the center of its addresses in that country, ordered by postcode and
+/// country. Addresses without a country are a country of their own, sorted first.
+fn centroids() -> Select<node::Entity> {
+    live_nodes()
+        .select_only()
+        .column(node::Column::Postcode)
+        .column(node::Column::Country)
+        .column_as(Expr::col(node::Column::Id).count(), "addresses")
+        .column_as(SimpleExpr::from(Func::avg(Expr::col(node::Column::Lat))), "lat")
+        .column_as(SimpleExpr::from(Func::avg(Expr::col(node::Column::Lon))), "lon")
+        .group_by(node::Column::Postcode)
+        .group_by(node::Column::Country)
+        .order_by_asc(node::Column::Postcode)
+        .order_by_asc(country_key())
+}
+
+/// The country to order and page by, NULL sorts first in SQLite and MySQL but last in Postgres.
+fn country_key() -> SimpleExpr {
+    Func::coalesce([Expr::col(node::Column::Country).into(), Expr::val("").into()]).into()
+}
+
+/// The cursor of the page after `centroid`, its country and postcode like `NL:1011AB`.
+pub fn centroid_cursor(centroid: &Centroid) -> String {
+    format!("{}:{}", centroid.country.as_deref().unwrap_or_default(), centroid.postcode)
+}
+
+/// A cursor made by [`centroid_cursor`], the country is empty for postcodes without one.
+#[derive(Clone)]
+pub struct PostcodeCursor {
+    country: String,
+    postcode: String,
+}
+
+impl PostcodeCursor {
+    pub fn parse(cursor: &str) -> Result<Self, String> {
+        cursor.split_once(':')
+            .filter(|(_, postcode)| !postcode.is_empty())
+            .map(|(country, postcode)| Self { country: country.to_uppercase(), postcode: postcode.to_string() })
+            .ok_or_else(|| format!("{} isn't a cursor like NL:1011AB", cursor))
+    }
+}
+
+/// A postcode in every country it's used in, in the order of their country codes.
+pub async fn postcode_centroids(db: &DatabaseConnection, postcode: &str) -> Result<Vec<Centroid>, DbErr> {
+    centroids()
+        .filter(node::Column::Postcode.eq(normalize_postcode(postcode)))
+        .into_model::<Centroid>()
+        .all(db)
+        .await
+}
+
+/// The postcodes starting with `prefix` in order, a prefix ending in `*` is treated the same. Only those after the
+/// cursor `after` and at most `limit` when given, to page through them.
+pub async fn postcodes_with_prefix(db: &DatabaseConnection, prefix: &str, after: Option<&PostcodeCursor>, limit: Option<u64>) -> Result<Vec<Centroid>, DbErr> {
+    let prefix = normalize_prefix(prefix);
+
+    let mut query = centroids()
+        .filter(node::Column::Postcode.gte(prefix.as_str()))
+        .filter(node::Column::Postcode.lt(range_end(&prefix)));
+
+    if let Some(PostcodeCursor { country, postcode }) = after {
+        query = query.filter(
+            Condition::any()
+                .add(node::Column::Postcode.gt(postcode.as_str()))
+                .add(Condition::all().add(node::Column::Postcode.eq(postcode.as_str())).add(Expr::expr(country_key()).gt(country.as_str())))
+        );
+    }
+
+    query.limit(limit).into_model::<Centroid>().all(db).await
+}
+
+pub fn print_centroids(centroids: &[Centroid], format: &str) -> Result<(), Box<dyn Error>> {
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(centroids)?);
+        return Ok(());
+    }
+
+    let rows: Vec<Vec<String>> = centroids.iter()
+        .map(|centroid| vec![
+            centroid.postcode.clone(),
+            centroid.country.clone().unwrap_or_default(),
+            centroid.addresses.to_string(),
+            format!("{:.5}", centroid.lat),
+            format!("{:.5}", centroid.lon),
+        ])
+        .collect();
+
+    print_table(&["postcode", "country", "addresses", "lat", "lon"], &rows);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spaced_prefix_selects_spaced_postcodes() {
+        let prefix = normalize_prefix(" sw1a  1* ");
+
+        assert_eq!(prefix, "SW1A 1");
+        assert!(("SW1A 1AA" >= prefix.as_str()) && ("SW1A 1AA" < range_end(&prefix).as_str()));
+        assert!("SW1A 2AA" >= range_end(&prefix).as_str());
+    }
+}
@@ -0,0 +1,221 @@
+//! Runs a statement given on the command line on a read-only connection, for looking into the database on servers that
+//! have no database client installed. SQLite files are opened read-only, Postgres and MySQL sessions only allow
+//! read-only transactions. The statement is prepared, so it can't be followed by another.
+
+use std::error::Error;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use sea_orm::prelude::Decimal;
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+use sqlx::database::HasArguments;
+use sqlx::mysql::{MySqlPool, MySqlRow};
+use sqlx::postgres::{PgPool, PgRow};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Column, ColumnIndex, Database, Decode, Executor, IntoArguments, Pool, Row, Statement, Type};
+
+use crate::database::{sqlite_read_only_pool, MYSQL_READ_ONLY, POSTGRES_READ_ONLY};
+
+use crate::table::print_table;
+
+struct Rows {
+    columns: Vec<String>,
+    rows: Vec<Vec<Value>>,
+}
+
+/// A row as a JSON object with the columns in the order of the statement.
+struct Object<'a> {
+    columns: &'a [String],
+    values: &'a [Value],
+}
+
+impl Serialize for Object<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.columns.len()))?;
+
+        for (column, value) in self.columns.iter().zip(self.values) {
+            map.serialize_entry(column, value)?;
+        }
+
+        map.end()
+    }
+}
+
+/// Decodes the types only some backends have.
+trait Special: Row {
+    fn special(&self, _index: usize) -> Option<Value> {
+        None
+    }
+}
+
+impl Special for SqliteRow {}
+
+/// What AVG and SUM of integers return
+fn decimal<R: Row>(row: &R, index: usize) -> Option<Value>
+where
+    usize: ColumnIndex<R>,
+    for<'r> Decimal: Decode<'r, R::Database> + Type<R::Database>,
+{
+    let value = row.try_get::<Option<Decimal>, _>(index).ok()?;
+
+    Some(value.and_then(|value| value.to_string().parse::<f64>().ok()).map_or(Value::Null, Value::from))
+}
+
+impl Special for PgRow {
+    fn special(&self, index: usize) -> Option<Value> {
+        decimal(self, index)
+    }
+}
+
+impl Special for MySqlRow {
+    fn special(&self, index: usize) -> Option<Value> {
+        decimal(self, index)
+    }
+}
+
+/// A column of a row as JSON, by trying the types the backends return one after the other. Types that aren't known
+/// are given by their name.
+fn decode<'r, R: Special>(row: &'r R, index: usize) -> Value
+where
+    usize: ColumnIndex<R>,
+    i64: Decode<'r, R::Database> + Type<R::Database>,
+    i32: Decode<'r, R::Database> + Type<R::Database>,
+    i16: Decode<'r, R::Database> + Type<R::Database>,
+    f64: Decode<'r, R::Database> + Type<R::Database>,
+    f32: Decode<'r, R::Database> + Type<R::Database>,
+    bool: Decode<'r, R::Database> + Type<R::Database>,
+    String: Decode<'r, R::Database> + Type<R::Database>,
+    NaiveDateTime: Decode<'r, R::Database> + Type<R::Database>,
+    DateTime<Utc>: Decode<'r, R::Database> + Type<R::Database>,
+    NaiveDate: Decode<'r, R::Database> + Type<R::Database>,
+    Value: Decode<'r, R::Database> + Type<R::Database>,
+    Vec<u8>: Decode<'r, R::Database> + Type<R::Database>,
+{
+    // Before bool, which SQLite also reads from integers
+    if let Ok(value) = row.try_get::<Option<i64>, _>(index) {
+        return value.map_or(Value::Null, Value::from);
+    }
+    if let Ok(value) = row.try_get::<Option<i32>, _>(index) {
+        return value.map_or(Value::Null, Value::from);
+    }
+    if let Ok(value) = row.try_get::<Option<i16>, _>(index) {
+        return value.map_or(Value::Null, Value::from);
+    }
+    if let Ok(value) = row.try_get::<Option<f64>, _>(index) {
+        return value.map_or(Value::Null, Value::from);
+    }
+    if let Ok(value) = row.try_get::<Option<f32>, _>(index) {
+        return value.map_or(Value::Null, Value::from);
+    }
+    if let Ok(value) = row.try_get::<Option<bool>, _>(index) {
+        return value.map_or(Value::Null, Value::from);
+    }
+    // Before JSON, which SQLite also reads from text
+    if let Ok(value) = row.try_get::<Option<String>, _>(index) {
+        return value.map_or(Value::Null, Value::from);
+    }
+    if let Some(value) = row.special(index) {
+        return value;
+    }
+    if let Ok(value) = row.try_get::<Option<NaiveDateTime>, _>(index) {
+        return value.map_or(Value::Null, |value| Value::from(value.to_string()));
+    }
+    if let Ok(value) = row.try_get::<Option<DateTime<Utc>>, _>(index) {
+        return value.map_or(Value::Null, |value| Value::from(value.to_rfc3339()));
+    }
+    if let Ok(value) = row.try_get::<Option<NaiveDate>, _>(index) {
+        return value.map_or(Value::Null, |value| Value::from(value.to_string()));
+    }
+    if let Ok(value) = row.try_get::<Option<Value>, _>(index) {
+        return value.unwrap_or(Value::Null);
+    }
+    if let Ok(value) = row.try_get::<Option<Vec<u8>>, _>(index) {
+        return value.map_or(Value::Null, |value| Value::from(hex::encode(value)));
+    }
+
+    Value::from(format!("<{}>", row.columns()[index].type_info()))
+}
+
+/// Prepares and runs the statement on a connection of the pool, after making the session read-only with `read_only`.
+async fn fetch<DB: Database>(pool: &Pool<DB>, read_only: Option<&str>, sql: &str) -> Result<Rows, sqlx::Error>
+where
+    DB::Row: Special,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    usize: ColumnIndex<DB::Row>,
+    for<'r> i64: Decode<'r, DB> + Type<DB>,
+    for<'r> i32: Decode<'r, DB> + Type<DB>,
+    for<'r> i16: Decode<'r, DB> + Type<DB>,
+    for<'r> f64: Decode<'r, DB> + Type<DB>,
+    for<'r> f32: Decode<'r, DB> + Type<DB>,
+    for<'r> bool: Decode<'r, DB> + Type<DB>,
+    for<'r> String: Decode<'r, DB> + Type<DB>,
+    for<'r> NaiveDateTime: Decode<'r, DB> + Type<DB>,
+    for<'r> DateTime<Utc>: Decode<'r, DB> + Type<DB>,
+    for<'r> NaiveDate: Decode<'r, DB> + Type<DB>,
+    for<'r> Value: Decode<'r, DB> + Type<DB>,
+    for<'r> Vec<u8>: Decode<'r, DB> + Type<DB>,
+{
+    let mut connection = pool.acquire().await?;
+
+    if let Some(read_only) = read_only {
+        connection.execute(read_only).await?;
+    }
+
+    let statement = connection.prepare(sql).await?;
+    let columns = statement.columns().iter().map(|column| column.name().to_string()).collect();
+    let rows = statement.query().fetch_all(&mut *connection).await?;
+
+    Ok(Rows { columns, rows: rows.iter().map(|row| (0..row.len()).map(|index| decode(row, index)).collect()).collect() })
+}
+
+/// Connects on its own rather than with the connection of the other subcommands, which can write and migrate.
+pub async fn run(db_uri: &str, sql: &str, format: &str) -> Result<(), Box<dyn Error>> {
+    let rows = if db_uri.starts_with("sqlite:") {
+        // Not immutable, which would miss what an import is writing
+        fetch(&sqlite_read_only_pool(db_uri, false).await?, None, sql).await?
+    } else if db_uri.starts_with("postgres") {
+        fetch(&PgPool::connect(db_uri).await?, Some(POSTGRES_READ_ONLY), sql).await?
+    } else {
+        fetch(&MySqlPool::connect(db_uri).await?, Some(MYSQL_READ_ONLY), sql).await?
+    };
+
+    match format {
+        "json" => {
+            let objects: Vec<Object> = rows.rows.iter()
+                .map(|values| Object { columns: &rows.columns, values })
+                .collect();
+
+            println!("{}", serde_json::to_string_pretty(&objects)?);
+        }
+        "csv" => {
+            let mut writer = csv::Writer::from_writer(std::io::stdout());
+
+            writer.write_record(&rows.columns)?;
+
+            for row in rows.rows {
+                writer.write_record(row.iter().map(text))?;
+            }
+
+            writer.flush()?;
+        }
+        _ => {
+            let columns: Vec<&str> = rows.columns.iter().map(String::as_str).collect();
+            let rows: Vec<Vec<String>> = rows.rows.iter().map(|row| row.iter().map(text).collect()).collect();
+
+            print_table(&columns, &rows);
+        }
+    }
+
+    Ok(())
+}
+
+/// A value as it's printed in a table or CSV, NULL as an empty field.
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
@@ -0,0 +1,111 @@
+//! How much every source contributes: OSM nodes, OSM ways and each external dataset.
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+
+use sea_orm::{ConnectionTrait, DatabaseConnection, Statement};
+use serde::Serialize;
+
+use crate::external::EXTERNAL_IDS;
+use crate::query::Ranked;
+use crate::table::print_table;
+
+#[derive(Default, Serialize)]
+struct SourceStats {
+    source: String,
+    /// Rows that are neither deleted nor superseded
+    rows: i64,
+    /// Postcodes with at least one row from the source
+    postcodes: usize,
+    /// Postcodes that only have rows from the source
+    only_source: usize,
+    superseded: i64,
+    deleted: i64,
+}
+
+/// `node` and `way` for OSM rows, the `--source` for external datasets.
+fn origin() -> String {
+    format!("CASE WHEN id >= {} THEN source WHEN id < 0 THEN 'way' ELSE 'node' END", EXTERNAL_IDS)
+}
+
+/// The sources with the most rows first, at most `limit` after the cursor `after`.
+pub async fn run(db: &DatabaseConnection, after: Option<&Ranked>, limit: Option<u64>, format: &str) -> Result<(), Box<dyn Error>> {
+    let backend = db.get_database_backend();
+    let mut stats: BTreeMap<String, SourceStats> = BTreeMap::new();
+
+    let counts = db.query_all(Statement::from_string(backend, format!(
+        "SELECT {} AS origin, \
+            SUM(CASE WHEN deleted_at IS NULL AND superseded_by IS NULL THEN 1 ELSE 0 END) AS live, \
+            SUM(CASE WHEN deleted_at IS NULL AND superseded_by IS NOT NULL THEN 1 ELSE 0 END) AS superseded, \
+            SUM(CASE WHEN deleted_at IS NOT NULL THEN 1 ELSE 0 END) AS deleted \
+        FROM node GROUP BY origin",
+        origin(),
+    ))).await?;
+
+    for row in counts {
+        let source: Option<String> = row.try_get("", "origin")?;
+        let source = source.unwrap_or_default();
+
+        stats.insert(source.clone(), SourceStats {
+            rows: row.try_get("", "live")?,
+            superseded: row.try_get("", "superseded")?,
+            deleted: row.try_get("", "deleted")?,
+            source,
+            ..Default::default()
+        });
+    }
+
+    let pairs = db.query_all(Statement::from_string(backend, format!(
+        "SELECT DISTINCT postcode, {} AS origin FROM node WHERE deleted_at IS NULL AND superseded_by IS NULL",
+        origin(),
+    ))).await?;
+
+    let mut sources_of: HashMap<String, Vec<String>> = HashMap::new();
+
+    for row in pairs {
+        let postcode: String = row.try_get("", "postcode")?;
+        let source: Option<String> = row.try_get("", "origin")?;
+        sources_of.entry(postcode).or_default().push(source.unwrap_or_default());
+    }
+
+    for sources in sources_of.values() {
+        for source in sources {
+            let entry = stats.entry(source.clone()).or_default();
+            entry.postcodes += 1;
+            entry.only_source += (sources.len() == 1) as usize;
+        }
+    }
+
+    let stats: Vec<SourceStats> = stats.into_values().collect();
+    let total_rows: i64 = stats.iter().map(|stats| stats.rows).sum();
+    let (stats, next) = Ranked::page(stats, after, limit, |stats| (stats.rows as f64, stats.source.as_str()));
+
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        Ranked::print_next(next);
+        return Ok(());
+    }
+
+    let total_postcodes = sources_of.len();
+    let share = |count: f64, total: f64| format!("{:.1}%", count * 100.0 / total.max(1.0));
+
+    let rows: Vec<Vec<String>> = stats.iter()
+        .map(|stats| vec![
+            stats.source.clone(),
+            stats.rows.to_string(),
+            share(stats.rows as f64, total_rows as f64),
+            stats.postcodes.to_string(),
+            share(stats.postcodes as f64, total_postcodes as f64),
+            stats.only_source.to_string(),
+            stats.superseded.to_string(),
+            stats.deleted.to_string(),
+        ])
+        .collect();
+
+    println!("{} addresses in {} postcodes", total_rows, total_postcodes);
+    print_table(&["source", "rows", "of_rows", "postcodes", "of_postcodes", "only_source", "superseded", "deleted"], &rows);
+
+    Ranked::print_next(next);
+
+    Ok(())
+}
@@ -0,0 +1,112 @@
+//! Addresses that are rejected while importing: counted by reason against the `--max-rejected` thresholds, and with
+//! `--quarantine` kept in the `quarantine` table with their tags instead of being dropped.
+
+use std::collections::BTreeMap;
+
+use sea_orm::prelude::DateTime;
+use sea_orm::ActiveValue;
+
+use crate::entities::*;
+use crate::metrics;
+use crate::parse_percentage;
+use crate::profile::Rejection;
+
+/// A `--max-rejected` threshold, for every rejection or those of one reason.
+#[derive(Clone, Copy)]
+pub struct MaxRejected {
+    reason: Option<Rejection>,
+    percentage: f64,
+}
+
+impl MaxRejected {
+    /// Parses `PERCENT` or `REASON=PERCENT`, like `5` or `postcode=1`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let Some((reason, percentage)) = value.split_once('=') else {
+            return Ok(Self { reason: None, percentage: parse_percentage(value)? });
+        };
+
+        let reason = Rejection::from_name(reason)
+            .ok_or_else(|| format!("unknown reason {}, expected one of {}", reason, Rejection::NAMES.join(", ")))?;
+
+        Ok(Self { reason: Some(reason), percentage: parse_percentage(percentage)? })
+    }
+}
+
+/// The rejections of an import by reason.
+#[derive(Default)]
+pub struct Rejections {
+    counts: BTreeMap<Rejection, u64>,
+}
+
+impl Rejections {
+    pub fn add(&mut self, rejection: Rejection) {
+        metrics::REJECTED_ROWS.with_label_values(&[rejection.name()]).inc();
+        *self.counts.entry(rejection).or_default() += 1;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    /// Prints how many addresses with a postcode were rejected out of those and the `imported`, by reason.
+    pub fn report(&self, imported: u64) {
+        let total = self.total();
+
+        if total == 0 {
+            return;
+        }
+
+        println!("Rejected {} addresses, {:.1}% of those with a postcode", total, self.share(None, imported));
+
+        for (rejection, count) in &self.counts {
+            println!("  {}: {} ({:.1}%)", rejection.name(), count, self.share(Some(*rejection), imported));
+        }
+    }
+
+    fn share(&self, reason: Option<Rejection>, imported: u64) -> f64 {
+        let rejected = match reason {
+            Some(reason) => self.counts.get(&reason).copied().unwrap_or_default(),
+            None => self.total(),
+        };
+
+        rejected as f64 * 100.0 / (self.total() + imported).max(1) as f64
+    }
+
+    /// The first threshold that's exceeded, as the reason to fail the import.
+    pub fn exceeded(&self, thresholds: &[MaxRejected], imported: u64) -> Option<String> {
+        thresholds.iter().find_map(|threshold| {
+            let share = self.share(threshold.reason, imported);
+
+            (share > threshold.percentage).then(|| match threshold.reason {
+                Some(reason) => format!("{:.1}% of the addresses were rejected for their {}, more than --max-rejected {}={}%", share, reason.name(), reason.name(), threshold.percentage),
+                None => format!("{:.1}% of the addresses were rejected, more than --max-rejected {}%", share, threshold.percentage),
+            })
+        })
+    }
+}
+
+/// The row that keeps a rejected element, `None` for elements without an id.
+pub fn quarantine_row(node: &node::ActiveModel, rejection: Rejection, tags: &BTreeMap<String, String>, now: DateTime) -> Option<quarantine::ActiveModel> {
+    let ActiveValue::Set(id) = node.id else {
+        return None;
+    };
+
+    let set = |value: &ActiveValue<f64>| match value {
+        ActiveValue::Set(value) => Some(*value),
+        _ => None,
+    };
+
+    Some(quarantine::ActiveModel {
+        id: ActiveValue::Set(id),
+        reason: ActiveValue::Set(rejection.name().to_string()),
+        country: ActiveValue::Set(match &node.country {
+            ActiveValue::Set(country) => country.clone(),
+            _ => None,
+        }),
+        postcode: ActiveValue::Set(tags.get("addr:postcode").cloned()),
+        lat: ActiveValue::Set(set(&node.lat)),
+        lon: ActiveValue::Set(set(&node.lon)),
+        tags: ActiveValue::Set(serde_json::json!(tags)),
+        quarantined_at: ActiveValue::Set(now),
+    })
+}
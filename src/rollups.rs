@@ -0,0 +1,93 @@
+//! Rollups of postcodes into the coarser levels of countries with hierarchical postcodes, like the sector, district
+//! and area of GB postcodes. The levels come from the `postcode_hierarchy` of the country profiles.
+
+use std::collections::BTreeMap;
+
+use sea_orm::sea_query::{Expr, Func, SimpleExpr};
+use sea_orm::{ActiveValue, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QuerySelect};
+
+use crate::batch::{BatchInsert, Upsert};
+use crate::database::live_nodes;
+use crate::entities::*;
+use crate::profile::profiles;
+
+const BATCH_SIZE: usize = 1024;
+const PENDING_WRITES: usize = 4;
+
+#[derive(Default)]
+struct Rollup {
+    parent: Option<String>,
+    postcodes: i32,
+    addresses: i32,
+    /// Sums of the coordinates of the addresses
+    lat: f64,
+    lon: f64,
+}
+
+/// Rebuilds `postcode_rollup`, returning how many rows it has.
+pub async fn build(db: &DatabaseConnection) -> Result<usize, DbErr> {
+    postcode_rollup::Entity::delete_many().exec(db).await?;
+
+    let countries = profiles().hierarchical_countries();
+
+    if countries.is_empty() {
+        return Ok(0);
+    }
+
+    let postcodes: Vec<(String, String, i64, f64, f64)> = live_nodes()
+        .select_only()
+        .column(node::Column::Country)
+        .column(node::Column::Postcode)
+        .column_as(node::Column::Id.count(), "addresses")
+        .column_as(SimpleExpr::from(Func::avg(Expr::col(node::Column::Lat))), "lat")
+        .column_as(SimpleExpr::from(Func::avg(Expr::col(node::Column::Lon))), "lon")
+        .filter(node::Column::Country.is_in(countries))
+        .group_by(node::Column::Country)
+        .group_by(node::Column::Postcode)
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    // By country, level and code, so rows are written in key order
+    let mut rollups: BTreeMap<(String, usize, String), Rollup> = BTreeMap::new();
+
+    for (country, postcode, addresses, lat, lon) in postcodes {
+        let levels = &profiles().get(Some(&country)).postcode_hierarchy;
+        let codes: Vec<Option<String>> = levels.iter().map(|level| level.code(&postcode)).collect();
+
+        for (i, code) in codes.iter().enumerate() {
+            let Some(code) = code else {
+                continue;
+            };
+
+            let rollup = rollups.entry((country.clone(), i, code.clone())).or_default();
+            rollup.parent = codes.get(i + 1).cloned().flatten();
+            rollup.postcodes += 1;
+            rollup.addresses += addresses as i32;
+            rollup.lat += lat * addresses as f64;
+            rollup.lon += lon * addresses as f64;
+        }
+    }
+
+    let count = rollups.len();
+    let mut rows = BatchInsert::new(Upsert::new(db.clone(), None), BATCH_SIZE, PENDING_WRITES, None);
+
+    for ((country, level, code), rollup) in rollups {
+        let level = profiles().get(Some(&country)).postcode_hierarchy[level].name.clone();
+
+        rows.push(postcode_rollup::ActiveModel {
+            country: ActiveValue::Set(country),
+            level: ActiveValue::Set(level),
+            code: ActiveValue::Set(code),
+            parent: ActiveValue::Set(rollup.parent),
+            postcodes: ActiveValue::Set(rollup.postcodes),
+            addresses: ActiveValue::Set(rollup.addresses),
+            lat: ActiveValue::Set(rollup.lat / rollup.addresses as f64),
+            lon: ActiveValue::Set(rollup.lon / rollup.addresses as f64),
+        }).await?;
+    }
+
+    rows.finish().await?;
+
+    Ok(count)
+}
@@ -0,0 +1,132 @@
+//! Describes the tables of the artifact from the entities, as SQL, as a Mermaid ER diagram or as JSON, so apps that
+//! read the database can check what to expect. Indexes and the distance function come from the migrations and aren't
+//! part of it.
+
+use clap::{arg, Command};
+use sea_orm::{ColumnTrait, ColumnType, DbBackend, EntityTrait, IdenStatic, Iterable, PrimaryKeyToColumn, Schema};
+use serde::Serialize;
+
+use crate::entities::*;
+use crate::error::Error;
+
+pub fn cli() -> Command {
+    Command::new("schema")
+        .about("Prints the tables of the database from the entities, without connecting to it")
+        .arg(arg!(--format <FORMAT> "`sql` for CREATE TABLE statements, `mermaid` for an ER diagram").value_parser(["sql", "mermaid", "json"]).default_value("sql"))
+        .arg(arg!(--backend <BACKEND> "Database the SQL is written for, defaults to the one of --db").value_parser(["sqlite", "postgres", "mysql"]))
+}
+
+#[derive(Serialize)]
+struct Table {
+    name: String,
+    columns: Vec<Column>,
+    #[serde(skip)]
+    sql: String,
+}
+
+#[derive(Serialize)]
+struct Column {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    nullable: bool,
+    primary_key: bool,
+}
+
+fn table<E: EntityTrait + Default>(backend: DbBackend) -> Table {
+    let entity = E::default();
+    let primary_key: Vec<String> = E::PrimaryKey::iter().map(|key| key.into_column().as_str().to_string()).collect();
+
+    let columns = E::Column::iter()
+        .map(|column| {
+            let name = column.as_str().to_string();
+            let definition = column.def();
+
+            Column {
+                primary_key: primary_key.contains(&name),
+                name,
+                kind: type_name(definition.get_column_type()),
+                nullable: definition.is_null(),
+            }
+        })
+        .collect();
+
+    Table {
+        name: entity.table_name().to_string(),
+        columns,
+        sql: backend.build(&Schema::new(backend).create_table_from_entity(entity)).to_string(),
+    }
+}
+
+/// The portable name of a column type, the SQL of every backend differs.
+fn type_name(column_type: &ColumnType) -> String {
+    match column_type {
+        ColumnType::Char(_) | ColumnType::String(_) | ColumnType::Text => "text".to_string(),
+        ColumnType::TinyInteger | ColumnType::SmallInteger | ColumnType::Integer => "integer".to_string(),
+        ColumnType::BigInteger => "bigint".to_string(),
+        ColumnType::Float | ColumnType::Double => "double".to_string(),
+        ColumnType::Boolean => "boolean".to_string(),
+        ColumnType::Date => "date".to_string(),
+        ColumnType::DateTime | ColumnType::Timestamp => "datetime".to_string(),
+        ColumnType::Binary(_) | ColumnType::VarBinary(_) => "blob".to_string(),
+        other => format!("{:?}", other).to_lowercase(),
+    }
+}
+
+fn tables(backend: DbBackend) -> Vec<Table> {
+    vec![
+        table::<node::Entity>(backend),
+        table::<postcode_area::Entity>(backend),
+        table::<postcode_neighbors::Entity>(backend),
+        table::<postcode_rollup::Entity>(backend),
+        table::<postcode_timezone::Entity>(backend),
+        table::<admin_area::Entity>(backend),
+        table::<suggestion::Entity>(backend),
+        table::<poi_postcode::Entity>(backend),
+        table::<quarantine::Entity>(backend),
+        table::<import_run::Entity>(backend),
+        table::<import_progress::Entity>(backend),
+        table::<import_lock::Entity>(backend),
+        table::<deferred_index::Entity>(backend),
+        table::<api_key::Entity>(backend),
+    ]
+}
+
+pub fn run(db_uri: &str, matches: &clap::ArgMatches) -> Result<(), Error> {
+    let backend = match matches.get_one::<String>("backend").map(String::as_str) {
+        Some("postgres") => DbBackend::Postgres,
+        Some("mysql") => DbBackend::MySql,
+        Some(_) => DbBackend::Sqlite,
+        None if db_uri.starts_with("postgres") => DbBackend::Postgres,
+        None if db_uri.starts_with("mysql") => DbBackend::MySql,
+        None => DbBackend::Sqlite,
+    };
+    let tables = tables(backend);
+
+    match matches.get_one::<String>("format").expect("defaulted in clap").as_str() {
+        "sql" => {
+            for table in tables {
+                println!("{};\n", table.sql);
+            }
+        }
+        "mermaid" => {
+            println!("erDiagram");
+
+            for table in tables {
+                println!("    {} {{", table.name);
+
+                for column in table.columns {
+                    let key = if column.primary_key { " PK" } else { "" };
+                    let comment = if column.nullable { " \"nullable\"" } else { "" };
+
+                    println!("        {} {}{}{}", column.kind, column.name, key, comment);
+                }
+
+                println!("    }}");
+            }
+        }
+        _ => println!("{}", serde_json::to_string_pretty(&tables).map_err(|e| Error::Output(e.to_string()))?),
+    }
+
+    Ok(())
+}
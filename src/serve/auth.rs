@@ -0,0 +1,93 @@
+//! API key authentication with a token bucket rate limit per key.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::extract::{Request, State};
+use axum::http::{HeaderName, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter};
+
+use crate::entities::*;
+use crate::keys::hash_key;
+use crate::serve::AppState;
+
+pub const API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn new(rate: f64) -> Self {
+        Self { rate, tokens: rate.max(1.0), updated_at: Instant::now() }
+    }
+
+    /// Refills the bucket for the elapsed time, the burst size equals one second worth of requests.
+    fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate.max(1.0));
+        self.updated_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The active keys by hash, each with its own bucket.
+#[derive(Clone, Default)]
+pub struct KeyStore {
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl KeyStore {
+    pub async fn load(db: &DatabaseConnection, default_rate: f64) -> Result<Self, DbErr> {
+        let buckets = api_key::Entity::find()
+            .filter(api_key::Column::RevokedAt.is_null())
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|key| (key.key_hash, Bucket::new(key.rate_limit.unwrap_or(default_rate))))
+            .collect();
+
+        Ok(Self { buckets: Arc::new(Mutex::new(buckets)) })
+    }
+
+    fn check(&self, key: &str) -> Result<(), StatusCode> {
+        let mut buckets = self.buckets.lock().expect("key store lock poisoned");
+
+        match buckets.get_mut(&hash_key(key)).map(Bucket::try_take) {
+            None => Err(StatusCode::UNAUTHORIZED),
+            Some(false) => Err(StatusCode::TOO_MANY_REQUESTS),
+            Some(true) => Ok(()),
+        }
+    }
+}
+
+pub async fn require_api_key(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let backend = state.backend();
+
+    let Some(keys) = &backend.keys else {
+        return next.run(request).await;
+    };
+
+    let key = request.headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok());
+
+    match key.map(|key| keys.check(key)) {
+        Some(Ok(())) => next.run(request).await,
+        Some(Err(status)) => status.into_response(),
+        None => (StatusCode::UNAUTHORIZED, "missing X-Api-Key header").into_response(),
+    }
+}
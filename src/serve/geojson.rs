@@ -0,0 +1,56 @@
+//! GeoJSON responses, for clients like map widgets that ask for them with `Accept: application/geo+json`. Every
+//! address becomes a Point feature with its other fields as properties.
+
+use axum::http::header::{ACCEPT, CONTENT_TYPE};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+const MEDIA_TYPE: &str = "application/geo+json";
+
+/// Whether the client prefers GeoJSON over plain JSON.
+pub fn accepted(headers: &HeaderMap) -> bool {
+    headers.get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_type| media_type.split(';').next().is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case(MEDIA_TYPE)))
+}
+
+/// A point at the `lat` and `lon` of the address, RFC 7946 puts the longitude first.
+fn feature<T: Serialize>(item: T) -> Value {
+    let mut properties = match serde_json::to_value(item) {
+        Ok(Value::Object(properties)) => properties,
+        _ => unreachable!("addresses serialize to objects"),
+    };
+
+    let lat = properties.remove("lat").unwrap_or(Value::Null);
+    let lon = properties.remove("lon").unwrap_or(Value::Null);
+    let id = properties.get("id").cloned();
+
+    let mut feature = json!({
+        "type": "Feature",
+        "geometry": { "type": "Point", "coordinates": [lon, lat] },
+        "properties": properties,
+    });
+
+    // Aggregates like postcode centers have no id
+    if let Some(id) = id {
+        feature["id"] = id;
+    }
+
+    feature
+}
+
+pub fn feature_collection<T: Serialize>(items: impl IntoIterator<Item = T>) -> Value {
+    json!({
+        "type": "FeatureCollection",
+        "features": items.into_iter().map(feature).collect::<Vec<_>>(),
+    })
+}
+
+pub fn response(body: Value) -> Response {
+    ([(CONTENT_TYPE, MEDIA_TYPE)], Json(body)).into_response()
+}
@@ -0,0 +1,463 @@
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use axum::extract::{ConnectInfo, OriginalUri, Path, Query, State};
+use axum::middleware;
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode, Uri};
+use axum::http::header::{ACCEPT, CONTENT_TYPE, LINK};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use clap::{arg, value_parser, ArgAction, ArgMatches, Command};
+use futures::{stream, StreamExt, TryStreamExt};
+use sea_orm::{DatabaseConnection, DbErr};
+use serde::{Deserialize, Serialize};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::autocomplete;
+use crate::database::{connect_read_only, live_nodes};
+use crate::format::Formatted;
+use crate::metrics::{self, metrics_handler};
+use crate::migrator::pending_migrations;
+use crate::plus_code;
+use crate::query::{centroid_cursor, lookup, lookup_plus_code, postcode_centroids, postcodes_with_prefix, Page, PostcodeCursor, Ranked};
+use crate::serve::auth::{KeyStore, API_KEY_HEADER};
+use crate::spatial::{nearest_n, MAX_NEAREST_LIMIT};
+
+mod auth;
+mod geojson;
+
+pub fn cli() -> Command {
+    Command::new("serve")
+        .about("Serves postcode lookups over HTTP from an existing database")
+        .arg(arg!(--listen <ADDRESS>).default_value("127.0.0.1:8080").value_parser(value_parser!(SocketAddr)))
+        .arg(arg!(--"require-api-key" "Only answer requests with a valid X-Api-Key header, see the `keys` subcommand"))
+        .arg(arg!(--"rate-limit" <PER_SECOND> "Requests per second for keys without their own limit").default_value("10").value_parser(value_parser!(f64)))
+        .arg(arg!(--"max-batch" <COUNT> "Most lookups in one request to /lookup/batch").default_value("100").value_parser(value_parser!(u64).range(1..)))
+        .arg(arg!(--"cors-origin" <ORIGIN> "Let web pages from this origin, like https://example.com, call the server. `*` allows any").action(ArgAction::Append))
+        .arg(arg!(--"cors-max-age" <SECONDS> "How long browsers may cache the answer to a CORS preflight").default_value("3600").value_parser(value_parser!(u64)).requires("cors-origin"))
+}
+
+/// Everything that is replaced when the database file is reloaded.
+struct Backend {
+    db: DatabaseConnection,
+    keys: Option<KeyStore>,
+}
+
+impl Backend {
+    async fn open(db_uri: &str, require_api_key: bool, rate_limit: f64) -> Result<Self, DbErr> {
+        let db = connect_read_only(db_uri).await?;
+
+        let keys = if require_api_key {
+            Some(KeyStore::load(&db, rate_limit).await?)
+        } else {
+            None
+        };
+
+        Ok(Self { db, keys })
+    }
+}
+
+#[derive(Clone)]
+struct AppState {
+    backend: Arc<RwLock<Arc<Backend>>>,
+    db_uri: Arc<str>,
+    require_api_key: bool,
+    rate_limit: f64,
+    max_batch: usize,
+}
+
+impl AppState {
+    fn backend(&self) -> Arc<Backend> {
+        self.backend.read().expect("backend lock poisoned").clone()
+    }
+
+    fn db(&self) -> DatabaseConnection {
+        self.backend().db.clone()
+    }
+
+    /// Opens the database again and swaps it in. Requests that are in flight finish on the old connection pool,
+    /// so replace the file with a rename to have it picked up atomically.
+    async fn reload(&self) -> Result<(), DbErr> {
+        let backend = Backend::open(&self.db_uri, self.require_api_key, self.rate_limit).await?;
+
+        *self.backend.write().expect("backend lock poisoned") = Arc::new(backend);
+        println!("Reloaded {}", self.db_uri);
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct LookupParams {
+    postcode: Option<String>,
+    pluscode: Option<String>,
+    housenumber: Option<String>,
+    housename: Option<String>,
+    after_id: Option<i64>,
+    limit: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct PrefixParams {
+    after: Option<String>,
+    #[serde(default = "default_prefix_limit")]
+    limit: u64,
+}
+
+const MAX_PREFIX_LIMIT: u64 = 1000;
+
+fn default_prefix_limit() -> u64 {
+    100
+}
+
+#[derive(Deserialize)]
+struct PageParams {
+    after_id: Option<i64>,
+    #[serde(default = "default_page_limit")]
+    limit: u64,
+}
+
+/// Most addresses on a page of `/addresses`, and of `/lookup` and every lookup of a batch when they're paged.
+const MAX_PAGE_LIMIT: u64 = 10000;
+
+fn default_page_limit() -> u64 {
+    1000
+}
+
+#[derive(Deserialize)]
+struct AutocompleteParams {
+    q: String,
+    after: Option<String>,
+    #[serde(default = "default_autocomplete_limit")]
+    limit: u64,
+}
+
+const MAX_AUTOCOMPLETE_LIMIT: u64 = 50;
+
+fn default_autocomplete_limit() -> u64 {
+    10
+}
+
+#[derive(Deserialize)]
+struct BatchLookup {
+    postcode: String,
+    housenumber: Option<String>,
+    housename: Option<String>,
+    after_id: Option<i64>,
+    limit: Option<u64>,
+}
+
+/// Lookups of a batch that run at the same time, each on its own connection.
+const BATCH_CONCURRENCY: usize = 8;
+
+#[derive(Deserialize)]
+struct ReverseParams {
+    lat: f64,
+    lon: f64,
+}
+
+#[derive(Deserialize)]
+struct NearestParams {
+    lat: f64,
+    lon: f64,
+    #[serde(default = "default_nearest_limit")]
+    limit: u64,
+    after_id: Option<i64>,
+}
+
+fn default_nearest_limit() -> u64 {
+    10
+}
+
+type ApiResult = Result<Response, (StatusCode, String)>;
+
+/// The addresses as JSON, or as GeoJSON when the client asks for it.
+fn respond<T: Serialize>(headers: &HeaderMap, items: Vec<T>) -> Response {
+    if geojson::accepted(headers) {
+        geojson::response(geojson::feature_collection(items))
+    } else {
+        Json(items).into_response()
+    }
+}
+
+/// Adds a `Link` header to the next page when there is one, the request with the `cursor` parameter set to `next`.
+fn link_next(mut response: Response, uri: &Uri, cursor: &str, next: Option<String>) -> Response {
+    let Some(next) = next else {
+        return response;
+    };
+
+    let mut query = form_urlencoded::Serializer::new(String::new());
+
+    for (key, value) in form_urlencoded::parse(uri.query().unwrap_or_default().as_bytes()).filter(|(key, _)| key != cursor) {
+        query.append_pair(&key, &value);
+    }
+
+    query.append_pair(cursor, &next);
+
+    let link = format!("<{}?{}>; rel=\"next\"", uri.path(), query.finish());
+
+    if let Ok(link) = HeaderValue::from_str(&link) {
+        response.headers_mut().insert(LINK, link);
+    }
+
+    response
+}
+
+/// Answers the preflight requests of browsers and adds the CORS headers for the `--cors-origin`s.
+fn cors(matches: &ArgMatches) -> Result<Option<CorsLayer>, Box<dyn Error>> {
+    let Some(origins) = matches.get_many::<String>("cors-origin") else {
+        return Ok(None);
+    };
+
+    let origins: Vec<&String> = origins.collect();
+    let allow_origin = if origins.iter().any(|origin| *origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(origins.into_iter().map(|origin| HeaderValue::from_str(origin)).collect::<Result<Vec<_>, _>>()?)
+    };
+    let max_age = *matches.get_one::<u64>("cors-max-age").expect("defaulted in clap");
+
+    Ok(Some(CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([ACCEPT, CONTENT_TYPE, API_KEY_HEADER])
+        // Pages link to the next one in a header, which scripts can only read when it's exposed
+        .expose_headers([LINK])
+        .max_age(Duration::from_secs(max_age))))
+}
+
+pub async fn run(db_uri: &str, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let listen = *matches.get_one::<SocketAddr>("listen").expect("defaulted in clap");
+    let rate_limit = *matches.get_one::<f64>("rate-limit").expect("defaulted in clap");
+    let require_api_key = matches.get_flag("require-api-key");
+
+    let state = AppState {
+        backend: Arc::new(RwLock::new(Arc::new(Backend::open(db_uri, require_api_key, rate_limit).await?))),
+        db_uri: db_uri.into(),
+        require_api_key,
+        rate_limit,
+        max_batch: *matches.get_one::<u64>("max-batch").expect("defaulted in clap") as usize,
+    };
+
+    metrics::register();
+
+    #[cfg(unix)]
+    spawn_reload_on_sighup(state.clone())?;
+
+    let app = Router::new()
+        .route("/lookup", get(lookup_handler))
+        .route("/lookup/batch", post(batch_lookup_handler))
+        .route("/postcode/:pattern", get(postcode_handler))
+        .route("/addresses", get(addresses_handler))
+        .route("/autocomplete", get(autocomplete_handler))
+        .route("/reverse", get(reverse_handler))
+        .route("/nearest", get(nearest_handler))
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth::require_api_key))
+        .route("/metrics", get(metrics_handler))
+        .route("/healthz", get(healthz_handler))
+        .route("/readyz", get(readyz_handler))
+        .route("/admin/reload", post(reload_handler))
+        .with_state(state);
+
+    // Outside the API key check, browsers send preflight requests without the key
+    let app = match cors(matches)? {
+        Some(cors) => app.layer(cors),
+        None => app,
+    };
+
+    println!("Listening on http://{}", listen);
+    axum::serve(tokio::net::TcpListener::bind(listen).await?, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn spawn_reload_on_sighup(state: AppState) -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            if let Err(e) = state.reload().await {
+                println!("Warning: reload failed, still serving the previous database: {}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn internal_error(e: DbErr) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+async fn lookup_handler(State(state): State<AppState>, headers: HeaderMap, OriginalUri(uri): OriginalUri, Query(params): Query<LookupParams>) -> ApiResult {
+    let _timer = metrics::QUERY_DURATION.with_label_values(&["lookup"]).start_timer();
+
+    if params.limit.is_some_and(|limit| limit > MAX_PAGE_LIMIT) {
+        return Err((StatusCode::BAD_REQUEST, format!("limit can't exceed {}", MAX_PAGE_LIMIT)));
+    }
+
+    let page = Page { after_id: params.after_id, limit: params.limit };
+
+    let found = match (&params.pluscode, &params.postcode) {
+        (Some(code), _) => {
+            let prefix = plus_code::prefix(code).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+            lookup_plus_code(&state.db(), &prefix, page).await
+        }
+        (None, Some(postcode)) => lookup(&state.db(), postcode, params.housenumber.as_deref(), params.housename.as_deref(), page).await,
+        (None, None) => return Err((StatusCode::BAD_REQUEST, "either postcode or pluscode is required".to_string())),
+    };
+
+    let models = found.map_err(internal_error)?;
+    let next = page.next(&models).map(|id| id.to_string());
+
+    Ok(link_next(respond(&headers, models.into_iter().map(Formatted::from).collect()), &uri, "after_id", next))
+}
+
+/// Every address in order of id, a page at a time, to iterate over the whole database.
+async fn addresses_handler(State(state): State<AppState>, headers: HeaderMap, OriginalUri(uri): OriginalUri, Query(params): Query<PageParams>) -> ApiResult {
+    let _timer = metrics::QUERY_DURATION.with_label_values(&["addresses"]).start_timer();
+
+    if params.limit == 0 || params.limit > MAX_PAGE_LIMIT {
+        return Err((StatusCode::BAD_REQUEST, format!("limit has to be between 1 and {}", MAX_PAGE_LIMIT)));
+    }
+
+    let page = Page { after_id: params.after_id, limit: Some(params.limit) };
+    let models = page.apply(live_nodes()).all(&state.db()).await.map_err(internal_error)?;
+    let next = page.next(&models).map(|id| id.to_string());
+
+    Ok(link_next(respond(&headers, models.into_iter().map(Formatted::from).collect()), &uri, "after_id", next))
+}
+
+/// Looks up every postcode and house number of the body, answering with the results of each in the same order. As
+/// GeoJSON that's a feature collection per lookup.
+async fn batch_lookup_handler(State(state): State<AppState>, headers: HeaderMap, Json(lookups): Json<Vec<BatchLookup>>) -> ApiResult {
+    let _timer = metrics::QUERY_DURATION.with_label_values(&["batch"]).start_timer();
+
+    if lookups.len() > state.max_batch {
+        return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("a batch can't have more than {} lookups", state.max_batch)));
+    }
+
+    if lookups.iter().any(|params| params.limit.is_some_and(|limit| limit > MAX_PAGE_LIMIT)) {
+        return Err((StatusCode::BAD_REQUEST, format!("limit can't exceed {}", MAX_PAGE_LIMIT)));
+    }
+
+    let db = state.db();
+
+    let found: Vec<Vec<Formatted>> = stream::iter(lookups)
+        .map(|params| {
+            let db = &db;
+
+            async move {
+                let page = Page { after_id: params.after_id, limit: params.limit };
+                let found = lookup(db, &params.postcode, params.housenumber.as_deref(), params.housename.as_deref(), page).await?;
+
+                Ok(found.into_iter().map(Formatted::from).collect())
+            }
+        })
+        .buffered(BATCH_CONCURRENCY)
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    if geojson::accepted(&headers) {
+        return Ok(geojson::response(found.into_iter().map(geojson::feature_collection).collect()));
+    }
+
+    Ok(Json(found).into_response())
+}
+
+/// The postcodes matching a postcode or a prefix like `10*`, each with its number of addresses and their center.
+async fn postcode_handler(State(state): State<AppState>, headers: HeaderMap, OriginalUri(uri): OriginalUri, Path(pattern): Path<String>, Query(params): Query<PrefixParams>) -> ApiResult {
+    let _timer = metrics::QUERY_DURATION.with_label_values(&["postcode"]).start_timer();
+
+    if params.limit > MAX_PREFIX_LIMIT {
+        return Err((StatusCode::BAD_REQUEST, format!("limit can't exceed {}", MAX_PREFIX_LIMIT)));
+    }
+
+    let Some(prefix) = pattern.strip_suffix('*') else {
+        let found = postcode_centroids(&state.db(), &pattern).await.map_err(internal_error)?;
+
+        return Ok(respond(&headers, found));
+    };
+
+    let after = params.after.as_deref().map(PostcodeCursor::parse).transpose().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let found = postcodes_with_prefix(&state.db(), prefix, after.as_ref(), Some(params.limit)).await.map_err(internal_error)?;
+    let next = found.last().filter(|_| found.len() as u64 >= params.limit).map(centroid_cursor);
+
+    Ok(link_next(respond(&headers, found), &uri, "after", next))
+}
+
+async fn autocomplete_handler(State(state): State<AppState>, headers: HeaderMap, OriginalUri(uri): OriginalUri, Query(params): Query<AutocompleteParams>) -> ApiResult {
+    let _timer = metrics::QUERY_DURATION.with_label_values(&["autocomplete"]).start_timer();
+
+    if params.limit > MAX_AUTOCOMPLETE_LIMIT {
+        return Err((StatusCode::BAD_REQUEST, format!("limit can't exceed {}", MAX_AUTOCOMPLETE_LIMIT)));
+    }
+
+    let after = params.after.as_deref().map(Ranked::parse).transpose().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let (found, next) = autocomplete::suggest(&state.db(), &params.q, after.as_ref(), params.limit).await.map_err(internal_error)?;
+
+    Ok(link_next(respond(&headers, found), &uri, "after", next))
+}
+
+/// The nearest address, or `null`. As GeoJSON a feature collection with at most one feature.
+async fn reverse_handler(State(state): State<AppState>, headers: HeaderMap, Query(params): Query<ReverseParams>) -> ApiResult {
+    let _timer = metrics::QUERY_DURATION.with_label_values(&["reverse"]).start_timer();
+
+    let found = nearest_n(&state.db(), params.lat, params.lon, 1, None).await.map_err(internal_error)?;
+
+    if geojson::accepted(&headers) {
+        return Ok(respond(&headers, found));
+    }
+
+    Ok(Json(found.into_iter().next()).into_response())
+}
+
+async fn nearest_handler(State(state): State<AppState>, headers: HeaderMap, OriginalUri(uri): OriginalUri, Query(params): Query<NearestParams>) -> ApiResult {
+    let _timer = metrics::QUERY_DURATION.with_label_values(&["nearest"]).start_timer();
+
+    if params.limit > MAX_NEAREST_LIMIT {
+        return Err((StatusCode::BAD_REQUEST, format!("limit can't exceed {}", MAX_NEAREST_LIMIT)));
+    }
+
+    let found = nearest_n(&state.db(), params.lat, params.lon, params.limit, params.after_id).await.map_err(internal_error)?;
+    let next = found.last().filter(|_| found.len() as u64 >= params.limit).map(|last| last.node.id.to_string());
+
+    Ok(link_next(respond(&headers, found), &uri, "after_id", next))
+}
+
+/// Liveness: the database can be reached.
+async fn healthz_handler(State(state): State<AppState>) -> (StatusCode, String) {
+    match state.db().ping().await {
+        Ok(()) => (StatusCode::OK, "ok".to_string()),
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, e.to_string()),
+    }
+}
+
+/// Readiness: the database can be reached and its schema is up to date with this binary.
+async fn readyz_handler(State(state): State<AppState>) -> (StatusCode, String) {
+    match pending_migrations(&state.db()).await {
+        Ok(pending) if pending.is_empty() => (StatusCode::OK, "ok".to_string()),
+        Ok(pending) => (StatusCode::SERVICE_UNAVAILABLE, format!("pending migrations: {}", pending.join(", "))),
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, e.to_string()),
+    }
+}
+
+/// Swaps in the database file, only accepted from the loopback interface.
+async fn reload_handler(State(state): State<AppState>, ConnectInfo(peer): ConnectInfo<SocketAddr>) -> (StatusCode, String) {
+    if !peer.ip().is_loopback() {
+        return (StatusCode::FORBIDDEN, "reload is only allowed from localhost".to_string());
+    }
+
+    match state.reload().await {
+        Ok(()) => (StatusCode::OK, "reloaded".to_string()),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
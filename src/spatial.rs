@@ -0,0 +1,136 @@
+use sea_orm::{ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, QueryFilter};
+use sea_orm::sea_query::{Alias, Expr, Func};
+use serde::Serialize;
+
+use crate::database::live_nodes;
+use crate::entities::*;
+use crate::format::format_address;
+use crate::query::{Key, Page};
+
+const EARTH_RADIUS_M: f64 = 6_371_008.8;
+pub const METERS_PER_DEGREE: f64 = EARTH_RADIUS_M * std::f64::consts::PI / 180.0;
+const INITIAL_RADIUS_DEG: f64 = 0.005;
+const MAX_RADIUS_DEG: f64 = 1.0;
+pub const MAX_NEAREST_LIMIT: u64 = 100;
+
+/// Great-circle distance in meters.
+pub fn haversine(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+/// Initial compass bearing in degrees from the first to the second coordinate.
+pub fn bearing(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lon = (lon2 - lon1).to_radians();
+
+    let y = d_lon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * d_lon.cos();
+
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+#[derive(Serialize)]
+pub struct Nearby {
+    #[serde(flatten)]
+    pub node: node::Model,
+    /// Meters
+    pub distance: f64,
+    /// Degrees from north, clockwise
+    pub bearing: f64,
+    /// The address in the layout of its country
+    pub formatted: String,
+}
+
+/// Finds the address closest to a coordinate.
+pub async fn nearest(db: &DatabaseConnection, lat: f64, lon: f64) -> Result<Option<(node::Model, f64)>, DbErr> {
+    Ok(nearest_n(db, lat, lon, 1, None).await?
+        .into_iter()
+        .next()
+        .map(|nearby| (nearby.node, nearby.distance)))
+}
+
+/// Converts Dutch RD New (EPSG:28992) coordinates to WGS84 latitude and longitude, with the polynomial approximation
+/// that's accurate to about a meter within the Netherlands.
+pub fn rd_to_wgs84(x: f64, y: f64) -> (f64, f64) {
+    let dx = (x - 155_000.0) * 1e-5;
+    let dy = (y - 463_000.0) * 1e-5;
+
+    let north = 3235.65389 * dy - 32.58297 * dx.powi(2) - 0.2475 * dy.powi(2) - 0.84978 * dx.powi(2) * dy
+        - 0.0655 * dy.powi(3) - 0.01709 * dx.powi(2) * dy.powi(2) - 0.00738 * dx + 0.0053 * dx.powi(4)
+        - 0.00039 * dx.powi(2) * dy.powi(3) + 0.00033 * dx.powi(4) * dy - 0.00012 * dx * dy;
+    let east = 5260.52916 * dx + 105.94684 * dx * dy + 2.45656 * dx * dy.powi(2) - 0.81885 * dx.powi(3)
+        + 0.05594 * dx * dy.powi(3) - 0.05607 * dx.powi(3) * dy + 0.01199 * dy - 0.00256 * dx.powi(3) * dy.powi(2)
+        + 0.00128 * dx * dy.powi(4) + 0.00022 * dy.powi(2) - 0.00022 * dx.powi(2) + 0.00026 * dx.powi(5);
+
+    (52.15517440 + north / 3600.0, 5.38720621 + east / 3600.0)
+}
+
+/// The longitudes within `radius` degrees of `lon`. Near the antimeridian the window wraps around to the other side,
+/// as two ranges on the `idx-lat-lon` index.
+fn lon_window(lon: f64, radius: f64) -> Condition {
+    let (west, east) = (lon - radius, lon + radius);
+
+    if radius >= 180.0 {
+        Condition::all()
+    } else if west < -180.0 {
+        Condition::any().add(node::Column::Lon.gte(west + 360.0)).add(node::Column::Lon.lte(east))
+    } else if east > 180.0 {
+        Condition::any().add(node::Column::Lon.gte(west)).add(node::Column::Lon.lte(east - 360.0))
+    } else {
+        Condition::all().add(node::Column::Lon.between(west, east))
+    }
+}
+
+/// Finds the `limit` addresses closest to a coordinate, after the address with id `after_id` when given, using the
+/// `idx-lat-lon` index and the `distance` SQL function. Addresses at the same distance are ordered by id. The search box
+/// grows until it is large enough to guarantee nothing outside of it is closer than the furthest match, or until it
+/// reaches [`MAX_RADIUS_DEG`].
+pub async fn nearest_n(db: &DatabaseConnection, lat: f64, lon: f64, limit: u64, after_id: Option<i64>) -> Result<Vec<Nearby>, DbErr> {
+    let page = Page { after_id, limit: Some(limit) };
+    let distance = Func::cust(Alias::new("distance")).args([Expr::val(lat).into(), Expr::val(lon).into(), Expr::col(node::Column::Lat).into(), Expr::col(node::Column::Lon).into()]);
+    let distance = [Key::new(distance)];
+    let mut radius = INITIAL_RADIUS_DEG;
+
+    // Later pages start with a box that reaches the last address of the previous one
+    if let Some(after_id) = after_id {
+        let Some(after) = node::Entity::find_by_id(after_id).one(db).await? else {
+            return Ok(Vec::new());
+        };
+
+        radius = (haversine(lat, lon, after.lat, after.lon) / METERS_PER_DEGREE).clamp(radius, MAX_RADIUS_DEG);
+    }
+
+    loop {
+        let lon_radius = (radius / lat.to_radians().cos().max(0.01)).min(180.0);
+
+        let nodes = live_nodes()
+            .filter(node::Column::Lat.between(lat - radius, lat + radius))
+            .filter(lon_window(lon, lon_radius));
+
+        let found: Vec<Nearby> = page.apply_ordered(nodes, &distance)
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|node| Nearby {
+                distance: haversine(lat, lon, node.lat, node.lon),
+                bearing: bearing(lat, lon, node.lat, node.lon),
+                formatted: format_address(&node),
+                node,
+            })
+            .collect();
+
+        let complete = found.len() as u64 == limit
+            && found.last().is_some_and(|furthest| furthest.distance <= radius * METERS_PER_DEGREE);
+
+        if complete || radius >= MAX_RADIUS_DEG {
+            return Ok(found);
+        }
+
+        radius = (radius * 4.0).min(MAX_RADIUS_DEG);
+    }
+}
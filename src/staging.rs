@@ -0,0 +1,127 @@
+//! Imports into a live Postgres database without touching the tables it's serving from.
+//!
+//! The import runs on a connection whose search path is the `postcode_db_generator_staging` schema, so every phase
+//! writes to its `node` table instead of the serving one. Once it's done the staged tables replace the serving ones in a single
+//! transaction, lookups see either the old or the new data and never a half finished import.
+
+use sea_orm::{ConnectionTrait, DatabaseConnection, DbErr, Statement, TransactionTrait};
+use sea_orm_migration::MigratorTrait;
+
+use crate::database;
+use crate::migrator::Migrator;
+
+const SCHEMA: &str = "postcode_db_generator_staging";
+
+/// Comment on the staging schema, a schema with the same name but without it isn't ours to drop.
+const MARKER: &str = "Staging tables of postcode-db-generator";
+
+/// Tables written by the import, swapped in at the end.
+const TABLES: [&str; 9] = ["admin_area", "node", "poi_postcode", "postcode_area", "postcode_neighbors", "postcode_rollup", "postcode_timezone", "quarantine", "suggestion"];
+
+/// Tables carried over from the serving tables, the others are rebuilt from them. Admin areas, timezones and the
+/// quarantine are only rebuilt by imports given `--admin-areas`, `--timezones` and `--quarantine`.
+const CARRIED_OVER: [&str; 5] = ["admin_area", "node", "poi_postcode", "postcode_timezone", "quarantine"];
+
+/// Collations the staged tables use. They're moved along with the tables, dropping the staging schema would drop
+/// the columns using them.
+const COLLATIONS: [&str; 1] = ["nocase"];
+
+/// The schema the serving tables live in.
+async fn live_schema(db: &DatabaseConnection) -> Result<String, DbErr> {
+    db.query_one(Statement::from_string(db.get_database_backend(), "SELECT current_schema()"))
+        .await?
+        .ok_or_else(|| DbErr::Custom("no current schema".to_string()))?
+        .try_get_by_index(0)
+}
+
+/// Fails when a schema named like the staging one exists that wasn't created by this tool, returns whether it exists.
+async fn check_owned(db: &DatabaseConnection) -> Result<bool, DbErr> {
+    let comment = db.query_one(Statement::from_sql_and_values(
+        db.get_database_backend(),
+        "SELECT obj_description(oid, 'pg_namespace') FROM pg_namespace WHERE nspname = $1",
+        [SCHEMA.into()],
+    )).await?;
+
+    match comment {
+        None => Ok(false),
+        Some(row) if row.try_get_by_index::<Option<String>>(0)?.as_deref() == Some(MARKER) => Ok(true),
+        Some(_) => Err(DbErr::Custom(format!("schema {} wasn't created by this tool, drop or rename it to stage imports", SCHEMA))),
+    }
+}
+
+fn not_staged() -> DbErr {
+    DbErr::Custom(format!("schema {} doesn't exist, there's no staged import to resume", SCHEMA))
+}
+
+/// Creates the staging tables, filled with the serving nodes and POIs unless `fresh`, and returns a connection that imports
+/// into them. A staging schema left behind by an aborted import is discarded.
+pub async fn prepare(db_uri: &str, db: &DatabaseConnection, fresh: bool) -> Result<DatabaseConnection, DbErr> {
+    let live = live_schema(db).await?;
+
+    if check_owned(db).await? {
+        db.execute_unprepared(&format!("DROP SCHEMA {} CASCADE", SCHEMA)).await?;
+    }
+
+    db.execute_unprepared(&format!("CREATE SCHEMA {}", SCHEMA)).await?;
+    db.execute_unprepared(&format!("COMMENT ON SCHEMA {} IS '{}'", SCHEMA, MARKER)).await?;
+
+    let staging = database::connect_to_schema(db_uri, SCHEMA).await?;
+
+    // Running the migrations gives the staged tables the same columns and index names as the serving ones. The other
+    // tables they create are dropped along with the schema.
+    Migrator::up(&staging, None).await?;
+
+    if !fresh {
+        for table in CARRIED_OVER {
+            db.execute_unprepared(&format!("INSERT INTO {}.{} SELECT * FROM {}.{}", SCHEMA, table, live, table)).await?;
+        }
+    }
+
+    Ok(staging)
+}
+
+/// A connection to the staging tables of an earlier, interrupted, import.
+pub async fn connect(db_uri: &str, db: &DatabaseConnection) -> Result<DatabaseConnection, DbErr> {
+    if !check_owned(db).await? {
+        return Err(not_staged());
+    }
+
+    database::connect_to_schema(db_uri, SCHEMA).await
+}
+
+/// Replaces the serving tables with the staged ones and drops the staging schema.
+pub async fn swap(db: &DatabaseConnection) -> Result<(), DbErr> {
+    let live = live_schema(db).await?;
+
+    if !check_owned(db).await? {
+        return Err(not_staged());
+    }
+
+    let transaction = db.begin().await?;
+
+    for table in TABLES {
+        transaction.execute_unprepared(&format!("DROP TABLE {}.{}", live, table)).await?;
+        transaction.execute_unprepared(&format!("ALTER TABLE {}.{} SET SCHEMA {}", SCHEMA, table, live)).await?;
+    }
+
+    for collation in COLLATIONS {
+        let staged = transaction.query_one(Statement::from_sql_and_values(
+            db.get_database_backend(),
+            "SELECT 1 FROM pg_collation JOIN pg_namespace ON pg_namespace.oid = collnamespace WHERE nspname = $1 AND collname = $2",
+            [SCHEMA.into(), collation.into()],
+        )).await?;
+
+        // Servers without ICU have none
+        if staged.is_some() {
+            // Only the serving tables that were just dropped used it
+            transaction.execute_unprepared(&format!("DROP COLLATION IF EXISTS {}.{}", live, collation)).await?;
+            transaction.execute_unprepared(&format!("ALTER COLLATION {}.{} SET SCHEMA {}", SCHEMA, collation, live)).await?;
+        }
+    }
+
+    transaction.commit().await?;
+
+    db.execute_unprepared(&format!("DROP SCHEMA {} CASCADE", SCHEMA)).await?;
+
+    Ok(())
+}
@@ -0,0 +1,219 @@
+//! Objects in S3 or Google Cloud Storage, addressed as `s3://bucket/key` and `gs://bucket/key`. Requests are signed
+//! with AWS Signature Version 4, which Cloud Storage accepts with HMAC keys through its XML API, so both work without
+//! their SDKs. Inputs are read and databases published through the same requests.
+//!
+//! Credentials are found like the SDKs do, see [`credentials`]. Cloud Storage is sent OAuth tokens as bearer tokens
+//! instead of a signature when there's no HMAC key. `AWS_ENDPOINT_URL` points at S3 compatible storage like MinIO,
+//! `STORAGE_EMULATOR_HOST` at a Cloud Storage emulator.
+
+use std::sync::Mutex;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::Method;
+use sha2::{Digest, Sha256};
+
+use crate::credentials::{self, variable, Credentials, Secret};
+
+/// The payload isn't hashed, uploads are streamed from disk and the connection is TLS.
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+const GCS_ENDPOINT: &str = "https://storage.googleapis.com";
+/// Cloud Storage ignores the region of a signature, but it has to be there.
+const GCS_REGION: &str = "auto";
+/// Temporary credentials are replaced this long before they expire, so a request doesn't outlive them.
+const REFRESH_MARGIN: chrono::Duration = chrono::Duration::minutes(5);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Provider {
+    S3,
+    Gcs,
+}
+
+/// An object in a bucket.
+#[derive(Clone)]
+pub struct Location {
+    provider: Provider,
+    pub bucket: String,
+    pub key: String,
+}
+
+impl Location {
+    /// Parses `s3://bucket/key` or `gs://bucket/key`, `None` for other URLs.
+    pub fn parse(url: &str) -> Option<Result<Self, String>> {
+        let (provider, rest) = if let Some(rest) = url.strip_prefix("s3://") {
+            (Provider::S3, rest)
+        } else if let Some(rest) = url.strip_prefix("gs://") {
+            (Provider::Gcs, rest)
+        } else {
+            return None;
+        };
+
+        Some(match rest.split_once('/') {
+            Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => {
+                Ok(Self { provider, bucket: bucket.to_string(), key: key.to_string() })
+            }
+            _ => Err(format!("{} has no bucket and key, like s3://bucket/path/file", url)),
+        })
+    }
+
+    /// Another object in the same bucket.
+    pub fn with_key(&self, key: String) -> Self {
+        Self { key, ..self.clone() }
+    }
+
+    /// The `s3://` or `gs://` URL of the object.
+    pub fn url(&self) -> String {
+        let scheme = match self.provider {
+            Provider::S3 => "s3",
+            Provider::Gcs => "gs",
+        };
+
+        format!("{}://{}/{}", scheme, self.bucket, self.key)
+    }
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent encodes everything but unreserved characters, and slashes when `path` is set.
+fn uri_encode(value: &str, path: bool) -> String {
+    value.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            b'/' if path => "/".to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn discover(provider: Provider) -> Result<Credentials, String> {
+    match provider {
+        Provider::S3 => credentials::s3(),
+        Provider::Gcs => credentials::gcs(),
+    }
+}
+
+/// Signs requests to the buckets of one provider.
+pub struct Storage {
+    client: Client,
+    provider: Provider,
+    region: String,
+    /// Buckets are in the path of the endpoint rather than its host name
+    endpoint: Option<String>,
+    credentials: Mutex<Credentials>,
+}
+
+impl Storage {
+    /// Finds the credentials for the location.
+    pub fn for_location(location: &Location, client: Client) -> Result<Self, String> {
+        let (region, endpoint) = match location.provider {
+            Provider::S3 => (credentials::aws_region(), variable("AWS_ENDPOINT_URL")),
+            Provider::Gcs => (GCS_REGION.to_string(), Some(variable("STORAGE_EMULATOR_HOST").unwrap_or(GCS_ENDPOINT.to_string()))),
+        };
+
+        Ok(Self { client, provider: location.provider, region, endpoint, credentials: Mutex::new(discover(location.provider)?) })
+    }
+
+    /// The current credentials, fetched again when they're about to expire. The old ones are kept when that fails,
+    /// the request then says why.
+    fn secret(&self) -> Secret {
+        let mut credentials = self.credentials.lock().expect("credentials aren't poisoned");
+
+        if credentials.expires_at.is_some_and(|expires_at| expires_at - REFRESH_MARGIN < Utc::now()) {
+            match discover(self.provider) {
+                Ok(refreshed) => *credentials = refreshed,
+                Err(e) => println!("Warning: failed to refresh the credentials: {}", e),
+            }
+        }
+
+        credentials.secret.clone()
+    }
+
+    /// The host and path of the object, virtual hosted on AWS and path style elsewhere.
+    fn address(&self, location: &Location) -> (String, String, String) {
+        let key = uri_encode(&location.key, true);
+
+        match &self.endpoint {
+            Some(endpoint) => {
+                let (scheme, host) = endpoint.trim_end_matches('/').split_once("://").unwrap_or(("https", endpoint));
+
+                (scheme.to_string(), host.to_string(), format!("/{}/{}", uri_encode(&location.bucket, false), key))
+            }
+            None => {
+                let host = format!("{}.s3.{}.amazonaws.com", location.bucket, self.region);
+
+                ("https".to_string(), host, format!("/{}", key))
+            }
+        }
+    }
+
+    /// A request for the object with the `x-amz-*` headers, which have to be lowercase. They're signed along with the
+    /// request, or sent as `x-goog-*` headers with a token. Other headers can be added to the request without being
+    /// signed.
+    pub fn request(&self, method: Method, location: &Location, headers: &[(String, String)]) -> RequestBuilder {
+        match self.secret() {
+            Secret::Key { access_key, secret_key, session_token } => {
+                self.signed(method, location, headers, &access_key, &secret_key, session_token)
+            }
+            Secret::Token(token) => {
+                let (scheme, host, path) = self.address(location);
+                let mut request = self.client.request(method, format!("{}://{}{}", scheme, host, path)).bearer_auth(token);
+
+                for (name, value) in headers {
+                    request = request.header(name.replacen("x-amz-", "x-goog-", 1), value);
+                }
+
+                request
+            }
+        }
+    }
+
+    /// A request signed with AWS Signature Version 4.
+    fn signed(&self, method: Method, location: &Location, headers: &[(String, String)], access_key: &str, secret_key: &str, session_token: Option<String>) -> RequestBuilder {
+        let (scheme, host, path) = self.address(location);
+        let now = Utc::now();
+        let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+
+        let mut signed: Vec<(String, String)> = vec![
+            ("host".to_string(), host.clone()),
+            ("x-amz-content-sha256".to_string(), UNSIGNED_PAYLOAD.to_string()),
+            ("x-amz-date".to_string(), timestamp.clone()),
+        ];
+
+        if let Some(token) = session_token {
+            signed.push(("x-amz-security-token".to_string(), token));
+        }
+
+        signed.extend(headers.iter().cloned());
+        signed.sort();
+
+        let canonical_headers: String = signed.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+        let signed_headers = signed.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(";");
+        let canonical_request = format!("{}\n{}\n\n{}\n{}\n{}", method, path, canonical_headers, signed_headers, UNSIGNED_PAYLOAD);
+
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", timestamp, scope, hex::encode(Sha256::digest(canonical_request.as_bytes())));
+
+        let key = ["s3", "aws4_request"].iter().fold(
+            hmac(hmac(format!("AWS4{}", secret_key).as_bytes(), &date).as_slice(), &self.region),
+            |key, part| hmac(&key, part),
+        );
+        let signature = hex::encode(hmac(&key, &string_to_sign));
+
+        let mut request = self.client.request(method, format!("{}://{}{}", scheme, host, path)).header(
+            "authorization",
+            format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}", access_key, scope, signed_headers, signature),
+        );
+
+        for (name, value) in signed.into_iter().filter(|(name, _)| name != "host") {
+            request = request.header(name, value);
+        }
+
+        request
+    }
+}
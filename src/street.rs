@@ -0,0 +1,191 @@
+//! Expands abbreviated street types, so `Kerkstr.` and `Kerkstraat` or `Main St` and `Main Street` are stored the same
+//! way and are merged as duplicates. Dictionaries are per language, a country profile picks one with its `language`.
+//! They can be added or replaced with a JSON file passed to `--street-abbreviations`:
+//!
+//! ```json
+//! {
+//!     "nl": { "prefixes": { "burg": "Burgemeester" }, "suffixes": { "str.": "straat", "gr.": "gracht" } },
+//!     "en": { "position": "last", "words": { "st": "Street", "ave": "Avenue" } }
+//! }
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Deserializer};
+
+static DICTIONARIES: OnceLock<Dictionaries> = OnceLock::new();
+
+const NL_WORDS: [(&str, &str); 1] = [("st", "Sint")];
+// `De Burg` is a street, `Burg. de Withstraat` is named after a mayor
+const NL_PREFIXES: [(&str, &str); 1] = [("burg", "Burgemeester")];
+const NL_SUFFIXES: [(&str, &str); 6] = [("str.", "straat"), ("ln.", "laan"), ("pl.", "plein"), ("gr.", "gracht"), ("kd.", "kade"), ("wg.", "weg")];
+
+const DE_WORDS: [(&str, &str); 2] = [("str", "Straße"), ("pl", "Platz")];
+const DE_SUFFIXES: [(&str, &str); 2] = [("str.", "straße"), ("pl.", "platz")];
+
+const EN_WORDS: [(&str, &str); 14] = [
+    ("st", "Street"), ("ave", "Avenue"), ("rd", "Road"), ("blvd", "Boulevard"), ("ln", "Lane"), ("dr", "Drive"),
+    ("ct", "Court"), ("pl", "Place"), ("sq", "Square"), ("hwy", "Highway"), ("pkwy", "Parkway"), ("cres", "Crescent"),
+    ("tce", "Terrace"), ("cl", "Close"),
+];
+
+const FR_WORDS: [(&str, &str); 10] = [
+    ("r", "Rue"), ("av", "Avenue"), ("bd", "Boulevard"), ("pl", "Place"), ("che", "Chemin"), ("imp", "Impasse"),
+    ("rte", "Route"), ("all", "Allée"), ("sq", "Square"), ("fbg", "Faubourg"),
+];
+
+const RU_WORDS: [(&str, &str); 8] = [
+    ("ул", "улица"), ("пер", "переулок"), ("пл", "площадь"), ("просп", "проспект"), ("пр-т", "проспект"),
+    ("наб", "набережная"), ("б-р", "бульвар"), ("ш", "шоссе"),
+];
+
+/// Which words of a street name can be an abbreviated type.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TypePosition {
+    /// Any word, for languages where the abbreviations are unambiguous
+    #[default]
+    Any,
+    /// The first word, `r. de Rivoli`
+    First,
+    /// The last word, so `St John's Rd` becomes `St John's Road`
+    Last,
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Dictionary {
+    pub position: TypePosition,
+    /// Whole words, matched case insensitively with or without a trailing dot
+    #[serde(deserialize_with = "deserialize_words")]
+    pub words: HashMap<String, String>,
+    /// Titles before a name, matched like words but only as the first word and followed by the name, like `Burg.` in
+    /// `Burg. de Withstraat`
+    #[serde(deserialize_with = "deserialize_words")]
+    pub prefixes: HashMap<String, String>,
+    /// Endings of compound words, matched case insensitively including the dot, like `str.` in `Kerkstr.`. Used in
+    /// any position, the longest that matches wins.
+    #[serde(deserialize_with = "deserialize_suffixes")]
+    pub suffixes: Vec<(String, String)>,
+}
+
+fn deserialize_words<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<String, String>, D::Error> {
+    Ok(HashMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(word, expansion)| (word.trim_end_matches('.').to_lowercase(), expansion))
+        .collect())
+}
+
+fn deserialize_suffixes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<(String, String)>, D::Error> {
+    Ok(longest_first(HashMap::<String, String>::deserialize(deserializer)?.into_iter().map(|(suffix, expansion)| (suffix.to_lowercase(), expansion))))
+}
+
+/// The suffixes in the order they're tried, longest first and alphabetically among those as long, so overlapping
+/// suffixes like `str.` and `tr.` always expand the same way.
+fn longest_first(suffixes: impl Iterator<Item = (String, String)>) -> Vec<(String, String)> {
+    let mut suffixes: Vec<(String, String)> = suffixes.collect();
+    suffixes.sort_by(|(a, _), (b, _)| b.chars().count().cmp(&a.chars().count()).then_with(|| a.cmp(b)));
+    suffixes
+}
+
+impl Dictionary {
+    fn new(position: TypePosition, words: &[(&str, &str)], prefixes: &[(&str, &str)], suffixes: &[(&str, &str)]) -> Self {
+        let entries = |entries: &[(&str, &str)]| entries.iter().map(|(from, to)| (from.to_string(), to.to_string())).collect::<Vec<_>>();
+
+        Self {
+            position,
+            words: entries(words).into_iter().collect(),
+            prefixes: entries(prefixes).into_iter().collect(),
+            suffixes: longest_first(entries(suffixes).into_iter()),
+        }
+    }
+
+    fn expand_word(&self, word: &str, typed: bool, prefix: bool) -> Option<String> {
+        let key = word.trim_end_matches('.').to_lowercase();
+
+        if let Some(expansion) = typed.then(|| self.words.get(&key)).flatten().or_else(|| prefix.then(|| self.prefixes.get(&key)).flatten()) {
+            return Some(expansion.clone());
+        }
+
+        self.suffixes.iter().find_map(|(suffix, expansion)| {
+            let cut = word.len().checked_sub(suffix.len()).filter(|cut| *cut > 0 && word.is_char_boundary(*cut))?;
+
+            (word[cut..].to_lowercase() == *suffix).then(|| format!("{}{}", &word[..cut], expansion))
+        })
+    }
+
+    /// The street with its abbreviations written out. A street that's a single word is only expanded by suffix, so a
+    /// street named `Dr` stays as it is.
+    pub fn expand(&self, street: &str) -> String {
+        let words: Vec<&str> = street.split_whitespace().collect();
+        let last = words.len().saturating_sub(1);
+        let mut changed = false;
+
+        let expanded: Vec<String> = words.iter().enumerate()
+            .map(|(i, word)| {
+                let typed = words.len() > 1 && match self.position {
+                    TypePosition::Any => true,
+                    TypePosition::First => i == 0,
+                    TypePosition::Last => i == last,
+                };
+
+                match self.expand_word(word, typed, i == 0 && words.len() > 1) {
+                    Some(expansion) => {
+                        changed = true;
+                        expansion
+                    }
+                    None => word.to_string(),
+                }
+            })
+            .collect();
+
+        // Keeps the whitespace of streets without abbreviations
+        if changed { expanded.join(" ") } else { street.to_string() }
+    }
+}
+
+pub struct Dictionaries {
+    languages: BTreeMap<String, Dictionary>,
+}
+
+impl Dictionaries {
+    /// The dictionaries shipped with the importer.
+    pub fn builtin() -> Self {
+        let languages = BTreeMap::from([
+            ("nl".to_string(), Dictionary::new(TypePosition::Any, &NL_WORDS, &NL_PREFIXES, &NL_SUFFIXES)),
+            ("de".to_string(), Dictionary::new(TypePosition::Any, &DE_WORDS, &[], &DE_SUFFIXES)),
+            ("en".to_string(), Dictionary::new(TypePosition::Last, &EN_WORDS, &[], &[])),
+            ("fr".to_string(), Dictionary::new(TypePosition::First, &FR_WORDS, &[], &[])),
+            // The type comes first or last, `ул. Ленина` and `Ленина ул.` are both common
+            ("ru".to_string(), Dictionary::new(TypePosition::Any, &RU_WORDS, &[], &[])),
+        ]);
+
+        Self { languages }
+    }
+
+    /// The built-in dictionaries, with those in the file added or replaced.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let overrides: HashMap<String, Dictionary> = serde_json::from_slice(&std::fs::read(path)?)?;
+
+        let mut dictionaries = Self::builtin();
+        dictionaries.languages.extend(overrides.into_iter().map(|(language, dictionary)| (language.to_lowercase(), dictionary)));
+
+        Ok(dictionaries)
+    }
+
+    pub fn get(&self, language: &str) -> Option<&Dictionary> {
+        self.languages.get(&language.to_lowercase())
+    }
+}
+
+/// Sets the dictionaries used by [`dictionaries`], only the first call has any effect.
+pub fn init(dictionaries: Dictionaries) {
+    let _ = DICTIONARIES.set(dictionaries);
+}
+
+pub fn dictionaries() -> &'static Dictionaries {
+    DICTIONARIES.get_or_init(Dictionaries::builtin)
+}
@@ -0,0 +1,112 @@
+//! Counts the `addr:*` tags in an extract without importing it, to see which tags a region uses and how its values
+//! are formatted before picking a country profile.
+
+use std::collections::HashMap;
+use std::io::{BufReader, Read};
+use std::path::PathBuf;
+
+use clap::{arg, value_parser, Command};
+use xml::reader::{EventReader, ParserConfig2, XmlEvent};
+
+use crate::error::Error;
+use crate::table::print_table;
+
+pub fn cli() -> Command {
+    Command::new("survey")
+        .about("Counts the addr:* tags in OSM XML and how their values are formatted, without importing anything")
+        .arg(arg!(--input <OSM_XML> "Read the OSM XML from this file or URL instead of stdin, decompressing .bz2 and .gz").value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--top <COUNT> "Number of value patterns listed per tag").value_parser(value_parser!(usize)).default_value("3"))
+}
+
+/// The shape of a value: digits become `9`, letters `A`, anything else is kept. `1234 AB` and `5678 CD` both become
+/// `9999 AA`.
+fn pattern(value: &str) -> String {
+    value.chars()
+        .map(|c| match c {
+            c if c.is_numeric() => '9',
+            c if c.is_alphabetic() => 'A',
+            c => c,
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct KeyStats {
+    count: u64,
+    patterns: HashMap<String, u64>,
+}
+
+pub fn run(input: Box<dyn Read>, top: usize) -> Result<(), Error> {
+    let parser_config = ParserConfig2::new()
+        .trim_whitespace(true)
+        .ignore_comments(true)
+        .cdata_to_characters(false);
+
+    let parser = EventReader::new_with_config(BufReader::with_capacity(10_000_000, input), parser_config);
+
+    let mut keys: HashMap<String, KeyStats> = HashMap::new();
+    let (mut elements, mut addressed) = (0u64, 0u64);
+    let mut has_address = false;
+
+    for event in parser {
+        let XmlEvent::StartElement { name, attributes, .. } = event? else {
+            continue;
+        };
+
+        match name.local_name.as_str() {
+            "node" | "way" | "relation" => {
+                elements += 1;
+                addressed += has_address as u64;
+                has_address = false;
+            }
+            "tag" => {
+                let attribute = |name: &str| attributes.iter()
+                    .find(|attribute| attribute.name.local_name == name)
+                    .map(|attribute| attribute.value.as_str());
+
+                let (Some(key), Some(value)) = (attribute("k"), attribute("v")) else {
+                    continue;
+                };
+
+                if !key.starts_with("addr:") {
+                    continue;
+                }
+
+                has_address = true;
+
+                let stats = keys.entry(key.to_string()).or_default();
+                stats.count += 1;
+                *stats.patterns.entry(pattern(value)).or_default() += 1;
+            }
+            _ => {}
+        }
+    }
+
+    addressed += has_address as u64;
+
+    println!("{} elements, {} with addr:* tags", elements, addressed);
+
+    let mut keys: Vec<(String, KeyStats)> = keys.into_iter().collect();
+    keys.sort_by(|(a_key, a), (b_key, b)| b.count.cmp(&a.count).then_with(|| a_key.cmp(b_key)));
+
+    let share = |count: u64, total: u64| format!("{:.1}%", count as f64 * 100.0 / total.max(1) as f64);
+
+    let rows: Vec<Vec<String>> = keys.into_iter()
+        .map(|(key, stats)| {
+            let mut patterns: Vec<(String, u64)> = stats.patterns.into_iter().collect();
+            patterns.sort_by(|(a_pattern, a), (b_pattern, b)| b.cmp(a).then_with(|| a_pattern.cmp(b_pattern)));
+
+            let common = patterns.iter()
+                .take(top)
+                .map(|(pattern, count)| format!("{} ({})", pattern, share(*count, stats.count)))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            vec![key, stats.count.to_string(), share(stats.count, addressed), patterns.len().to_string(), common]
+        })
+        .collect();
+
+    print_table(&["key", "count", "of_addresses", "patterns", "most_common"], &rows);
+
+    Ok(())
+}
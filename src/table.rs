@@ -0,0 +1,35 @@
+/// Prints rows as an ASCII table, like the sqlite/mysql command line clients do.
+pub fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.chars().count()).collect();
+
+    for row in rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+
+    let separator = widths.iter()
+        .map(|width| "-".repeat(width + 2))
+        .collect::<Vec<_>>()
+        .join("+");
+    let separator = format!("+{}+", separator);
+
+    let format_row = |values: Vec<&str>| {
+        let cells = values.iter().zip(&widths)
+            .map(|(value, width)| format!(" {:<width$} ", value, width = width))
+            .collect::<Vec<_>>()
+            .join("|");
+
+        format!("|{}|", cells)
+    };
+
+    println!("{}", separator);
+    println!("{}", format_row(headers.to_vec()));
+    println!("{}", separator);
+
+    for row in rows {
+        println!("{}", format_row(row.iter().map(String::as_str).collect()));
+    }
+
+    println!("{}", separator);
+}
@@ -0,0 +1,57 @@
+//! The IANA timezone of every postcode, looked up at the center of its addresses in timezone boundaries like the
+//! `combined.json` of timezone-boundary-builder. Delivery scheduling joins it to know the local time at an address.
+
+use sea_orm::sea_query::{Expr, Func, SimpleExpr};
+use sea_orm::{ActiveValue, DatabaseConnection, DbErr, EntityTrait, QueryOrder, QuerySelect};
+
+use crate::batch::{BatchInsert, Upsert};
+use crate::boundaries::Boundaries;
+use crate::database::live_nodes;
+use crate::entities::*;
+
+/// The property timezone-boundary-builder names its polygons by.
+pub const PROPERTY: &str = "tzid";
+
+const BATCH_SIZE: usize = 1024;
+const PENDING_WRITES: usize = 4;
+
+/// Rebuilds `postcode_timezone` per country and postcode, returning how many are outside every timezone and left out.
+pub async fn build(db: &DatabaseConnection, timezones: &Boundaries) -> Result<usize, DbErr> {
+    postcode_timezone::Entity::delete_many().exec(db).await?;
+
+    let centroids: Vec<(Option<String>, String, f64, f64)> = live_nodes()
+        .select_only()
+        .column(node::Column::Country)
+        .column(node::Column::Postcode)
+        .column_as(SimpleExpr::from(Func::avg(Expr::col(node::Column::Lat))), "lat")
+        .column_as(SimpleExpr::from(Func::avg(Expr::col(node::Column::Lon))), "lon")
+        .group_by(node::Column::Country)
+        .group_by(node::Column::Postcode)
+        .order_by_asc(node::Column::Country)
+        .order_by_asc(node::Column::Postcode)
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let mut rows = BatchInsert::new(Upsert::new(db.clone(), None), BATCH_SIZE, PENDING_WRITES, None);
+    let mut outside = 0;
+
+    for (country, postcode, lat, lon) in centroids {
+        let Some(timezone) = timezones.locate(lat, lon) else {
+            outside += 1;
+            continue;
+        };
+
+        rows.push(postcode_timezone::ActiveModel {
+            country: ActiveValue::Set(country.unwrap_or_default()),
+            postcode: ActiveValue::Set(postcode),
+            timezone: ActiveValue::Set(timezone.to_string()),
+            lat: ActiveValue::Set(lat),
+            lon: ActiveValue::Set(lon),
+        }).await?;
+    }
+
+    rows.finish().await?;
+
+    Ok(outside)
+}
@@ -0,0 +1,99 @@
+//! Wall time, throughput, memory and database growth of every import phase, to compare runs across versions.
+
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use sea_orm::{DatabaseConnection, DbErr};
+use serde::Serialize;
+
+use crate::database;
+use crate::table::print_table;
+
+#[derive(Serialize)]
+pub struct Phase {
+    name: &'static str,
+    seconds: f64,
+    rows: Option<u64>,
+    rows_per_second: Option<f64>,
+    /// Highest resident memory during the phase, only known on Linux
+    peak_rss_bytes: Option<u64>,
+    db_size_growth_bytes: Option<i64>,
+}
+
+/// A phase that's still running.
+pub struct Running {
+    name: &'static str,
+    started: Instant,
+    db_size: Option<u64>,
+}
+
+pub struct Timings {
+    db_uri: String,
+    phases: Vec<Phase>,
+}
+
+impl Timings {
+    pub fn new(db_uri: &str) -> Self {
+        Self { db_uri: db_uri.to_string(), phases: Vec::new() }
+    }
+
+    pub async fn start(&self, db: &DatabaseConnection, name: &'static str) -> Result<Running, DbErr> {
+        reset_peak_rss();
+
+        Ok(Running { name, started: Instant::now(), db_size: database::size(db, &self.db_uri).await? })
+    }
+
+    /// Records a finished phase, `rows` is the number of rows it wrote or went through.
+    pub async fn finish(&mut self, db: &DatabaseConnection, running: Running, rows: Option<u64>) -> Result<(), DbErr> {
+        let seconds = running.started.elapsed().as_secs_f64();
+        let db_size = database::size(db, &self.db_uri).await?;
+
+        self.phases.push(Phase {
+            name: running.name,
+            seconds,
+            rows,
+            rows_per_second: rows.filter(|_| seconds > 0.0).map(|rows| rows as f64 / seconds),
+            peak_rss_bytes: peak_rss(),
+            db_size_growth_bytes: running.db_size.zip(db_size).map(|(before, after)| after as i64 - before as i64),
+        });
+
+        Ok(())
+    }
+
+    pub fn print(&self) {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+
+        let rows: Vec<Vec<String>> = self.phases.iter()
+            .map(|phase| vec![
+                phase.name.to_string(),
+                format!("{:.1}s", phase.seconds),
+                optional(phase.rows.map(|rows| rows.to_string())),
+                optional(phase.rows_per_second.map(|rate| format!("{:.0}", rate))),
+                optional(phase.peak_rss_bytes.map(|bytes| format!("{:.1} MiB", bytes as f64 / MIB))),
+                optional(phase.db_size_growth_bytes.map(|bytes| format!("{:+.1} MiB", bytes as f64 / MIB))),
+            ])
+            .collect();
+
+        print_table(&["phase", "time", "rows", "rows/s", "peak rss", "db growth"], &rows);
+    }
+
+    pub fn write_json(&self, path: &Path) -> std::io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(&self.phases)?)
+    }
+}
+
+const MIB: f64 = 1024.0 * 1024.0;
+
+/// Resets the peak resident memory reported by the kernel, so it covers the next phase only.
+fn reset_peak_rss() {
+    let _ = fs::write("/proc/self/clear_refs", "5");
+}
+
+fn peak_rss() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes: u64 = line.trim_start_matches("VmHWM:").trim().trim_end_matches("kB").trim().parse().ok()?;
+
+    Some(kilobytes * 1024)
+}
@@ -0,0 +1,83 @@
+//! Checks that the input is the extract that was published, and recent enough to build from.
+
+use std::io::{self, Read};
+use std::path::Path;
+
+use chrono::{DateTime, Duration, Utc};
+use md5::{Digest, Md5};
+
+use crate::download::{self, Download};
+use crate::error::Error;
+
+/// The checksum published next to an extract as `<file>.md5`, like Geofabrik does. The file holds the hash followed
+/// by the file name.
+pub fn published_md5(input: &Path) -> Result<String, Error> {
+    let sidecar = format!("{}.md5", input.display());
+    let read_error = |e: io::Error| Error::Input(format!("failed to read the checksum {}: {}", sidecar, e));
+
+    let mut contents = String::new();
+
+    if download::is_remote(&sidecar) {
+        Download::start(sidecar.clone()).read_to_string(&mut contents).map_err(read_error)?;
+    } else {
+        std::fs::File::open(&sidecar).and_then(|mut file| file.read_to_string(&mut contents)).map_err(read_error)?;
+    }
+
+    contents.split_whitespace()
+        .next()
+        .filter(|hash| hash.len() == 32 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_lowercase)
+        .ok_or_else(|| Error::Input(format!("{} doesn't start with an md5 hash", sidecar)))
+}
+
+/// Hashes everything read through it and fails the read at the end of the input when the hash doesn't match. The
+/// import stops there, before duplicates are merged and before a staged import is swapped in.
+pub struct Md5Reader<R> {
+    inner: R,
+    hasher: Md5,
+    /// Taken once the end is reached
+    expected: Option<String>,
+}
+
+impl<R: Read> Md5Reader<R> {
+    pub fn new(inner: R, expected: String) -> Self {
+        Self { inner, hasher: Md5::new(), expected: Some(expected) }
+    }
+}
+
+impl<R: Read> Read for Md5Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+
+        if read > 0 {
+            self.hasher.update(&buf[..read]);
+        } else if !buf.is_empty() {
+            if let Some(expected) = self.expected.take() {
+                let actual = hex::encode(std::mem::take(&mut self.hasher).finalize());
+
+                if actual != expected {
+                    return Err(io::Error::other(format!("md5 mismatch, expected {} but the input is {}", expected, actual)));
+                }
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+/// Fails when the replication timestamp of the extract is older than `max_age`. Only warns when the extract doesn't
+/// have one.
+pub fn check_freshness(timestamp: Option<DateTime<Utc>>, max_age: Duration) -> Result<(), Error> {
+    let Some(timestamp) = timestamp else {
+        println!("Warning: the input has no timestamp, can't tell whether it's older than --max-age");
+        return Ok(());
+    };
+
+    let age = Utc::now() - timestamp;
+
+    if age > max_age {
+        return Err(Error::Input(format!("the extract is from {}, {} days old, which is older than --max-age", timestamp, age.num_days())));
+    }
+
+    Ok(())
+}
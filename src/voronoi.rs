@@ -0,0 +1,125 @@
+//! Voronoi cells clipped to a convex region.
+//!
+//! Every cell starts as the region and is cut by the bisector with each nearby site. Sites are bucketed in a grid
+//! which is searched in rings around the site until the next ring is too far away to cut the cell any further.
+
+use crate::hull::Point;
+
+fn distance(a: Point, b: Point) -> f64 {
+    (a.0 - b.0).hypot(a.1 - b.1)
+}
+
+/// A corner of a cell, `neighbor` is the site whose bisector forms the edge to the next corner, or `None` on the
+/// border of the region.
+#[derive(Clone, Copy)]
+struct Corner {
+    point: Point,
+    neighbor: Option<usize>,
+}
+
+/// Keeps the part of a convex polygon that's closer to `site` than to `sites[other]`.
+fn clip(cell: &[Corner], site: Point, sites: &[Point], other: usize) -> Vec<Corner> {
+    let middle = ((site.0 + sites[other].0) / 2.0, (site.1 + sites[other].1) / 2.0);
+    let normal = (sites[other].0 - site.0, sites[other].1 - site.1);
+    let side = |p: Point| (p.0 - middle.0) * normal.0 + (p.1 - middle.1) * normal.1;
+
+    let mut clipped = Vec::with_capacity(cell.len() + 1);
+
+    for (i, &current) in cell.iter().enumerate() {
+        let next = cell[(i + 1) % cell.len()];
+        let (a, b) = (side(current.point), side(next.point));
+        let crossing = || {
+            let t = a / (a - b);
+            (current.point.0 + t * (next.point.0 - current.point.0), current.point.1 + t * (next.point.1 - current.point.1))
+        };
+
+        if a == 0.0 && b > 0.0 {
+            clipped.push(Corner { point: current.point, neighbor: Some(other) });
+        } else if a < 0.0 && b > 0.0 {
+            clipped.push(current);
+            clipped.push(Corner { point: crossing(), neighbor: Some(other) });
+        } else if a > 0.0 && b < 0.0 {
+            clipped.push(Corner { point: crossing(), neighbor: current.neighbor });
+        } else if a <= 0.0 {
+            clipped.push(current);
+        }
+    }
+
+    clipped
+}
+
+/// A counter-clockwise Voronoi cell with the border shared with each neighboring site.
+pub struct Cell {
+    pub ring: Vec<Point>,
+    pub neighbors: Vec<(usize, (Point, Point))>,
+}
+
+/// The cell of every site within the convex, counter-clockwise `region`. Cells are empty when the site lies outside the
+/// region. Sites sharing a location get the same, overlapping, cell and aren't each other's neighbors.
+pub fn voronoi_cells(sites: &[Point], region: &[Point]) -> Vec<Cell> {
+    if sites.is_empty() {
+        return Vec::new();
+    }
+
+    let (min_x, max_x) = sites.iter().fold((f64::MAX, f64::MIN), |(min, max), p| (min.min(p.0), max.max(p.0)));
+    let (min_y, max_y) = sites.iter().fold((f64::MAX, f64::MIN), |(min, max), p| (min.min(p.1), max.max(p.1)));
+
+    // Roughly one site per bucket
+    let size = (((max_x - min_x) * (max_y - min_y)) / sites.len() as f64).sqrt().max((max_x - min_x).max(max_y - min_y) / sites.len() as f64).max(1e-9);
+    let columns = ((max_x - min_x) / size) as usize + 1;
+    let rows = ((max_y - min_y) / size) as usize + 1;
+
+    let bucket_of = |p: Point| (((p.0 - min_x) / size) as usize, ((p.1 - min_y) / size) as usize);
+
+    let mut buckets = vec![Vec::new(); columns * rows];
+
+    for (i, &site) in sites.iter().enumerate() {
+        let (x, y) = bucket_of(site);
+        buckets[y * columns + x].push(i);
+    }
+
+    sites.iter()
+        .enumerate()
+        .map(|(i, &site)| {
+            let (x, y) = (bucket_of(site).0 as isize, bucket_of(site).1 as isize);
+            let mut cell: Vec<Corner> = region.iter().map(|&point| Corner { point, neighbor: None }).collect();
+
+            for ring in 0..=columns.max(rows) as isize {
+                let ring_buckets = (y - ring..=y + ring)
+                    .flat_map(|by| {
+                        let step = if by == y - ring || by == y + ring { 1 } else { (2 * ring).max(1) as usize };
+
+                        (x - ring..=x + ring).step_by(step).map(move |bx| (bx, by))
+                    })
+                    .filter(|&(bx, by)| bx >= 0 && by >= 0 && (bx as usize) < columns && (by as usize) < rows);
+
+                for (bx, by) in ring_buckets {
+                    for &other in &buckets[by as usize * columns + bx as usize] {
+                        if other != i && sites[other] != site {
+                            cell = clip(&cell, site, sites, other);
+                        }
+                    }
+                }
+
+                // Sites outside the searched rings are at least `ring * size` away, and a site can only cut the cell
+                // when it's closer than twice the distance to the cell's furthest corner
+                let radius = cell.iter().map(|corner| distance(site, corner.point)).fold(0.0, f64::max);
+
+                if cell.is_empty() || ring as f64 * size >= 2.0 * radius {
+                    break;
+                }
+            }
+
+            let neighbors = cell.iter()
+                .enumerate()
+                .filter_map(|(index, corner)| {
+                    let next = cell[(index + 1) % cell.len()].point;
+
+                    corner.neighbor.filter(|_| distance(corner.point, next) > 0.0).map(|neighbor| (neighbor, (corner.point, next)))
+                })
+                .collect();
+
+            Cell { ring: cell.into_iter().map(|corner| corner.point).collect(), neighbors }
+        })
+        .collect()
+}
@@ -0,0 +1,109 @@
+//! Locations for addresses tagged on ways, usually buildings.
+//!
+//! Ways only reference their nodes, so the coordinates of every node are kept in memory while parsing XML, or just
+//! those of the nodes of ways for PBF. They're stored at OSM's own precision of 1e-7 degrees, 16 bytes per node.
+
+use std::collections::HashSet;
+
+const PRECISION: f64 = 1e7;
+
+#[derive(Default)]
+pub struct NodeIndex {
+    coordinates: Vec<(i64, i32, i32)>,
+    sorted: bool,
+    main_entrances: HashSet<i64>,
+}
+
+impl NodeIndex {
+    pub fn add(&mut self, id: i64, lat: f64, lon: f64) {
+        // A later version of the same node, in full history files
+        if self.coordinates.last().is_some_and(|(last, _, _)| *last == id) {
+            self.coordinates.pop();
+        }
+
+        if self.coordinates.last().is_some_and(|(last, _, _)| *last > id) {
+            self.sorted = false;
+        } else if self.coordinates.is_empty() {
+            self.sorted = true;
+        }
+
+        self.coordinates.push((id, (lat * PRECISION).round() as i32, (lon * PRECISION).round() as i32));
+    }
+
+    pub fn mark_main_entrance(&mut self, id: i64) {
+        self.main_entrances.insert(id);
+    }
+
+    /// Extracts are sorted by id, this only happens for hand made files.
+    pub fn sort(&mut self) {
+        if !self.sorted {
+            self.coordinates.sort_unstable_by_key(|(id, _, _)| *id);
+            self.sorted = true;
+        }
+    }
+
+    fn get(&self, id: i64) -> Option<(f64, f64)> {
+        self.coordinates
+            .binary_search_by_key(&id, |(id, _, _)| *id)
+            .ok()
+            .map(|i| (self.coordinates[i].1 as f64 / PRECISION, self.coordinates[i].2 as f64 / PRECISION))
+    }
+
+    /// The location of a way: its `entrance=main` node if it has one, otherwise the centroid of its outline. The flag
+    /// is set when the entrance was used. `None` when none of the nodes are known.
+    pub fn locate(&mut self, refs: &[i64]) -> Option<(f64, f64, bool)> {
+        self.sort();
+        self.find(refs)
+    }
+
+    /// Like [`Self::locate`], on an index that's sorted already so it can be shared between threads.
+    pub fn find(&self, refs: &[i64]) -> Option<(f64, f64, bool)> {
+        debug_assert!(self.sorted || self.coordinates.is_empty());
+
+        if let Some((lat, lon)) = refs.iter()
+            .filter(|id| self.main_entrances.contains(id))
+            .find_map(|id| self.get(*id))
+        {
+            return Some((lat, lon, true));
+        }
+
+        let points: Vec<(f64, f64)> = refs.iter().filter_map(|id| self.get(*id)).collect();
+
+        centroid(&points).map(|(lat, lon)| (lat, lon, false))
+    }
+
+    /// The locations of the nodes as lat, lon, `None` when one of them isn't known.
+    pub fn line(&mut self, refs: &[i64]) -> Option<Vec<(f64, f64)>> {
+        self.sort();
+
+        refs.iter().map(|id| self.get(*id)).collect()
+    }
+}
+
+/// Area weighted centroid of a closed outline, or the average of the points when it has no area.
+fn centroid(points: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let first = *points.first()?;
+
+    // Relative to the first point to keep the products small
+    let (mut area, mut lat, mut lon) = (0.0, 0.0, 0.0);
+
+    for pair in points.windows(2) {
+        let (a, b) = ((pair[0].0 - first.0, pair[0].1 - first.1), (pair[1].0 - first.0, pair[1].1 - first.1));
+        let cross = a.1 * b.0 - b.1 * a.0;
+
+        area += cross;
+        lat += (a.0 + b.0) * cross;
+        lon += (a.1 + b.1) * cross;
+    }
+
+    if area.abs() < f64::EPSILON {
+        let count = points.len() as f64;
+
+        return Some((
+            points.iter().map(|(lat, _)| lat).sum::<f64>() / count,
+            points.iter().map(|(_, lon)| lon).sum::<f64>() / count,
+        ));
+    }
+
+    Some((first.0 + lat / (3.0 * area), first.1 + lon / (3.0 * area)))
+}